@@ -21,11 +21,11 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 
 use super::config;
-use super::request;
+use super::identity::Scope;
 use super::services::ServiceType;
 #[cfg(feature = "stream")]
-use super::stream::{paginated, Resource};
-use super::{ApiVersion, AuthType, EndpointFilters, Error, InterfaceType, Session};
+use super::stream::{session_paginated, Resource};
+use super::{ApiVersion, AuthType, EndpointFilters, Error, InterfaceType, Session, VersionInfo};
 
 /// Adapter for a specific service.
 ///
@@ -51,6 +51,9 @@ impl<Srv> Adapter<Srv> {
     }
 
     /// Create a new adapter from a `clouds.yaml` configuration file.
+    ///
+    /// This function is only available when the `config-file` feature is enabled.
+    #[cfg(feature = "config-file")]
     #[inline]
     pub fn from_config<S: AsRef<str>>(cloud_name: S, service: Srv) -> Result<Adapter<Srv>, Error> {
         Ok(config::from_config(cloud_name)?.into_adapter(service))
@@ -175,6 +178,33 @@ impl<Srv> Adapter<Srv> {
 }
 
 impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
+    /// Create a new adapter with a token of a different scope.
+    ///
+    /// See [Session::scoped_to](struct.Session.html#method.scoped_to).
+    pub async fn scoped_to(&self, scope: Scope) -> Result<Adapter<Srv>, Error> {
+        let mut adapter = self.clone();
+        adapter.inner = self.inner.scoped_to(scope).await?;
+        Ok(adapter)
+    }
+
+    /// Create a new adapter with a different scope without authenticating.
+    ///
+    /// See [Session::with_scope](struct.Session.html#method.with_scope).
+    pub fn with_scope(&self, scope: Scope) -> Result<Adapter<Srv>, Error> {
+        let mut adapter = self.clone();
+        adapter.inner = self.inner.with_scope(scope)?;
+        Ok(adapter)
+    }
+
+    /// Create a new adapter using endpoints from a different region.
+    ///
+    /// See [Session::for_region](struct.Session.html#method.for_region).
+    pub fn for_region<S: Into<String>>(&self, region: S) -> Adapter<Srv> {
+        let mut adapter = self.clone();
+        adapter.inner = self.inner.for_region(region);
+        adapter
+    }
+
     /// Get minimum/maximum API (micro)version information.
     ///
     /// Returns `None` if the range cannot be determined, which usually means
@@ -214,6 +244,33 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
         self.inner.get_endpoint(self.service.clone(), path).await
     }
 
+    /// Construct an endpoint from the path using a specific interface.
+    ///
+    /// The interface only applies to this call and the result is not cached.
+    pub async fn get_endpoint_with_interface<I>(
+        &self,
+        interface: InterfaceType,
+        path: I,
+    ) -> Result<Url, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+    {
+        self.inner
+            .get_endpoint_with_interface(self.service.clone(), interface, path)
+            .await
+    }
+
+    /// Use the given endpoint instead of the one from the service catalog.
+    ///
+    /// This call clears the cached service information for this `Adapter`.
+    /// It does not, however, affect clones of this `Adapter`.
+    #[inline]
+    pub fn set_endpoint(&mut self, endpoint: Url) {
+        self.inner.set_endpoint(self.service.clone(), endpoint);
+    }
+
     /// Get the currently used major version from the given service.
     ///
     /// Can return `None` if the service does not support API version discovery at all.
@@ -262,6 +319,22 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
             .map(|x| x.is_some())
     }
 
+    /// Check if the service supports the microversion `major.minor`.
+    ///
+    /// See [Session::supports_microversion](struct.Session.html#method.supports_microversion).
+    #[inline]
+    pub async fn supports_microversion(&self, major: u16, minor: u16) -> Result<bool, Error> {
+        self.supports_api_version(ApiVersion(major, minor)).await
+    }
+
+    /// Get all major API versions from the version discovery document of the service.
+    ///
+    /// See [Session::get_versions](struct.Session.html#method.get_versions).
+    #[inline]
+    pub async fn get_versions(&self) -> Result<Vec<VersionInfo>, Error> {
+        self.inner.get_versions(self.service.clone()).await
+    }
+
     /// Make an HTTP request.
     ///
     /// The `path` argument is a URL path without the service endpoint (e.g. `/servers/1234`).
@@ -308,6 +381,27 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
             .await
     }
 
+    /// Prepare an HTTP request using a specific interface for this request only.
+    ///
+    /// See [request](#method.request) for an explanation of the parameters.
+    pub async fn request_with_interface<I>(
+        &self,
+        interface: InterfaceType,
+        method: Method,
+        path: I,
+        api_version: Option<ApiVersion>,
+    ) -> Result<RequestBuilder, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+    {
+        let real_version = api_version.or(self.default_api_version);
+        self.inner
+            .request_with_interface(self.service.clone(), interface, method, path, real_version)
+            .await
+    }
+
     /// Issue a GET request.
     ///
     /// See [request](#method.request) for an explanation of the parameters.
@@ -318,7 +412,9 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
         I::Item: AsRef<str>,
         I::IntoIter: Send,
     {
-        request::send_checked(self.request(Method::GET, path, api_version).await?).await
+        self.inner
+            .send_checked(self.request(Method::GET, path, api_version).await?)
+            .await
     }
 
     /// Fetch a JSON using the GET request.
@@ -361,11 +457,16 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
         I::IntoIter: Send,
         T: DeserializeOwned + Send,
     {
-        request::fetch_json(self.request(Method::GET, path, api_version).await?).await
+        self.inner
+            .fetch_json(self.request(Method::GET, path, api_version).await?)
+            .await
     }
 
     /// Fetch a paginated list of JSON objects using the GET request.
     ///
+    /// Markers are used for pagination unless the resource provides links to the next pages via
+    /// [Resource::next_link](stream/trait.Resource.html#method.next_link).
+    ///
     /// ```rust,no_run
     /// # async fn example() -> Result<(), osauth::Error> {
     /// use futures::pin_mut;
@@ -433,7 +534,12 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
         <T as Resource>::Root: Into<Vec<T>> + Send,
     {
         let builder = self.request(Method::GET, path, api_version).await?;
-        Ok(paginated(builder, limit, starting_with))
+        Ok(session_paginated(
+            &self.inner,
+            builder,
+            limit,
+            starting_with,
+        ))
     }
 
     /// Fetch a JSON using the GET request with a query.
@@ -454,12 +560,13 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
         Q: Serialize + Send,
         T: DeserializeOwned + Send,
     {
-        request::fetch_json(
-            self.request(Method::GET, path, api_version)
-                .await?
-                .query(&query),
-        )
-        .await
+        self.inner
+            .fetch_json(
+                self.request(Method::GET, path, api_version)
+                    .await?
+                    .query(&query),
+            )
+            .await
     }
 
     /// Fetch a paginated list of JSON objects using the GET request with a query.
@@ -487,7 +594,12 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
             .request(Method::GET, path, api_version)
             .await?
             .query(&query);
-        Ok(paginated(builder, limit, starting_with))
+        Ok(session_paginated(
+            &self.inner,
+            builder,
+            limit,
+            starting_with,
+        ))
     }
 
     /// Issue a GET request with a query
@@ -507,12 +619,13 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
         I::IntoIter: Send,
         Q: Serialize + Send,
     {
-        request::send_checked(
-            self.request(Method::GET, path, api_version)
-                .await?
-                .query(&query),
-        )
-        .await
+        self.inner
+            .send_checked(
+                self.request(Method::GET, path, api_version)
+                    .await?
+                    .query(&query),
+            )
+            .await
     }
 
     /// POST a JSON object.
@@ -533,12 +646,13 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
         I::IntoIter: Send,
         T: Serialize + Send,
     {
-        request::send_checked(
-            self.request(Method::POST, path, api_version)
-                .await?
-                .json(&body),
-        )
-        .await
+        self.inner
+            .send_checked(
+                self.request(Method::POST, path, api_version)
+                    .await?
+                    .json(&body),
+            )
+            .await
     }
 
     /// POST a JSON object and receive a JSON back.
@@ -560,12 +674,13 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
         T: Serialize + Send,
         R: DeserializeOwned + Send,
     {
-        request::fetch_json(
-            self.request(Method::POST, path, api_version)
-                .await?
-                .json(&body),
-        )
-        .await
+        self.inner
+            .fetch_json(
+                self.request(Method::POST, path, api_version)
+                    .await?
+                    .json(&body),
+            )
+            .await
     }
 
     /// PUT a JSON object.
@@ -586,12 +701,13 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
         I::IntoIter: Send,
         T: Serialize + Send,
     {
-        request::send_checked(
-            self.request(Method::PUT, path, api_version)
-                .await?
-                .json(&body),
-        )
-        .await
+        self.inner
+            .send_checked(
+                self.request(Method::PUT, path, api_version)
+                    .await?
+                    .json(&body),
+            )
+            .await
     }
 
     /// Issue an empty PUT request.
@@ -608,7 +724,9 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
         I::Item: AsRef<str>,
         I::IntoIter: Send,
     {
-        request::send_checked(self.request(Method::PUT, path, api_version).await?).await
+        self.inner
+            .send_checked(self.request(Method::PUT, path, api_version).await?)
+            .await
     }
 
     /// PUT a JSON object and receive a JSON back.
@@ -630,12 +748,68 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
         T: Serialize + Send,
         R: DeserializeOwned + Send,
     {
-        request::fetch_json(
-            self.request(Method::PUT, path, api_version)
-                .await?
-                .json(&body),
-        )
-        .await
+        self.inner
+            .fetch_json(
+                self.request(Method::PUT, path, api_version)
+                    .await?
+                    .json(&body),
+            )
+            .await
+    }
+
+    /// PATCH a JSON object.
+    ///
+    /// The `body` argument is anything that can be serialized into JSON.
+    ///
+    /// See [request](#method.request) for an explanation of the other parameters.
+    #[inline]
+    pub async fn patch<I, T>(
+        &self,
+        path: I,
+        body: T,
+        api_version: Option<ApiVersion>,
+    ) -> Result<Response, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+        T: Serialize + Send,
+    {
+        self.inner
+            .send_checked(
+                self.request(Method::PATCH, path, api_version)
+                    .await?
+                    .json(&body),
+            )
+            .await
+    }
+
+    /// PATCH a JSON object and receive a JSON back.
+    ///
+    /// The `body` argument is anything that can be serialized into JSON.
+    ///
+    /// See [request](#method.request) for an explanation of the other parameters.
+    #[inline]
+    pub async fn patch_json<I, T, R>(
+        &self,
+        path: I,
+        body: T,
+        api_version: Option<ApiVersion>,
+    ) -> Result<R, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+        T: Serialize + Send,
+        R: DeserializeOwned + Send,
+    {
+        self.inner
+            .fetch_json(
+                self.request(Method::PATCH, path, api_version)
+                    .await?
+                    .json(&body),
+            )
+            .await
     }
 
     /// Issue a DELETE request.
@@ -652,6 +826,8 @@ impl<Srv: ServiceType + Send + Clone> Adapter<Srv> {
         I::Item: AsRef<str>,
         I::IntoIter: Send,
     {
-        request::send_checked(self.request(Method::DELETE, path, api_version).await?).await
+        self.inner
+            .send_checked(self.request(Method::DELETE, path, api_version).await?)
+            .await
     }
 }
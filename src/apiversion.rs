@@ -117,8 +117,6 @@ impl<'de> Deserialize<'de> for ApiVersion {
 pub mod test {
     use std::str::FromStr;
 
-    use serde_json;
-
     use super::ApiVersion;
 
     #[test]
@@ -14,12 +14,16 @@
 
 //! Base code for authentication.
 
-use std::fmt::Debug;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
 
 use async_trait::async_trait;
 use reqwest::{Client, IntoUrl, Method, RequestBuilder, Url};
 
-use super::{EndpointFilters, Error};
+use super::identity::{Scope, TokenInfo};
+use super::request;
+use super::{EndpointFilters, Error, ErrorKind, Metrics};
 
 /// Trait for an authentication type.
 ///
@@ -29,6 +33,62 @@ use super::{EndpointFilters, Error};
 /// 2. get an endpoint URL for the given service type.
 ///
 /// An authentication type should cache the token as long as it's valid.
+///
+/// Besides the built-in types, the trait can be implemented to take tokens and endpoints from
+/// custom sources, e.g. a credential broker. Use the re-exported
+/// [async_trait](attr.async_trait.html) attribute for the implementation:
+///
+/// ```rust
+/// use osauth::{async_trait, AuthType, EndpointFilters, Error, ErrorKind};
+/// use reqwest::{Client, Method, RequestBuilder, Url};
+///
+/// #[derive(Debug)]
+/// struct Broker {
+///     client: Client,
+///     compute: Url,
+/// }
+///
+/// impl Broker {
+///     async fn fetch_token(&self) -> Result<String, Error> {
+///         // Ask the credential broker for a token.
+///         Ok("abcdef".to_string())
+///     }
+/// }
+///
+/// #[async_trait]
+/// impl AuthType for Broker {
+///     async fn get_endpoint(
+///         &self,
+///         service_type: String,
+///         _filters: EndpointFilters,
+///     ) -> Result<Url, Error> {
+///         match service_type.as_str() {
+///             "compute" => Ok(self.compute.clone()),
+///             other => Err(Error::new(
+///                 ErrorKind::EndpointNotFound,
+///                 format!("No endpoint for {}", other),
+///             )),
+///         }
+///     }
+///
+///     async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+///         let token = self.fetch_token().await?;
+///         Ok(self.client.request(method, url).header("x-auth-token", token))
+///     }
+///
+///     async fn refresh(&self) -> Result<(), Error> {
+///         Ok(())
+///     }
+/// }
+///
+/// let session = osauth::Session::new(Broker {
+///     client: Client::new(),
+///     compute: Url::parse("https://cloud.local/compute").unwrap(),
+/// });
+/// ```
+///
+/// The trait is also implemented for `Box` and `Arc` of an authentication type, so an
+/// authentication type chosen at run time can be passed as `Box<dyn AuthType>`.
 #[async_trait]
 pub trait AuthType: Debug + Sync + Send {
     /// Get a URL for the requested service.
@@ -44,10 +104,170 @@ pub trait AuthType: Debug + Sync + Send {
     /// Refresh the authentication (renew the token, etc).
     async fn refresh(&self) -> Result<(), Error>;
 
+    /// The URL of the Identity service used for authentication (if any).
+    ///
+    /// Defaults to `None`.
+    fn identity_url(&self) -> Option<&Url> {
+        None
+    }
+
     /// Default endpoint filters (if any).
     fn default_filters(&self) -> Option<&EndpointFilters> {
         None
     }
+
+    /// The service catalog in the format returned by the Identity service (if available).
+    ///
+    /// Defaults to `None`.
+    async fn raw_catalog(&self) -> Result<Option<serde_json::Value>, Error> {
+        Ok(None)
+    }
+
+    /// Information about the current authentication token (if available).
+    ///
+    /// Defaults to `None`.
+    async fn token_info(&self) -> Result<Option<TokenInfo>, Error> {
+        Ok(None)
+    }
+
+    /// A copy of this authentication type with a different scope (if supported).
+    ///
+    /// The copy uses the same credentials, but does not share the cached token.
+    ///
+    /// Defaults to `None`.
+    fn with_new_scope(&self, _scope: Scope) -> Option<Box<dyn AuthType>> {
+        None
+    }
+
+    /// Whether `refresh` obtains a new token using the original credentials.
+    ///
+    /// If `true`, an HTTP 401 from a service causes re-authentication and the request is retried
+    /// once with the new token. If re-authentication fails, the error is reported as
+    /// `ErrorKind::AuthenticationFailed`.
+    ///
+    /// Defaults to `false`.
+    fn can_reauthenticate(&self) -> bool {
+        false
+    }
+
+    /// Report the token requests of this authentication type to the metrics.
+    ///
+    /// Called by [Session::set_metrics](struct.Session.html#method.set_metrics), see
+    /// [Metrics::record_token_refresh](trait.Metrics.html#method.record_token_refresh).
+    ///
+    /// Defaults to doing nothing.
+    fn set_metrics(&self, _metrics: Arc<dyn Metrics>) {}
+
+    /// Revoke the current authentication (e.g. the token) so that it can no longer be used.
+    ///
+    /// A new token is requested on the next use, if the authentication type can do that.
+    ///
+    /// Defaults to doing nothing.
+    async fn revoke(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: AuthType + ?Sized> AuthType for Box<T> {
+    async fn get_endpoint(
+        &self,
+        service_type: String,
+        filters: EndpointFilters,
+    ) -> Result<Url, Error> {
+        (**self).get_endpoint(service_type, filters).await
+    }
+
+    async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+        (**self).request(method, url).await
+    }
+
+    async fn refresh(&self) -> Result<(), Error> {
+        (**self).refresh().await
+    }
+
+    fn identity_url(&self) -> Option<&Url> {
+        (**self).identity_url()
+    }
+
+    fn default_filters(&self) -> Option<&EndpointFilters> {
+        (**self).default_filters()
+    }
+
+    async fn raw_catalog(&self) -> Result<Option<serde_json::Value>, Error> {
+        (**self).raw_catalog().await
+    }
+
+    async fn token_info(&self) -> Result<Option<TokenInfo>, Error> {
+        (**self).token_info().await
+    }
+
+    fn with_new_scope(&self, scope: Scope) -> Option<Box<dyn AuthType>> {
+        (**self).with_new_scope(scope)
+    }
+
+    fn can_reauthenticate(&self) -> bool {
+        (**self).can_reauthenticate()
+    }
+
+    fn set_metrics(&self, metrics: Arc<dyn Metrics>) {
+        (**self).set_metrics(metrics)
+    }
+
+    async fn revoke(&self) -> Result<(), Error> {
+        (**self).revoke().await
+    }
+}
+
+#[async_trait]
+impl<T: AuthType + ?Sized> AuthType for Arc<T> {
+    async fn get_endpoint(
+        &self,
+        service_type: String,
+        filters: EndpointFilters,
+    ) -> Result<Url, Error> {
+        (**self).get_endpoint(service_type, filters).await
+    }
+
+    async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+        (**self).request(method, url).await
+    }
+
+    async fn refresh(&self) -> Result<(), Error> {
+        (**self).refresh().await
+    }
+
+    fn identity_url(&self) -> Option<&Url> {
+        (**self).identity_url()
+    }
+
+    fn default_filters(&self) -> Option<&EndpointFilters> {
+        (**self).default_filters()
+    }
+
+    async fn raw_catalog(&self) -> Result<Option<serde_json::Value>, Error> {
+        (**self).raw_catalog().await
+    }
+
+    async fn token_info(&self) -> Result<Option<TokenInfo>, Error> {
+        (**self).token_info().await
+    }
+
+    fn with_new_scope(&self, scope: Scope) -> Option<Box<dyn AuthType>> {
+        (**self).with_new_scope(scope)
+    }
+
+    fn can_reauthenticate(&self) -> bool {
+        (**self).can_reauthenticate()
+    }
+
+    fn set_metrics(&self, metrics: Arc<dyn Metrics>) {
+        (**self).set_metrics(metrics)
+    }
+
+    async fn revoke(&self) -> Result<(), Error> {
+        (**self).revoke().await
+    }
 }
 
 /// Authentication type that provides no authentication.
@@ -58,10 +278,22 @@ pub trait AuthType: Debug + Sync + Send {
 ///     .expect("Invalid auth URL");
 /// let session = osauth::Session::new(auth);
 /// ```
+///
+/// Several standalone services can be accessed with per-service endpoints:
+/// ```rust,no_run
+/// let auth = osauth::NoAuth::without_default_endpoint()
+///     .with_service_endpoint("baremetal", "https://cloud.local/baremetal")
+///     .and_then(|auth| {
+///         auth.with_service_endpoint("baremetal-introspection", "https://cloud.local/inspector")
+///     })
+///     .expect("Invalid endpoint");
+/// let session = osauth::Session::new(auth);
+/// ```
 #[derive(Clone, Debug)]
 pub struct NoAuth {
     client: Client,
-    endpoint: Url,
+    endpoint: Option<Url>,
+    service_endpoints: HashMap<String, Url>,
 }
 
 impl NoAuth {
@@ -70,14 +302,70 @@ impl NoAuth {
     /// This endpoint will be returned in response to all `get_endpoint` calls
     /// of the [AuthType](trait.AuthType.html) trait.
     pub fn new<U>(endpoint: U) -> Result<NoAuth, Error>
+    where
+        U: IntoUrl,
+    {
+        NoAuth::new_with_client(endpoint, request::default_client())
+    }
+
+    /// Create a new fake authentication method using a fixed endpoint and an HTTP client.
+    pub fn new_with_client<U>(endpoint: U, client: Client) -> Result<NoAuth, Error>
     where
         U: IntoUrl,
     {
         Ok(NoAuth {
-            client: Client::new(),
-            endpoint: endpoint.into_url()?,
+            client,
+            endpoint: Some(endpoint.into_url()?),
+            service_endpoints: HashMap::new(),
         })
     }
+
+    /// Create a new fake authentication method with only per-service endpoints.
+    ///
+    /// Add endpoints with [with_service_endpoint](#method.with_service_endpoint), other service
+    /// types are not available.
+    #[inline]
+    pub fn without_default_endpoint() -> NoAuth {
+        NoAuth::without_default_endpoint_with_client(request::default_client())
+    }
+
+    /// Create a new fake authentication method with only per-service endpoints and an HTTP
+    /// client.
+    #[inline]
+    pub fn without_default_endpoint_with_client(client: Client) -> NoAuth {
+        NoAuth {
+            client,
+            endpoint: None,
+            service_endpoints: HashMap::new(),
+        }
+    }
+
+    /// Set an endpoint for the given service type, replacing the default one.
+    pub fn set_service_endpoint<S, U>(&mut self, service_type: S, endpoint: U) -> Result<(), Error>
+    where
+        S: Into<String>,
+        U: IntoUrl,
+    {
+        let _ = self
+            .service_endpoints
+            .insert(service_type.into(), endpoint.into_url()?);
+        Ok(())
+    }
+
+    /// Add an endpoint for the given service type, replacing the default one.
+    #[inline]
+    pub fn with_service_endpoint<S, U>(
+        mut self,
+        service_type: S,
+        endpoint: U,
+    ) -> Result<Self, Error>
+    where
+        S: Into<String>,
+        U: IntoUrl,
+    {
+        self.set_service_endpoint(service_type, endpoint)?;
+        Ok(self)
+    }
 }
 
 #[async_trait]
@@ -87,6 +375,94 @@ impl AuthType for NoAuth {
         Ok(self.client.request(method, url))
     }
 
+    /// Get a predefined endpoint for the service type or the default one.
+    async fn get_endpoint(
+        &self,
+        service_type: String,
+        _filters: EndpointFilters,
+    ) -> Result<Url, Error> {
+        self.service_endpoints
+            .get(&service_type)
+            .or(self.endpoint.as_ref())
+            .cloned()
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::EndpointNotFound,
+                    format!("No endpoint configured for service {}", service_type),
+                )
+            })
+    }
+
+    /// This call does nothing for `NoAuth`.
+    async fn refresh(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Authentication type that uses a static admin token.
+///
+/// This is the legacy `ADMIN_TOKEN` of the Identity service, which is sent as `X-Auth-Token` to
+/// a pre-defined endpoint without any authentication request. It is intended only for
+/// bootstrapping the Identity service before any users exist and must not be used otherwise:
+/// ```rust,no_run
+/// let auth = osauth::AdminToken::new("https://cloud.local/identity/v3", "ADMIN")
+///     .expect("Invalid endpoint");
+/// let session = osauth::Session::new(auth);
+/// ```
+#[derive(Clone)]
+pub struct AdminToken {
+    client: Client,
+    endpoint: Url,
+    token: String,
+}
+
+impl Debug for AdminToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AdminToken")
+            .field("client", &self.client)
+            .field("endpoint", &self.endpoint)
+            .field("token", &"***")
+            .finish()
+    }
+}
+
+impl AdminToken {
+    /// Create a new admin token authentication using a fixed endpoint.
+    ///
+    /// This endpoint will be returned in response to all `get_endpoint` calls
+    /// of the [AuthType](trait.AuthType.html) trait.
+    pub fn new<U, S>(endpoint: U, token: S) -> Result<AdminToken, Error>
+    where
+        U: IntoUrl,
+        S: Into<String>,
+    {
+        AdminToken::new_with_client(endpoint, request::default_client(), token)
+    }
+
+    /// Create a new admin token authentication using a fixed endpoint and an HTTP client.
+    pub fn new_with_client<U, S>(endpoint: U, client: Client, token: S) -> Result<AdminToken, Error>
+    where
+        U: IntoUrl,
+        S: Into<String>,
+    {
+        Ok(AdminToken {
+            client,
+            endpoint: endpoint.into_url()?,
+            token: token.into(),
+        })
+    }
+}
+
+#[async_trait]
+impl AuthType for AdminToken {
+    /// Create a request with the admin token.
+    async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+        Ok(self
+            .client
+            .request(method, url)
+            .header("x-auth-token", &self.token))
+    }
+
     /// Get a predefined endpoint for all service types
     async fn get_endpoint(
         &self,
@@ -96,7 +472,7 @@ impl AuthType for NoAuth {
         Ok(self.endpoint.clone())
     }
 
-    /// This call does nothing for `NoAuth`.
+    /// This call does nothing for `AdminToken`.
     async fn refresh(&self) -> Result<(), Error> {
         Ok(())
     }
@@ -104,18 +480,28 @@ impl AuthType for NoAuth {
 
 #[cfg(test)]
 pub mod test {
-    use super::{AuthType, NoAuth};
+    use reqwest::Method;
+
+    use super::super::ErrorKind;
+    use super::{AdminToken, AuthType, NoAuth};
 
     #[test]
     fn test_noauth_new() {
         let a = NoAuth::new("http://127.0.0.1:8080/v1").unwrap();
-        let e = a.endpoint;
+        let e = a.endpoint.unwrap();
         assert_eq!(e.scheme(), "http");
         assert_eq!(e.host_str().unwrap(), "127.0.0.1");
         assert_eq!(e.port().unwrap(), 8080u16);
         assert_eq!(e.path(), "/v1");
     }
 
+    #[test]
+    fn test_noauth_new_with_client() {
+        let a =
+            NoAuth::new_with_client("http://127.0.0.1:8080/v1", reqwest::Client::new()).unwrap();
+        assert_eq!(a.endpoint.unwrap().as_str(), "http://127.0.0.1:8080/v1");
+    }
+
     #[test]
     fn test_noauth_new_fail() {
         let _ = NoAuth::new("foo bar").err().unwrap();
@@ -133,4 +519,70 @@ pub mod test {
         assert_eq!(e.port().unwrap(), 8080u16);
         assert_eq!(e.path(), "/v1");
     }
+
+    #[tokio::test]
+    async fn test_noauth_service_endpoints() {
+        let a = NoAuth::new("http://127.0.0.1:8080/v1")
+            .unwrap()
+            .with_service_endpoint("baremetal-introspection", "http://127.0.0.1:5050")
+            .unwrap();
+        let get = |service: &'static str| a.get_endpoint(service.to_string(), Default::default());
+        assert_eq!(
+            get("baremetal-introspection").await.unwrap().as_str(),
+            "http://127.0.0.1:5050/"
+        );
+        assert_eq!(
+            get("baremetal").await.unwrap().as_str(),
+            "http://127.0.0.1:8080/v1"
+        );
+
+        let a = NoAuth::without_default_endpoint()
+            .with_service_endpoint("baremetal", "http://127.0.0.1:6385")
+            .unwrap();
+        let _ = a
+            .get_endpoint("baremetal".to_string(), Default::default())
+            .await
+            .unwrap();
+        let err = a
+            .get_endpoint("compute".to_string(), Default::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::EndpointNotFound);
+    }
+
+    #[tokio::test]
+    async fn test_admin_token() {
+        let a = AdminToken::new("http://127.0.0.1:5000/v3", "ADMIN").unwrap();
+        let e = a
+            .get_endpoint(String::from("identity"), Default::default())
+            .await
+            .unwrap();
+        assert_eq!(e.as_str(), "http://127.0.0.1:5000/v3");
+        let request = a
+            .request(Method::GET, e.join("users").unwrap())
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()["x-auth-token"], "ADMIN");
+        assert!(!format!("{:?}", a).contains("ADMIN"));
+    }
+
+    #[tokio::test]
+    async fn test_boxed_auth_type() {
+        let auth: Box<dyn AuthType> =
+            Box::new(AdminToken::new("http://127.0.0.1:5000/v3", "ADMIN").unwrap());
+        assert!(!auth.can_reauthenticate());
+        let session = crate::Session::new(auth);
+        let e = session
+            .auth_type()
+            .get_endpoint(String::from("identity"), Default::default())
+            .await
+            .unwrap();
+        assert_eq!(e.as_str(), "http://127.0.0.1:5000/v3");
+
+        let auth = std::sync::Arc::new(NoAuth::new("http://127.0.0.1:8080/v1").unwrap());
+        let session = crate::Session::new(auth.clone());
+        assert!(session.auth_type().identity_url().is_none());
+    }
 }
@@ -0,0 +1,111 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Service catalog as returned by the Identity service.
+
+use serde::Deserialize;
+
+use super::{Error, ErrorKind};
+
+/// A service from the service catalog.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CatalogService {
+    /// Service type (e.g. `compute`).
+    #[serde(rename = "type")]
+    pub service_type: String,
+    /// Service name (e.g. `nova`), if provided.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Service ID, if provided.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Endpoints of the service in the catalog order.
+    #[serde(default)]
+    pub endpoints: Vec<CatalogEndpoint>,
+}
+
+/// An endpoint of a service from the service catalog.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CatalogEndpoint {
+    /// Endpoint interface (e.g. `public`).
+    pub interface: String,
+    /// Region name (if any).
+    #[serde(default)]
+    pub region: Option<String>,
+    /// Region ID (if any), usually the same as the region name.
+    #[serde(default)]
+    pub region_id: Option<String>,
+    /// Endpoint URL.
+    pub url: String,
+    /// Endpoint ID, if provided.
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+impl CatalogEndpoint {
+    /// Region of the endpoint: its name or, if missing, its ID.
+    #[inline]
+    pub fn region(&self) -> Option<&str> {
+        self.region.as_deref().or(self.region_id.as_deref())
+    }
+}
+
+/// Parse the catalog in the format returned by the Identity service.
+pub(crate) fn parse_catalog(raw: serde_json::Value) -> Result<Vec<CatalogService>, Error> {
+    serde_json::from_value(raw).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidResponse,
+            format!("Invalid service catalog: {}", e),
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::ErrorKind;
+    use super::parse_catalog;
+
+    #[test]
+    fn test_parse_catalog() {
+        let catalog = parse_catalog(serde_json::json!([
+            {"type": "compute", "name": "nova", "id": "abcd", "endpoints": [
+                {"id": "1", "interface": "public", "region": "RegionOne",
+                 "region_id": "RegionOne", "url": "http://compute"},
+                {"interface": "internal", "region_id": "RegionTwo", "url": "http://compute.local"},
+            ]},
+            {"type": "object-store"},
+        ]))
+        .unwrap();
+        assert_eq!(catalog.len(), 2);
+        assert_eq!(catalog[0].service_type, "compute");
+        assert_eq!(catalog[0].name.as_deref(), Some("nova"));
+        assert_eq!(catalog[0].endpoints.len(), 2);
+        assert_eq!(catalog[0].endpoints[0].id.as_deref(), Some("1"));
+        assert_eq!(catalog[0].endpoints[0].region(), Some("RegionOne"));
+        assert_eq!(catalog[0].endpoints[1].region(), Some("RegionTwo"));
+        assert_eq!(catalog[0].endpoints[1].url, "http://compute.local");
+        assert!(catalog[1].name.is_none());
+        assert!(catalog[1].endpoints.is_empty());
+    }
+
+    #[test]
+    fn test_parse_catalog_invalid() {
+        let err = parse_catalog(serde_json::json!({"type": "compute"}))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidResponse);
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! High-level cloud handle.
+
+use reqwest::Url;
+
+use super::config;
+use super::identity::TokenInfo;
+use super::request::NO_PATH;
+use super::services::ServiceType;
+use super::{Adapter, Error, ErrorKind, Session};
+
+/// A handle to a cloud.
+///
+/// A `Cloud` is an entry point similar to the `Connection` in openstacksdk: it is created from
+/// the configuration and wraps a [Session](struct.Session.html), offering convenience calls on
+/// top of it. Authentication happens lazily on the first call that needs a token.
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// let cloud = osauth::Cloud::from_env()?;
+/// let endpoint = cloud.endpoint(osauth::services::COMPUTE).await?;
+/// println!("Compute endpoint of {:?}: {}", cloud.name(), endpoint);
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cloud {
+    session: Session,
+}
+
+impl From<Session> for Cloud {
+    fn from(value: Session) -> Cloud {
+        Cloud::new(value)
+    }
+}
+
+impl From<Cloud> for Session {
+    fn from(value: Cloud) -> Session {
+        value.session
+    }
+}
+
+impl Cloud {
+    /// Create a cloud handle from an existing session.
+    #[inline]
+    pub fn new(session: Session) -> Cloud {
+        Cloud { session }
+    }
+
+    /// Create a cloud handle from the config file.
+    ///
+    /// See [from_config](fn.from_config.html) for details. This function is only available when
+    /// the `config-file` feature is enabled.
+    #[cfg(feature = "config-file")]
+    #[inline]
+    pub fn from_config<S: AsRef<str>>(cloud_name: S) -> Result<Cloud, Error> {
+        config::from_config(cloud_name).map(Cloud::new)
+    }
+
+    /// Create a cloud handle from environment variables.
+    ///
+    /// See [from_env](fn.from_env.html) for details.
+    #[inline]
+    pub fn from_env() -> Result<Cloud, Error> {
+        config::from_env().map(Cloud::new)
+    }
+
+    /// Name of the cloud (if created from the config file).
+    #[inline]
+    pub fn name(&self) -> Option<&str> {
+        self.session.cloud_name()
+    }
+
+    /// The underlying session.
+    #[inline]
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Mutable reference to the underlying session.
+    #[inline]
+    pub fn session_mut(&mut self) -> &mut Session {
+        &mut self.session
+    }
+
+    /// Convert this handle into the underlying session.
+    #[inline]
+    pub fn into_session(self) -> Session {
+        self.session
+    }
+
+    /// Create an adapter for the specific service type.
+    #[inline]
+    pub fn adapter<Srv>(&self, service: Srv) -> Adapter<Srv> {
+        self.session.adapter(service)
+    }
+
+    /// Endpoint of the given service.
+    ///
+    /// Authenticates if needed.
+    pub async fn endpoint<Srv>(&self, service: Srv) -> Result<Url, Error>
+    where
+        Srv: ServiceType + Send,
+    {
+        self.session.get_endpoint(service, NO_PATH).await
+    }
+
+    /// Information about the current token.
+    ///
+    /// Authenticates if needed. Fails with `InvalidInput` if the cloud does not use tokens.
+    pub async fn token_info(&self) -> Result<TokenInfo, Error> {
+        self.session.token_info().await
+    }
+
+    /// Authenticate again, dropping the cached token and service information.
+    ///
+    /// Fails with `InvalidInput` if the authentication type cannot obtain new tokens (e.g. when
+    /// an existing token is used).
+    pub async fn reauthenticate(&mut self) -> Result<(), Error> {
+        if !self.session.auth_type().can_reauthenticate() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The authentication type cannot obtain new tokens",
+            ));
+        }
+        self.session.refresh().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::Url;
+
+    use super::super::services::OBJECT_STORAGE;
+    use super::super::{ErrorKind, NoAuth, Session};
+    use super::Cloud;
+
+    fn new_cloud() -> Cloud {
+        let auth = NoAuth::new("http://127.0.0.1:8080/swift").unwrap();
+        Cloud::new(Session::new(auth).with_cloud_name("cloud1"))
+    }
+
+    #[tokio::test]
+    async fn test_cloud_endpoint() {
+        let cloud = new_cloud();
+        assert_eq!(cloud.name(), Some("cloud1"));
+        assert_eq!(
+            cloud.endpoint(OBJECT_STORAGE).await.unwrap(),
+            Url::parse("http://127.0.0.1:8080/swift").unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cloud_reauthenticate_unsupported() {
+        let mut cloud = new_cloud();
+        let err = cloud.reauthenticate().await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Remote consoles of servers (Compute) and bare metal nodes (Bare Metal).
+//!
+//! These helpers only resolve the console URL and prepare the WebSocket handshake, the
+//! connection itself is left to a WebSocket client of your choice:
+//!
+//! ```rust,no_run
+//! use osauth::console::{self, ConsoleProtocol};
+//!
+//! # async fn example() -> Result<(), osauth::Error> {
+//! let session =
+//!     osauth::from_env().expect("Failed to create an identity provider from the environment");
+//! let console =
+//!     console::server_console(&session, "8a3f2b1c", ConsoleProtocol::Serial).await?;
+//! let url = console.websocket_url().expect("Not a WebSocket console");
+//! let headers = console::handshake_headers(&session, &console).await?;
+//! println!("Connect to {} with headers {:?}", url, headers);
+//! # Ok(()) }
+//! # #[tokio::main]
+//! # async fn main() { example().await.unwrap(); }
+//! ```
+
+use reqwest::header::{HeaderMap, HeaderValue, ORIGIN, SEC_WEBSOCKET_PROTOCOL};
+use reqwest::{Method, Url};
+use serde::{Deserialize, Serialize};
+
+use super::services::{BAREMETAL, COMPUTE};
+use super::{ApiVersion, Error, ErrorKind, Session};
+
+/// Remote console protocol of a server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConsoleProtocol {
+    /// VNC via noVNC.
+    Vnc,
+    /// SPICE via the HTML5 client.
+    Spice,
+    /// Serial console over WebSocket.
+    Serial,
+    /// RDP via the HTML5 client (Hyper-V only).
+    Rdp,
+    /// WebMKS (VMware only).
+    Mks,
+}
+
+impl ConsoleProtocol {
+    /// Protocol name and console type for the Compute API.
+    fn names(self) -> (&'static str, &'static str) {
+        match self {
+            ConsoleProtocol::Vnc => ("vnc", "novnc"),
+            ConsoleProtocol::Spice => ("spice", "spice-html5"),
+            ConsoleProtocol::Serial => ("serial", "serial"),
+            ConsoleProtocol::Rdp => ("rdp", "rdp-html5"),
+            ConsoleProtocol::Mks => ("mks", "webmks"),
+        }
+    }
+
+    /// The minimum compute microversion supporting the protocol.
+    fn api_version(self) -> ApiVersion {
+        match self {
+            ConsoleProtocol::Mks => ApiVersion(2, 8),
+            _ => ApiVersion(2, 6),
+        }
+    }
+}
+
+/// A remote console.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RemoteConsole {
+    /// Console protocol (e.g. `serial`), not reported by the Bare Metal service.
+    #[serde(default)]
+    pub protocol: Option<String>,
+    /// Console type (e.g. `novnc` or `socat`).
+    #[serde(rename = "type")]
+    pub console_type: String,
+    /// URL of the console, usually including a one-time console token.
+    pub url: Url,
+}
+
+impl RemoteConsole {
+    /// URL for the WebSocket connection (if the console uses WebSocket).
+    ///
+    /// HTTP URLs (e.g. of noVNC) are converted to the corresponding WebSocket ones. Returns
+    /// `None` for other consoles, e.g. the plain TCP `socat` consoles of bare metal nodes.
+    pub fn websocket_url(&self) -> Option<Url> {
+        let scheme = match self.url.scheme() {
+            "ws" | "http" => "ws",
+            "wss" | "https" => "wss",
+            _ => return None,
+        };
+        let mut url = self.url.clone();
+        // NOTE: switching between these special schemes always succeeds.
+        url.set_scheme(scheme).ok()?;
+        Some(url)
+    }
+
+    /// Origin of the console proxy.
+    ///
+    /// Console proxies of the Compute service reject WebSocket connections from origins other
+    /// than their own.
+    pub fn origin(&self) -> String {
+        self.url.origin().ascii_serialization()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ConsoleRequest {
+    protocol: &'static str,
+    #[serde(rename = "type")]
+    console_type: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct ConsoleRequestRoot {
+    remote_console: ConsoleRequest,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConsoleRoot {
+    remote_console: RemoteConsole,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodeConsole {
+    console_enabled: bool,
+    #[serde(default)]
+    console_info: Option<RemoteConsole>,
+}
+
+/// Create a remote console of a server.
+///
+/// Requires compute API version 2.6 (2.8 for [Mks](enum.ConsoleProtocol.html#variant.Mks)).
+pub async fn server_console(
+    session: &Session,
+    server_id: &str,
+    protocol: ConsoleProtocol,
+) -> Result<RemoteConsole, Error> {
+    let (protocol_name, console_type) = protocol.names();
+    let body = ConsoleRequestRoot {
+        remote_console: ConsoleRequest {
+            protocol: protocol_name,
+            console_type,
+        },
+    };
+    let root: ConsoleRoot = session
+        .post_json(
+            COMPUTE,
+            &["servers", server_id, "remote-consoles"],
+            body,
+            Some(protocol.api_version()),
+        )
+        .await?;
+    Ok(root.remote_console)
+}
+
+/// Get the console of a bare metal node.
+///
+/// Fails with `ErrorKind::InvalidInput` if the console is not enabled for the node.
+pub async fn node_console(session: &Session, node_id: &str) -> Result<RemoteConsole, Error> {
+    let node: NodeConsole = session
+        .get_json(BAREMETAL, &["nodes", node_id, "states", "console"], None)
+        .await?;
+    match node.console_info {
+        Some(console) if node.console_enabled => Ok(console),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Console is not enabled for node {}", node_id),
+        )),
+    }
+}
+
+/// Headers for the WebSocket handshake with the console proxy.
+///
+/// Includes the origin of the proxy, the `binary` subprotocol and the authentication headers
+/// of the session (e.g. `X-Auth-Token`) for proxies that require them.
+pub async fn handshake_headers(
+    session: &Session,
+    console: &RemoteConsole,
+) -> Result<HeaderMap, Error> {
+    let request = session
+        .auth_type()
+        .request(Method::GET, console.url.clone())
+        .await?
+        .build()?;
+    let mut headers = request.headers().clone();
+    let origin = HeaderValue::from_str(&console.origin())
+        .map_err(|e| Error::new(ErrorKind::InvalidResponse, e.to_string()))?;
+    let _ = headers.insert(ORIGIN, origin);
+    let _ = headers.insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static("binary"));
+    Ok(headers)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use reqwest::{RequestBuilder, Response, Url};
+
+    use super::super::protocol::ServiceInfo;
+    use super::super::{AdminToken, Error, ErrorKind, Middleware, Next, NoAuth, Session};
+    use super::{handshake_headers, node_console, server_console, ConsoleProtocol};
+
+    const URL: &str = "http://127.0.0.1:1/";
+
+    type Log = Arc<Mutex<Vec<(String, String, String)>>>;
+
+    #[derive(Debug)]
+    struct FakeConsoles(Log);
+
+    #[async_trait]
+    impl Middleware for FakeConsoles {
+        async fn handle(
+            &self,
+            request: RequestBuilder,
+            _next: Next<'_>,
+        ) -> Result<Response, Error> {
+            let request = request.build()?;
+            let body = request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .map(|body| String::from_utf8_lossy(body).into_owned())
+                .unwrap_or_default();
+            let version = request
+                .headers()
+                .get("openstack-api-version")
+                .map(|value| value.to_str().unwrap().to_string())
+                .unwrap_or_default();
+            let url = request.url().to_string();
+            let response = if url.ends_with("/remote-consoles") {
+                r#"{"remote_console": {"protocol": "serial", "type": "serial",
+                    "url": "ws://127.0.0.1:6083/?token=abcd"}}"#
+            } else if url.contains("/enabled/") {
+                r#"{"console_enabled": true, "console_info": {"type": "shellinabox",
+                    "url": "https://127.0.0.1:8023/"}}"#
+            } else {
+                r#"{"console_enabled": false, "console_info": null}"#
+            };
+            self.0.lock().unwrap().push((url, version, body));
+            Ok(http::Response::builder()
+                .status(200)
+                .body(response)
+                .unwrap()
+                .into())
+        }
+    }
+
+    fn new_session() -> (Session, Log) {
+        let log = Log::default();
+        let mut session = Session::new(NoAuth::new(URL).unwrap());
+        for service in &["compute", "baremetal"] {
+            session.cache_fake_service(
+                service,
+                ServiceInfo {
+                    root_url: Url::parse(URL).unwrap(),
+                    major_version: None,
+                    current_version: None,
+                    minimum_version: None,
+                },
+            );
+        }
+        session.add_middleware(FakeConsoles(log.clone()));
+        (session, log)
+    }
+
+    #[tokio::test]
+    async fn test_server_console() {
+        let (session, log) = new_session();
+        let console = server_console(&session, "1234", ConsoleProtocol::Serial)
+            .await
+            .unwrap();
+        assert_eq!(console.protocol.as_deref(), Some("serial"));
+        assert_eq!(
+            console.websocket_url().unwrap().as_str(),
+            "ws://127.0.0.1:6083/?token=abcd"
+        );
+        let log = log.lock().unwrap();
+        assert_eq!(log[0].0, "http://127.0.0.1:1/servers/1234/remote-consoles");
+        assert_eq!(log[0].1, "compute 2.6");
+        assert_eq!(
+            log[0].2,
+            r#"{"remote_console":{"protocol":"serial","type":"serial"}}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_node_console() {
+        let (session, _log) = new_session();
+        let console = node_console(&session, "enabled").await.unwrap();
+        assert_eq!(console.console_type, "shellinabox");
+        assert!(console.protocol.is_none());
+        assert_eq!(
+            console.websocket_url().unwrap().as_str(),
+            "wss://127.0.0.1:8023/"
+        );
+        let err = node_console(&session, "disabled").await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_headers() {
+        let console: super::RemoteConsole = serde_json::from_str(
+            r#"{"protocol": "vnc", "type": "novnc",
+                "url": "https://proxy.local:6080/vnc_auto.html?path=%3Ftoken%3Dabcd"}"#,
+        )
+        .unwrap();
+        assert_eq!(console.origin(), "https://proxy.local:6080");
+        let session = Session::new(AdminToken::new(URL, "s3cr3t").unwrap());
+        let headers = handshake_headers(&session, &console).await.unwrap();
+        assert_eq!(headers["origin"], "https://proxy.local:6080");
+        assert_eq!(headers["sec-websocket-protocol"], "binary");
+        assert_eq!(headers["x-auth-token"], "s3cr3t");
+    }
+}
@@ -15,48 +15,66 @@
 //! Endpoint filters for looking up endpoints.
 
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::iter::FromIterator;
 use std::ops::Deref;
 use std::str::FromStr;
 
-use log::{debug, error};
+use log::{debug, error, warn};
 use osproto::identity::{CatalogRecord, Endpoint};
 use reqwest::Url;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use super::{Error, ErrorKind};
 
 /// Interface type: public, internal or admin.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+///
+/// Serialized as a lowercase string (`public`, `internal` or `admin`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum InterfaceType {
     /// Public interface (used by default).
+    #[serde(alias = "publicURL")]
     Public,
     /// Internal interface.
+    #[serde(alias = "internalURL")]
     Internal,
     /// Administrator interface.
+    ///
+    /// Deprecated: many services no longer register admin endpoints. A warning is logged when
+    /// an admin endpoint is used.
+    #[serde(alias = "adminURL")]
     Admin,
 }
 
+impl Default for InterfaceType {
+    fn default() -> Self {
+        InterfaceType::Public
+    }
+}
+
 /// A list of acceptable interface types.
-#[derive(Debug, Clone, Copy, Eq, Hash)]
+#[derive(Debug, Clone, Copy, Eq)]
 pub struct ValidInterfaces {
     items: [InterfaceType; 3],
     len: u8,
 }
 
 /// Endpoint filters for looking up endpoints.
-#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+///
+/// Can be serialized to persist the endpoint selection and restore it later. Missing fields
+/// are set to their defaults on deserialization.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(default)]
 #[non_exhaustive]
 pub struct EndpointFilters {
     /// Acceptable endpoint interfaces in the reverse priority order.
     pub interfaces: ValidInterfaces,
-    /// Cloud region.
-    pub region: Option<String>,
-}
-
-impl Default for InterfaceType {
-    fn default() -> Self {
-        InterfaceType::Public
-    }
+    /// Acceptable cloud regions in the priority order, any region is accepted if empty.
+    ///
+    /// Regions after the first one are only used if the first one does not have the requested
+    /// service. This only affects the endpoint selection, not the authentication token.
+    pub regions: Vec<String>,
 }
 
 impl fmt::Display for InterfaceType {
@@ -106,6 +124,20 @@ impl Deref for ValidInterfaces {
     }
 }
 
+impl Serialize for ValidInterfaces {
+    /// Serialize as a sequence of interface types.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for ValidInterfaces {
+    /// Deserialize from a sequence of interface types, ignoring duplicates.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ValidInterfaces, D::Error> {
+        Vec::<InterfaceType>::deserialize(deserializer).map(ValidInterfaces::from)
+    }
+}
+
 impl From<InterfaceType> for ValidInterfaces {
     fn from(value: InterfaceType) -> ValidInterfaces {
         ValidInterfaces::one(value)
@@ -161,6 +193,12 @@ impl PartialEq for ValidInterfaces {
     }
 }
 
+impl Hash for ValidInterfaces {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.deref().hash(state)
+    }
+}
+
 impl ValidInterfaces {
     /// Append all items from another collection.
     ///
@@ -214,6 +252,41 @@ impl ValidInterfaces {
     }
 }
 
+/// Select an endpoint URL for the service type from the service catalog.
+///
+/// Only endpoints matching the `filters` are considered. If several of them match, the one with
+/// the interface coming first in `filters.interfaces` wins.
+///
+/// Returns `None` if no endpoint matches or if the matching endpoint has an invalid URL.
+///
+/// ```rust
+/// use osauth::{select_endpoint, EndpointFilters, InterfaceType};
+/// use osproto::identity::{CatalogRecord, Endpoint};
+///
+/// let catalog = vec![CatalogRecord {
+///     service_type: "compute".to_string(),
+///     endpoints: vec![Endpoint {
+///         interface: "internal".to_string(),
+///         region: "RegionOne".to_string(),
+///         url: "http://compute.local/v2.1".to_string(),
+///     }],
+/// }];
+/// let filters = EndpointFilters::default()
+///     .with_interfaces(vec![InterfaceType::Public, InterfaceType::Internal]);
+/// let url = select_endpoint(&catalog, "compute", &filters).expect("No endpoint");
+/// assert_eq!(url.as_str(), "http://compute.local/v2.1");
+/// ```
+pub fn select_endpoint(
+    catalog: &[CatalogRecord],
+    service_type: &str,
+    filters: &EndpointFilters,
+) -> Option<Url> {
+    filters
+        .find_endpoint(catalog, service_type)
+        .ok()
+        .and_then(|endp| Url::parse(&endp.url).ok())
+}
+
 impl FromStr for InterfaceType {
     type Err = Error;
 
@@ -241,7 +314,7 @@ impl EndpointFilters {
     {
         EndpointFilters {
             interfaces: interfaces.into_iter().collect(),
-            region: Some(region.into()),
+            regions: vec![region.into()],
         }
     }
 
@@ -251,10 +324,15 @@ impl EndpointFilters {
             return false;
         }
 
-        if let Some(ref region) = self.region {
-            endpoint.region == *region
+        self.region_priority(endpoint).is_some()
+    }
+
+    /// Position of the endpoint's region in the list of acceptable regions.
+    fn region_priority(&self, endpoint: &Endpoint) -> Option<usize> {
+        if self.regions.is_empty() {
+            Some(0)
         } else {
-            true
+            self.regions.iter().position(|x| endpoint.region == *x)
         }
     }
 
@@ -287,10 +365,34 @@ impl EndpointFilters {
         self.interfaces = value.into();
     }
 
+    /// Cloud region (the first of `regions`).
+    #[inline]
+    pub fn region(&self) -> Option<&str> {
+        self.regions.first().map(String::as_str)
+    }
+
     /// Set region.
+    ///
+    /// Replaces all regions set by [set_regions](#method.set_regions).
     #[inline]
     pub fn set_region<T: Into<String>>(&mut self, value: T) {
-        self.region = Some(value.into());
+        self.regions = vec![value.into()];
+    }
+
+    /// Set several regions to try in order.
+    #[inline]
+    pub fn set_regions<I, T>(&mut self, value: I)
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.regions = value.into_iter().map(Into::into).collect();
+    }
+
+    /// Regions to try in order if the first one does not have the requested service.
+    #[inline]
+    pub fn fallback_regions(&self) -> &[String] {
+        self.regions.get(1..).unwrap_or(&[])
     }
 
     /// Add one or more valid interfaces.
@@ -309,33 +411,117 @@ impl EndpointFilters {
         self
     }
 
+    /// Add several regions to try in order.
+    #[inline]
+    pub fn with_regions<I, T>(mut self, value: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.set_regions(value);
+        self
+    }
+
+    /// Find all matching endpoints in the service catalog, the preferred one first.
+    ///
+    /// Endpoints are ordered by region (in the order of `regions`), then by
+    /// interface (in the order of `interfaces`). Endpoints equal in both keep their catalog order,
+    /// including ones from duplicated catalog records of the service.
+    pub(crate) fn find_endpoints<'c>(
+        &self,
+        catalog: &'c [CatalogRecord],
+        service_type: &str,
+    ) -> Result<Vec<&'c Endpoint>, Error> {
+        if !catalog.iter().any(|x| x.service_type == *service_type) {
+            return Err(Error::new_endpoint_not_found(service_type));
+        }
+        let all = catalog
+            .iter()
+            .filter(|x| x.service_type == *service_type)
+            .flat_map(|x| x.endpoints.iter())
+            .collect::<Vec<_>>();
+
+        let mut endpoints: Vec<_> = all.iter().copied().filter(|x| self.check(x)).collect();
+        if endpoints.is_empty() {
+            return Err(self.no_matching_endpoint(service_type, &all));
+        }
+        // NOTE(dtantsur): because of the filter above unwrap never fails. The sort must be stable
+        // for the choice between otherwise equal endpoints to be deterministic.
+        endpoints.sort_by_key(|x| {
+            (
+                self.region_priority(x).unwrap(),
+                self.interfaces.find(&x.interface).unwrap(),
+            )
+        });
+        Ok(endpoints)
+    }
+
     /// Find an endpoint in the service catalog.
+    ///
+    /// The first endpoint from `find_endpoints` is used.
     pub(crate) fn find_endpoint<'c>(
         &self,
         catalog: &'c [CatalogRecord],
         service_type: &str,
     ) -> Result<&'c Endpoint, Error> {
-        let svc = match catalog.iter().find(|x| x.service_type == *service_type) {
-            Some(s) => s,
-            None => return Err(Error::new_endpoint_not_found(service_type)),
-        };
+        let endp = self.find_endpoints(catalog, service_type)?[0];
+        if InterfaceType::Admin == endp.interface {
+            warn!(
+                "Using the admin interface for {}, admin interfaces are deprecated \
+                 and may be removed from the service catalog",
+                service_type
+            );
+        }
+        Ok(endp)
+    }
 
-        let mut endpoints: Vec<_> = svc.endpoints.iter().filter(|x| self.check(x)).collect();
-        endpoints
-            // NOTE(dtantsur): because of the filter above unwrap never fails
-            .sort_unstable_by_key(|x| self.interfaces.find(&x.interface).unwrap());
-        endpoints
-            .into_iter()
-            .next()
-            .ok_or_else(|| Error::new_endpoint_not_found(service_type))
+    /// Explain why no endpoint of the service matches the filters.
+    ///
+    /// If the service has endpoints in the requested region(s), but not with any of the requested
+    /// interfaces, the error lists the interfaces that are available.
+    fn no_matching_endpoint(&self, service_type: &str, endpoints: &[&Endpoint]) -> Error {
+        let mut available = endpoints
+            .iter()
+            .filter(|x| self.region_priority(x).is_some())
+            .map(|x| x.interface.as_str())
+            .collect::<Vec<_>>();
+        if available.is_empty() {
+            return Error::new_endpoint_not_found(service_type);
+        }
+        available.sort_unstable();
+        available.dedup();
+
+        let interfaces = self
+            .interfaces
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" or ");
+        let region = if self.regions.is_empty() {
+            "any region".to_string()
+        } else {
+            self.regions.join(" or ")
+        };
+        Error::new(
+            ErrorKind::EndpointNotFound,
+            format!(
+                "No {} endpoint for {} in {}, available interfaces: {}",
+                interfaces,
+                service_type,
+                region,
+                available.join(", ")
+            ),
+        )
     }
 
     /// Clone defaults from the provided filters.
     pub(crate) fn with_defaults(mut self, other: &EndpointFilters) -> EndpointFilters {
         if self.interfaces.is_empty() {
-            self.interfaces = other.interfaces.clone();
+            self.interfaces = other.interfaces;
+        }
+        if self.regions.is_empty() {
+            self.regions = other.regions.clone();
         }
-        self.region = self.region.or_else(|| other.region.clone());
         self
     }
 }
@@ -346,7 +532,7 @@ pub mod test {
 
     use super::super::{Error, ErrorKind};
 
-    use super::{EndpointFilters, InterfaceType, ValidInterfaces};
+    use super::{select_endpoint, EndpointFilters, InterfaceType, ValidInterfaces};
     use InterfaceType::*;
 
     fn demo_service1() -> CatalogRecord {
@@ -390,19 +576,33 @@ pub mod test {
         }
     }
 
+    fn demo_service_all_interfaces() -> CatalogRecord {
+        CatalogRecord {
+            service_type: String::from("compute"),
+            endpoints: ["public", "internal", "admin"]
+                .iter()
+                .map(|interface| Endpoint {
+                    interface: interface.to_string(),
+                    region: String::from("RegionOne"),
+                    url: format!("https://{}.local/compute", interface),
+                })
+                .collect(),
+        }
+    }
+
     pub fn demo_catalog() -> Vec<CatalogRecord> {
         vec![demo_service1(), demo_service2()]
     }
 
     fn find_endpoint<'a>(
-        cat: &'a Vec<CatalogRecord>,
+        cat: &'a [CatalogRecord],
         service_type: &str,
         interface_type: InterfaceType,
         region: Option<&str>,
     ) -> Result<&'a Endpoint, Error> {
         EndpointFilters {
             interfaces: ValidInterfaces::one(interface_type),
-            region: region.map(|x| x.to_string()),
+            regions: region.map(|x| x.to_string()).into_iter().collect(),
         }
         .find_endpoint(cat, service_type)
     }
@@ -421,6 +621,57 @@ pub mod test {
         assert_eq!(&e3.url, "https://host.one/baremetal");
     }
 
+    #[test]
+    fn test_find_endpoint_duplicated() {
+        let endpoint = |interface: &str, url: &str| Endpoint {
+            interface: interface.to_string(),
+            region: String::from("RegionOne"),
+            url: url.to_string(),
+        };
+        let cat = vec![
+            CatalogRecord {
+                service_type: String::from("compute"),
+                endpoints: vec![
+                    endpoint("internal", "http://internal.one/compute"),
+                    endpoint("public", "https://public.one/compute"),
+                    endpoint("public", "https://public.two/compute"),
+                ],
+            },
+            CatalogRecord {
+                service_type: String::from("compute"),
+                endpoints: vec![
+                    endpoint("public", "https://public.three/compute"),
+                    endpoint("admin", "http://admin.one/compute"),
+                ],
+            },
+        ];
+
+        let filters = EndpointFilters::default().with_interfaces(vec![Public, Internal, Admin]);
+        let urls = filters
+            .find_endpoints(&cat, "compute")
+            .unwrap()
+            .into_iter()
+            .map(|x| x.url.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            vec![
+                "https://public.one/compute",
+                "https://public.two/compute",
+                "https://public.three/compute",
+                "http://internal.one/compute",
+                "http://admin.one/compute",
+            ]
+        );
+        for _ in 0..10 {
+            let e = filters.find_endpoint(&cat, "compute").unwrap();
+            assert_eq!(&e.url, "https://public.one/compute");
+        }
+
+        let e = find_endpoint(&cat, "compute", Admin, None).unwrap();
+        assert_eq!(&e.url, "http://admin.one/compute");
+    }
+
     #[test]
     fn test_find_endpoint_from_many() {
         let cat = demo_catalog();
@@ -466,6 +717,32 @@ pub mod test {
         }
     }
 
+    #[test]
+    fn test_find_endpoint_missing_interface() {
+        let cat = demo_catalog();
+
+        let err = find_endpoint(&cat, "baremetal", Internal, Some("RegionOne"))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::EndpointNotFound);
+        assert_eq!(
+            err.to_string(),
+            "Requested endpoint was not found: No internal endpoint for baremetal in RegionOne, \
+             available interfaces: public"
+        );
+
+        let err = EndpointFilters::default()
+            .with_interfaces(vec![Admin, Internal])
+            .find_endpoint(&cat, "baremetal")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::EndpointNotFound);
+        assert!(err.to_string().ends_with(
+            "No admin or internal endpoint for baremetal in any region, \
+             available interfaces: public"
+        ));
+    }
+
     #[test]
     fn test_find_endpoint_not_found() {
         let cat = demo_catalog();
@@ -481,15 +758,188 @@ pub mod test {
         assert_not_found(e1);
     }
 
+    fn select(
+        cat: &[CatalogRecord],
+        service_type: &str,
+        interfaces: Vec<InterfaceType>,
+        region: Option<&str>,
+    ) -> Option<String> {
+        let mut filters = EndpointFilters::default().with_interfaces(interfaces);
+        filters.regions = region.map(|x| x.to_string()).into_iter().collect();
+        select_endpoint(cat, service_type, &filters).map(|url| url.to_string())
+    }
+
+    #[test]
+    fn test_select_endpoint_interface() {
+        let cat = demo_catalog();
+        assert_eq!(
+            select(&cat, "identity", vec![Public], None).unwrap(),
+            "https://host.one/identity"
+        );
+        assert_eq!(
+            select(&cat, "identity", vec![Internal], None).unwrap(),
+            "http://192.168.22.1/identity"
+        );
+        assert!(select(&cat, "identity", vec![Admin], None).is_none());
+        assert!(select(&cat, "identity", vec![], None).is_none());
+    }
+
+    #[test]
+    fn test_select_endpoint_all_interfaces() {
+        let cat = vec![demo_service_all_interfaces()];
+        for interface in &[Public, Internal, Admin] {
+            assert_eq!(
+                select(&cat, "compute", vec![*interface], None).unwrap(),
+                format!("https://{}.local/compute", interface)
+            );
+        }
+        assert_eq!(
+            select(&cat, "compute", vec![Admin, Public], None).unwrap(),
+            "https://admin.local/compute"
+        );
+    }
+
+    #[test]
+    fn test_interface_type_round_trip() {
+        for interface in &[Public, Internal, Admin] {
+            let converted: InterfaceType = interface.to_string().parse().unwrap();
+            assert_eq!(converted, *interface);
+        }
+        assert_eq!("admin".parse::<InterfaceType>().unwrap(), Admin);
+        assert_eq!("adminURL".parse::<InterfaceType>().unwrap(), Admin);
+    }
+
+    #[test]
+    fn test_select_endpoint_interface_fallback() {
+        let cat = demo_catalog();
+        assert_eq!(
+            select(&cat, "identity", vec![Internal, Public], None).unwrap(),
+            "http://192.168.22.1/identity"
+        );
+        assert_eq!(
+            select(&cat, "identity", vec![Admin, Public], None).unwrap(),
+            "https://host.one/identity"
+        );
+        assert_eq!(
+            select(&cat, "baremetal", vec![Internal, Admin, Public], None).unwrap(),
+            "https://host.one/baremetal"
+        );
+        assert!(select(&cat, "baremetal", vec![Internal, Admin], None).is_none());
+    }
+
+    #[test]
+    fn test_select_endpoint_region() {
+        let cat = demo_catalog();
+        assert_eq!(
+            select(&cat, "identity", vec![Public], Some("RegionTwo")).unwrap(),
+            "https://host.two:5000/"
+        );
+        assert_eq!(
+            select(&cat, "identity", vec![Internal, Public], Some("RegionTwo")).unwrap(),
+            "https://host.two:5000/"
+        );
+        assert!(select(&cat, "identity", vec![Internal], Some("RegionTwo")).is_none());
+        assert!(select(&cat, "identity", vec![Public], Some("RegionThree")).is_none());
+        assert!(select(&cat, "identity", vec![Public], Some("")).is_none());
+    }
+
+    #[test]
+    fn test_find_endpoint_multiple_regions() {
+        let cat = demo_catalog();
+        let filters = EndpointFilters::default()
+            .with_interfaces(Internal)
+            .with_regions(vec!["RegionTwo", "RegionOne"]);
+        assert_eq!(filters.region(), Some("RegionTwo"));
+        assert_eq!(filters.fallback_regions(), &["RegionOne".to_string()]);
+        let e = filters.find_endpoint(&cat, "identity").unwrap();
+        assert_eq!(&e.url, "http://192.168.22.1/identity");
+
+        let filters = filters.with_interfaces(vec![Internal, Public]);
+        let e = filters.find_endpoint(&cat, "identity").unwrap();
+        assert_eq!(&e.url, "https://host.two:5000");
+
+        let filters = EndpointFilters::default().with_regions(vec!["RegionThree", "RegionTwo"]);
+        let e = filters.find_endpoint(&cat, "baremetal").unwrap();
+        assert_eq!(&e.url, "https://host.two:6385");
+
+        let filters = EndpointFilters::default().with_regions(vec!["RegionThree", "RegionFour"]);
+        assert_not_found(filters.find_endpoint(&cat, "baremetal"));
+
+        // Setting one region removes the fallback regions.
+        let filters = EndpointFilters::default()
+            .with_regions(vec!["RegionThree", "RegionTwo"])
+            .with_region("RegionThree");
+        assert!(filters.fallback_regions().is_empty());
+        assert_not_found(filters.find_endpoint(&cat, "baremetal"));
+
+        let mut filters = EndpointFilters::default().with_regions(vec!["RegionThree", "RegionTwo"]);
+        filters.regions = vec!["RegionThree".to_string()];
+        assert!(filters.fallback_regions().is_empty());
+        assert_not_found(filters.find_endpoint(&cat, "baremetal"));
+    }
+
+    #[test]
+    fn test_with_defaults_regions() {
+        let defaults = EndpointFilters::default().with_regions(vec!["RegionOne", "RegionTwo"]);
+        let filters = EndpointFilters::default().with_defaults(&defaults);
+        assert_eq!(filters, defaults);
+        let filters = EndpointFilters::default()
+            .with_region("RegionThree")
+            .with_defaults(&defaults);
+        assert_eq!(filters.region(), Some("RegionThree"));
+        assert!(filters.fallback_regions().is_empty());
+    }
+
+    #[test]
+    fn test_select_endpoint_unknown_service() {
+        let cat = demo_catalog();
+        assert!(select(&cat, "compute", vec![Public], None).is_none());
+        assert!(select(&[], "identity", vec![Public], None).is_none());
+        let empty = vec![CatalogRecord {
+            service_type: String::from("compute"),
+            endpoints: Vec::new(),
+        }];
+        assert!(select(&empty, "compute", vec![Public], None).is_none());
+    }
+
+    #[test]
+    fn test_select_endpoint_invalid_url() {
+        let cat = vec![CatalogRecord {
+            service_type: String::from("compute"),
+            endpoints: vec![Endpoint {
+                interface: String::from("public"),
+                region: String::from("RegionOne"),
+                url: String::from("not a URL"),
+            }],
+        }];
+        assert!(select(&cat, "compute", vec![Public], None).is_none());
+    }
+
+    #[test]
+    fn test_select_endpoint_legacy_interface_names() {
+        let cat = vec![CatalogRecord {
+            service_type: String::from("compute"),
+            endpoints: vec![Endpoint {
+                interface: String::from("internalURL"),
+                region: String::from("RegionOne"),
+                url: String::from("http://compute.local"),
+            }],
+        }];
+        assert_eq!(
+            select(&cat, "compute", vec![Public, Internal], None).unwrap(),
+            "http://compute.local/"
+        );
+    }
+
     #[test]
     fn test_valid_interfaces_basics() {
-        assert_eq!(std::mem::size_of::<ValidInterfaces>(), 4);
+        assert_eq!(size_of::<ValidInterfaces>(), 4);
 
         let empty = ValidInterfaces::empty();
         assert_eq!(empty.len(), 0);
         assert!(empty
             .iter()
-            .map(|x| *x)
+            .copied()
             .collect::<Vec<InterfaceType>>()
             .is_empty());
         assert_eq!(
@@ -540,4 +990,29 @@ pub mod test {
         let vi: ValidInterfaces = vec![Public, Internal, Public, Public, Admin, Internal].into();
         assert_eq!(*vi, [Public, Internal, Admin]);
     }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let filters = EndpointFilters::default()
+            .with_interfaces(vec![Internal, Public])
+            .with_regions(vec!["RegionOne", "RegionTwo"]);
+        let json = serde_json::to_string(&filters).unwrap();
+        assert_eq!(
+            json,
+            r#"{"interfaces":["internal","public"],"regions":["RegionOne","RegionTwo"]}"#
+        );
+        let restored: EndpointFilters = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, filters);
+    }
+
+    #[test]
+    fn test_deserialize_partial() {
+        let filters: EndpointFilters =
+            serde_json::from_str(r#"{"interfaces": ["adminURL", "admin"]}"#).unwrap();
+        assert_eq!(filters.interfaces, ValidInterfaces::one(Admin));
+        assert!(filters.region().is_none());
+        let filters: EndpointFilters = serde_json::from_str("{}").unwrap();
+        assert_eq!(filters, EndpointFilters::default());
+        assert!(serde_json::from_str::<EndpointFilters>(r#"{"interfaces": ["private"]}"#).is_err());
+    }
 }
@@ -15,9 +15,10 @@
 //! Error and Result implementations.
 
 use std::fmt;
+use std::time::Duration;
 
 use reqwest::Error as HttpClientError;
-use reqwest::StatusCode;
+use reqwest::{StatusCode, Url};
 
 /// Kind of an error.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -28,11 +29,29 @@ pub enum ErrorKind {
     /// Maps to HTTP 401.
     AuthenticationFailed,
 
+    /// Authentication could not be completed, although the credentials were not rejected.
+    ///
+    /// Reported e.g. when the Identity service requires additional authentication methods that
+    /// cannot be provided.
+    Authentication,
+
+    /// Authentication token has expired or was revoked.
+    ///
+    /// Maps to HTTP 401 if re-authentication with the original credentials succeeds, but the
+    /// request could not be retried automatically or the retry was rejected as well.
+    TokenExpired,
+
     /// Access denied.
     ///
     /// Maps to HTTP 403.
     AccessDenied,
 
+    /// Request is not permitted by the access rules of the application credential in use.
+    ///
+    /// Reported instead of `AccessDenied` (and `AuthenticationFailed` if the response says so)
+    /// when the token carries access rules.
+    AccessRuleDenied,
+
     /// Requested resource was not found.
     ///
     /// Roughly maps to HTTP 404 and 410.
@@ -57,6 +76,11 @@ pub enum ErrorKind {
     /// Conflict in the request.
     Conflict,
 
+    /// A quota or a rate limit of the project is exceeded.
+    ///
+    /// Reported for HTTP 403 and 413 responses that mention a quota or an `overLimit` fault.
+    QuotaExceeded,
+
     /// Operation has reached the specified time out.
     OperationTimedOut,
 
@@ -64,8 +88,22 @@ pub enum ErrorKind {
     OperationFailed,
 
     /// Protocol-level error reported by underlying HTTP library.
+    ///
+    /// Not returned any more, errors of the HTTP library are reported as `Transport` or
+    /// `Protocol` instead.
     ProtocolError,
 
+    /// Network or transport failure: connection, DNS, TLS or time out.
+    ///
+    /// No response was received, so idempotent requests are safe to retry.
+    Transport,
+
+    /// The server was reached, but its response could not be handled by the HTTP library.
+    ///
+    /// E.g. the body cannot be decoded or there are too many redirects. Responses that are
+    /// valid HTTP, but have unexpected contents, are reported as `InvalidResponse`.
+    Protocol,
+
     /// Response received from the server is malformed.
     InvalidResponse,
 
@@ -78,12 +116,39 @@ pub enum ErrorKind {
     InvalidConfig,
 }
 
+/// A fault reported by a service in the body of an error response.
+///
+/// Services use slightly different formats, e.g. `{"itemNotFound": {"code": 404, "message":
+/// "..."}}` (Compute), `{"error": {"code": 401, "title": "...", "message": "..."}}` (Identity),
+/// `{"NeutronError": {"type": "...", "message": "...", "detail": "..."}}` (Networking) or
+/// `{"faultstring": "...", "faultcode": "...", "debuginfo": "..."}` (Bare Metal). They are all
+/// converted into this structure.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Fault {
+    /// Name of the object wrapping the fault (e.g. `itemNotFound`), if any.
+    pub name: Option<String>,
+    /// Human-readable message (`message` or `faultstring`).
+    pub message: String,
+    /// Numeric code, usually the HTTP status, if provided.
+    pub code: Option<u16>,
+    /// Type of the fault (`type`, `title` or `faultcode`), if provided.
+    pub fault_type: Option<String>,
+    /// Additional details (`details`, `detail` or `debuginfo`), if provided.
+    pub details: Option<String>,
+}
+
 /// Error from an OpenStack call.
 #[derive(Debug, Clone)]
 pub struct Error {
     kind: ErrorKind,
     message: String,
     status: Option<StatusCode>,
+    request_id: Option<String>,
+    retry_after: Option<Duration>,
+    // NOTE: boxed to keep the error (and thus every result) small.
+    fault: Option<Box<Fault>>,
+    url: Option<Box<Url>>,
 }
 
 impl Error {
@@ -94,6 +159,10 @@ impl Error {
             kind,
             message: message.into(),
             status: None,
+            request_id: None,
+            retry_after: None,
+            fault: None,
+            url: None,
         }
     }
 
@@ -110,12 +179,111 @@ impl Error {
         self
     }
 
+    /// HTTP status code of the response (if any).
+    #[inline]
+    pub fn status(&self) -> Option<StatusCode> {
+        self.status
+    }
+
+    /// Add the delay requested by the server in the `Retry-After` header.
+    #[inline]
+    pub fn set_retry_after(&mut self, retry_after: Duration) {
+        self.retry_after = Some(retry_after);
+    }
+
+    /// Add the delay requested by the server in the `Retry-After` header.
+    #[inline]
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.set_retry_after(retry_after);
+        self
+    }
+
+    /// Delay requested by the server in the `Retry-After` header (if any).
+    ///
+    /// Usually set for HTTP 429 (Too Many Requests) and 503 (Service Unavailable).
+    #[inline]
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    /// Add the request ID reported by the server to the error.
+    #[inline]
+    pub fn set_request_id<S: Into<String>>(&mut self, request_id: S) {
+        self.request_id = Some(request_id.into());
+    }
+
+    /// Add the request ID reported by the server to the error.
+    #[inline]
+    pub fn with_request_id<S: Into<String>>(mut self, request_id: S) -> Self {
+        self.set_request_id(request_id);
+        self
+    }
+
+    /// Request ID reported by the server in the `X-OpenStack-Request-ID` header (if any).
+    ///
+    /// Include it when reporting issues to the cloud operators.
+    #[inline]
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    /// Add the fault reported by the service to the error.
+    #[inline]
+    pub fn set_fault(&mut self, fault: Fault) {
+        self.fault = Some(Box::new(fault));
+    }
+
+    /// Add the fault reported by the service to the error.
+    #[inline]
+    pub fn with_fault(mut self, fault: Fault) -> Self {
+        self.set_fault(fault);
+        self
+    }
+
+    /// Fault reported by the service in the response body (if it could be parsed).
+    #[inline]
+    pub fn fault(&self) -> Option<&Fault> {
+        self.fault.as_deref()
+    }
+
+    /// Add the URL of the failed request to the error.
+    #[inline]
+    pub fn set_url(&mut self, url: Url) {
+        self.url = Some(Box::new(url));
+    }
+
+    /// Add the URL of the failed request to the error.
+    #[inline]
+    pub fn with_url(mut self, url: Url) -> Self {
+        self.set_url(url);
+        self
+    }
+
+    /// URL of the failed request (if known).
+    #[inline]
+    pub fn url(&self) -> Option<&Url> {
+        self.url.as_deref()
+    }
+
+    /// Error message without the kind.
+    #[inline]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
     /// Error kind.
     #[inline]
     pub fn kind(&self) -> ErrorKind {
         self.kind
     }
 
+    /// Change the kind of the error.
+    #[inline]
+    pub(crate) fn with_kind(mut self, kind: ErrorKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
     /// Helper - error of kind EndpointNotFound.
     pub(crate) fn new_endpoint_not_found<D: fmt::Display>(service_type: D) -> Error {
         Error::new(
@@ -132,16 +300,22 @@ impl ErrorKind {
     pub fn description(&self) -> &'static str {
         match self {
             ErrorKind::AuthenticationFailed => "Failed to authenticate",
+            ErrorKind::Authentication => "Authentication could not be completed",
+            ErrorKind::TokenExpired => "Authentication token has expired",
             ErrorKind::AccessDenied => "Access to the resource is denied",
+            ErrorKind::AccessRuleDenied => "Request is not permitted by the access rules",
             ErrorKind::ResourceNotFound => "Requested resource was not found",
             ErrorKind::TooManyItems => "Request returned too many items",
             ErrorKind::EndpointNotFound => "Requested endpoint was not found",
             ErrorKind::InvalidInput => "Input value(s) are invalid or missing",
             ErrorKind::IncompatibleApiVersion => "Incompatible or unsupported API version",
             ErrorKind::Conflict => "Requested cannot be fulfilled due to a conflict",
+            ErrorKind::QuotaExceeded => "Quota exceeded",
             ErrorKind::OperationTimedOut => "Time out reached while waiting for the operation",
             ErrorKind::OperationFailed => "Requested operation has failed",
             ErrorKind::ProtocolError => "Error when accessing the server",
+            ErrorKind::Transport => "Failed to reach the server",
+            ErrorKind::Protocol => "Response from the server cannot be handled",
             ErrorKind::InvalidResponse => "Received invalid response",
             ErrorKind::InternalServerError => "Internal server error or bad gateway",
             ErrorKind::InvalidConfig => "clouds.yaml cannot be found or is invalid",
@@ -157,7 +331,11 @@ impl fmt::Display for ErrorKind {
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}: {}", self.kind, self.message)
+        write!(f, "{}: {}", self.kind, self.message)?;
+        if let Some(ref request_id) = self.request_id {
+            write!(f, " (request ID {})", request_id)?;
+        }
+        Ok(())
     }
 }
 
@@ -192,13 +370,19 @@ impl From<HttpClientError> for Error {
         let kind = if value.is_builder() {
             ErrorKind::InvalidInput
         } else {
-            value
-                .status()
-                .map(From::from)
-                .unwrap_or(ErrorKind::ProtocolError)
+            value.status().map(From::from).unwrap_or_else(|| {
+                if value.is_decode() || value.is_redirect() {
+                    ErrorKind::Protocol
+                } else {
+                    ErrorKind::Transport
+                }
+            })
         };
 
-        let error = Error::new(kind, msg);
+        let mut error = Error::new(kind, msg);
+        if let Some(url) = value.url() {
+            error.set_url(url.clone());
+        }
         if let Some(status) = value.status() {
             error.with_status(status)
         } else {
@@ -217,5 +401,23 @@ pub mod test {
         assert_eq!(error.kind(), ErrorKind::InvalidInput);
         let s = format!("{}", error);
         assert_eq!(&s, "Input value(s) are invalid or missing: boom");
+        let error = error.with_request_id("req-1234");
+        assert_eq!(error.request_id(), Some("req-1234"));
+        let s = format!("{}", error);
+        assert_eq!(
+            &s,
+            "Input value(s) are invalid or missing: boom (request ID req-1234)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transport_error() {
+        // Nothing listens on port 1.
+        let err: Error = reqwest::get("http://127.0.0.1:1/")
+            .await
+            .err()
+            .unwrap()
+            .into();
+        assert_eq!(err.kind(), ErrorKind::Transport);
     }
 }
@@ -0,0 +1,81 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Results of connection health checks.
+
+use std::time::Duration;
+
+use reqwest::Url;
+
+use super::identity::TokenInfo;
+use super::{Error, ErrorKind};
+
+/// Status of one service checked by [Session::validate](struct.Session.html#method.validate).
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ServiceHealth {
+    /// Service type as requested (e.g. `compute`).
+    pub service_type: String,
+    /// Endpoint of the service (if found).
+    pub endpoint: Option<Url>,
+    /// Time it took the endpoint to respond (only set for the probed service).
+    pub response_time: Option<Duration>,
+    /// Why the service cannot be used (if it cannot).
+    pub error: Option<Error>,
+}
+
+impl ServiceHealth {
+    /// Whether the service has an endpoint and the probe (if any) has succeeded.
+    #[inline]
+    pub fn is_healthy(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Whether the service is missing from the catalog.
+    #[inline]
+    pub fn is_missing(&self) -> bool {
+        self.error
+            .as_ref()
+            .map_or(false, |e| e.kind() == ErrorKind::EndpointNotFound)
+    }
+}
+
+/// Report of [Session::validate](struct.Session.html#method.validate).
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct HealthReport {
+    /// The authentication token (if the authentication type uses tokens).
+    pub token: Option<TokenInfo>,
+    /// Time it took to authenticate (close to zero if a valid token has been cached).
+    pub auth_time: Duration,
+    /// The requested services in the requested order.
+    pub services: Vec<ServiceHealth>,
+}
+
+impl HealthReport {
+    /// Whether all requested services can be used.
+    #[inline]
+    pub fn is_healthy(&self) -> bool {
+        self.services.iter().all(ServiceHealth::is_healthy)
+    }
+
+    /// Requested services that are missing from the catalog.
+    pub fn missing_services(&self) -> Vec<&str> {
+        self.services
+            .iter()
+            .filter(|service| service.is_missing())
+            .map(|service| service.service_type.as_str())
+            .collect()
+    }
+}
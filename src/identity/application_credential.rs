@@ -0,0 +1,455 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Application credential authentication.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Url};
+
+use super::super::{
+    request, AuthType, EndpointFilters, Error, InterfaceType, Metrics, ValidInterfaces,
+};
+use super::internal::Internal;
+use super::protocol;
+use super::{AuthEvent, AuthHook, CredentialsProvider, IdOrName, Identity, TokenInfo};
+
+/// Application credential authentication using Identity API V3.
+///
+/// Application credentials are always scoped to the project they were created in, so no scope
+/// can be requested.
+///
+/// ```rust,no_run
+/// let auth = osauth::identity::ApplicationCredential::new(
+///     "https://cloud.local/identity",
+///     "423f19a4ac1e4f48bbb4180756e6eb6c",
+///     "<a secret>",
+/// )
+/// .expect("Invalid auth_url");
+///
+/// let session = osauth::Session::new(auth);
+/// ```
+///
+/// A credential can also be referenced by its name, in which case the owning user (and optionally
+/// the user's domain) must be provided:
+///
+/// ```rust,no_run
+/// # use osauth::identity::IdOrName;
+/// let auth = osauth::identity::ApplicationCredential::new_by_name(
+///     "https://cloud.local/identity",
+///     "monitoring",
+///     "<a secret>",
+///     IdOrName::Name("admin".to_string()),
+///     IdOrName::Name("Default".to_string()),
+/// )
+/// .expect("Invalid auth_url");
+/// ```
+///
+/// Credentials with access rules authenticate the same way. The rules are reported in
+/// [TokenInfo](struct.TokenInfo.html#method.access_rules), and requests rejected because of them
+/// fail with `ErrorKind::AccessRuleDenied`.
+///
+/// The authentication token is cached while it's still valid or until
+/// [refresh](../trait.AuthType.html#tymethod.refresh) is called.
+/// Clones of an `ApplicationCredential` also start with an empty cache.
+#[derive(Clone, Debug)]
+pub struct ApplicationCredential {
+    inner: Internal,
+}
+
+impl Identity for ApplicationCredential {
+    fn auth_url(&self) -> &Url {
+        self.inner.auth_url()
+    }
+}
+
+impl ApplicationCredential {
+    /// Create an application credential authentication from the credential ID.
+    pub fn new<U, S1, S2>(auth_url: U, id: S1, secret: S2) -> Result<ApplicationCredential, Error>
+    where
+        U: IntoUrl,
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        ApplicationCredential::new_with_client(auth_url, request::default_client(), id, secret)
+    }
+
+    /// Create an application credential authentication from the credential ID with the provided
+    /// HTTP client.
+    pub fn new_with_client<U, S1, S2>(
+        auth_url: U,
+        client: Client,
+        id: S1,
+        secret: S2,
+    ) -> Result<ApplicationCredential, Error>
+    where
+        U: IntoUrl,
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        let cred = protocol::ApplicationCredential {
+            id: IdOrName::Id(id.into()),
+            secret: secret.into(),
+            user: None,
+        };
+        ApplicationCredential::from_credential(auth_url, client, cred)
+    }
+
+    /// Create an application credential authentication from the credential name.
+    ///
+    /// Names are only unique per user, so the owning user must be provided.
+    pub fn new_by_name<U, S1, S2>(
+        auth_url: U,
+        name: S1,
+        secret: S2,
+        user: IdOrName,
+        user_domain: impl Into<Option<IdOrName>>,
+    ) -> Result<ApplicationCredential, Error>
+    where
+        U: IntoUrl,
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        ApplicationCredential::new_by_name_with_client(
+            auth_url,
+            request::default_client(),
+            name,
+            secret,
+            user,
+            user_domain,
+        )
+    }
+
+    /// Create an application credential authentication from the credential name with the
+    /// provided HTTP client.
+    pub fn new_by_name_with_client<U, S1, S2>(
+        auth_url: U,
+        client: Client,
+        name: S1,
+        secret: S2,
+        user: IdOrName,
+        user_domain: impl Into<Option<IdOrName>>,
+    ) -> Result<ApplicationCredential, Error>
+    where
+        U: IntoUrl,
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        let cred = protocol::ApplicationCredential {
+            id: IdOrName::Name(name.into()),
+            secret: secret.into(),
+            user: Some(protocol::User {
+                user,
+                domain: user_domain.into(),
+            }),
+        };
+        ApplicationCredential::from_credential(auth_url, client, cred)
+    }
+
+    fn from_credential<U: IntoUrl>(
+        auth_url: U,
+        client: Client,
+        cred: protocol::ApplicationCredential,
+    ) -> Result<ApplicationCredential, Error> {
+        Ok(ApplicationCredential {
+            inner: Internal::new(
+                client,
+                auth_url.into_url()?,
+                protocol::Identity::ApplicationCredential(cred),
+            ),
+        })
+    }
+
+    /// Endpoint filters.
+    #[inline]
+    pub fn endpoint_filters(&self) -> &EndpointFilters {
+        self.inner.endpoint_filters()
+    }
+
+    /// Mutable endpoint filters.
+    #[inline]
+    pub fn endpoint_filters_mut(&mut self) -> &mut EndpointFilters {
+        self.inner.endpoint_filters_mut()
+    }
+
+    /// Set the default endpoint interface to use.
+    ///
+    /// Re-authentication uses the identity endpoint of this interface from the service catalog
+    /// if it is not `public`: the credentials are sent to that endpoint. The authentication URL
+    /// is used if the endpoint cannot be reached.
+    pub fn set_default_endpoint_interface(&mut self, endpoint_interface: InterfaceType) {
+        self.endpoint_filters_mut().interfaces = ValidInterfaces::one(endpoint_interface);
+    }
+
+    /// Set endpoint filters.
+    #[inline]
+    pub fn set_endpoint_filters(&mut self, filters: EndpointFilters) {
+        *self.endpoint_filters_mut() = filters;
+    }
+
+    /// Set a time out for authentication requests.
+    ///
+    /// Defaults to 60 seconds, `None` disables the time out.
+    #[inline]
+    pub fn set_auth_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.inner.set_auth_timeout(timeout);
+    }
+
+    /// Set the cloud name reported to the [auth hook](#method.set_auth_hook).
+    #[inline]
+    pub(crate) fn set_cloud_name(&mut self, cloud_name: String) {
+        self.inner.set_cloud_name(cloud_name);
+    }
+
+    /// Set a hook called after each authentication attempt.
+    ///
+    /// See [Password::set_auth_hook](struct.Password.html#method.set_auth_hook).
+    pub fn set_auth_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&AuthEvent) + Send + Sync + 'static,
+    {
+        self.inner.set_auth_hook(AuthHook::new(hook));
+    }
+
+    /// Set the maximum size of a token response in bytes.
+    ///
+    /// Defaults to 64 MiB, see
+    /// [Password::set_max_response_size](struct.Password.html#method.set_max_response_size).
+    #[inline]
+    pub fn set_max_response_size(&mut self, max_size: usize) {
+        self.inner.set_max_response_size(max_size);
+    }
+
+    /// Enable discovery of the Identity API v3 endpoint.
+    ///
+    /// When enabled, the version document at the auth URL is requested first to find the
+    /// endpoint of a stable Identity API v3 instead of assuming `<auth_url>/v3`. If discovery
+    /// fails, the default endpoint is used. Disabled by default.
+    #[inline]
+    pub fn set_version_discovery(&mut self, enabled: bool) {
+        self.inner.set_version_discovery(enabled);
+    }
+
+    /// Set how long before its expiration the token is refreshed.
+    ///
+    /// Defaults to 10 minutes, see
+    /// [Password::set_token_refresh_skew](struct.Password.html#method.set_token_refresh_skew).
+    #[inline]
+    pub fn set_token_refresh_skew(&mut self, skew: std::time::Duration) -> Result<(), Error> {
+        self.inner.set_token_refresh_skew(skew)
+    }
+
+    /// Share tokens with other processes via an on-disk cache.
+    ///
+    /// Tokens are stored in `osauth/tokens` in the user cache directory (e.g. `~/.cache` on
+    /// Linux), readable only by the current user. A cached token is used by all authentications
+    /// with the same cloud name, authentication URL, user and scope until it expires or is
+    /// rejected by a service. Requires the `token-cache` feature.
+    #[cfg(feature = "token-cache")]
+    #[inline]
+    pub fn set_token_cache(&mut self, enabled: bool) {
+        self.inner.enable_token_cache(enabled);
+    }
+
+    /// Fetch the application credential secret from the provider on every authentication.
+    ///
+    /// The application credential secret passed to [new](#method.new) is ignored afterwards.
+    #[inline]
+    pub fn set_credentials_provider<P>(&mut self, provider: P)
+    where
+        P: CredentialsProvider + 'static,
+    {
+        self.inner.set_credentials_provider(Arc::new(provider));
+    }
+
+    /// Convert this authentication into one using the given endpoint interface.
+    #[inline]
+    pub fn with_default_endpoint_interface(mut self, endpoint_interface: InterfaceType) -> Self {
+        self.set_default_endpoint_interface(endpoint_interface);
+        self
+    }
+
+    /// Convert this authentication into one fetching the application credential secret from the provider.
+    #[inline]
+    pub fn with_credentials_provider<P>(mut self, provider: P) -> Self
+    where
+        P: CredentialsProvider + 'static,
+    {
+        self.set_credentials_provider(provider);
+        self
+    }
+
+    /// Add endpoint filters.
+    #[inline]
+    pub fn with_endpoint_filters(mut self, filters: EndpointFilters) -> Self {
+        self.set_endpoint_filters(filters);
+        self
+    }
+
+    /// Set the maximum size of a token response in bytes.
+    #[inline]
+    pub fn with_max_response_size(mut self, max_size: usize) -> Self {
+        self.set_max_response_size(max_size);
+        self
+    }
+
+    /// Set a hook called after each authentication attempt.
+    #[inline]
+    pub fn with_auth_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&AuthEvent) + Send + Sync + 'static,
+    {
+        self.set_auth_hook(hook);
+        self
+    }
+
+    /// Enable discovery of the Identity API v3 endpoint.
+    ///
+    /// See [set_version_discovery](#method.set_version_discovery) for details.
+    #[inline]
+    pub fn with_version_discovery(mut self, enabled: bool) -> Self {
+        self.set_version_discovery(enabled);
+        self
+    }
+
+    /// Set a region for this authentication method.
+    #[inline]
+    pub fn with_region<S>(mut self, region: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.endpoint_filters_mut().set_region(region);
+        self
+    }
+
+    /// Convert this authentication into one sharing tokens via an on-disk cache.
+    ///
+    /// See [set_token_cache](#method.set_token_cache) for details.
+    #[cfg(feature = "token-cache")]
+    #[inline]
+    pub fn with_token_cache(mut self, enabled: bool) -> Self {
+        self.set_token_cache(enabled);
+        self
+    }
+}
+
+#[async_trait]
+impl AuthType for ApplicationCredential {
+    /// The authentication URL.
+    fn identity_url(&self) -> Option<&Url> {
+        Some(self.inner.auth_url())
+    }
+
+    /// Endpoint filters in use.
+    fn default_filters(&self) -> Option<&EndpointFilters> {
+        Some(self.inner.endpoint_filters())
+    }
+
+    /// Create an authenticated request.
+    async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+        self.inner.request(method, url).await
+    }
+
+    /// The service catalog received with the token.
+    async fn raw_catalog(&self) -> Result<Option<serde_json::Value>, Error> {
+        self.inner.raw_catalog().await.map(Some)
+    }
+
+    /// Information about the current token.
+    async fn token_info(&self) -> Result<Option<TokenInfo>, Error> {
+        self.inner.token_info().await.map(Some)
+    }
+
+    /// Get a URL for the requested service.
+    async fn get_endpoint(
+        &self,
+        service_type: String,
+        filters: EndpointFilters,
+    ) -> Result<Url, Error> {
+        self.inner.get_endpoint(service_type, filters).await
+    }
+
+    /// Refresh the cached token and service catalog.
+    async fn refresh(&self) -> Result<(), Error> {
+        self.inner.do_refresh(true).await
+    }
+
+    /// Revoke the cached token (if any).
+    async fn revoke(&self) -> Result<(), Error> {
+        self.inner.revoke().await
+    }
+
+    /// Application credentials can be used to obtain new tokens.
+    fn can_reauthenticate(&self) -> bool {
+        true
+    }
+
+    /// Report token requests to the metrics.
+    fn set_metrics(&self, metrics: Arc<dyn Metrics>) {
+        self.inner.set_metrics(metrics);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::super::AuthType;
+    use super::super::protocol::test::compare;
+    use super::{ApplicationCredential, IdOrName, Identity};
+
+    #[test]
+    fn test_application_credential_new() {
+        let id = ApplicationCredential::new("http://127.0.0.1:8080/identity", "abcdef", "secret")
+            .unwrap();
+        assert_eq!(id.auth_url().to_string(), "http://127.0.0.1:8080/identity");
+        assert_eq!(
+            id.inner.token_endpoint(),
+            "http://127.0.0.1:8080/identity/v3/auth/tokens"
+        );
+        assert!(id.can_reauthenticate());
+        compare(
+            r#"{"methods": ["application_credential"],
+                "application_credential": {"id": "abcdef", "secret": "secret"}}"#,
+            id.inner.identity(),
+        );
+    }
+
+    #[test]
+    fn test_application_credential_new_by_name() {
+        let id = ApplicationCredential::new_by_name(
+            "http://127.0.0.1:8080/identity",
+            "monitoring",
+            "secret",
+            IdOrName::Name("admin".to_string()),
+            IdOrName::Id("default".to_string()),
+        )
+        .unwrap();
+        compare(
+            r#"{"methods": ["application_credential"],
+                "application_credential": {
+                    "name": "monitoring",
+                    "secret": "secret",
+                    "user": {"name": "admin", "domain": {"id": "default"}}
+                }}"#,
+            id.inner.identity(),
+        );
+    }
+
+    #[test]
+    fn test_application_credential_new_invalid() {
+        let _ = ApplicationCredential::new("http://127.0.0.1 8080/", "abcdef", "secret")
+            .err()
+            .unwrap();
+    }
+}
@@ -0,0 +1,267 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! On-disk cache of authentication tokens shared between processes.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chrono::{DateTime, FixedOffset, Local};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+/// A cached token as stored on disk.
+#[derive(Debug, Deserialize, Serialize)]
+struct Entry {
+    /// Full cache key, protects against hash collisions.
+    key: String,
+    /// Expiration time, used for pruning without parsing the whole token.
+    expires_at: DateTime<FixedOffset>,
+    /// Token value.
+    value: String,
+    /// Token body as received from the Identity service.
+    body: serde_json::Value,
+}
+
+/// Directory with cached tokens, one file per cache key.
+///
+/// Files are only readable by the current user. All errors are logged and otherwise ignored:
+/// a broken cache results in a new authentication.
+#[derive(Clone, Debug)]
+pub(crate) struct TokenCache {
+    dir: PathBuf,
+}
+
+impl TokenCache {
+    /// Create a cache in the given directory.
+    #[inline]
+    pub fn new(dir: PathBuf) -> TokenCache {
+        TokenCache { dir }
+    }
+
+    /// Create a cache in the default directory (`osauth/tokens` in the user cache directory).
+    pub fn with_default_dir() -> Option<TokenCache> {
+        dirs::cache_dir().map(|dir| TokenCache::new(dir.join("osauth").join("tokens")))
+    }
+
+    /// Path to the file for the key.
+    fn path(&self, key: &str) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.dir.join(format!("{:016x}.json", hasher.finish()))
+    }
+
+    /// Load the token value and body for the key (if any).
+    pub fn load(&self, key: &str) -> Option<(String, serde_json::Value)> {
+        let path = self.path(key);
+        let entry = read_entry(&path)?;
+        if entry.key != key {
+            debug!("Cached token {:?} belongs to another key", path);
+            return None;
+        }
+        debug!(
+            "Using a cached token from {:?} expiring at {}",
+            path, entry.expires_at
+        );
+        Some((entry.value, entry.body))
+    }
+
+    /// Remove the token for the key.
+    pub fn remove(&self, key: &str) {
+        let path = self.path(key);
+        if let Err(e) = fs::remove_file(&path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Cannot remove cached token {:?}: {}", path, e);
+            }
+        }
+    }
+
+    /// Store the token for the key, removing expired tokens of other keys.
+    pub fn store(
+        &self,
+        key: &str,
+        expires_at: DateTime<FixedOffset>,
+        value: &str,
+        body: &serde_json::Value,
+    ) {
+        let entry = Entry {
+            key: key.to_string(),
+            expires_at,
+            value: value.to_string(),
+            body: body.clone(),
+        };
+        let path = self.path(key);
+        match self.write_entry(&path, &entry) {
+            Ok(()) => debug!("Cached the token in {:?}", path),
+            Err(e) => warn!("Cannot cache the token in {:?}: {}", path, e),
+        }
+        self.prune();
+    }
+
+    /// Atomically write the entry to the path.
+    fn write_entry(&self, path: &Path, entry: &Entry) -> io::Result<()> {
+        create_private_dir(&self.dir)?;
+        let tmp_path = path.with_extension(format!("tmp.{}", process::id()));
+        let result = create_private_file(&tmp_path)
+            .and_then(|mut file| {
+                serde_json::to_writer(&mut file, entry)?;
+                file.flush()
+            })
+            .and_then(|()| fs::rename(&tmp_path, path));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    }
+
+    /// Remove all expired tokens.
+    fn prune(&self) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                debug!("Cannot read token cache {:?}: {}", self.dir, e);
+                return;
+            }
+        };
+        let now = Local::now();
+        for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+            if path.extension().map_or(true, |ext| ext != "json") {
+                continue;
+            }
+            if read_entry(&path).map_or(false, |entry| entry.expires_at > now) {
+                continue;
+            }
+            debug!("Removing expired or invalid cached token {:?}", path);
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+/// Read a cache entry, returning `None` on errors.
+fn read_entry(path: &Path) -> Option<Entry> {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Cannot read cached token {:?}: {}", path, e);
+            }
+            return None;
+        }
+    };
+    serde_json::from_slice(&content)
+        .map_err(|e| debug!("Invalid cached token {:?}: {}", path, e))
+        .ok()
+}
+
+/// Create the directory (and its parents) only accessible by the current user.
+#[cfg(unix)]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(path)
+}
+
+/// Create the directory (and its parents) only accessible by the current user.
+#[cfg(not(unix))]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    fs::create_dir_all(path)
+}
+
+/// Create a new file only accessible by the current user.
+#[cfg(unix)]
+fn create_private_file(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+/// Create a new file only accessible by the current user.
+#[cfg(not(unix))]
+fn create_private_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use chrono::{DateTime, Duration, FixedOffset, Local};
+
+    use super::TokenCache;
+
+    fn cache(name: &str) -> (PathBuf, TokenCache) {
+        let dir = env::temp_dir().join(format!(
+            "osauth-token-cache-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        (dir.clone(), TokenCache::new(dir))
+    }
+
+    fn in_hours(hours: i64) -> DateTime<FixedOffset> {
+        (Local::now() + Duration::hours(hours)).into()
+    }
+
+    #[test]
+    fn test_store_load() {
+        let (dir, cache) = cache("store");
+        assert!(cache.load("key1").is_none());
+        let body = serde_json::json!({"token": {}});
+        cache.store("key1", in_hours(1), "abcd", &body);
+        assert_eq!(cache.load("key1").unwrap(), ("abcd".to_string(), body));
+        assert!(cache.load("key2").is_none());
+        cache.remove("key1");
+        assert!(cache.load("key1").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (dir, cache) = cache("permissions");
+        cache.store("key1", in_hours(1), "abcd", &serde_json::Value::Null);
+        let mode = |path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(dir.clone()), 0o700);
+        assert_eq!(mode(cache.path("key1")), 0o600);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune() {
+        let (dir, cache) = cache("prune");
+        cache.store("expired", in_hours(-1), "abcd", &serde_json::Value::Null);
+        fs::write(dir.join("broken.json"), "{").unwrap();
+        fs::write(dir.join("README"), "not a token").unwrap();
+        cache.store("valid", in_hours(1), "efgh", &serde_json::Value::Null);
+        assert!(cache.load("expired").is_none());
+        assert!(cache.load("valid").is_some());
+        assert!(!dir.join("broken.json").exists());
+        assert!(dir.join("README").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Providers of secrets fetched on every authentication.
+
+use std::env;
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+
+use super::super::{Error, ErrorKind};
+
+/// A source of the secret used for authentication.
+///
+/// The provider is queried every time a new token is requested, so that rotated secrets are
+/// picked up without re-creating the session. Depending on the authentication type, the secret
+/// is a password, an application credential secret or a TOTP passcode.
+///
+/// ```rust,no_run
+/// use async_trait::async_trait;
+///
+/// #[derive(Debug)]
+/// struct Vault;
+///
+/// #[async_trait]
+/// impl osauth::identity::CredentialsProvider for Vault {
+///     async fn get_secret(&self) -> Result<String, osauth::Error> {
+///         // Fetch the secret from the secrets manager.
+///         Ok("pa$$w0rd".to_string())
+///     }
+/// }
+///
+/// let auth = osauth::identity::Password::new(
+///     "https://cloud.local/identity",
+///     "admin",
+///     "",
+///     "Default",
+/// )
+/// .expect("Invalid auth_url")
+/// .with_credentials_provider(Vault);
+/// ```
+#[async_trait]
+pub trait CredentialsProvider: Debug + Send + Sync {
+    /// Fetch the current secret.
+    async fn get_secret(&self) -> Result<String, Error>;
+}
+
+/// Secret from an environment variable, read on every authentication.
+#[derive(Clone, Debug)]
+pub struct EnvCredentials {
+    name: String,
+}
+
+impl EnvCredentials {
+    /// Read the secret from the given environment variable.
+    #[inline]
+    pub fn new<S: Into<String>>(name: S) -> EnvCredentials {
+        EnvCredentials { name: name.into() }
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for EnvCredentials {
+    async fn get_secret(&self) -> Result<String, Error> {
+        env::var(&self.name)
+            .ok()
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidConfig,
+                    format!("Environment variable {} is not set", self.name),
+                )
+            })
+    }
+}
+
+/// Password of a cloud from `clouds.yaml`, re-read on every authentication.
+///
+/// The configuration (including `secure.yaml`) is loaded the same way as in
+/// [from_config](../fn.from_config.html), `password_command` is run if there is no `password`
+/// and the secret store is queried if neither is set (with the `keyring` feature). If `OS_CLOUD`
+/// is set to this cloud, `OS_PASSWORD` and `OS_PASSWORD_COMMAND` take precedence.
+///
+/// The configuration is read (and the command is run) on the blocking thread pool of tokio.
+#[cfg(feature = "config-file")]
+#[derive(Clone, Debug)]
+pub struct ConfigCredentials {
+    cloud_name: String,
+}
+
+#[cfg(feature = "config-file")]
+impl ConfigCredentials {
+    /// Read the password of the given cloud.
+    #[inline]
+    pub fn new<S: Into<String>>(cloud_name: S) -> ConfigCredentials {
+        ConfigCredentials {
+            cloud_name: cloud_name.into(),
+        }
+    }
+}
+
+#[cfg(feature = "config-file")]
+#[async_trait]
+impl CredentialsProvider for ConfigCredentials {
+    async fn get_secret(&self) -> Result<String, Error> {
+        let cloud_name = self.cloud_name.clone();
+        super::run_blocking(move || super::super::config::load_password(&cloud_name)).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::super::ErrorKind;
+    use super::{CredentialsProvider, EnvCredentials};
+
+    #[tokio::test]
+    async fn test_env_credentials() {
+        std::env::set_var("OSAUTH_TEST_ENV_CREDENTIALS", "pa$$w0rd");
+        let provider = EnvCredentials::new("OSAUTH_TEST_ENV_CREDENTIALS");
+        assert_eq!(provider.get_secret().await.unwrap(), "pa$$w0rd");
+        std::env::remove_var("OSAUTH_TEST_ENV_CREDENTIALS");
+        let err = provider.get_secret().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidConfig);
+    }
+}
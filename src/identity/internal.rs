@@ -0,0 +1,1812 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Internal implementation of the Identity API v3 authentication.
+
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::{Duration, Local};
+use log::{debug, error, trace, warn};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode, Url};
+use tokio::sync::RwLock;
+
+use super::super::telemetry::{self, Outcome};
+use super::super::{request, url, EndpointFilters, Error, ErrorKind, Metrics, ValidInterfaces};
+#[cfg(feature = "token-cache")]
+use super::cache::TokenCache;
+use super::{protocol, AuthHook, AuthReporter, CredentialsProvider, IdOrName, Scope, TokenInfo};
+
+const MISSING_SUBJECT_HEADER: &str = "Missing X-Subject-Token header";
+const INVALID_SUBJECT_HEADER: &str = "Invalid X-Subject-Token header";
+const INVALID_RECEIPT_HEADER: &str = "Invalid Openstack-Auth-Receipt header";
+const RECEIPT_HEADER: &str = "openstack-auth-receipt";
+// Required validity time in minutes. By default we refresh the token if it expires
+// in 10 minutes or less. This is more than the usual clock skew, so that requests started
+// with a cached token do not outlive it.
+const TOKEN_MIN_VALIDITY: i64 = 10;
+// Default time out for authentication requests in seconds.
+const DEFAULT_AUTH_TIMEOUT: u64 = 60;
+// Default maximum size of a token response in bytes.
+pub(crate) const DEFAULT_MAX_RESPONSE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Plain authentication token without additional details.
+#[derive(Clone)]
+pub(crate) struct Token {
+    value: String,
+    body: protocol::Token,
+    raw_catalog: serde_json::Value,
+    info: TokenInfo,
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut hasher = DefaultHasher::new();
+        self.value.hash(&mut hasher);
+        write!(
+            f,
+            "Token {{ value: hash({}), body: {:?}, .. }}",
+            hasher.finish(),
+            self.body
+        )
+    }
+}
+
+impl Outcome for (Token, Option<String>) {
+    #[cfg(feature = "tracing")]
+    fn record(&self, span: &tracing_crate::Span) {
+        if let Some(ref request_id) = self.1 {
+            let _ = span.record("request_id", request_id.as_str());
+        }
+    }
+}
+
+/// Source of a TOTP passcode used as an additional authentication factor.
+#[derive(Clone)]
+pub(crate) enum TotpPasscode {
+    /// A passcode sent together with the primary method.
+    Static(String),
+    /// A provider queried when the Identity service requires a passcode.
+    Provider(Arc<dyn CredentialsProvider>),
+}
+
+impl fmt::Debug for TotpPasscode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TotpPasscode::Static(..) => f.write_str("Static(***)"),
+            TotpPasscode::Provider(ref provider) => {
+                f.debug_tuple("Provider").field(provider).finish()
+            }
+        }
+    }
+}
+
+/// Common state of all Identity API v3 authentication types.
+///
+/// The authentication token is cached while it's still valid or until `refresh` is called.
+/// Clones start with an empty cache.
+#[derive(Debug)]
+pub(crate) struct Internal {
+    client: Client,
+    auth_url: Url,
+    reporter: AuthReporter,
+    body: protocol::AuthRoot,
+    token_file: Option<PathBuf>,
+    credentials_provider: Option<Arc<dyn CredentialsProvider>>,
+    totp: Option<TotpPasscode>,
+    token_endpoint: String,
+    version_discovery: bool,
+    discovered_endpoint: RwLock<Option<String>>,
+    cached_token: RwLock<Option<Token>>,
+    token_generation: AtomicUsize,
+    filters: EndpointFilters,
+    refresh_skew: Duration,
+    auth_timeout: Option<std::time::Duration>,
+    max_response_size: usize,
+    #[cfg(feature = "token-cache")]
+    token_cache: Option<TokenCache>,
+}
+
+impl Clone for Internal {
+    fn clone(&self) -> Internal {
+        Internal {
+            client: self.client.clone(),
+            auth_url: self.auth_url.clone(),
+            reporter: self.reporter.clone(),
+            body: self.body.clone(),
+            token_file: self.token_file.clone(),
+            credentials_provider: self.credentials_provider.clone(),
+            totp: self.totp.clone(),
+            token_endpoint: self.token_endpoint.clone(),
+            version_discovery: self.version_discovery,
+            discovered_endpoint: RwLock::new(None),
+            cached_token: RwLock::new(None),
+            token_generation: AtomicUsize::new(0),
+            filters: self.filters.clone(),
+            refresh_skew: self.refresh_skew,
+            auth_timeout: self.auth_timeout,
+            max_response_size: self.max_response_size,
+            #[cfg(feature = "token-cache")]
+            token_cache: self.token_cache.clone(),
+        }
+    }
+}
+
+impl Internal {
+    /// Create a new internal state for the given identity.
+    pub fn new(client: Client, auth_url: Url, identity: protocol::Identity) -> Internal {
+        let token_endpoint = token_endpoint_for(&auth_url);
+        Internal {
+            client,
+            auth_url,
+            reporter: AuthReporter::default(),
+            body: protocol::AuthRoot {
+                auth: protocol::Auth {
+                    identity,
+                    scope: None,
+                },
+            },
+            token_file: None,
+            credentials_provider: None,
+            totp: None,
+            token_endpoint,
+            version_discovery: false,
+            discovered_endpoint: RwLock::new(None),
+            cached_token: RwLock::new(None),
+            token_generation: AtomicUsize::new(0),
+            filters: EndpointFilters::default(),
+            refresh_skew: Duration::minutes(TOKEN_MIN_VALIDITY),
+            auth_timeout: Some(std::time::Duration::from_secs(DEFAULT_AUTH_TIMEOUT)),
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            #[cfg(feature = "token-cache")]
+            token_cache: None,
+        }
+    }
+
+    /// Authentication URL.
+    #[inline]
+    pub fn auth_url(&self) -> &Url {
+        &self.auth_url
+    }
+
+    /// Authentication identity.
+    #[inline]
+    pub fn identity(&self) -> &protocol::Identity {
+        &self.body.auth.identity
+    }
+
+    /// Mutable authentication identity.
+    #[inline]
+    pub fn identity_mut(&mut self) -> &mut protocol::Identity {
+        &mut self.body.auth.identity
+    }
+
+    /// Whether the token is re-read from a file on every authentication.
+    #[inline]
+    pub fn has_token_file(&self) -> bool {
+        self.token_file.is_some()
+    }
+
+    /// Read the token from the file on every authentication.
+    ///
+    /// The file is read immediately to make sure it is valid.
+    pub fn set_token_file(&mut self, path: PathBuf) -> Result<(), Error> {
+        self.body.auth.identity = protocol::Identity::Token(read_token_file(&path)?);
+        self.token_file = Some(path);
+        Ok(())
+    }
+
+    /// Fetch the secret from the provider on every authentication.
+    #[inline]
+    pub fn set_credentials_provider(&mut self, provider: Arc<dyn CredentialsProvider>) {
+        self.credentials_provider = Some(provider);
+    }
+
+    /// Whether the secret is fetched from a provider.
+    #[inline]
+    pub fn has_credentials_provider(&self) -> bool {
+        self.credentials_provider.is_some()
+    }
+
+    /// Add a TOTP passcode as an additional authentication factor.
+    #[inline]
+    pub fn set_totp(&mut self, totp: TotpPasscode) {
+        self.totp = Some(totp);
+    }
+
+    /// URL to request tokens from.
+    #[cfg(test)]
+    #[inline]
+    pub fn token_endpoint(&self) -> &str {
+        &self.token_endpoint
+    }
+
+    /// Endpoint filters.
+    #[inline]
+    pub fn endpoint_filters(&self) -> &EndpointFilters {
+        &self.filters
+    }
+
+    /// Mutable endpoint filters.
+    #[inline]
+    pub fn endpoint_filters_mut(&mut self) -> &mut EndpointFilters {
+        &mut self.filters
+    }
+
+    /// Project name or ID (if project scoped).
+    #[inline]
+    pub fn project(&self) -> Option<&IdOrName> {
+        match self.body.auth.scope {
+            Some(protocol::Scope::Project(ref prj)) => Some(&prj.project),
+            _ => None,
+        }
+    }
+
+    /// Add a scope to the authentication.
+    pub fn set_scope(&mut self, scope: Scope) {
+        self.body.auth.scope = Some(match scope {
+            Scope::Project { project, domain } => {
+                protocol::Scope::Project(protocol::Project { project, domain })
+            }
+            Scope::Domain(domain) => protocol::Scope::Domain(domain),
+            Scope::System => protocol::Scope::System,
+            Scope::Trust(id) => protocol::Scope::Trust(protocol::Trust { id }),
+        });
+    }
+
+    /// Set a time out for authentication requests.
+    #[inline]
+    pub fn set_auth_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.auth_timeout = timeout;
+    }
+
+    /// Time out for authentication requests.
+    #[inline]
+    pub fn auth_timeout(&self) -> Option<std::time::Duration> {
+        self.auth_timeout
+    }
+
+    /// Set the cloud name reported to the auth hook.
+    #[inline]
+    pub fn set_cloud_name(&mut self, cloud_name: String) {
+        self.reporter.set_cloud_name(cloud_name);
+    }
+
+    /// Set the hook called after each authentication attempt.
+    #[inline]
+    pub fn set_auth_hook(&mut self, hook: AuthHook) {
+        self.reporter.set_hook(hook);
+    }
+
+    /// Report token requests to the metrics.
+    #[inline]
+    pub fn set_metrics(&self, metrics: Arc<dyn Metrics>) {
+        self.reporter.set_metrics(metrics);
+    }
+
+    /// Enable or disable version discovery of the Identity service.
+    #[inline]
+    pub fn set_version_discovery(&mut self, enabled: bool) {
+        self.version_discovery = enabled;
+    }
+
+    /// Set the maximum size of a token response in bytes.
+    #[inline]
+    pub fn set_max_response_size(&mut self, max_size: usize) {
+        self.max_response_size = max_size;
+    }
+
+    /// Share tokens with other processes via the cache (or stop doing so).
+    #[cfg(all(test, feature = "token-cache"))]
+    #[inline]
+    pub fn set_token_cache(&mut self, cache: Option<TokenCache>) {
+        self.token_cache = cache;
+    }
+
+    /// Share tokens with other processes via the cache in the default directory.
+    #[cfg(feature = "token-cache")]
+    pub fn enable_token_cache(&mut self, enabled: bool) {
+        self.token_cache = if enabled {
+            let cache = TokenCache::with_default_dir();
+            if cache.is_none() {
+                warn!("No cache directory found, tokens will not be cached");
+            }
+            cache
+        } else {
+            None
+        };
+    }
+
+    /// Key of the token in the cache.
+    ///
+    /// Tokens are shared between authentications with the same cloud, Identity URL, user and
+    /// scope. The key contains no secrets. Token authentication is never cached since the token
+    /// cannot be part of the key.
+    #[cfg(feature = "token-cache")]
+    fn cache_key(&self) -> Option<String> {
+        if let protocol::Identity::Token(..) = self.body.auth.identity {
+            return None;
+        }
+        // NOTE: the Debug output of the identity does not include secrets.
+        Some(format!(
+            "{}\n{}\n{:?}\n{:?}",
+            self.reporter.cloud_name().unwrap_or_default(),
+            self.auth_url,
+            self.body.auth.identity,
+            self.body.auth.scope
+        ))
+    }
+
+    /// Load a valid token from the cache (if enabled).
+    #[cfg(feature = "token-cache")]
+    fn load_cached_token(&self) -> Option<Token> {
+        let cache = self.token_cache.as_ref()?;
+        let key = self.cache_key()?;
+        let (value, raw) = cache.load(&key)?;
+        match token_from_raw(value, raw) {
+            Ok(token) if token_valid(&token, self.refresh_skew) => Some(token),
+            Ok(..) => {
+                debug!("Cached token expires soon, not using it");
+                None
+            }
+            Err(e) => {
+                warn!("Removing an invalid cached token: {}", e);
+                cache.remove(&key);
+                None
+            }
+        }
+    }
+
+    /// Store the token in the cache (if enabled).
+    #[cfg(feature = "token-cache")]
+    fn store_cached_token(&self, token: &Token, raw: &serde_json::Value) {
+        if let (Some(cache), Some(key)) = (self.token_cache.as_ref(), self.cache_key()) {
+            cache.store(&key, token.body.expires_at, &token.value, raw);
+        }
+    }
+
+    /// Remove the token from the cache (if enabled) unless it has been replaced meanwhile.
+    #[cfg(feature = "token-cache")]
+    fn remove_cached_token(&self, token: &Token) {
+        if let (Some(cache), Some(key)) = (self.token_cache.as_ref(), self.cache_key()) {
+            if let Some((value, _)) = cache.load(&key) {
+                if value == token.value {
+                    cache.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Set how long before its expiration the token is refreshed.
+    pub fn set_token_refresh_skew(&mut self, skew: std::time::Duration) -> Result<(), Error> {
+        self.refresh_skew = Duration::from_std(skew)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+        Ok(())
+    }
+
+    /// Time before expiration when the token is refreshed.
+    #[inline]
+    pub fn token_refresh_skew(&self) -> std::time::Duration {
+        // The skew is always created from a valid std::time::Duration.
+        self.refresh_skew.to_std().unwrap()
+    }
+
+    /// Refresh the token if it is missing or expires soon (or unconditionally if `force`).
+    ///
+    /// Concurrent calls result in only one authentication request: the first caller holds the
+    /// write lock while authenticating, the others wait for it and reuse the new token.
+    pub async fn do_refresh(&self, force: bool) -> Result<(), Error> {
+        // This is executed every request at least once, so it's important to start with a read
+        // lock. We expect to hit this branch most of the time.
+        let generation = self.token_generation.load(Ordering::SeqCst);
+        if !force && token_alive(&self.cached_token.read().await, self.refresh_skew) {
+            return Ok(());
+        }
+
+        let mut lock = self.cached_token.write().await;
+        // Additonal check in case another thread has updated the token while we were waiting for
+        // the write lock. A forced refresh is also satisfied by a token received meanwhile.
+        let replaced = self.token_generation.load(Ordering::SeqCst) != generation;
+        if (replaced || !force) && token_alive(&lock, self.refresh_skew) {
+            return Ok(());
+        }
+
+        // Another process may have received a token meanwhile.
+        #[cfg(feature = "token-cache")]
+        {
+            if !force {
+                if let Some(token) = self.load_cached_token() {
+                    *lock = Some(token);
+                    let _ = self.token_generation.fetch_add(1, Ordering::SeqCst);
+                    return Ok(());
+                }
+            }
+        }
+
+        let result = match lock.as_ref().and_then(|t| self.catalog_token_endpoint(t)) {
+            Some(endpoint) => match self.authenticate_at(&endpoint).await {
+                Err(e) if e.kind() == ErrorKind::Transport => {
+                    let fallback = self.get_token_endpoint().await;
+                    warn!(
+                        "Token endpoint {} from the catalog is not reachable, using {}: {}",
+                        endpoint, fallback, e
+                    );
+                    self.authenticate_at(&fallback).await
+                }
+                result => result,
+            },
+            None => self.authenticate_at(&self.get_token_endpoint().await).await,
+        };
+        *lock = Some(result?);
+        let _ = self.token_generation.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Authenticate at the token endpoint, reporting the result.
+    async fn authenticate_at(&self, token_endpoint: &str) -> Result<Token, Error> {
+        let started = Instant::now();
+        let result = telemetry::authentication(&self.auth_url, token_endpoint)
+            .run(self.fetch_token(token_endpoint))
+            .await;
+        let request_id = match result {
+            Ok((_, ref request_id)) => request_id.clone(),
+            Err(ref e) => e.request_id().map(ToString::to_string),
+        };
+        debug!(
+            "Authentication at {} finished with request ID {:?}",
+            token_endpoint, request_id
+        );
+        self.reporter
+            .report(&self.auth_url, started, result.is_ok(), request_id);
+        result.map(|(token, _)| token)
+    }
+
+    /// Request a new token, return it with the request ID (if any).
+    async fn fetch_token(&self, token_endpoint: &str) -> Result<(Token, Option<String>), Error> {
+        let body = self.auth_body().await?;
+        let mut resp = self.send_auth_request(token_endpoint, &body, None).await?;
+        if resp.status() == StatusCode::UNAUTHORIZED && resp.headers().contains_key(RECEIPT_HEADER)
+        {
+            resp = self
+                .continue_with_receipt(token_endpoint, &body, resp)
+                .await?;
+        }
+        let resp = request::check(resp).await?;
+        let request_id = request::request_id(&resp);
+        let with_request_id = |e: Error| match request_id {
+            Some(ref request_id) => e.with_request_id(request_id.clone()),
+            None => e,
+        };
+        let (value, raw) = read_token_response(resp, self.max_response_size)
+            .await
+            .map_err(with_request_id)?;
+        #[cfg(feature = "token-cache")]
+        let cached_raw = self.token_cache.as_ref().map(|_| raw.clone());
+        let token = token_from_raw(value, raw).map_err(with_request_id)?;
+        #[cfg(feature = "token-cache")]
+        {
+            if let Some(raw) = cached_raw {
+                self.store_cached_token(&token, &raw);
+            }
+        }
+        Ok((token, request_id))
+    }
+
+    /// Send an authentication request (optionally with a receipt).
+    async fn send_auth_request(
+        &self,
+        token_endpoint: &str,
+        body: &protocol::AuthRoot,
+        receipt: Option<&str>,
+    ) -> Result<Response, Error> {
+        let mut builder = self.client.post(token_endpoint).json(body);
+        if let Some(receipt) = receipt {
+            builder = builder.header(RECEIPT_HEADER, receipt);
+        }
+        if let Some(timeout) = self.auth_timeout {
+            builder = builder.timeout(timeout);
+        }
+        Ok(builder.send().await?)
+    }
+
+    /// Continue a multi-factor authentication with the receipt from the response.
+    ///
+    /// Only a TOTP passcode from a provider can be added at this point, a static passcode has
+    /// already been sent with the first request.
+    async fn continue_with_receipt(
+        &self,
+        token_endpoint: &str,
+        body: &protocol::AuthRoot,
+        resp: Response,
+    ) -> Result<Response, Error> {
+        let receipt = resp
+            .headers()
+            .get(RECEIPT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidResponse, INVALID_RECEIPT_HEADER))?;
+        let root: protocol::ReceiptRoot = resp.json().await?;
+        let missing = root.missing_methods();
+        debug!(
+            "Received an authentication receipt, methods {:?} are still required",
+            missing
+        );
+        let provider = match self.totp {
+            Some(TotpPasscode::Provider(ref provider)) if missing == ["totp"] => provider,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::Authentication,
+                    format!(
+                        "Additional authentication methods are required: {}",
+                        missing.join(", ")
+                    ),
+                ))
+            }
+        };
+        let mut body = body.clone();
+        body.auth.identity = totp_identity(&body.auth.identity, provider.get_secret().await?)?;
+        self.send_auth_request(token_endpoint, &body, Some(&receipt))
+            .await
+    }
+
+    /// Request body for authentication.
+    ///
+    /// The token is re-read from the file and the secret is fetched from the credentials
+    /// provider if needed. A static TOTP passcode is added as a second method.
+    async fn auth_body(&self) -> Result<Cow<'_, protocol::AuthRoot>, Error> {
+        let mut body = Cow::Borrowed(&self.body);
+        if let Some(ref path) = self.token_file {
+            let path = path.clone();
+            let token = super::run_blocking(move || read_token_file(&path)).await?;
+            body.to_mut().auth.identity = protocol::Identity::Token(token);
+        } else if let Some(ref provider) = self.credentials_provider {
+            let secret = provider.get_secret().await?;
+            body.to_mut().auth.identity.set_secret(secret);
+        }
+
+        if let Some(TotpPasscode::Static(ref passcode)) = self.totp {
+            let identity = &mut body.to_mut().auth.identity;
+            let totp = totp_identity(identity, passcode.clone())?;
+            *identity = protocol::Identity::MultiFactor(vec![identity.clone(), totp]);
+        }
+        Ok(body)
+    }
+
+    /// Token endpoint from the catalog of the previous token.
+    ///
+    /// Only used when a non-default interface is selected, so that re-authentication goes to
+    /// the identity endpoint of that interface rather than to the initial authentication URL.
+    /// The credentials are sent to this endpoint, callers fall back to the authentication URL
+    /// if it cannot be reached.
+    fn catalog_token_endpoint(&self, previous: &Token) -> Option<String> {
+        if self.filters.interfaces == ValidInterfaces::default() {
+            return None;
+        }
+
+        match self
+            .filters
+            .find_in_catalog(&previous.body.catalog, "identity")
+        {
+            Ok(endpoint) => {
+                let token_endpoint = token_endpoint_for(&endpoint);
+                debug!(
+                    "Using token endpoint {} from the catalog for interfaces {:?}",
+                    token_endpoint, self.filters.interfaces
+                );
+                Some(token_endpoint)
+            }
+            Err(e) => {
+                debug!(
+                    "No identity endpoint in the catalog for interfaces {:?}: {}",
+                    self.filters.interfaces, e
+                );
+                None
+            }
+        }
+    }
+
+    /// URL to request tokens from, discovering it if enabled.
+    async fn get_token_endpoint(&self) -> String {
+        if !self.version_discovery {
+            return self.token_endpoint.clone();
+        }
+
+        if let Some(ref endpoint) = *self.discovered_endpoint.read().await {
+            return endpoint.clone();
+        }
+
+        let mut lock = self.discovered_endpoint.write().await;
+        // Additonal check in case another thread has updated the endpoint while we were waiting
+        // for the write lock.
+        if let Some(ref endpoint) = *lock {
+            return endpoint.clone();
+        }
+
+        match self.discover_token_endpoint().await {
+            Ok(endpoint) => {
+                debug!("Discovered token endpoint {}", endpoint);
+                *lock = Some(endpoint.clone());
+                endpoint
+            }
+            Err(e) => {
+                // NOTE: the fallback is not cached, discovery is retried on the next
+                // authentication in case the failure was temporary.
+                warn!(
+                    "Identity version discovery at {} failed, using {}: {}",
+                    self.auth_url, self.token_endpoint, e
+                );
+                self.token_endpoint.clone()
+            }
+        }
+    }
+
+    /// Find the token endpoint of a stable Identity API v3 from the version document.
+    async fn discover_token_endpoint(&self) -> Result<String, Error> {
+        let mut builder = self.client.get(self.auth_url.clone());
+        if let Some(timeout) = self.auth_timeout {
+            builder = builder.timeout(timeout);
+        }
+        let root: protocol::VersionsRoot = request::fetch_json(builder).await?;
+        let version = root
+            .into_versions()
+            .into_iter()
+            .filter(|v| v.id.0 == 3 && v.is_stable())
+            .max()
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::EndpointNotFound,
+                    "Identity API v3 is not available",
+                )
+            })?;
+        let link = version
+            .links
+            .into_iter()
+            .find(|link| link.rel == "self")
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidResponse,
+                    "Invalid version - missing self link",
+                )
+            })?;
+        Ok(url::extend(link.href, &["auth", "tokens"]).to_string())
+    }
+
+    /// Revoke the current token (if any) and forget it.
+    ///
+    /// The token is also removed from the token cache. Nothing is done if no token has been
+    /// received yet. A token that the Identity service no longer knows is not an error.
+    pub async fn revoke(&self) -> Result<(), Error> {
+        let token = match self.cached_token.write().await.take() {
+            Some(token) => token,
+            None => return Ok(()),
+        };
+        let _ = self.token_generation.fetch_add(1, Ordering::SeqCst);
+        #[cfg(feature = "token-cache")]
+        self.remove_cached_token(&token);
+
+        let result = match self.catalog_token_endpoint(&token) {
+            Some(endpoint) => match self.revoke_at(&endpoint, &token).await {
+                Err(e) if e.kind() == ErrorKind::Transport => {
+                    let fallback = self.get_token_endpoint().await;
+                    warn!(
+                        "Token endpoint {} from the catalog is not reachable, using {}: {}",
+                        endpoint, fallback, e
+                    );
+                    self.revoke_at(&fallback, &token).await
+                }
+                result => result,
+            },
+            None => {
+                self.revoke_at(&self.get_token_endpoint().await, &token)
+                    .await
+            }
+        };
+        match result {
+            Err(e) if e.kind() == ErrorKind::ResourceNotFound => {
+                debug!("The token has already been revoked or has expired");
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Revoke the token at the token endpoint.
+    async fn revoke_at(&self, token_endpoint: &str, token: &Token) -> Result<(), Error> {
+        debug!("Revoking the token at {}", token_endpoint);
+        let mut builder = self
+            .client
+            .delete(token_endpoint)
+            .header("x-auth-token", &token.value)
+            .header("x-subject-token", &token.value);
+        if let Some(timeout) = self.auth_timeout {
+            builder = builder.timeout(timeout);
+        }
+        let _ = request::send_checked(builder).await?;
+        Ok(())
+    }
+
+    /// Get a valid token, refreshing it if needed.
+    #[inline]
+    pub async fn get_token(&self) -> Result<String, Error> {
+        self.do_refresh(false).await?;
+        // unwrap is safe because do_refresh unconditionally populates the token
+        Ok(self
+            .cached_token
+            .read()
+            .await
+            .as_ref()
+            .unwrap()
+            .value
+            .clone())
+    }
+
+    /// Create an authenticated request.
+    pub async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+        let token = self.get_token().await?;
+        Ok(self
+            .client
+            .request(method, url)
+            .header("x-auth-token", token))
+    }
+
+    /// The service catalog received with the token.
+    pub async fn raw_catalog(&self) -> Result<serde_json::Value, Error> {
+        self.do_refresh(false).await?;
+        let lock = self.cached_token.read().await;
+        // unwrap is safe because do_refresh unconditionally populates the token
+        Ok(lock.as_ref().unwrap().raw_catalog.clone())
+    }
+
+    /// Information about the current token.
+    pub async fn token_info(&self) -> Result<TokenInfo, Error> {
+        self.do_refresh(false).await?;
+        let lock = self.cached_token.read().await;
+        // unwrap is safe because do_refresh unconditionally populates the token
+        Ok(lock.as_ref().unwrap().info.clone())
+    }
+
+    /// Get a URL for the requested service.
+    pub async fn get_endpoint(
+        &self,
+        service_type: String,
+        filters: EndpointFilters,
+    ) -> Result<Url, Error> {
+        let real_filters = filters.with_defaults(&self.filters);
+        debug!(
+            "Requesting a catalog endpoint for service '{}', filters {:?}",
+            service_type, real_filters
+        );
+        self.do_refresh(false).await?;
+        let lock = self.cached_token.read().await;
+        // unwrap is safe because do_refresh unconditionally populates the token
+        real_filters.find_in_catalog(&lock.as_ref().unwrap().body.catalog, &service_type)
+    }
+}
+
+/// Read an authentication token from a file, removing surrounding whitespace.
+fn read_token_file(path: &Path) -> Result<String, Error> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidConfig,
+            format!("Cannot read token file {}: {}", path.display(), e),
+        )
+    })?;
+    let token = content.trim();
+    if token.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidConfig,
+            format!("Token file {} is empty", path.display()),
+        ));
+    }
+    Ok(token.to_string())
+}
+
+/// TOTP identity for the user of the given identity.
+fn totp_identity(
+    identity: &protocol::Identity,
+    passcode: String,
+) -> Result<protocol::Identity, Error> {
+    match identity {
+        protocol::Identity::Password(ref pw) => {
+            Ok(protocol::Identity::Totp(protocol::UserAndPasscode {
+                user: pw.user.clone(),
+                domain: pw.domain.clone(),
+                passcode,
+            }))
+        }
+        protocol::Identity::Totp(ref totp) => {
+            Ok(protocol::Identity::Totp(protocol::UserAndPasscode {
+                passcode,
+                ..totp.clone()
+            }))
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidConfig,
+            "A TOTP passcode can only be added to password authentication",
+        )),
+    }
+}
+
+/// Token endpoint for the given Identity URL.
+#[inline]
+pub(crate) fn token_endpoint_for(identity_url: &Url) -> String {
+    auth_endpoint_for(identity_url, "tokens")
+}
+
+/// Endpoint of the `/v3/auth` resource (e.g. `tokens`) for the given Identity URL.
+#[inline]
+pub(crate) fn auth_endpoint_for(identity_url: &Url, resource: &str) -> String {
+    v3_endpoint_for(identity_url, &["auth", resource])
+}
+
+/// Endpoint of the Identity API v3 with the given path segments for the given Identity URL.
+pub(crate) fn v3_endpoint_for(identity_url: &Url, segments: &[&str]) -> String {
+    // NOTE: only the path is modified, a query (used by some API gateways) is preserved.
+    let mut endpoint = identity_url.clone();
+    if !endpoint.path().trim_end_matches('/').ends_with("/v3") {
+        endpoint = url::extend(endpoint, &["v3"]);
+    }
+    url::extend(endpoint, segments).to_string()
+}
+
+#[inline]
+fn token_alive(token: &impl Deref<Target = Option<Token>>, skew: Duration) -> bool {
+    token
+        .deref()
+        .as_ref()
+        .map_or(false, |value| token_valid(value, skew))
+}
+
+/// Whether the token is valid for longer than `skew`.
+fn token_valid(token: &Token, skew: Duration) -> bool {
+    let validity_time_left = token.body.expires_at.signed_duration_since(Local::now());
+    trace!("Token is valid for {:?}", validity_time_left);
+    validity_time_left > skew
+}
+
+/// Read the response body, failing if it is larger than `max_size`.
+pub(crate) async fn read_limited(mut resp: Response, max_size: usize) -> Result<Vec<u8>, Error> {
+    let too_large = || {
+        Error::new(
+            ErrorKind::InvalidResponse,
+            format!("Token response is larger than {} bytes", max_size),
+        )
+    };
+    if resp
+        .content_length()
+        .map_or(false, |len| len > max_size as u64)
+    {
+        return Err(too_large());
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        if body.len() + chunk.len() > max_size {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Read the token value and the parsed body from the response.
+async fn read_token_response(
+    resp: Response,
+    max_size: usize,
+) -> Result<(String, serde_json::Value), Error> {
+    let value = match resp.headers().get("x-subject-token") {
+        Some(hdr) => match hdr.to_str() {
+            Ok(s) => Ok(s.to_string()),
+            Err(e) => {
+                error!(
+                    "Invalid X-Subject-Token {:?} received from {}: {}",
+                    hdr,
+                    resp.url(),
+                    e
+                );
+                Err(Error::new(
+                    ErrorKind::InvalidResponse,
+                    INVALID_SUBJECT_HEADER,
+                ))
+            }
+        },
+        None => {
+            error!("No X-Subject-Token header received from {}", resp.url());
+            // NOTE: there is no point in reading the body: without the header there is no token.
+            Err(Error::new(
+                ErrorKind::InvalidResponse,
+                format!(
+                    "{} in the {} response from {}, it may have been stripped by a proxy",
+                    MISSING_SUBJECT_HEADER,
+                    resp.status(),
+                    resp.url()
+                ),
+            ))
+        }
+    }?;
+
+    let body = read_limited(resp, max_size).await?;
+    let raw = serde_json::from_slice::<serde_json::Value>(&body).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidResponse,
+            format!("Invalid token received: {}", e),
+        )
+    })?;
+    Ok((value, raw))
+}
+
+/// Create a token from its value and the body received from the Identity service.
+fn token_from_raw(value: String, mut raw: serde_json::Value) -> Result<Token, Error> {
+    if let Some(token) = raw.get_mut("token").and_then(|t| t.as_object_mut()) {
+        // Unscoped tokens have neither roles nor a catalog.
+        for key in &["roles", "catalog"] {
+            let _ = token
+                .entry(*key)
+                .or_insert_with(|| serde_json::Value::Array(Vec::new()));
+        }
+    }
+    let raw_catalog = raw
+        .pointer("/token/catalog")
+        .cloned()
+        .unwrap_or_else(|| serde_json::Value::Array(Vec::new()));
+    let invalid_token = |e: serde_json::Error| {
+        Error::new(
+            ErrorKind::InvalidResponse,
+            format!("Invalid token received: {}", e),
+        )
+    };
+    let info = raw
+        .get("token")
+        .cloned()
+        .map(serde_json::from_value::<TokenInfo>)
+        .transpose()
+        .map_err(invalid_token)?;
+    let root = serde_json::from_value::<protocol::TokenRoot>(raw).map_err(invalid_token)?;
+    debug!("Received a token expiring at {}", root.token.expires_at);
+    trace!("Received catalog: {:?}", root.token.catalog);
+    Ok(Token {
+        value,
+        // The token body has been validated above.
+        info: info.unwrap(),
+        body: root.token,
+        raw_catalog,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use chrono::{Duration, Local};
+    use osproto::identity::{CatalogRecord, Endpoint};
+    use reqwest::{Method, StatusCode, Url};
+
+    use super::super::super::test::{token_mock, Mock, MockServer, TOKENS_PATH, TOKEN_BODY};
+    use super::super::super::{request, Error, ErrorKind, InterfaceType};
+    use super::{
+        auth_endpoint_for, protocol, token_alive, token_endpoint_for, token_from_raw, AuthHook,
+        CredentialsProvider, IdOrName, Internal, Scope, Token, TokenInfo, TotpPasscode,
+    };
+
+    fn token_expiring_in(validity: Duration) -> Option<Token> {
+        token_with_catalog(validity, Vec::new())
+    }
+
+    fn token_with_catalog(validity: Duration, catalog: Vec<CatalogRecord>) -> Option<Token> {
+        let expires_at = (Local::now() + validity).into();
+        Some(Token {
+            value: "abcdef".to_string(),
+            body: protocol::Token {
+                roles: Vec::new(),
+                expires_at,
+                catalog,
+            },
+            raw_catalog: serde_json::Value::Array(Vec::new()),
+            info: TokenInfo {
+                issued_at: None,
+                expires_at,
+                audit_ids: Vec::new(),
+                methods: Vec::new(),
+                application_credential: None,
+                user: None,
+                project: None,
+                domain: None,
+                roles: Vec::new(),
+            },
+        })
+    }
+
+    #[test]
+    fn test_token_info_parse() {
+        let info: TokenInfo = serde_json::from_str(
+            r#"{
+                "issued_at": "2020-01-20T12:00:00.000000Z",
+                "expires_at": "2020-01-20T13:00:00.000000Z",
+                "audit_ids": ["3T2dc1CGQxyJsHdDu1xkcw"],
+                "methods": ["password"],
+                "user": {"id": "ee4dfb6e5540447cb3741905149d9b6e", "name": "admin",
+                         "domain": {"id": "default", "name": "Default"}},
+                "project": {"id": "a6944d763bf64ee6a275f1263fae0352", "name": "admin"},
+                "roles": [{"id": "51cc68287d524c759f47c811e6463340", "name": "admin"},
+                          {"id": "9fe2ff9ee4384b1894a90878d3e92bab", "name": "member"}],
+                "catalog": []
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(info.audit_ids, vec!["3T2dc1CGQxyJsHdDu1xkcw".to_string()]);
+        assert_eq!(info.methods, vec!["password".to_string()]);
+        assert!(info.access_rules().is_empty());
+        assert_eq!(info.user.as_ref().unwrap().name.as_deref(), Some("admin"));
+        assert_eq!(
+            info.project.as_ref().unwrap().id,
+            "a6944d763bf64ee6a275f1263fae0352"
+        );
+        assert!(info.domain.is_none());
+        assert_eq!(info.role_names(), vec!["admin", "member"]);
+        assert!(info.has_role("Admin"));
+        assert!(!info.has_role("reader"));
+        assert_eq!(
+            info.expires_at
+                .signed_duration_since(info.issued_at.unwrap()),
+            Duration::hours(1)
+        );
+    }
+
+    #[test]
+    fn test_token_info_access_rules() {
+        let info: TokenInfo = serde_json::from_str(
+            r#"{
+                "expires_at": "2020-01-20T13:00:00.000000Z",
+                "methods": ["application_credential"],
+                "application_credential": {
+                    "id": "423f19a4ac1e4f48bbb4180756e6eb6c",
+                    "name": "monitoring",
+                    "restricted": true,
+                    "access_rules": [
+                        {"id": "abcdef", "method": "GET", "path": "/v2.1/servers",
+                         "service": "compute"}
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+        let rules = info.access_rules();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].method, "GET");
+        assert_eq!(rules[0].path, "/v2.1/servers");
+        assert_eq!(rules[0].service, "compute");
+    }
+
+    #[test]
+    fn test_token_from_raw_unscoped() {
+        let token = token_from_raw(
+            "abcdef".to_string(),
+            serde_json::json!({"token": {
+                "expires_at": "2020-01-20T13:00:00.000000Z",
+                "methods": ["password"],
+                "user": {"id": "1234", "name": "admin"}
+            }}),
+        )
+        .unwrap();
+        assert!(token.body.catalog.is_empty());
+        assert!(token.body.roles.is_empty());
+        assert_eq!(token.raw_catalog, serde_json::json!([]));
+        assert_eq!(token.info.methods, vec!["password".to_string()]);
+    }
+
+    #[test]
+    fn test_token_alive() {
+        let token = token_expiring_in(Duration::minutes(5));
+        assert!(!token_alive(&&token, Duration::minutes(10)));
+        assert!(token_alive(&&token, Duration::seconds(30)));
+        let token = token_expiring_in(Duration::seconds(10));
+        assert!(!token_alive(&&token, Duration::seconds(30)));
+        assert!(!token_alive(&&None, Duration::seconds(30)));
+    }
+
+    /// Mock server answering to the token API once with the status, headers and body.
+    fn serve_token_once(status: StatusCode, headers: &[(&str, &str)], body: String) -> MockServer {
+        let server = MockServer::start();
+        let mock = headers.iter().fold(
+            Mock::new(Method::POST, TOKENS_PATH)
+                .with_status(status)
+                .with_header("content-type", "application/json")
+                .with_body(body)
+                .with_times(1),
+            |mock, (name, value)| mock.with_header(*name, *value),
+        );
+        server.mock(mock);
+        server
+    }
+
+    fn internal_with_discovery(auth_url: Url) -> Internal {
+        let mut internal = Internal::new(
+            request::default_client(),
+            auth_url,
+            protocol::Identity::Token("abcdef".to_string()),
+        );
+        internal.set_version_discovery(true);
+        internal
+    }
+
+    #[tokio::test]
+    async fn test_version_discovery() {
+        let body = r#"{"versions": {"values": [
+            {"id": "v2.0", "status": "deprecated",
+             "links": [{"rel": "self", "href": "http://127.0.0.1/identity/v2.0/"}]},
+            {"id": "v3.14", "status": "stable",
+             "links": [{"rel": "self", "href": "http://127.0.0.1/identity/api/v3/"}]}
+        ]}}"#;
+        let server = MockServer::start();
+        server.mock(Mock::new(Method::GET, "/identity").with_body(body));
+        let internal = internal_with_discovery(server.identity_url());
+        assert_eq!(
+            internal.get_token_endpoint().await,
+            "http://127.0.0.1/identity/api/v3/auth/tokens"
+        );
+        // The result is cached.
+        assert_eq!(
+            internal.get_token_endpoint().await,
+            "http://127.0.0.1/identity/api/v3/auth/tokens"
+        );
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_version_discovery_fallback() {
+        let server = MockServer::start();
+        let url = server.identity_url();
+        let internal = internal_with_discovery(url.clone());
+        assert_eq!(
+            internal.get_token_endpoint().await,
+            format!("{}/v3/auth/tokens", url)
+        );
+
+        // The fallback is not cached.
+        let body = r#"{"versions": {"values": [
+            {"id": "v3.14", "status": "stable",
+             "links": [{"rel": "self", "href": "http://127.0.0.1/identity/api/v3/"}]}
+        ]}}"#;
+        server.mock(Mock::new(Method::GET, "/identity").with_body(body));
+        assert_eq!(
+            internal.get_token_endpoint().await,
+            "http://127.0.0.1/identity/api/v3/auth/tokens"
+        );
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_auth_hook() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = events.clone();
+        let server = serve_token_once(
+            StatusCode::UNAUTHORIZED,
+            &[("x-openstack-request-id", "req-auth")],
+            "{}".to_string(),
+        );
+        let mut internal = Internal::new(
+            request::default_client(),
+            server.identity_url(),
+            protocol::Identity::Token("abcdef".to_string()),
+        );
+        internal.set_cloud_name("test-auth-hook".to_string());
+        internal.set_auth_hook(AuthHook::new(move |event| {
+            events_clone.lock().unwrap().push((
+                event.cloud_name.clone(),
+                event.success,
+                event.request_id.clone(),
+            ));
+        }));
+        let err = internal.do_refresh(true).await.err().unwrap();
+        assert_eq!(err.request_id(), Some("req-auth"));
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![(
+                Some("test-auth-hook".to_string()),
+                false,
+                Some("req-auth".to_string())
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_subject_token() {
+        let body = r#"{"token": {"expires_at": "2100-01-01T00:00:00Z"}}"#.to_string();
+        let server = serve_token_once(StatusCode::CREATED, &[], body);
+        let internal = Internal::new(
+            request::default_client(),
+            server.identity_url(),
+            protocol::Identity::Token("abcdef".to_string()),
+        );
+        let err = internal.do_refresh(true).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidResponse);
+        assert!(err.to_string().contains("Missing X-Subject-Token header"));
+        assert!(internal.cached_token.read().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_max_response_size() {
+        let body = format!(
+            r#"{{"token": {{"expires_at": "2100-01-01T00:00:00Z", "padding": "{}"}}}}"#,
+            "x".repeat(1024)
+        );
+        let server = serve_token_once(StatusCode::CREATED, &[("x-subject-token", "abcdef")], body);
+        let mut internal = Internal::new(
+            request::default_client(),
+            server.identity_url(),
+            protocol::Identity::Token("abcdef".to_string()),
+        );
+        internal.set_max_response_size(512);
+        let err = internal.do_refresh(true).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidResponse);
+        assert!(err.to_string().contains("larger than 512 bytes"));
+    }
+
+    #[test]
+    fn test_token_endpoint_for() {
+        let cases = [
+            (
+                "http://127.0.0.1/identity",
+                "http://127.0.0.1/identity/v3/auth/tokens",
+            ),
+            (
+                "http://127.0.0.1/identity/",
+                "http://127.0.0.1/identity/v3/auth/tokens",
+            ),
+            (
+                "http://127.0.0.1:5000/v3",
+                "http://127.0.0.1:5000/v3/auth/tokens",
+            ),
+            (
+                "http://127.0.0.1:5000/v3/",
+                "http://127.0.0.1:5000/v3/auth/tokens",
+            ),
+            (
+                "https://host/v3?routing=keystone",
+                "https://host/v3/auth/tokens?routing=keystone",
+            ),
+            (
+                "https://host/identity/?routing=keystone",
+                "https://host/identity/v3/auth/tokens?routing=keystone",
+            ),
+        ];
+        for (url, expected) in cases.iter() {
+            assert_eq!(token_endpoint_for(&Url::parse(url).unwrap()), *expected);
+        }
+        assert_eq!(
+            auth_endpoint_for(&Url::parse("http://127.0.0.1/v3/").unwrap(), "projects"),
+            "http://127.0.0.1/v3/auth/projects"
+        );
+    }
+
+    fn identity_catalog(public: &str, admin: &str) -> Vec<CatalogRecord> {
+        let endpoint = |interface: &str, url: &str| Endpoint {
+            interface: interface.to_string(),
+            region: "RegionOne".to_string(),
+            url: url.to_string(),
+        };
+        vec![CatalogRecord {
+            service_type: "identity".to_string(),
+            endpoints: vec![endpoint("public", public), endpoint("admin", admin)],
+        }]
+    }
+
+    #[test]
+    fn test_catalog_token_endpoint() {
+        let mut internal = Internal::new(
+            request::default_client(),
+            Url::parse("http://127.0.0.1/identity").unwrap(),
+            protocol::Identity::Token("abcdef".to_string()),
+        );
+        let token = token_with_catalog(
+            Duration::hours(1),
+            identity_catalog("http://public/identity", "http://admin/identity/v3/"),
+        )
+        .unwrap();
+        // The authentication URL is used with the default interface.
+        assert_eq!(internal.catalog_token_endpoint(&token), None);
+
+        internal
+            .endpoint_filters_mut()
+            .set_interfaces(InterfaceType::Admin);
+        assert_eq!(
+            internal.catalog_token_endpoint(&token).as_deref(),
+            Some("http://admin/identity/v3/auth/tokens")
+        );
+
+        internal
+            .endpoint_filters_mut()
+            .set_interfaces(InterfaceType::Internal);
+        assert_eq!(internal.catalog_token_endpoint(&token), None);
+    }
+
+    #[tokio::test]
+    async fn test_reauthenticate_with_interface() {
+        let server = MockServer::start();
+        server.mock(token_mock("fedcba"));
+        // Nothing listens on the authentication URL and the public endpoint.
+        let mut internal = Internal::new(
+            request::default_client(),
+            Url::parse("http://127.0.0.1:1/identity").unwrap(),
+            protocol::Identity::Token("abcdef".to_string()),
+        );
+        internal
+            .endpoint_filters_mut()
+            .set_interfaces(InterfaceType::Admin);
+        *internal.cached_token.write().await = token_with_catalog(
+            Duration::minutes(1),
+            identity_catalog("http://127.0.0.1:1/public", server.identity_url().as_str()),
+        );
+
+        internal.do_refresh(false).await.unwrap();
+        assert_eq!(internal.get_token().await.unwrap(), "fedcba");
+    }
+
+    #[tokio::test]
+    async fn test_reauthenticate_with_interface_unreachable() {
+        let server = MockServer::start();
+        server.mock(token_mock("fedcba"));
+        server.mock(Mock::new(Method::DELETE, TOKENS_PATH).with_status(StatusCode::NO_CONTENT));
+        let mut internal = Internal::new(
+            request::default_client(),
+            server.identity_url(),
+            protocol::Identity::Token("abcdef".to_string()),
+        );
+        internal
+            .endpoint_filters_mut()
+            .set_interfaces(InterfaceType::Admin);
+        // Nothing listens on the endpoints from the catalog.
+        let catalog = identity_catalog("http://127.0.0.1:1/public", "http://127.0.0.1:1/admin");
+        *internal.cached_token.write().await =
+            token_with_catalog(Duration::minutes(1), catalog.clone());
+
+        internal.do_refresh(false).await.unwrap();
+        assert_eq!(internal.get_token().await.unwrap(), "fedcba");
+
+        *internal.cached_token.write().await = token_with_catalog(Duration::hours(1), catalog);
+        internal.revoke().await.unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].method, Method::DELETE);
+    }
+
+    /// Mock server issuing tokens `token-1`, `token-2`, etc.
+    fn serve_tokens() -> MockServer {
+        let server = MockServer::start();
+        for number in 1..=3 {
+            server.mock(token_mock(&format!("token-{}", number)));
+        }
+        server
+    }
+
+    /// Mock server issuing a token on `/redirected` and redirecting token requests to `target`
+    /// (or to themselves).
+    fn serve_redirects(target: Option<&str>) -> MockServer {
+        let server = MockServer::start();
+        let location = target.map_or_else(
+            || server.url().join(TOKENS_PATH).unwrap().to_string(),
+            String::from,
+        );
+        server.mock(
+            Mock::new(Method::POST, TOKENS_PATH)
+                .with_status(StatusCode::TEMPORARY_REDIRECT)
+                .with_header("location", location),
+        );
+        server.mock(
+            Mock::new(Method::POST, "/redirected")
+                .with_status(StatusCode::CREATED)
+                .with_header("content-type", "application/json")
+                .with_header("x-subject-token", "redirected")
+                .with_body(TOKEN_BODY),
+        );
+        server
+    }
+
+    #[tokio::test]
+    async fn test_redirect_followed() {
+        let server = serve_redirects(Some("/redirected"));
+        let internal = Internal::new(
+            request::default_client(),
+            server.identity_url(),
+            protocol::Identity::Token("abcdef".to_string()),
+        );
+        assert_eq!(internal.get_token().await.unwrap(), "redirected");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_loop() {
+        let server = serve_redirects(None);
+        let internal = Internal::new(
+            request::default_client(),
+            server.identity_url(),
+            protocol::Identity::Token("abcdef".to_string()),
+        );
+        let err = internal.get_token().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Protocol);
+        assert!(err.to_string().contains("Too many redirects"), "{}", err);
+        assert_eq!(server.requests().len(), request::DEFAULT_MAX_REDIRECTS + 1);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_other_host() {
+        let server = serve_redirects(Some("http://localhost:1/redirected"));
+        let internal = Internal::new(
+            request::default_client(),
+            server.identity_url(),
+            protocol::Identity::Token("abcdef".to_string()),
+        );
+        let err = internal.get_token().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Protocol);
+        assert!(err.to_string().contains("different host"), "{}", err);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_first_use() {
+        let server = serve_tokens();
+        let internal = Internal::new(
+            request::default_client(),
+            server.identity_url(),
+            protocol::Identity::Token("abcdef".to_string()),
+        );
+        let (t1, t2, t3, t4) = tokio::join!(
+            internal.get_token(),
+            internal.get_token(),
+            internal.get_token(),
+            internal.get_token()
+        );
+        for token in &[t1, t2, t3, t4] {
+            assert_eq!(token.as_ref().unwrap(), "token-1");
+        }
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_forced_refresh() {
+        let server = serve_tokens();
+        let internal = Internal::new(
+            request::default_client(),
+            server.identity_url(),
+            protocol::Identity::Token("abcdef".to_string()),
+        );
+        assert_eq!(internal.get_token().await.unwrap(), "token-1");
+
+        let (r1, r2, r3) = tokio::join!(
+            internal.do_refresh(true),
+            internal.do_refresh(true),
+            internal.do_refresh(true)
+        );
+        r1.unwrap();
+        r2.unwrap();
+        r3.unwrap();
+        assert_eq!(internal.get_token().await.unwrap(), "token-2");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_project_with_domain_body() {
+        let id = |x: &str| IdOrName::Id(x.to_string());
+        let name = |x: &str| IdOrName::Name(x.to_string());
+        let cases = vec![
+            (
+                id("p1"),
+                id("d1"),
+                r#"{"project": {"id": "p1", "domain": {"id": "d1"}}}"#,
+            ),
+            (
+                id("p1"),
+                name("Default"),
+                r#"{"project": {"id": "p1", "domain": {"name": "Default"}}}"#,
+            ),
+            (
+                name("project1"),
+                id("d1"),
+                r#"{"project": {"name": "project1", "domain": {"id": "d1"}}}"#,
+            ),
+            (
+                name("project1"),
+                name("Default"),
+                r#"{"project": {"name": "project1", "domain": {"name": "Default"}}}"#,
+            ),
+        ];
+        for (project, domain, expected) in cases {
+            let mut internal = Internal::new(
+                request::default_client(),
+                Url::parse("http://127.0.0.1/identity").unwrap(),
+                protocol::Identity::Token("abcdef".to_string()),
+            );
+            internal.set_scope(Scope::project_with_domain(project, domain));
+            protocol::test::compare(expected, &internal.body.auth.scope);
+        }
+    }
+
+    async fn body_secret(internal: &Internal) -> String {
+        match internal.auth_body().await.unwrap().auth.identity {
+            protocol::Identity::Token(ref token) => token.clone(),
+            protocol::Identity::Password(ref pw) => pw.password.clone(),
+            protocol::Identity::ApplicationCredential(ref cred) => cred.secret.clone(),
+            protocol::Identity::Totp(ref totp) => totp.passcode.clone(),
+            protocol::Identity::MultiFactor(..) => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_file_reread() {
+        let path = std::env::temp_dir().join(format!("osauth-token-file-{}", std::process::id()));
+        std::fs::write(&path, " first\n").unwrap();
+        let mut internal = Internal::new(
+            request::default_client(),
+            Url::parse("http://127.0.0.1/identity").unwrap(),
+            protocol::Identity::Token(String::new()),
+        );
+        internal.set_token_file(path.clone()).unwrap();
+        assert!(internal.has_token_file());
+        assert_eq!(body_secret(&internal).await, "first");
+
+        std::fs::write(&path, "second").unwrap();
+        assert_eq!(body_secret(&internal).await, "second");
+
+        std::fs::write(&path, "\n").unwrap();
+        let err = internal.auth_body().await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidConfig);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[derive(Debug, Default)]
+    struct RotatingSecret {
+        count: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl CredentialsProvider for RotatingSecret {
+        async fn get_secret(&self) -> Result<String, Error> {
+            let number = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(format!("secret-{}", number))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_credentials_provider() {
+        let mut internal = Internal::new(
+            request::default_client(),
+            Url::parse("http://127.0.0.1/identity").unwrap(),
+            protocol::Identity::Password(protocol::UserAndPassword {
+                user: IdOrName::Name("admin".to_string()),
+                password: "initial".to_string(),
+                domain: None,
+            }),
+        );
+        assert_eq!(body_secret(&internal).await, "initial");
+        internal.set_credentials_provider(Arc::new(RotatingSecret::default()));
+        assert_eq!(body_secret(&internal).await, "secret-1");
+        assert_eq!(body_secret(&internal).await, "secret-2");
+    }
+
+    const RECEIPT_BODY: &str = r#"{"receipt": {"methods": ["password"]},
+        "required_auth_methods": [["password", "totp"]]}"#;
+    fn password_internal(auth_url: Url) -> Internal {
+        Internal::new(
+            request::default_client(),
+            auth_url,
+            protocol::Identity::Password(protocol::UserAndPassword {
+                user: IdOrName::Name("admin".to_string()),
+                password: "pa$$w0rd".to_string(),
+                domain: None,
+            }),
+        )
+    }
+
+    fn receipt_mock() -> Mock {
+        Mock::new(Method::POST, TOKENS_PATH)
+            .with_status(StatusCode::UNAUTHORIZED)
+            .with_header("content-type", "application/json")
+            .with_header("openstack-auth-receipt", "receipt1")
+            .with_body(RECEIPT_BODY)
+            .with_times(1)
+    }
+
+    #[tokio::test]
+    async fn test_totp_receipt() {
+        let server = MockServer::start();
+        server.mock(receipt_mock());
+        server.mock(token_mock("abcdef"));
+        let mut internal = password_internal(server.identity_url());
+        internal.set_totp(TotpPasscode::Provider(Arc::new(RotatingSecret::default())));
+        assert_eq!(internal.get_token().await.unwrap(), "abcdef");
+
+        let requests = server.requests();
+        assert!(!requests[0].headers.contains_key("openstack-auth-receipt"));
+        let body = requests[0].json::<serde_json::Value>().unwrap();
+        let identity = &body["auth"]["identity"];
+        assert_eq!(identity["methods"], serde_json::json!(["password"]));
+        assert_eq!(requests[1].headers["openstack-auth-receipt"], "receipt1");
+        let body = requests[1].json::<serde_json::Value>().unwrap();
+        let identity = &body["auth"]["identity"];
+        assert_eq!(identity["methods"], serde_json::json!(["totp"]));
+        assert_eq!(identity["totp"]["user"]["passcode"], "secret-1");
+        assert!(!body.to_string().contains("pa$$w0rd"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_totp_static() {
+        let server = MockServer::start();
+        server.mock(token_mock("abcdef"));
+        let mut internal = password_internal(server.identity_url());
+        internal.set_totp(TotpPasscode::Static("123456".to_string()));
+        assert!(!format!("{:?}", internal).contains("123456"));
+        assert_eq!(internal.get_token().await.unwrap(), "abcdef");
+
+        let body = server.requests()[0].json::<serde_json::Value>().unwrap();
+        let identity = &body["auth"]["identity"];
+        assert_eq!(identity["methods"], serde_json::json!(["password", "totp"]));
+        assert_eq!(identity["totp"]["user"]["passcode"], "123456");
+    }
+
+    #[tokio::test]
+    async fn test_receipt_without_totp() {
+        let server = MockServer::start();
+        server.mock(receipt_mock());
+        let internal = password_internal(server.identity_url());
+        let err = internal.get_token().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Authentication);
+        assert!(err.to_string().contains("totp"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_revoke() {
+        let server = MockServer::start();
+        server.mock(token_mock("abcdef"));
+        server.mock(
+            Mock::new(Method::DELETE, TOKENS_PATH)
+                .with_status(StatusCode::NO_CONTENT)
+                .with_times(1),
+        );
+        server.mock(token_mock("ghijkl"));
+        server.mock(Mock::new(Method::DELETE, TOKENS_PATH).with_status(StatusCode::NOT_FOUND));
+        let internal = password_internal(server.identity_url());
+        // Nothing to revoke yet.
+        internal.revoke().await.unwrap();
+        assert_eq!(internal.get_token().await.unwrap(), "abcdef");
+        internal.revoke().await.unwrap();
+        assert!(internal.cached_token.read().await.is_none());
+        assert_eq!(internal.get_token().await.unwrap(), "ghijkl");
+        // An unknown token is not an error.
+        internal.revoke().await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[1].method, Method::DELETE);
+        assert_eq!(requests[1].path, TOKENS_PATH);
+        assert_eq!(requests[1].headers["x-subject-token"], "abcdef");
+        assert_eq!(requests[1].headers["x-auth-token"], "abcdef");
+        assert_eq!(requests[2].method, Method::POST);
+    }
+
+    #[cfg(feature = "token-cache")]
+    fn internal_with_cache(auth_url: Url, user: &str, cache_dir: &std::path::Path) -> Internal {
+        let mut internal = Internal::new(
+            request::default_client(),
+            auth_url,
+            protocol::Identity::Password(protocol::UserAndPassword {
+                user: IdOrName::Name(user.to_string()),
+                password: "pa$$w0rd".to_string(),
+                domain: None,
+            }),
+        );
+        internal.set_token_cache(Some(super::TokenCache::new(cache_dir.to_path_buf())));
+        internal
+    }
+
+    #[cfg(feature = "token-cache")]
+    fn cache_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "osauth-internal-cache-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[cfg(feature = "token-cache")]
+    #[tokio::test]
+    async fn test_token_cache_shared() {
+        let dir = cache_dir("shared");
+        let server = MockServer::start();
+        server.mock(token_mock("abcdef"));
+        let first = internal_with_cache(server.identity_url(), "admin", &dir);
+        assert_eq!(first.get_token().await.unwrap(), "abcdef");
+        // The server only answers once, the second process uses the cached token.
+        let second = internal_with_cache(server.identity_url(), "admin", &dir);
+        assert_eq!(second.get_token().await.unwrap(), "abcdef");
+        assert_eq!(
+            second.token_info().await.unwrap().expires_at.to_rfc3339(),
+            "2100-01-01T00:00:00+00:00"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "token-cache")]
+    #[tokio::test]
+    async fn test_token_cache_revoke() {
+        let dir = cache_dir("revoke");
+        let server = MockServer::start();
+        server.mock(token_mock("abcdef"));
+        server.mock(Mock::new(Method::DELETE, TOKENS_PATH).with_status(StatusCode::NO_CONTENT));
+        let internal = internal_with_cache(server.identity_url(), "admin", &dir);
+        assert_eq!(internal.get_token().await.unwrap(), "abcdef");
+        assert!(internal.load_cached_token().is_some());
+        internal.revoke().await.unwrap();
+        assert!(internal.load_cached_token().is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "token-cache")]
+    #[test]
+    fn test_token_cache_key() {
+        let dir = cache_dir("key");
+        let url = Url::parse("http://127.0.0.1/identity").unwrap();
+        let admin = internal_with_cache(url.clone(), "admin", &dir);
+        let key = admin.cache_key().unwrap();
+        assert!(!key.contains("pa$$w0rd"));
+        assert_ne!(
+            internal_with_cache(url.clone(), "demo", &dir).cache_key(),
+            Some(key.clone())
+        );
+        let mut scoped = internal_with_cache(url.clone(), "admin", &dir);
+        scoped.set_scope(Scope::project("admin"));
+        assert_ne!(scoped.cache_key(), Some(key));
+        let token = Internal::new(
+            request::default_client(),
+            url,
+            protocol::Identity::Token("abcdef".to_string()),
+        );
+        assert!(token.cache_key().is_none());
+    }
+
+    #[cfg(feature = "token-cache")]
+    #[test]
+    fn test_token_cache_expiring() {
+        let dir = cache_dir("expiring");
+        let url = Url::parse("http://127.0.0.1/identity").unwrap();
+        let mut internal = internal_with_cache(url, "admin", &dir);
+        let expires_at = (Local::now() + Duration::minutes(5)).into();
+        let body = serde_json::json!({
+            "token": {"expires_at": expires_at, "roles": [], "catalog": []}
+        });
+        super::TokenCache::new(dir.clone()).store(
+            &internal.cache_key().unwrap(),
+            expires_at,
+            "abcdef",
+            &body,
+        );
+        // Valid, but refreshed soon anyway.
+        assert!(internal.load_cached_token().is_none());
+        internal
+            .set_token_refresh_skew(std::time::Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(internal.load_cached_token().unwrap().value, "abcdef");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,475 @@
+// Copyright 2019-2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Authentication using Identity API v3.
+//!
+//! Currently supports [Password](struct.Password.html), [Token](struct.Token.html),
+//! [ApplicationCredential](struct.ApplicationCredential.html) and [Totp](struct.Totp.html)
+//! authentication. A TOTP passcode can also be combined with a password for multi-factor
+//! authentication. For legacy clouds
+//! without Identity API v3, [PasswordV2](struct.PasswordV2.html) implements password
+//! authentication using Identity API v2.0. [Oidc](struct.Oidc.html) implements federated
+//! authentication with OpenID Connect.
+
+mod application_credential;
+#[cfg(feature = "token-cache")]
+mod cache;
+mod credentials;
+mod internal;
+mod oidc;
+mod password;
+mod password_v2;
+mod protocol;
+mod token;
+mod totp;
+
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, FixedOffset};
+use reqwest::Url;
+use serde::Deserialize;
+
+use super::{Error, ErrorKind, Metrics};
+
+pub use osproto::identity::IdOrName;
+
+pub use self::application_credential::ApplicationCredential;
+#[cfg(feature = "config-file")]
+pub use self::credentials::ConfigCredentials;
+pub use self::credentials::{CredentialsProvider, EnvCredentials};
+pub use self::oidc::Oidc;
+pub use self::password::Password;
+pub use self::password_v2::PasswordV2;
+pub use self::token::Token;
+pub use self::totp::Totp;
+
+#[cfg(feature = "ec2")]
+pub(crate) use self::internal::v3_endpoint_for;
+pub(crate) use self::internal::{auth_endpoint_for, token_endpoint_for};
+
+/// A scope of a token.
+///
+/// Project scopes can be created from a project name or a `(project, domain)` tuple of names:
+///
+/// ```rust
+/// use osauth::identity::Scope;
+///
+/// let scope: Scope = ("project1", "Default").into();
+/// let scope = Scope::project("project1");
+/// ```
+///
+/// Domain and system scopes are used by cloud administrators for operations on a whole domain
+/// (e.g. managing its users) or on the deployment itself (e.g. managing services and endpoints):
+///
+/// ```rust
+/// use osauth::identity::Scope;
+///
+/// let scope = Scope::domain("Users");
+/// let scope = Scope::System;
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Scope {
+    /// A token scoped to a project.
+    Project {
+        /// Project ID or name.
+        project: IdOrName,
+        /// ID or name of the project domain.
+        domain: Option<IdOrName>,
+    },
+    /// A token scoped to a domain given by its ID or name.
+    Domain(IdOrName),
+    /// A token scoped to the whole system (the `all` system scope).
+    System,
+    /// A token delegated via the trust with the given ID (`OS-TRUST`).
+    ///
+    /// The authenticating user must be the trustee of the trust.
+    Trust(String),
+}
+
+impl Scope {
+    /// A scope for a project with the given name.
+    ///
+    /// The project domain is not specified, use
+    /// [project_with_domain](#method.project_with_domain) if needed.
+    #[inline]
+    pub fn project<S: Into<String>>(name: S) -> Scope {
+        Scope::Project {
+            project: IdOrName::Name(name.into()),
+            domain: None,
+        }
+    }
+
+    /// A scope for a project in a domain, each given by either its ID or name.
+    ///
+    /// ```rust
+    /// use osauth::identity::{IdOrName, Scope};
+    ///
+    /// let scope = Scope::project_with_domain(
+    ///     IdOrName::Name("project1".to_string()),
+    ///     IdOrName::Id("default".to_string()),
+    /// );
+    /// ```
+    #[inline]
+    pub fn project_with_domain(project: IdOrName, domain: IdOrName) -> Scope {
+        Scope::Project {
+            project,
+            domain: Some(domain),
+        }
+    }
+
+    /// A scope for a domain with the given name.
+    #[inline]
+    pub fn domain<S: Into<String>>(name: S) -> Scope {
+        Scope::Domain(IdOrName::Name(name.into()))
+    }
+}
+
+/// Treat a string as a project name.
+impl From<String> for Scope {
+    fn from(value: String) -> Scope {
+        Scope::project(value)
+    }
+}
+
+/// Treat a string as a project name.
+impl From<&str> for Scope {
+    fn from(value: &str) -> Scope {
+        Scope::project(value)
+    }
+}
+
+/// Treat a tuple as a project name and a domain name.
+impl From<(&str, &str)> for Scope {
+    fn from(value: (&str, &str)) -> Scope {
+        Scope::Project {
+            project: IdOrName::Name(value.0.to_string()),
+            domain: Some(IdOrName::Name(value.1.to_string())),
+        }
+    }
+}
+
+/// An access rule of an application credential.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AccessRule {
+    /// ID of the rule (if reported by the Identity service).
+    #[serde(default)]
+    pub id: Option<String>,
+    /// HTTP method, e.g. `GET`.
+    pub method: String,
+    /// Path template, e.g. `/v2.1/servers/*`.
+    pub path: String,
+    /// Service type, e.g. `compute`.
+    pub service: String,
+}
+
+/// An application credential a token was obtained with.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TokenApplicationCredential {
+    /// Application credential ID.
+    pub id: String,
+    /// Application credential name (if reported by the Identity service).
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Whether the application credential is restricted from managing other credentials.
+    #[serde(default)]
+    pub restricted: bool,
+    /// Access rules limiting the API calls allowed with the token.
+    ///
+    /// Empty if the application credential is not limited.
+    #[serde(default)]
+    pub access_rules: Vec<AccessRule>,
+}
+
+/// A user, a project or a domain of a token.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TokenEntity {
+    /// ID of the entity.
+    pub id: String,
+    /// Name of the entity (if reported by the Identity service).
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// A role assigned to the user of a token.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TokenRole {
+    /// ID of the role (not reported by Identity API v2.0).
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Name of the role.
+    pub name: String,
+}
+
+/// Information about an authentication token.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TokenInfo {
+    /// When the token was issued (if reported by the Identity service).
+    #[serde(default)]
+    pub issued_at: Option<DateTime<FixedOffset>>,
+    /// When the token expires.
+    pub expires_at: DateTime<FixedOffset>,
+    /// Audit IDs of the token and the token it was obtained with (if any).
+    #[serde(default)]
+    pub audit_ids: Vec<String>,
+    /// Authentication methods used to obtain the token.
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// Application credential used to obtain the token (if any).
+    #[serde(default)]
+    pub application_credential: Option<TokenApplicationCredential>,
+    /// User the token belongs to (if reported by the Identity service).
+    #[serde(default)]
+    pub user: Option<TokenEntity>,
+    /// Project the token is scoped to (if any).
+    #[serde(default)]
+    pub project: Option<TokenEntity>,
+    /// Domain the token is scoped to (if any).
+    #[serde(default)]
+    pub domain: Option<TokenEntity>,
+    /// Roles assigned to the user in the scope of the token (empty for unscoped tokens).
+    #[serde(default)]
+    pub roles: Vec<TokenRole>,
+}
+
+impl TokenInfo {
+    /// Access rules limiting the API calls allowed with the token.
+    ///
+    /// Empty unless the token was obtained with an application credential with access rules.
+    pub fn access_rules(&self) -> &[AccessRule] {
+        self.application_credential
+            .as_ref()
+            .map(|cred| cred.access_rules.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Whether the role with the given name is assigned to the user.
+    ///
+    /// Role names are compared case-insensitively, like the Identity service does.
+    pub fn has_role(&self, name: &str) -> bool {
+        self.roles
+            .iter()
+            .any(|role| role.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Names of the roles assigned to the user.
+    pub fn role_names(&self) -> Vec<&str> {
+        self.roles.iter().map(|role| role.name.as_str()).collect()
+    }
+}
+
+/// A project the user has access to.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AvailableProject {
+    /// Project ID.
+    pub id: String,
+    /// Project name.
+    pub name: String,
+    /// ID of the project domain (if reported by the Identity service).
+    #[serde(default)]
+    pub domain_id: Option<String>,
+    /// Whether the project is enabled.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+impl AvailableProject {
+    /// A scope for this project.
+    #[inline]
+    pub fn scope(&self) -> Scope {
+        Scope::Project {
+            project: IdOrName::Id(self.id.clone()),
+            domain: None,
+        }
+    }
+}
+
+#[inline]
+fn default_enabled() -> bool {
+    true
+}
+
+/// An authentication attempt reported to the auth hook.
+///
+/// See [Password::set_auth_hook](struct.Password.html#method.set_auth_hook).
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct AuthEvent {
+    /// Authentication URL.
+    pub auth_url: Url,
+    /// Name of the cloud (if created from configuration).
+    pub cloud_name: Option<String>,
+    /// Whether a token was received.
+    pub success: bool,
+    /// How long the attempt took.
+    pub duration: Duration,
+    /// Request ID reported by the Identity service (if any).
+    pub request_id: Option<String>,
+}
+
+/// A function called after each authentication attempt.
+#[derive(Clone)]
+pub(crate) struct AuthHook(Arc<dyn Fn(&AuthEvent) + Send + Sync>);
+
+impl fmt::Debug for AuthHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("AuthHook")
+    }
+}
+
+impl AuthHook {
+    /// Wrap a function.
+    pub fn new<F>(hook: F) -> AuthHook
+    where
+        F: Fn(&AuthEvent) + Send + Sync + 'static,
+    {
+        AuthHook(Arc::new(hook))
+    }
+
+    /// Call the function.
+    #[inline]
+    pub fn call(&self, event: &AuthEvent) {
+        (self.0)(event)
+    }
+}
+
+/// Reports the authentication attempts of an authentication type.
+///
+/// All token requests go through here, so that the hook and the metrics see the same attempts.
+#[derive(Debug, Default)]
+pub(crate) struct AuthReporter {
+    cloud_name: Option<String>,
+    hook: Option<AuthHook>,
+    metrics: RwLock<Option<Arc<dyn Metrics>>>,
+}
+
+impl Clone for AuthReporter {
+    fn clone(&self) -> AuthReporter {
+        AuthReporter {
+            cloud_name: self.cloud_name.clone(),
+            hook: self.hook.clone(),
+            metrics: RwLock::new(self.metrics()),
+        }
+    }
+}
+
+impl AuthReporter {
+    fn metrics(&self) -> Option<Arc<dyn Metrics>> {
+        self.metrics
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Name of the cloud (if created from configuration).
+    #[cfg(feature = "token-cache")]
+    #[inline]
+    pub fn cloud_name(&self) -> Option<&str> {
+        self.cloud_name.as_deref()
+    }
+
+    /// Set the cloud name reported to the hook.
+    #[inline]
+    pub fn set_cloud_name(&mut self, cloud_name: String) {
+        self.cloud_name = Some(cloud_name);
+    }
+
+    /// Set the hook called after each authentication attempt.
+    #[inline]
+    pub fn set_hook(&mut self, hook: AuthHook) {
+        self.hook = Some(hook);
+    }
+
+    /// Report token requests to the metrics.
+    pub fn set_metrics(&self, metrics: Arc<dyn Metrics>) {
+        *self.metrics.write().unwrap_or_else(|e| e.into_inner()) = Some(metrics);
+    }
+
+    /// Report an authentication attempt that has started at `started`.
+    pub fn report(
+        &self,
+        auth_url: &Url,
+        started: Instant,
+        success: bool,
+        request_id: Option<String>,
+    ) {
+        if let Some(metrics) = self.metrics() {
+            metrics.record_token_refresh(success);
+        }
+        if let Some(ref hook) = self.hook {
+            hook.call(&AuthEvent {
+                auth_url: auth_url.clone(),
+                cloud_name: self.cloud_name.clone(),
+                success,
+                duration: started.elapsed(),
+                request_id,
+            });
+        }
+    }
+}
+
+/// Run a blocking function (e.g. reading a file) on the blocking thread pool.
+pub(crate) async fn run_blocking<F, T>(func: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(func).await.map_err(|e| {
+        Error::new(
+            ErrorKind::OperationFailed,
+            format!("Blocking task failed: {}", e),
+        )
+    })?
+}
+
+/// Generic trait for authentication using Identity API V3.
+pub trait Identity {
+    /// Get a reference to the auth URL.
+    fn auth_url(&self) -> &Url;
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IdOrName, Scope};
+
+    #[test]
+    fn test_scope_project() {
+        let expected = Scope::Project {
+            project: IdOrName::Name("project1".to_string()),
+            domain: None,
+        };
+        assert_eq!(Scope::project("project1"), expected);
+        assert_eq!(Scope::from("project1"), expected);
+        assert_eq!(Scope::from("project1".to_string()), expected);
+    }
+
+    #[test]
+    fn test_scope_from_tuple() {
+        let scope: Scope = ("project1", "Default").into();
+        assert_eq!(
+            scope,
+            Scope::Project {
+                project: IdOrName::Name("project1".to_string()),
+                domain: Some(IdOrName::Name("Default".to_string())),
+            }
+        );
+    }
+}
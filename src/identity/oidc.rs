@@ -0,0 +1,776 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Federated authentication using OpenID Connect.
+
+use std::fmt;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use log::debug;
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Url};
+use serde::Deserialize;
+
+use super::super::{request, AuthType, EndpointFilters, Error, ErrorKind, InterfaceType, Metrics};
+use super::internal::{v3_endpoint_for, Internal};
+use super::protocol;
+use super::{AuthEvent, AuthHook, CredentialsProvider, IdOrName, Identity, Scope, TokenInfo};
+
+// Default time out for authentication requests in seconds.
+const DEFAULT_AUTH_TIMEOUT: u64 = 60;
+// Default scope requested from the identity provider.
+const DEFAULT_OPENID_SCOPE: &str = "openid profile";
+
+#[derive(Debug, Deserialize)]
+struct DiscoveryDocument {
+    token_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct AccessTokenResponse {
+    access_token: String,
+}
+
+/// Source of the OpenID Connect access token.
+#[derive(Clone)]
+enum AccessToken {
+    /// A pre-issued access token.
+    Static(String),
+    /// An access token obtained with the resource owner password grant.
+    Password(Box<PasswordGrant>),
+}
+
+/// Parameters of the resource owner password grant.
+#[derive(Clone)]
+struct PasswordGrant {
+    discovery_endpoint: Option<Url>,
+    access_token_endpoint: Option<Url>,
+    client_id: String,
+    client_secret: Option<String>,
+    username: String,
+    password: String,
+    openid_scope: String,
+}
+
+/// Obtains unscoped federated tokens, which are then used for token authentication.
+#[derive(Clone)]
+struct FederatedToken {
+    client: Client,
+    federation_endpoint: String,
+    access_token: AccessToken,
+    auth_timeout: Option<std::time::Duration>,
+}
+
+impl fmt::Debug for FederatedToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct("FederatedToken");
+        let _ = debug.field("federation_endpoint", &self.federation_endpoint);
+        if let AccessToken::Password(ref grant) = self.access_token {
+            let _ = debug
+                .field("discovery_endpoint", &grant.discovery_endpoint)
+                .field("access_token_endpoint", &grant.access_token_endpoint)
+                .field("client_id", &grant.client_id)
+                .field("username", &grant.username);
+        }
+        debug.finish()
+    }
+}
+
+impl FederatedToken {
+    /// Apply the time out for authentication requests (if any).
+    fn with_timeout(&self, builder: RequestBuilder) -> RequestBuilder {
+        match self.auth_timeout {
+            Some(timeout) => builder.timeout(timeout),
+            None => builder,
+        }
+    }
+
+    /// Token endpoint of the identity provider, discovering it if needed.
+    async fn idp_token_endpoint(&self, grant: &PasswordGrant) -> Result<Url, Error> {
+        if let Some(ref endpoint) = grant.access_token_endpoint {
+            return Ok(endpoint.clone());
+        }
+
+        let discovery_endpoint = grant.discovery_endpoint.clone().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidConfig,
+                "Either a discovery endpoint or an access token endpoint is required",
+            )
+        })?;
+        let builder = self.with_timeout(self.client.get(discovery_endpoint.clone()));
+        let document: DiscoveryDocument = request::fetch_json(builder).await?;
+        debug!(
+            "Discovered token endpoint {} from {}",
+            document.token_endpoint, discovery_endpoint
+        );
+        Url::parse(&document.token_endpoint).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidResponse,
+                format!("Invalid token endpoint in the discovery document: {}", e),
+            )
+        })
+    }
+
+    /// Obtain an access token using the password grant.
+    async fn password_grant(&self, grant: &PasswordGrant) -> Result<String, Error> {
+        let token_endpoint = self.idp_token_endpoint(grant).await?;
+        let form = [
+            ("grant_type", "password"),
+            ("username", &grant.username),
+            ("password", &grant.password),
+            ("scope", &grant.openid_scope),
+        ];
+        let builder = self
+            .client
+            .post(token_endpoint)
+            .basic_auth(&grant.client_id, grant.client_secret.as_ref())
+            .form(&form);
+        let response: AccessTokenResponse = request::fetch_json(self.with_timeout(builder)).await?;
+        Ok(response.access_token)
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for FederatedToken {
+    async fn get_secret(&self) -> Result<String, Error> {
+        let access_token = match self.access_token {
+            AccessToken::Static(ref token) => token.clone(),
+            AccessToken::Password(ref grant) => self.password_grant(grant).await?,
+        };
+        let builder = self
+            .client
+            .post(&self.federation_endpoint)
+            .bearer_auth(access_token);
+        let resp = request::check(self.with_timeout(builder).send().await?).await?;
+        debug!(
+            "Received an unscoped federated token from {}",
+            self.federation_endpoint
+        );
+        match resp.headers().get("x-subject-token") {
+            Some(value) => value.to_str().map(ToString::to_string).map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidResponse,
+                    "Invalid X-Subject-Token header in the federated token response",
+                )
+            }),
+            None => Err(Error::new(
+                ErrorKind::InvalidResponse,
+                "Missing X-Subject-Token header in the federated token response",
+            )),
+        }
+    }
+}
+
+/// Federated authentication with OpenID Connect using Identity API V3.
+///
+/// An OpenID Connect access token (either provided or obtained from the identity provider using
+/// the password grant) is exchanged for an unscoped token via `OS-FEDERATION`, which is then
+/// exchanged for a token with the requested scope (if any).
+///
+/// ```rust,no_run
+/// let auth = osauth::identity::Oidc::new_password(
+///     "https://cloud.local/identity",
+///     "myidp",
+///     "openid",
+///     "osauth",
+///     "user@example.com",
+///     "pa$$w0rd",
+/// )
+/// .expect("Invalid auth_url")
+/// .with_discovery_endpoint("https://idp.local/.well-known/openid-configuration")
+///  .expect("Invalid discovery endpoint")
+/// .with_project_scope(osauth::identity::IdOrName::Name("project1".to_string()), None);
+///
+/// let session = osauth::Session::new(auth);
+/// ```
+///
+/// The authentication token is cached while it's still valid or until
+/// [refresh](../trait.AuthType.html#tymethod.refresh) is called.
+/// Clones of an `Oidc` also start with an empty cache.
+#[derive(Clone, Debug)]
+pub struct Oidc {
+    inner: Internal,
+    provider: FederatedToken,
+}
+
+impl Identity for Oidc {
+    fn auth_url(&self) -> &Url {
+        self.inner.auth_url()
+    }
+}
+
+impl Oidc {
+    /// Create an authentication exchanging the provided OpenID Connect access token.
+    ///
+    /// Since the access token cannot be renewed, authentication stops working once it has
+    /// expired.
+    pub fn new_access_token<U, S1, S2, S3>(
+        auth_url: U,
+        identity_provider: S1,
+        protocol: S2,
+        access_token: S3,
+    ) -> Result<Oidc, Error>
+    where
+        U: IntoUrl,
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+        S3: Into<String>,
+    {
+        Oidc::new_access_token_with_client(
+            auth_url,
+            request::default_client(),
+            identity_provider,
+            protocol,
+            access_token,
+        )
+    }
+
+    /// Create an authentication exchanging the provided OpenID Connect access token with the
+    /// provided HTTP client.
+    pub fn new_access_token_with_client<U, S1, S2, S3>(
+        auth_url: U,
+        client: Client,
+        identity_provider: S1,
+        protocol: S2,
+        access_token: S3,
+    ) -> Result<Oidc, Error>
+    where
+        U: IntoUrl,
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+        S3: Into<String>,
+    {
+        Oidc::new_impl(
+            auth_url.into_url()?,
+            client,
+            identity_provider.as_ref(),
+            protocol.as_ref(),
+            AccessToken::Static(access_token.into()),
+        )
+    }
+
+    /// Create an authentication obtaining access tokens with the password grant.
+    ///
+    /// The token endpoint of the identity provider must be set with either
+    /// [set_discovery_endpoint](#method.set_discovery_endpoint) or
+    /// [set_access_token_endpoint](#method.set_access_token_endpoint).
+    pub fn new_password<U, S1, S2, S3, S4, S5>(
+        auth_url: U,
+        identity_provider: S1,
+        protocol: S2,
+        client_id: S3,
+        user_name: S4,
+        password: S5,
+    ) -> Result<Oidc, Error>
+    where
+        U: IntoUrl,
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+        S3: Into<String>,
+        S4: Into<String>,
+        S5: Into<String>,
+    {
+        Oidc::new_password_with_client(
+            auth_url,
+            request::default_client(),
+            identity_provider,
+            protocol,
+            client_id,
+            user_name,
+            password,
+        )
+    }
+
+    /// Create an authentication obtaining access tokens with the password grant with the
+    /// provided HTTP client.
+    pub fn new_password_with_client<U, S1, S2, S3, S4, S5>(
+        auth_url: U,
+        client: Client,
+        identity_provider: S1,
+        protocol: S2,
+        client_id: S3,
+        user_name: S4,
+        password: S5,
+    ) -> Result<Oidc, Error>
+    where
+        U: IntoUrl,
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+        S3: Into<String>,
+        S4: Into<String>,
+        S5: Into<String>,
+    {
+        Oidc::new_impl(
+            auth_url.into_url()?,
+            client,
+            identity_provider.as_ref(),
+            protocol.as_ref(),
+            AccessToken::Password(Box::new(PasswordGrant {
+                discovery_endpoint: None,
+                access_token_endpoint: None,
+                client_id: client_id.into(),
+                client_secret: None,
+                username: user_name.into(),
+                password: password.into(),
+                openid_scope: DEFAULT_OPENID_SCOPE.to_string(),
+            })),
+        )
+    }
+
+    fn new_impl(
+        auth_url: Url,
+        client: Client,
+        identity_provider: &str,
+        protocol: &str,
+        access_token: AccessToken,
+    ) -> Result<Oidc, Error> {
+        let federation_endpoint = v3_endpoint_for(
+            &auth_url,
+            &[
+                "OS-FEDERATION",
+                "identity_providers",
+                identity_provider,
+                "protocols",
+                protocol,
+                "auth",
+            ],
+        );
+        let provider = FederatedToken {
+            client: client.clone(),
+            federation_endpoint,
+            access_token,
+            auth_timeout: Some(std::time::Duration::from_secs(DEFAULT_AUTH_TIMEOUT)),
+        };
+        let mut oidc = Oidc {
+            inner: Internal::new(client, auth_url, protocol::Identity::Token(String::new())),
+            provider,
+        };
+        oidc.update_provider();
+        Ok(oidc)
+    }
+
+    /// Pass the current settings of the federated token provider to the authentication.
+    #[inline]
+    fn update_provider(&mut self) {
+        self.inner
+            .set_credentials_provider(Arc::new(self.provider.clone()));
+    }
+
+    /// Modify the password grant parameters (if used).
+    fn update_grant<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut PasswordGrant),
+    {
+        if let AccessToken::Password(ref mut grant) = self.provider.access_token {
+            f(grant);
+            self.update_provider();
+        }
+    }
+
+    /// Endpoint filters.
+    #[inline]
+    pub fn endpoint_filters(&self) -> &EndpointFilters {
+        self.inner.endpoint_filters()
+    }
+
+    /// Mutable endpoint filters.
+    #[inline]
+    pub fn endpoint_filters_mut(&mut self) -> &mut EndpointFilters {
+        self.inner.endpoint_filters_mut()
+    }
+
+    /// Set the default endpoint interface to use.
+    pub fn set_default_endpoint_interface(&mut self, endpoint_interface: InterfaceType) {
+        self.endpoint_filters_mut().interfaces = endpoint_interface.into();
+    }
+
+    /// Set endpoint filters.
+    #[inline]
+    pub fn set_endpoint_filters(&mut self, filters: EndpointFilters) {
+        *self.endpoint_filters_mut() = filters;
+    }
+
+    /// Set a time out for authentication requests.
+    ///
+    /// Applies to requests to both the identity provider and the Identity service. Defaults to
+    /// 60 seconds, `None` disables the time out.
+    pub fn set_auth_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.inner.set_auth_timeout(timeout);
+        self.provider.auth_timeout = timeout;
+        self.update_provider();
+    }
+
+    /// Time out for authentication requests.
+    #[inline]
+    pub fn auth_timeout(&self) -> Option<std::time::Duration> {
+        self.inner.auth_timeout()
+    }
+
+    /// Set a hook called after each authentication attempt.
+    ///
+    /// See [Password::set_auth_hook](struct.Password.html#method.set_auth_hook).
+    pub fn set_auth_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&AuthEvent) + Send + Sync + 'static,
+    {
+        self.inner.set_auth_hook(AuthHook::new(hook));
+    }
+
+    /// Set the maximum size of a token response in bytes.
+    ///
+    /// Defaults to 64 MiB, see
+    /// [Password::set_max_response_size](struct.Password.html#method.set_max_response_size).
+    #[inline]
+    pub fn set_max_response_size(&mut self, max_size: usize) {
+        self.inner.set_max_response_size(max_size);
+    }
+
+    /// Set the cloud name reported to the [auth hook](#method.set_auth_hook).
+    #[inline]
+    pub(crate) fn set_cloud_name(&mut self, cloud_name: String) {
+        self.inner.set_cloud_name(cloud_name);
+    }
+
+    /// Set how long before its expiration the token is refreshed.
+    ///
+    /// Defaults to 10 minutes, see
+    /// [Password::set_token_refresh_skew](struct.Password.html#method.set_token_refresh_skew).
+    #[inline]
+    pub fn set_token_refresh_skew(&mut self, skew: std::time::Duration) -> Result<(), Error> {
+        self.inner.set_token_refresh_skew(skew)
+    }
+
+    /// Set the OpenID Connect discovery document of the identity provider.
+    ///
+    /// The token endpoint for the password grant is taken from it. Ignored for access token
+    /// authentication.
+    pub fn set_discovery_endpoint<U: IntoUrl>(&mut self, endpoint: U) -> Result<(), Error> {
+        let endpoint = endpoint.into_url()?;
+        self.update_grant(|grant| grant.discovery_endpoint = Some(endpoint));
+        Ok(())
+    }
+
+    /// Set the token endpoint of the identity provider, skipping the discovery.
+    ///
+    /// Ignored for access token authentication.
+    pub fn set_access_token_endpoint<U: IntoUrl>(&mut self, endpoint: U) -> Result<(), Error> {
+        let endpoint = endpoint.into_url()?;
+        self.update_grant(|grant| grant.access_token_endpoint = Some(endpoint));
+        Ok(())
+    }
+
+    /// Set the client secret for the password grant.
+    ///
+    /// Ignored for access token authentication.
+    pub fn set_client_secret<S: Into<String>>(&mut self, client_secret: S) {
+        let client_secret = client_secret.into();
+        self.update_grant(|grant| grant.client_secret = Some(client_secret));
+    }
+
+    /// Set the scope requested from the identity provider (defaults to `openid profile`).
+    ///
+    /// Ignored for access token authentication.
+    pub fn set_openid_scope<S: Into<String>>(&mut self, openid_scope: S) {
+        let openid_scope = openid_scope.into();
+        self.update_grant(|grant| grant.openid_scope = openid_scope);
+    }
+
+    /// Scope authentication to the given project.
+    ///
+    /// A convenience wrapper around `set_scope`.
+    #[inline]
+    pub fn set_project_scope(&mut self, project: IdOrName, domain: impl Into<Option<IdOrName>>) {
+        self.set_scope(Scope::Project {
+            project,
+            domain: domain.into(),
+        });
+    }
+
+    /// Add a scope to the authentication.
+    ///
+    /// Without a scope, the unscoped federated token is used.
+    #[inline]
+    pub fn set_scope(&mut self, scope: Scope) {
+        self.inner.set_scope(scope);
+    }
+
+    /// Convert this authentication into one using the given endpoint interface.
+    #[inline]
+    pub fn with_default_endpoint_interface(mut self, endpoint_interface: InterfaceType) -> Self {
+        self.set_default_endpoint_interface(endpoint_interface);
+        self
+    }
+
+    /// Add endpoint filters.
+    #[inline]
+    pub fn with_endpoint_filters(mut self, filters: EndpointFilters) -> Self {
+        self.set_endpoint_filters(filters);
+        self
+    }
+
+    /// Convert this authentication into one using the given time out for authentication requests.
+    #[inline]
+    pub fn with_auth_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.set_auth_timeout(Some(timeout));
+        self
+    }
+
+    /// Set the maximum size of a token response in bytes.
+    #[inline]
+    pub fn with_max_response_size(mut self, max_size: usize) -> Self {
+        self.set_max_response_size(max_size);
+        self
+    }
+
+    /// Set a hook called after each authentication attempt.
+    #[inline]
+    pub fn with_auth_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&AuthEvent) + Send + Sync + 'static,
+    {
+        self.set_auth_hook(hook);
+        self
+    }
+
+    /// Set the OpenID Connect discovery document of the identity provider.
+    #[inline]
+    pub fn with_discovery_endpoint<U: IntoUrl>(mut self, endpoint: U) -> Result<Self, Error> {
+        self.set_discovery_endpoint(endpoint)?;
+        Ok(self)
+    }
+
+    /// Set the token endpoint of the identity provider, skipping the discovery.
+    #[inline]
+    pub fn with_access_token_endpoint<U: IntoUrl>(mut self, endpoint: U) -> Result<Self, Error> {
+        self.set_access_token_endpoint(endpoint)?;
+        Ok(self)
+    }
+
+    /// Set the client secret for the password grant.
+    #[inline]
+    pub fn with_client_secret<S: Into<String>>(mut self, client_secret: S) -> Self {
+        self.set_client_secret(client_secret);
+        self
+    }
+
+    /// Set the scope requested from the identity provider.
+    #[inline]
+    pub fn with_openid_scope<S: Into<String>>(mut self, openid_scope: S) -> Self {
+        self.set_openid_scope(openid_scope);
+        self
+    }
+
+    /// Scope authentication to the given project.
+    ///
+    /// A convenience wrapper around `with_scope`.
+    #[inline]
+    pub fn with_project_scope(
+        mut self,
+        project: IdOrName,
+        domain: impl Into<Option<IdOrName>>,
+    ) -> Oidc {
+        self.set_project_scope(project, domain);
+        self
+    }
+
+    /// Set a region for this authentication method.
+    #[inline]
+    pub fn with_region<S>(mut self, region: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.endpoint_filters_mut().set_region(region);
+        self
+    }
+
+    /// Add a scope to the authentication.
+    #[inline]
+    pub fn with_scope(mut self, scope: Scope) -> Self {
+        self.set_scope(scope);
+        self
+    }
+
+    /// Project name or ID (if project scoped).
+    #[inline]
+    pub fn project(&self) -> Option<&IdOrName> {
+        self.inner.project()
+    }
+}
+
+#[async_trait]
+impl AuthType for Oidc {
+    /// The authentication URL.
+    fn identity_url(&self) -> Option<&Url> {
+        Some(self.inner.auth_url())
+    }
+
+    /// Endpoint filters in use.
+    fn default_filters(&self) -> Option<&EndpointFilters> {
+        Some(self.inner.endpoint_filters())
+    }
+
+    /// The password grant can always obtain a new token, an access token cannot be renewed.
+    fn can_reauthenticate(&self) -> bool {
+        match self.provider.access_token {
+            AccessToken::Password(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Report token requests to the metrics.
+    fn set_metrics(&self, metrics: Arc<dyn Metrics>) {
+        self.inner.set_metrics(metrics);
+    }
+
+    /// A copy with the same credentials and a different scope.
+    fn with_new_scope(&self, scope: Scope) -> Option<Box<dyn AuthType>> {
+        Some(Box::new(self.clone().with_scope(scope)))
+    }
+
+    /// Create an authenticated request.
+    async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+        self.inner.request(method, url).await
+    }
+
+    /// The service catalog received with the token.
+    async fn raw_catalog(&self) -> Result<Option<serde_json::Value>, Error> {
+        self.inner.raw_catalog().await.map(Some)
+    }
+
+    /// Information about the current token.
+    async fn token_info(&self) -> Result<Option<TokenInfo>, Error> {
+        self.inner.token_info().await.map(Some)
+    }
+
+    /// Get a URL for the requested service.
+    async fn get_endpoint(
+        &self,
+        service_type: String,
+        filters: EndpointFilters,
+    ) -> Result<Url, Error> {
+        self.inner.get_endpoint(service_type, filters).await
+    }
+
+    /// Refresh the cached token and service catalog.
+    async fn refresh(&self) -> Result<(), Error> {
+        self.inner.do_refresh(true).await
+    }
+
+    /// Revoke the cached token (if any).
+    async fn revoke(&self) -> Result<(), Error> {
+        self.inner.revoke().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::Method;
+
+    use super::super::super::test::{token_mock, token_mock_at, Mock, MockServer, TOKENS_PATH};
+    use super::super::super::AuthType;
+    use super::{IdOrName, Oidc};
+
+    #[tokio::test]
+    async fn test_access_token() {
+        let server = MockServer::start();
+        let federation_path =
+            "/identity/v3/OS-FEDERATION/identity_providers/my%20idp/protocols/openid/auth";
+        server.mock(token_mock_at(federation_path, "unscoped"));
+        server.mock(token_mock("scoped"));
+        let id =
+            Oidc::new_access_token(server.identity_url().as_str(), "my idp", "openid", "access")
+                .unwrap()
+                .with_project_scope(IdOrName::Name("project1".to_string()), None);
+        assert!(!id.can_reauthenticate());
+        let request = id
+            .request(Method::GET, server.url().clone())
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()["x-auth-token"], "scoped");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].path, federation_path);
+        assert_eq!(requests[0].headers["authorization"], "Bearer access");
+        assert_eq!(requests[1].path, TOKENS_PATH);
+        let body = requests[1].json::<serde_json::Value>().unwrap();
+        assert_eq!(
+            body["auth"]["identity"]["token"],
+            serde_json::json!({"id": "unscoped"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_password_grant() {
+        let server = MockServer::start();
+        server.mock(
+            Mock::new(Method::POST, "/idp/token")
+                .with_json(&serde_json::json!({"access_token": "access"})),
+        );
+        server.mock(token_mock_at(
+            "/identity/v3/OS-FEDERATION/identity_providers/myidp/protocols/openid/auth",
+            "unscoped",
+        ));
+        server.mock(token_mock("rescoped"));
+        let id = Oidc::new_password(
+            server.identity_url().as_str(),
+            "myidp",
+            "openid",
+            "osauth",
+            "user",
+            "pa$$w0rd",
+        )
+        .unwrap()
+        .with_access_token_endpoint(server.url().join("idp/token").unwrap().as_str())
+        .unwrap()
+        .with_client_secret("s3cr3t")
+        .with_openid_scope("openid");
+        assert!(id.can_reauthenticate());
+        assert!(!format!("{:?}", id).contains("pa$$w0rd"));
+        let info = id.token_info().await.unwrap().unwrap();
+        assert_eq!(info.expires_at.to_rfc3339(), "2100-01-01T00:00:00+00:00");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].path, "/idp/token");
+        // base64 of osauth:s3cr3t
+        assert_eq!(
+            requests[0].headers["authorization"],
+            "Basic b3NhdXRoOnMzY3IzdA=="
+        );
+        assert_eq!(
+            requests[0].body,
+            b"grant_type=password&username=user&password=pa%24%24w0rd&scope=openid"
+        );
+        assert_eq!(requests[1].headers["authorization"], "Bearer access");
+        assert_eq!(requests[2].path, TOKENS_PATH);
+    }
+
+    #[tokio::test]
+    async fn test_password_grant_no_endpoint() {
+        let id = Oidc::new_password(
+            "http://127.0.0.1/identity",
+            "myidp",
+            "openid",
+            "osauth",
+            "user",
+            "pa$$w0rd",
+        )
+        .unwrap();
+        let err = id.refresh().await.unwrap_err();
+        assert_eq!(err.kind(), super::ErrorKind::InvalidConfig);
+    }
+}
@@ -0,0 +1,699 @@
+// Copyright 2019-2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Password authentication.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Url};
+
+use super::super::{
+    request, AuthType, EndpointFilters, Error, InterfaceType, Metrics, ValidInterfaces,
+};
+use super::internal::{Internal, TotpPasscode};
+use super::protocol;
+use super::{AuthEvent, AuthHook, CredentialsProvider, IdOrName, Identity, Scope, TokenInfo};
+
+/// Password authentication using Identity API V3.
+///
+/// For any Identity authentication you need to know `auth_url`, which is an authentication endpoint
+/// of the Identity service. For the Password authentication you also need:
+/// 1. User name and password.
+/// 2. Domain of the user.
+/// 3. Name of the project to use.
+/// 4. Domain of the project.
+///
+/// Note: currently only names are supported for user, user domain and project domain. ID support is
+/// coming later.
+///
+/// Start with creating a `Password` object using [new](#method.new), then add a project scope
+/// with [with_project_scope](#method.with_project_scope):
+///
+/// ```rust,no_run
+/// # use osauth::identity::IdOrName;
+/// let auth = osauth::identity::Password::new(
+///     "https://cloud.local/identity",
+///     "admin",
+///     "pa$$w0rd",
+///     "Default"
+/// )
+/// .expect("Invalid auth_url")
+/// .with_project_scope(IdOrName::Name("project1".to_string()), None);
+///
+/// let session = osauth::Session::new(auth);
+/// ```
+///
+/// If your cloud has several regions, pick one using [with_region](#method.with_region):
+///
+/// ```rust,no_run
+/// use osauth::identity::IdOrName;
+///
+/// let scope = osauth::identity::Scope::Project {
+///     project: IdOrName::Name("project1".to_string()),
+///     domain: Some(IdOrName::Id("default".to_string())),
+/// };
+/// let auth = osauth::identity::Password::new(
+///     "https://cloud.local/identity",
+///     "admin",
+///     "pa$$w0rd",
+///     "Default"
+/// )
+/// .expect("Invalid auth_url")
+/// .with_scope(scope)
+/// .with_region("US-East");
+///
+/// let session = osauth::Session::new(auth);
+/// ```
+///
+/// By default, the `public` endpoint interface is used. f you would prefer to default to another
+/// one, you can set it with
+/// [with_default_endpoint_interface](#method.with_default_endpoint_interface).
+///
+/// ```rust,no_run
+/// use osauth::identity::IdOrName;
+///
+/// let scope = osauth::identity::Scope::Project {
+///     project: IdOrName::Name("project1".to_string()),
+///     domain: Some(IdOrName::Id("default".to_string())),
+/// };
+/// let auth = osauth::identity::Password::new(
+///     "https://cloud.local/identity",
+///     "admin",
+///     "pa$$w0rd",
+///     "Default"
+/// )
+/// .expect("Invalid auth_url")
+/// .with_scope(scope)
+/// .with_default_endpoint_interface(osauth::InterfaceType::Internal);
+/// ```
+///
+/// For accounts with multi-factor authentication, add a TOTP passcode with
+/// [with_totp_passcode](#method.with_totp_passcode) or a source of passcodes with
+/// [with_totp_provider](#method.with_totp_provider).
+///
+/// The authentication token is cached while it's still valid or until
+/// [refresh](../trait.AuthType.html#tymethod.refresh) is called.
+/// Clones of a `Password` also start with an empty cache.
+#[derive(Clone, Debug)]
+pub struct Password {
+    inner: Internal,
+}
+
+impl Identity for Password {
+    fn auth_url(&self) -> &Url {
+        self.inner.auth_url()
+    }
+}
+
+impl Password {
+    /// Create a password authentication.
+    pub fn new<U, S1, S2, S3>(
+        auth_url: U,
+        user_name: S1,
+        password: S2,
+        user_domain_name: S3,
+    ) -> Result<Password, Error>
+    where
+        U: IntoUrl,
+        S1: Into<String>,
+        S2: Into<String>,
+        S3: Into<String>,
+    {
+        Password::new_with_client(
+            auth_url,
+            request::default_client(),
+            user_name,
+            password,
+            user_domain_name,
+        )
+    }
+
+    /// Create a password authentication with the provided HTTP client.
+    pub fn new_with_client<U, S1, S2, S3>(
+        auth_url: U,
+        client: Client,
+        user_name: S1,
+        password: S2,
+        user_domain_name: S3,
+    ) -> Result<Password, Error>
+    where
+        U: IntoUrl,
+        S1: Into<String>,
+        S2: Into<String>,
+        S3: Into<String>,
+    {
+        let pw = protocol::UserAndPassword {
+            user: IdOrName::Name(user_name.into()),
+            password: password.into(),
+            domain: Some(IdOrName::Name(user_domain_name.into())),
+        };
+        Ok(Password {
+            inner: Internal::new(
+                client,
+                auth_url.into_url()?,
+                protocol::Identity::Password(pw),
+            ),
+        })
+    }
+
+    /// Endpoint filters.
+    #[inline]
+    pub fn endpoint_filters(&self) -> &EndpointFilters {
+        self.inner.endpoint_filters()
+    }
+
+    /// Mutable endpoint filters.
+    #[inline]
+    pub fn endpoint_filters_mut(&mut self) -> &mut EndpointFilters {
+        self.inner.endpoint_filters_mut()
+    }
+
+    /// Set the default endpoint interface to use.
+    ///
+    /// Re-authentication uses the identity endpoint of this interface from the service catalog
+    /// if it is not `public`: the credentials are sent to that endpoint. The authentication URL
+    /// is used if the endpoint cannot be reached.
+    pub fn set_default_endpoint_interface(&mut self, endpoint_interface: InterfaceType) {
+        self.endpoint_filters_mut().interfaces = ValidInterfaces::one(endpoint_interface);
+    }
+
+    /// Set endpoint filters.
+    #[inline]
+    pub fn set_endpoint_filters(&mut self, filters: EndpointFilters) {
+        *self.endpoint_filters_mut() = filters;
+    }
+
+    /// Set a region for this authentication method.
+    #[deprecated(since = "0.3.0", note = "Use set_filters or filters_mut")]
+    pub fn set_region<S>(&mut self, region: S)
+    where
+        S: Into<String>,
+    {
+        self.endpoint_filters_mut().set_region(region);
+    }
+
+    /// Set a time out for authentication requests.
+    ///
+    /// Applies to requests to the Identity service to obtain a token, requests to other services
+    /// are not affected. Defaults to 60 seconds, `None` disables the time out.
+    #[inline]
+    pub fn set_auth_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.inner.set_auth_timeout(timeout);
+    }
+
+    /// Time out for authentication requests.
+    #[inline]
+    pub fn auth_timeout(&self) -> Option<std::time::Duration> {
+        self.inner.auth_timeout()
+    }
+
+    /// Set the cloud name reported to the [auth hook](#method.set_auth_hook).
+    #[inline]
+    pub(crate) fn set_cloud_name(&mut self, cloud_name: String) {
+        self.inner.set_cloud_name(cloud_name);
+    }
+
+    /// Set a hook called after each authentication attempt.
+    ///
+    /// The hook receives an [AuthEvent](struct.AuthEvent.html) and can be used to collect
+    /// metrics:
+    ///
+    /// ```rust,no_run
+    /// let mut auth = osauth::identity::Password::new(
+    ///     "https://cloud.local/identity",
+    ///     "admin",
+    ///     "pa$$w0rd",
+    ///     "Default",
+    /// )
+    /// .expect("Invalid auth_url");
+    /// auth.set_auth_hook(|event| {
+    ///     println!(
+    ///         "Authentication against {} {} in {:?}",
+    ///         event.auth_url,
+    ///         if event.success { "succeeded" } else { "failed" },
+    ///         event.duration
+    ///     );
+    /// });
+    /// ```
+    ///
+    /// The hook is called on the same task that authenticates, so it should be fast. Copies
+    /// with a different scope keep the hook. For sessions created from configuration, use
+    /// [SessionBuilder::auth_hook](../struct.SessionBuilder.html#method.auth_hook).
+    pub fn set_auth_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&AuthEvent) + Send + Sync + 'static,
+    {
+        self.inner.set_auth_hook(AuthHook::new(hook));
+    }
+
+    /// Set the maximum size of a token response in bytes.
+    ///
+    /// Token responses include the whole service catalog, so a broken or malicious Identity
+    /// service could otherwise make the client buffer an arbitrary amount of data. Responses
+    /// larger than this fail with `ErrorKind::InvalidResponse`. Defaults to 64 MiB.
+    ///
+    /// Can also be set with `max_response_size` in `clouds.yaml` or
+    /// [SessionBuilder::max_response_size](../struct.SessionBuilder.html#method.max_response_size).
+    #[inline]
+    pub fn set_max_response_size(&mut self, max_size: usize) {
+        self.inner.set_max_response_size(max_size);
+    }
+
+    /// Enable discovery of the Identity API v3 endpoint.
+    ///
+    /// When enabled, the version document at the auth URL is requested first to find the
+    /// endpoint of a stable Identity API v3 instead of assuming `<auth_url>/v3`. If discovery
+    /// fails, the default endpoint is used and discovery is retried on the next authentication.
+    /// Disabled by default.
+    #[inline]
+    pub fn set_version_discovery(&mut self, enabled: bool) {
+        self.inner.set_version_discovery(enabled);
+    }
+
+    /// Set how long before its expiration the token is refreshed.
+    ///
+    /// A token is renewed proactively once it is valid for less than this time. Increase it if
+    /// the clocks of the client and the Identity service may be out of sync.
+    ///
+    /// Defaults to 10 minutes, the refresh margin of earlier versions. It is larger than the
+    /// clock skew alone requires, so that a request started just before the refresh (e.g. a long
+    /// upload or a paginated listing) does not outlive its token. Lower it (e.g. to 30 seconds)
+    /// to re-use tokens for longer when requests are short.
+    #[inline]
+    pub fn set_token_refresh_skew(&mut self, skew: std::time::Duration) -> Result<(), Error> {
+        self.inner.set_token_refresh_skew(skew)
+    }
+
+    /// Time before expiration when the token is refreshed.
+    #[inline]
+    pub fn token_refresh_skew(&self) -> std::time::Duration {
+        self.inner.token_refresh_skew()
+    }
+
+    /// Share tokens with other processes via an on-disk cache.
+    ///
+    /// Tokens are stored in `osauth/tokens` in the user cache directory (e.g. `~/.cache` on
+    /// Linux), readable only by the current user. A cached token is used by all authentications
+    /// with the same cloud name, authentication URL, user and scope until it expires or is
+    /// rejected by a service. Requires the `token-cache` feature.
+    #[cfg(feature = "token-cache")]
+    #[inline]
+    pub fn set_token_cache(&mut self, enabled: bool) {
+        self.inner.enable_token_cache(enabled);
+    }
+
+    /// Set the domain of the user by its ID or name.
+    ///
+    /// Overrides the domain name passed to [new](#method.new).
+    #[inline]
+    pub fn set_user_domain(&mut self, domain: IdOrName) {
+        match self.inner.identity_mut() {
+            protocol::Identity::Password(ref mut pw) => pw.domain = Some(domain),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Scope authentication to the given project.
+    ///
+    /// A convenience wrapper around `set_scope`.
+    #[inline]
+    pub fn set_project_scope(&mut self, project: IdOrName, domain: impl Into<Option<IdOrName>>) {
+        self.set_scope(Scope::Project {
+            project,
+            domain: domain.into(),
+        });
+    }
+
+    /// Scope authentication to the trust with the given ID.
+    ///
+    /// The user must be the trustee of the trust. A convenience wrapper around `set_scope`.
+    #[inline]
+    pub fn set_trust_scope<S: Into<String>>(&mut self, trust_id: S) {
+        self.set_scope(Scope::Trust(trust_id.into()));
+    }
+
+    /// Add a scope to the authentication.
+    ///
+    /// This is required in the most cases. Without a scope, an unscoped token is received: it
+    /// has no service catalog, but can be used to list the
+    /// [available projects](../struct.Session.html#method.available_projects).
+    #[inline]
+    pub fn set_scope(&mut self, scope: Scope) {
+        self.inner.set_scope(scope);
+    }
+
+    /// Fetch the password from the provider on every authentication.
+    ///
+    /// The password passed to [new](#method.new) is ignored afterwards.
+    #[inline]
+    pub fn set_credentials_provider<P>(&mut self, provider: P)
+    where
+        P: CredentialsProvider + 'static,
+    {
+        self.inner.set_credentials_provider(Arc::new(provider));
+    }
+
+    /// Send a TOTP passcode together with the password.
+    ///
+    /// Since passcodes expire quickly, this is only suitable for a single authentication. Use
+    /// [set_totp_provider](#method.set_totp_provider) for long-running sessions.
+    #[inline]
+    pub fn set_totp_passcode<S: Into<String>>(&mut self, passcode: S) {
+        self.inner.set_totp(TotpPasscode::Static(passcode.into()));
+    }
+
+    /// Fetch a TOTP passcode from the provider when the Identity service requires it.
+    ///
+    /// The password is sent first. If the Identity service responds with an authentication
+    /// receipt requiring TOTP, a passcode is requested from the provider and sent with the
+    /// receipt.
+    #[inline]
+    pub fn set_totp_provider<P>(&mut self, provider: P)
+    where
+        P: CredentialsProvider + 'static,
+    {
+        self.inner
+            .set_totp(TotpPasscode::Provider(Arc::new(provider)));
+    }
+
+    /// Convert this authentication into one using the given endpoint interface.
+    #[inline]
+    pub fn with_default_endpoint_interface(mut self, endpoint_interface: InterfaceType) -> Self {
+        self.set_default_endpoint_interface(endpoint_interface);
+        self
+    }
+
+    /// Convert this authentication into one fetching the password from the provider.
+    #[inline]
+    pub fn with_credentials_provider<P>(mut self, provider: P) -> Self
+    where
+        P: CredentialsProvider + 'static,
+    {
+        self.set_credentials_provider(provider);
+        self
+    }
+
+    /// Convert this authentication into one sending a TOTP passcode with the password.
+    #[inline]
+    pub fn with_totp_passcode<S: Into<String>>(mut self, passcode: S) -> Self {
+        self.set_totp_passcode(passcode);
+        self
+    }
+
+    /// Convert this authentication into one fetching a TOTP passcode when required.
+    #[inline]
+    pub fn with_totp_provider<P>(mut self, provider: P) -> Self
+    where
+        P: CredentialsProvider + 'static,
+    {
+        self.set_totp_provider(provider);
+        self
+    }
+
+    /// Add endpoint filters.
+    #[inline]
+    pub fn with_endpoint_filters(mut self, filters: EndpointFilters) -> Self {
+        self.set_endpoint_filters(filters);
+        self
+    }
+
+    /// Set the maximum size of a token response in bytes.
+    #[inline]
+    pub fn with_max_response_size(mut self, max_size: usize) -> Self {
+        self.set_max_response_size(max_size);
+        self
+    }
+
+    /// Set a hook called after each authentication attempt.
+    #[inline]
+    pub fn with_auth_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&AuthEvent) + Send + Sync + 'static,
+    {
+        self.set_auth_hook(hook);
+        self
+    }
+
+    /// Enable discovery of the Identity API v3 endpoint.
+    ///
+    /// See [set_version_discovery](#method.set_version_discovery) for details.
+    #[inline]
+    pub fn with_version_discovery(mut self, enabled: bool) -> Self {
+        self.set_version_discovery(enabled);
+        self
+    }
+
+    /// Convert this authentication into one using the given time out for authentication requests.
+    #[inline]
+    pub fn with_auth_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.set_auth_timeout(Some(timeout));
+        self
+    }
+
+    /// Convert this authentication into one sharing tokens via an on-disk cache.
+    ///
+    /// See [set_token_cache](#method.set_token_cache) for details.
+    #[cfg(feature = "token-cache")]
+    #[inline]
+    pub fn with_token_cache(mut self, enabled: bool) -> Self {
+        self.set_token_cache(enabled);
+        self
+    }
+
+    /// Convert this authentication into one refreshing the token at the given time before its
+    /// expiration.
+    #[inline]
+    pub fn with_token_refresh_skew(mut self, skew: std::time::Duration) -> Result<Self, Error> {
+        self.set_token_refresh_skew(skew)?;
+        Ok(self)
+    }
+
+    /// Scope authentication to the given project.
+    ///
+    /// A convenience wrapper around `with_scope`.
+    #[inline]
+    pub fn with_project_scope(
+        mut self,
+        project: IdOrName,
+        domain: impl Into<Option<IdOrName>>,
+    ) -> Password {
+        self.set_project_scope(project, domain);
+        self
+    }
+
+    /// Scope authentication to the trust with the given ID.
+    ///
+    /// A convenience wrapper around `with_scope`.
+    #[inline]
+    pub fn with_trust_scope<S: Into<String>>(mut self, trust_id: S) -> Self {
+        self.set_trust_scope(trust_id);
+        self
+    }
+
+    /// Convert this authentication into one with the given user domain ID or name.
+    #[inline]
+    pub fn with_user_domain(mut self, domain: IdOrName) -> Self {
+        self.set_user_domain(domain);
+        self
+    }
+
+    /// Set a region for this authentication method.
+    #[inline]
+    pub fn with_region<S>(mut self, region: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.endpoint_filters_mut().set_region(region);
+        self
+    }
+
+    /// Add a scope to the authentication.
+    #[inline]
+    pub fn with_scope(mut self, scope: Scope) -> Self {
+        self.set_scope(scope);
+        self
+    }
+
+    /// User name or ID.
+    #[inline]
+    pub fn user(&self) -> &IdOrName {
+        match self.inner.identity() {
+            protocol::Identity::Password(ref pw) => &pw.user,
+            _ => unreachable!(),
+        }
+    }
+
+    /// User domain name or ID.
+    #[inline]
+    pub fn user_domain(&self) -> Option<&IdOrName> {
+        match self.inner.identity() {
+            protocol::Identity::Password(ref pw) => pw.domain.as_ref(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Project name or ID (if project scoped).
+    #[inline]
+    pub fn project(&self) -> Option<&IdOrName> {
+        self.inner.project()
+    }
+}
+
+#[async_trait]
+impl AuthType for Password {
+    /// The authentication URL.
+    fn identity_url(&self) -> Option<&Url> {
+        Some(self.inner.auth_url())
+    }
+
+    /// Endpoint filters in use.
+    fn default_filters(&self) -> Option<&EndpointFilters> {
+        Some(self.inner.endpoint_filters())
+    }
+
+    /// Password authentication can always obtain a new token.
+    fn can_reauthenticate(&self) -> bool {
+        true
+    }
+
+    /// Report token requests to the metrics.
+    fn set_metrics(&self, metrics: Arc<dyn Metrics>) {
+        self.inner.set_metrics(metrics);
+    }
+
+    /// A copy with the same credentials and a different scope.
+    fn with_new_scope(&self, scope: Scope) -> Option<Box<dyn AuthType>> {
+        Some(Box::new(self.clone().with_scope(scope)))
+    }
+
+    /// Create an authenticated request.
+    async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+        self.inner.request(method, url).await
+    }
+
+    /// The service catalog received with the token.
+    async fn raw_catalog(&self) -> Result<Option<serde_json::Value>, Error> {
+        self.inner.raw_catalog().await.map(Some)
+    }
+
+    /// Information about the current token.
+    async fn token_info(&self) -> Result<Option<TokenInfo>, Error> {
+        self.inner.token_info().await.map(Some)
+    }
+
+    /// Get a URL for the requested service.
+    async fn get_endpoint(
+        &self,
+        service_type: String,
+        filters: EndpointFilters,
+    ) -> Result<Url, Error> {
+        self.inner.get_endpoint(service_type, filters).await
+    }
+
+    /// Refresh the cached token and service catalog.
+    async fn refresh(&self) -> Result<(), Error> {
+        self.inner.do_refresh(true).await
+    }
+
+    /// Revoke the cached token (if any).
+    async fn revoke(&self) -> Result<(), Error> {
+        self.inner.revoke().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(unused_results)]
+
+    use std::time::Duration as StdDuration;
+
+    use super::{IdOrName, Identity, Password};
+
+    #[test]
+    fn test_identity_new() {
+        let id = Password::new("http://127.0.0.1:8080/", "admin", "pa$$w0rd", "Default").unwrap();
+        let e = id.auth_url();
+        assert_eq!(e.scheme(), "http");
+        assert_eq!(e.host_str().unwrap(), "127.0.0.1");
+        assert_eq!(e.port().unwrap(), 8080u16);
+        assert_eq!(e.path(), "/");
+        assert_eq!(id.user(), &IdOrName::Name("admin".to_string()));
+    }
+
+    #[test]
+    fn test_identity_user_domain() {
+        let id = Password::new("http://127.0.0.1:8080/", "admin", "pa$$w0rd", "Default").unwrap();
+        assert_eq!(
+            id.user_domain(),
+            Some(&IdOrName::Name("Default".to_string()))
+        );
+        let id = id.with_user_domain(IdOrName::Id("default".to_string()));
+        assert_eq!(id.user_domain(), Some(&IdOrName::Id("default".to_string())));
+    }
+
+    #[test]
+    fn test_identity_new_invalid() {
+        Password::new("http://127.0.0.1 8080/", "admin", "pa$$w0rd", "Default")
+            .err()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_identity_create() {
+        let id = Password::new(
+            "http://127.0.0.1:8080/identity",
+            "user",
+            "pa$$w0rd",
+            "example.com",
+        )
+        .unwrap()
+        .with_project_scope(
+            IdOrName::Name("cool project".to_string()),
+            IdOrName::Name("example.com".to_string()),
+        );
+        assert_eq!(id.auth_url().to_string(), "http://127.0.0.1:8080/identity");
+        assert_eq!(id.user(), &IdOrName::Name("user".to_string()));
+        assert_eq!(
+            id.project(),
+            Some(&IdOrName::Name("cool project".to_string()))
+        );
+        assert_eq!(
+            id.inner.token_endpoint(),
+            "http://127.0.0.1:8080/identity/v3/auth/tokens"
+        );
+        assert_eq!(id.endpoint_filters().region(), None);
+    }
+
+    #[test]
+    fn test_token_refresh_skew() {
+        let id = Password::new("http://127.0.0.1:8080/", "admin", "pa$$w0rd", "Default").unwrap();
+        assert_eq!(id.token_refresh_skew(), StdDuration::from_secs(600));
+        let id = id
+            .with_token_refresh_skew(StdDuration::from_secs(30))
+            .unwrap();
+        assert_eq!(id.token_refresh_skew(), StdDuration::from_secs(30));
+        assert_eq!(id.clone().token_refresh_skew(), StdDuration::from_secs(30));
+    }
+
+    #[test]
+    fn test_auth_timeout() {
+        let id = Password::new("http://127.0.0.1:8080/", "admin", "pa$$w0rd", "Default").unwrap();
+        assert_eq!(id.auth_timeout(), Some(StdDuration::from_secs(60)));
+        let mut id = id.with_auth_timeout(StdDuration::from_secs(120));
+        assert_eq!(id.auth_timeout(), Some(StdDuration::from_secs(120)));
+        id.set_auth_timeout(None);
+        assert_eq!(id.auth_timeout(), None);
+    }
+}
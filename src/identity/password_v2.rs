@@ -0,0 +1,807 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Password authentication using the legacy Identity API v2.0.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, FixedOffset, Local};
+use log::{debug, trace, warn};
+use osproto::identity::{CatalogRecord, Endpoint};
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Url};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use super::super::telemetry::{self, Outcome};
+use super::super::{request, url, AuthType, EndpointFilters, Error, ErrorKind, Metrics};
+use super::internal::{read_limited, DEFAULT_MAX_RESPONSE_SIZE};
+use super::{
+    AuthEvent, AuthHook, AuthReporter, IdOrName, Identity, Scope, TokenEntity, TokenInfo, TokenRole,
+};
+
+// Required validity time in minutes, same as for Identity API v3.
+const TOKEN_MIN_VALIDITY: i64 = 10;
+// Default time out for authentication requests in seconds.
+const DEFAULT_AUTH_TIMEOUT: u64 = 60;
+// Keys of endpoint URLs in the v2.0 catalog and the corresponding interfaces.
+const INTERFACE_KEYS: &[(&str, &str)] = &[
+    ("publicURL", "public"),
+    ("internalURL", "internal"),
+    ("adminURL", "admin"),
+];
+
+#[derive(Debug, Deserialize)]
+struct AccessRoot {
+    access: Access,
+}
+
+#[derive(Debug, Deserialize)]
+struct Access {
+    token: AccessToken,
+    #[serde(rename = "serviceCatalog", default)]
+    service_catalog: Vec<Service>,
+    #[serde(default)]
+    user: Option<AccessUser>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccessUser {
+    id: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    roles: Vec<TokenRole>,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    id: String,
+    expires: DateTime<FixedOffset>,
+    #[serde(default)]
+    issued_at: Option<DateTime<FixedOffset>>,
+    #[serde(default)]
+    audit_ids: Vec<String>,
+    #[serde(default)]
+    tenant: Option<TokenEntity>,
+}
+
+impl fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut hasher = DefaultHasher::new();
+        self.id.hash(&mut hasher);
+        write!(
+            f,
+            "AccessToken {{ id: hash({}), expires: {}, .. }}",
+            hasher.finish(),
+            self.expires
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Service {
+    #[serde(rename = "type")]
+    service_type: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    endpoints: Vec<serde_json::Map<String, serde_json::Value>>,
+}
+
+/// A token with the catalog converted to the Identity API v3 format.
+#[derive(Clone)]
+struct Token {
+    value: String,
+    catalog: Vec<CatalogRecord>,
+    raw_catalog: serde_json::Value,
+    info: TokenInfo,
+}
+
+impl Outcome for (Token, Option<String>) {
+    #[cfg(feature = "tracing")]
+    fn record(&self, span: &tracing_crate::Span) {
+        if let Some(ref request_id) = self.1 {
+            let _ = span.record("request_id", request_id.as_str());
+        }
+    }
+}
+
+/// Password authentication using the legacy Identity API v2.0.
+///
+/// Only use it for clouds that do not provide Identity API v3, prefer
+/// [Password](struct.Password.html) otherwise. Instead of a project in a domain, Identity API
+/// v2.0 uses a tenant (the historical name of a project), users have no domains.
+///
+/// ```rust,no_run
+/// use osauth::identity::IdOrName;
+///
+/// let auth = osauth::identity::PasswordV2::new(
+///     "https://cloud.local/identity/v2.0",
+///     "admin",
+///     "pa$$w0rd",
+/// )
+/// .expect("Invalid auth_url")
+/// .with_tenant(IdOrName::Name("project1".to_string()));
+///
+/// let session = osauth::Session::new(auth);
+/// ```
+///
+/// The service catalog is converted to the Identity API v3 format, each of the `publicURL`,
+/// `internalURL` and `adminURL` becoming an endpoint with the corresponding interface.
+///
+/// The authentication token is cached while it's still valid or until
+/// [refresh](../trait.AuthType.html#tymethod.refresh) is called.
+/// Clones of a `PasswordV2` also start with an empty cache.
+pub struct PasswordV2 {
+    client: Client,
+    auth_url: Url,
+    token_endpoint: String,
+    reporter: AuthReporter,
+    user_name: String,
+    password: String,
+    tenant: Option<IdOrName>,
+    filters: EndpointFilters,
+    auth_timeout: Option<std::time::Duration>,
+    max_response_size: usize,
+    cached_token: RwLock<Option<Token>>,
+    token_generation: AtomicUsize,
+}
+
+impl fmt::Debug for PasswordV2 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PasswordV2")
+            .field("auth_url", &self.auth_url)
+            .field("user_name", &self.user_name)
+            .field("tenant", &self.tenant)
+            .field("filters", &self.filters)
+            .field("auth_timeout", &self.auth_timeout)
+            .field("max_response_size", &self.max_response_size)
+            .finish()
+    }
+}
+
+impl Clone for PasswordV2 {
+    fn clone(&self) -> PasswordV2 {
+        PasswordV2 {
+            client: self.client.clone(),
+            auth_url: self.auth_url.clone(),
+            token_endpoint: self.token_endpoint.clone(),
+            reporter: self.reporter.clone(),
+            user_name: self.user_name.clone(),
+            password: self.password.clone(),
+            tenant: self.tenant.clone(),
+            filters: self.filters.clone(),
+            auth_timeout: self.auth_timeout,
+            max_response_size: self.max_response_size,
+            cached_token: RwLock::new(None),
+            token_generation: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Identity for PasswordV2 {
+    fn auth_url(&self) -> &Url {
+        &self.auth_url
+    }
+}
+
+impl PasswordV2 {
+    /// Create a password authentication.
+    ///
+    /// The `auth_url` may or may not include the `/v2.0` suffix.
+    pub fn new<U, S1, S2>(auth_url: U, user_name: S1, password: S2) -> Result<PasswordV2, Error>
+    where
+        U: IntoUrl,
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        PasswordV2::new_with_client(auth_url, request::default_client(), user_name, password)
+    }
+
+    /// Create a password authentication with the provided HTTP client.
+    pub fn new_with_client<U, S1, S2>(
+        auth_url: U,
+        client: Client,
+        user_name: S1,
+        password: S2,
+    ) -> Result<PasswordV2, Error>
+    where
+        U: IntoUrl,
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        let auth_url = auth_url.into_url()?;
+        let token_endpoint = token_endpoint_v2_for(&auth_url);
+        Ok(PasswordV2 {
+            client,
+            auth_url,
+            token_endpoint,
+            reporter: AuthReporter::default(),
+            user_name: user_name.into(),
+            password: password.into(),
+            tenant: None,
+            filters: EndpointFilters::default(),
+            auth_timeout: Some(std::time::Duration::from_secs(DEFAULT_AUTH_TIMEOUT)),
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            cached_token: RwLock::new(None),
+            token_generation: AtomicUsize::new(0),
+        })
+    }
+
+    /// Endpoint filters.
+    #[inline]
+    pub fn endpoint_filters(&self) -> &EndpointFilters {
+        &self.filters
+    }
+
+    /// Mutable endpoint filters.
+    #[inline]
+    pub fn endpoint_filters_mut(&mut self) -> &mut EndpointFilters {
+        &mut self.filters
+    }
+
+    /// Set endpoint filters.
+    #[inline]
+    pub fn set_endpoint_filters(&mut self, filters: EndpointFilters) {
+        self.filters = filters;
+    }
+
+    /// Set a time out for authentication requests.
+    ///
+    /// Defaults to 60 seconds, `None` disables the time out.
+    #[inline]
+    pub fn set_auth_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.auth_timeout = timeout;
+    }
+
+    /// Time out for authentication requests.
+    #[inline]
+    pub fn auth_timeout(&self) -> Option<std::time::Duration> {
+        self.auth_timeout
+    }
+
+    /// Set the cloud name reported to the [auth hook](#method.set_auth_hook).
+    #[inline]
+    pub(crate) fn set_cloud_name(&mut self, cloud_name: String) {
+        self.reporter.set_cloud_name(cloud_name);
+    }
+
+    /// Set a hook called after each authentication attempt.
+    ///
+    /// See [Password::set_auth_hook](struct.Password.html#method.set_auth_hook).
+    pub fn set_auth_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&AuthEvent) + Send + Sync + 'static,
+    {
+        self.reporter.set_hook(AuthHook::new(hook));
+    }
+
+    /// Set the maximum size of a token response in bytes.
+    ///
+    /// Defaults to 64 MiB, see
+    /// [Password::set_max_response_size](struct.Password.html#method.set_max_response_size).
+    #[inline]
+    pub fn set_max_response_size(&mut self, max_size: usize) {
+        self.max_response_size = max_size;
+    }
+
+    /// Scope authentication to the given tenant (project) ID or name.
+    #[inline]
+    pub fn set_tenant(&mut self, tenant: IdOrName) {
+        self.tenant = Some(tenant);
+    }
+
+    /// Add a scope to the authentication.
+    ///
+    /// The project becomes the tenant, its domain is ignored since Identity API v2.0 has no
+    /// domains. Domain and system scopes are not supported and ignored with a warning.
+    pub fn set_scope(&mut self, scope: Scope) {
+        match scope {
+            Scope::Project { project, .. } => self.set_tenant(project),
+            other => warn!(
+                "Identity API v2.0 only supports project scopes, ignoring {:?}",
+                other
+            ),
+        }
+    }
+
+    /// Add endpoint filters.
+    #[inline]
+    pub fn with_endpoint_filters(mut self, filters: EndpointFilters) -> Self {
+        self.set_endpoint_filters(filters);
+        self
+    }
+
+    /// Convert this authentication into one using the given time out for authentication requests.
+    #[inline]
+    pub fn with_auth_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.set_auth_timeout(Some(timeout));
+        self
+    }
+
+    /// Set the maximum size of a token response in bytes.
+    #[inline]
+    pub fn with_max_response_size(mut self, max_size: usize) -> Self {
+        self.set_max_response_size(max_size);
+        self
+    }
+
+    /// Set a hook called after each authentication attempt.
+    #[inline]
+    pub fn with_auth_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&AuthEvent) + Send + Sync + 'static,
+    {
+        self.set_auth_hook(hook);
+        self
+    }
+
+    /// Set a region for this authentication method.
+    #[inline]
+    pub fn with_region<S>(mut self, region: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.filters.set_region(region);
+        self
+    }
+
+    /// Scope authentication to the given tenant (project) ID or name.
+    #[inline]
+    pub fn with_tenant(mut self, tenant: IdOrName) -> Self {
+        self.set_tenant(tenant);
+        self
+    }
+
+    /// Add a scope to the authentication.
+    #[inline]
+    pub fn with_scope(mut self, scope: Scope) -> Self {
+        self.set_scope(scope);
+        self
+    }
+
+    /// User name.
+    #[inline]
+    pub fn user(&self) -> &str {
+        &self.user_name
+    }
+
+    /// Tenant (project) name or ID (if scoped).
+    #[inline]
+    pub fn tenant(&self) -> Option<&IdOrName> {
+        self.tenant.as_ref()
+    }
+
+    /// Request body for authentication.
+    fn auth_body(&self) -> serde_json::Value {
+        let mut auth = serde_json::json!({
+            "passwordCredentials": {
+                "username": self.user_name,
+                "password": self.password,
+            }
+        });
+        match self.tenant {
+            Some(IdOrName::Id(ref id)) => auth["tenantId"] = id.clone().into(),
+            Some(IdOrName::Name(ref name)) => auth["tenantName"] = name.clone().into(),
+            None => {}
+        }
+        serde_json::json!({ "auth": auth })
+    }
+
+    /// Refresh the token if it is missing or expires soon (or unconditionally if `force`).
+    ///
+    /// Concurrent calls result in only one authentication request: the first caller holds the
+    /// write lock while authenticating, the others wait for it and reuse the new token.
+    async fn do_refresh(&self, force: bool) -> Result<(), Error> {
+        let generation = self.token_generation.load(Ordering::SeqCst);
+        if !force && token_alive(&self.cached_token.read().await) {
+            return Ok(());
+        }
+
+        let mut lock = self.cached_token.write().await;
+        // Additonal check in case another thread has updated the token while we were waiting for
+        // the write lock. A forced refresh is also satisfied by a token received meanwhile.
+        let replaced = self.token_generation.load(Ordering::SeqCst) != generation;
+        if (replaced || !force) && token_alive(&lock) {
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        let result = telemetry::authentication(&self.auth_url, &self.token_endpoint)
+            .run(self.fetch_token())
+            .await;
+        let request_id = match result {
+            Ok((_, ref request_id)) => request_id.clone(),
+            Err(ref e) => e.request_id().map(ToString::to_string),
+        };
+        debug!(
+            "Authentication at {} finished with request ID {:?}",
+            self.token_endpoint, request_id
+        );
+        self.reporter
+            .report(&self.auth_url, started, result.is_ok(), request_id);
+        *lock = Some(result?.0);
+        let _ = self.token_generation.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Request a new token, return it with the request ID (if any).
+    async fn fetch_token(&self) -> Result<(Token, Option<String>), Error> {
+        let mut builder = self
+            .client
+            .post(&self.token_endpoint)
+            .json(&self.auth_body());
+        if let Some(timeout) = self.auth_timeout {
+            builder = builder.timeout(timeout);
+        }
+        let resp = request::check(builder.send().await?).await?;
+        let request_id = request::request_id(&resp);
+        let with_request_id = |e: Error| match request_id {
+            Some(ref request_id) => e.with_request_id(request_id.clone()),
+            None => e,
+        };
+        let body = read_limited(resp, self.max_response_size)
+            .await
+            .map_err(with_request_id)?;
+        let root = serde_json::from_slice::<AccessRoot>(&body)
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidResponse,
+                    format!("Invalid token received: {}", e),
+                )
+            })
+            .map_err(with_request_id)?;
+        Ok((token_from_access(root.access), request_id))
+    }
+
+    /// Run a function on the valid token, refreshing it if needed.
+    async fn with_token<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Token) -> T + Send,
+    {
+        self.do_refresh(false).await?;
+        let lock = self.cached_token.read().await;
+        // unwrap is safe because do_refresh unconditionally populates the token
+        Ok(f(lock.as_ref().unwrap()))
+    }
+}
+
+#[async_trait]
+impl AuthType for PasswordV2 {
+    /// The authentication URL.
+    fn identity_url(&self) -> Option<&Url> {
+        Some(&self.auth_url)
+    }
+
+    /// Endpoint filters in use.
+    fn default_filters(&self) -> Option<&EndpointFilters> {
+        Some(&self.filters)
+    }
+
+    /// Password authentication can always obtain a new token.
+    fn can_reauthenticate(&self) -> bool {
+        true
+    }
+
+    /// Report token requests to the metrics.
+    fn set_metrics(&self, metrics: Arc<dyn Metrics>) {
+        self.reporter.set_metrics(metrics);
+    }
+
+    /// A copy with the same credentials and a different tenant.
+    ///
+    /// Only project scopes are supported.
+    fn with_new_scope(&self, scope: Scope) -> Option<Box<dyn AuthType>> {
+        match scope {
+            Scope::Project { .. } => Some(Box::new(self.clone().with_scope(scope))),
+            _ => None,
+        }
+    }
+
+    /// Create an authenticated request.
+    async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+        let token = self.with_token(|token| token.value.clone()).await?;
+        Ok(self
+            .client
+            .request(method, url)
+            .header("x-auth-token", token))
+    }
+
+    /// The service catalog (in the Identity API v3 format) received with the token.
+    async fn raw_catalog(&self) -> Result<Option<serde_json::Value>, Error> {
+        self.with_token(|token| Some(token.raw_catalog.clone()))
+            .await
+    }
+
+    /// Information about the current token.
+    async fn token_info(&self) -> Result<Option<TokenInfo>, Error> {
+        self.with_token(|token| Some(token.info.clone())).await
+    }
+
+    /// Get a URL for the requested service.
+    async fn get_endpoint(
+        &self,
+        service_type: String,
+        filters: EndpointFilters,
+    ) -> Result<Url, Error> {
+        let real_filters = filters.with_defaults(&self.filters);
+        debug!(
+            "Requesting a catalog endpoint for service '{}', filters {:?}",
+            service_type, real_filters
+        );
+        self.with_token(|token| real_filters.find_in_catalog(&token.catalog, &service_type))
+            .await?
+    }
+
+    /// Refresh the cached token and service catalog.
+    async fn refresh(&self) -> Result<(), Error> {
+        self.do_refresh(true).await
+    }
+}
+
+/// Token endpoint of Identity API v2.0 for the given Identity URL.
+fn token_endpoint_v2_for(identity_url: &Url) -> String {
+    let path = identity_url.path().trim_end_matches('/');
+    let segments: &[&str] = if path.ends_with("/v2.0") || path.ends_with("/v2") {
+        &["tokens"]
+    } else {
+        &["v2.0", "tokens"]
+    };
+    url::extend(identity_url.clone(), segments).to_string()
+}
+
+#[inline]
+fn token_alive(token: &impl Deref<Target = Option<Token>>) -> bool {
+    token.deref().as_ref().map_or(false, |value| {
+        let validity_time_left = value.info.expires_at.signed_duration_since(Local::now());
+        trace!("Token is valid for {:?}", validity_time_left);
+        validity_time_left > Duration::minutes(TOKEN_MIN_VALIDITY)
+    })
+}
+
+/// Convert the v2.0 token response, including the catalog, to the Identity API v3 format.
+fn token_from_access(access: Access) -> Token {
+    debug!("Received a token expiring at {}", access.token.expires);
+    let mut catalog = Vec::with_capacity(access.service_catalog.len());
+    let mut raw_catalog = Vec::with_capacity(access.service_catalog.len());
+    for service in access.service_catalog {
+        let mut endpoints = Vec::new();
+        let mut raw_endpoints = Vec::new();
+        for endpoint in &service.endpoints {
+            let region = endpoint.get("region").and_then(|v| v.as_str());
+            let id = endpoint.get("id").and_then(|v| v.as_str());
+            for (key, interface) in INTERFACE_KEYS {
+                let url = match endpoint.get(*key).and_then(|v| v.as_str()) {
+                    Some(url) => url,
+                    None => continue,
+                };
+                endpoints.push(Endpoint {
+                    interface: interface.to_string(),
+                    region: region.unwrap_or_default().to_string(),
+                    url: url.to_string(),
+                });
+                raw_endpoints.push(serde_json::json!({
+                    "interface": interface,
+                    "region": region,
+                    "region_id": region,
+                    "url": url,
+                    "id": id,
+                }));
+            }
+        }
+        raw_catalog.push(serde_json::json!({
+            "type": service.service_type,
+            "name": service.name,
+            "endpoints": raw_endpoints,
+        }));
+        catalog.push(CatalogRecord {
+            service_type: service.service_type,
+            endpoints,
+        });
+    }
+    trace!("Received catalog: {:?}", catalog);
+
+    let (user, roles) = match access.user {
+        Some(user) => (
+            Some(TokenEntity {
+                id: user.id,
+                name: user.name,
+            }),
+            user.roles,
+        ),
+        None => (None, Vec::new()),
+    };
+    Token {
+        info: TokenInfo {
+            issued_at: access.token.issued_at,
+            expires_at: access.token.expires,
+            audit_ids: access.token.audit_ids,
+            methods: vec!["password".to_string()],
+            application_credential: None,
+            user,
+            project: access.token.tenant,
+            domain: None,
+            roles,
+        },
+        value: access.token.id,
+        catalog,
+        raw_catalog: serde_json::Value::Array(raw_catalog),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Local};
+    use reqwest::Method;
+
+    use super::super::super::test::{Mock, MockServer};
+    use super::super::super::{AuthType, EndpointFilters, ErrorKind, InterfaceType};
+    use super::{token_endpoint_v2_for, AccessRoot, IdOrName, PasswordV2, Scope};
+
+    const TOKEN_RESPONSE: &str = r#"{"access": {
+        "token": {"id": "abcdef", "expires": "EXPIRES", "issued_at": "2020-01-20T12:00:00Z",
+                  "audit_ids": ["audit"], "tenant": {"id": "1234", "name": "project1"}},
+        "serviceCatalog": [
+            {"type": "compute", "name": "nova", "endpoints": [
+                {"region": "RegionOne", "id": "1", "publicURL": "http://compute/public",
+                 "internalURL": "http://compute/internal"}
+            ]},
+            {"type": "identity", "name": "keystone", "endpoints": [
+                {"publicURL": "http://identity/v2.0"}
+            ]}
+        ],
+        "user": {"id": "5678", "name": "admin", "roles": [{"name": "admin"}]}
+    }}"#;
+
+    fn token_response() -> String {
+        let expires = (Local::now() + Duration::hours(1)).to_rfc3339();
+        TOKEN_RESPONSE.replace("EXPIRES", &expires)
+    }
+
+    #[test]
+    fn test_token_endpoint() {
+        for (auth_url, expected) in &[
+            (
+                "http://127.0.0.1/identity",
+                "http://127.0.0.1/identity/v2.0/tokens",
+            ),
+            (
+                "http://127.0.0.1/identity/",
+                "http://127.0.0.1/identity/v2.0/tokens",
+            ),
+            ("http://127.0.0.1/v2.0", "http://127.0.0.1/v2.0/tokens"),
+            ("http://127.0.0.1/v2.0/", "http://127.0.0.1/v2.0/tokens"),
+            ("http://127.0.0.1/v2", "http://127.0.0.1/v2/tokens"),
+        ] {
+            assert_eq!(token_endpoint_v2_for(&auth_url.parse().unwrap()), *expected);
+        }
+    }
+
+    #[test]
+    fn test_auth_body() {
+        let id = PasswordV2::new("http://127.0.0.1/identity/v2.0", "admin", "pa$$w0rd").unwrap();
+        assert_eq!(
+            id.auth_body(),
+            serde_json::json!({"auth": {
+                "passwordCredentials": {"username": "admin", "password": "pa$$w0rd"}
+            }})
+        );
+        let id = id.with_scope(Scope::project("project1"));
+        assert_eq!(id.tenant(), Some(&IdOrName::Name("project1".to_string())));
+        assert_eq!(id.auth_body()["auth"]["tenantName"], "project1");
+        let id = id.with_tenant(IdOrName::Id("1234".to_string()));
+        assert_eq!(id.auth_body()["auth"]["tenantId"], "1234");
+        assert!(id.auth_body()["auth"].get("tenantName").is_none());
+        assert!(!format!("{:?}", id).contains("pa$$w0rd"));
+    }
+
+    #[test]
+    fn test_token_from_access() {
+        let root: AccessRoot = serde_json::from_str(&token_response()).unwrap();
+        let token = super::token_from_access(root.access);
+        assert_eq!(token.value, "abcdef");
+        assert_eq!(token.info.audit_ids, vec!["audit".to_string()]);
+        assert_eq!(
+            token.info.user.as_ref().unwrap().name.as_deref(),
+            Some("admin")
+        );
+        assert_eq!(token.info.project.as_ref().unwrap().id, "1234");
+        assert!(token.info.has_role("admin"));
+        assert_eq!(token.catalog.len(), 2);
+        assert_eq!(token.catalog[0].endpoints.len(), 2);
+        assert_eq!(token.catalog[0].endpoints[1].interface, "internal");
+        assert_eq!(token.catalog[1].endpoints[0].region, "");
+        assert_eq!(
+            token.raw_catalog[0]["endpoints"][0],
+            serde_json::json!({"interface": "public", "region": "RegionOne",
+                               "region_id": "RegionOne", "url": "http://compute/public",
+                               "id": "1"})
+        );
+    }
+
+    /// Start a mock server issuing tokens on `/identity/v2.0/tokens`.
+    fn serve_token() -> MockServer {
+        let server = MockServer::start();
+        server.mock(
+            Mock::new(Method::POST, "/identity/v2.0/tokens")
+                .with_header("content-type", "application/json")
+                .with_body(token_response()),
+        );
+        server
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let server = serve_token();
+        let auth_url = server.url().join("identity/v2.0").unwrap();
+        let id = PasswordV2::new(auth_url.as_str(), "admin", "pa$$w0rd")
+            .unwrap()
+            .with_tenant(IdOrName::Name("project1".to_string()));
+        let endpoint = id
+            .get_endpoint(
+                "compute".to_string(),
+                EndpointFilters::default().with_interfaces(InterfaceType::Internal),
+            )
+            .await
+            .unwrap();
+        assert_eq!(endpoint.as_str(), "http://compute/internal");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        let body = requests[0].json::<serde_json::Value>().unwrap();
+        assert_eq!(body["auth"]["tenantName"], "project1");
+
+        // The token is cached.
+        let info = id.token_info().await.unwrap().unwrap();
+        assert_eq!(info.methods, vec!["password".to_string()]);
+        let request = id
+            .request(Method::GET, endpoint)
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()["x-auth-token"], "abcdef");
+    }
+
+    #[tokio::test]
+    async fn test_max_response_size() {
+        let server = serve_token();
+        let auth_url = server.url().join("identity/v2.0").unwrap();
+        let id = PasswordV2::new(auth_url.as_str(), "admin", "pa$$w0rd")
+            .unwrap()
+            .with_max_response_size(512);
+        let err = id.token_info().await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidResponse);
+        assert!(err.to_string().contains("larger than 512 bytes"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_forced_refresh() {
+        let server = serve_token();
+        let auth_url = server.url().join("identity/v2.0").unwrap();
+        let id = PasswordV2::new(auth_url.as_str(), "admin", "pa$$w0rd").unwrap();
+        let _ = id.token_info().await.unwrap();
+        let (r1, r2, r3) = tokio::join!(id.refresh(), id.refresh(), id.refresh());
+        r1.unwrap();
+        r2.unwrap();
+        r3.unwrap();
+        assert_eq!(server.requests().len(), 2);
+    }
+}
@@ -0,0 +1,595 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Identity V3 JSON structures for authentication requests.
+//!
+//! Extends the structures from `osproto` with identities it does not support.
+
+use std::fmt;
+
+use osproto::common::{Root, Version};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+
+pub use osproto::identity::{IdOrName, Project, Token, TokenRoot, UserAndPassword};
+
+/// A reference to a user.
+#[derive(Clone, Debug, Serialize)]
+pub struct User {
+    #[serde(flatten)]
+    pub user: IdOrName,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub domain: Option<IdOrName>,
+}
+
+/// An application credential.
+///
+/// The user is only sent when the credential is referenced by name.
+#[derive(Clone)]
+pub struct ApplicationCredential {
+    pub id: IdOrName,
+    pub secret: String,
+    pub user: Option<User>,
+}
+
+/// A user with a time-based one-time passcode.
+#[derive(Clone)]
+pub struct UserAndPasscode {
+    pub user: IdOrName,
+    pub domain: Option<IdOrName>,
+    pub passcode: String,
+}
+
+/// Authentication identity.
+///
+/// The `Debug` output does not contain passwords and other secrets.
+#[derive(Clone)]
+pub enum Identity {
+    /// Authentication with a user and a password.
+    Password(UserAndPassword),
+    /// Authentication with a token.
+    Token(String),
+    /// Authentication with an application credential.
+    ApplicationCredential(ApplicationCredential),
+    /// Authentication with a TOTP passcode.
+    Totp(UserAndPasscode),
+    /// Several authentication methods in one request.
+    MultiFactor(Vec<Identity>),
+}
+
+impl Identity {
+    /// Replace the secret (the password, the token, etc) of the identity.
+    ///
+    /// For several methods, the secret of the first one is replaced.
+    pub fn set_secret(&mut self, secret: String) {
+        match self {
+            Identity::Password(ref mut pw) => pw.password = secret,
+            Identity::Token(ref mut token) => *token = secret,
+            Identity::ApplicationCredential(ref mut cred) => cred.secret = secret,
+            Identity::Totp(ref mut totp) => totp.passcode = secret,
+            Identity::MultiFactor(ref mut identities) => {
+                if let Some(first) = identities.first_mut() {
+                    first.set_secret(secret);
+                }
+            }
+        }
+    }
+
+    /// Names of the authentication methods.
+    fn methods(&self, result: &mut Vec<&'static str>) {
+        match self {
+            Identity::Password(..) => result.push("password"),
+            Identity::Token(..) => result.push("token"),
+            Identity::ApplicationCredential(..) => result.push("application_credential"),
+            Identity::Totp(..) => result.push("totp"),
+            Identity::MultiFactor(ref identities) => {
+                for identity in identities {
+                    identity.methods(result);
+                }
+            }
+        }
+    }
+
+    /// Serialize the fields of all authentication methods.
+    fn serialize_methods<S>(&self, inner: &mut S) -> Result<(), S::Error>
+    where
+        S: SerializeStruct,
+    {
+        match self {
+            Identity::Password(ref user) => {
+                inner.serialize_field("password", &PasswordAuth { user })?
+            }
+            Identity::Token(ref token) => {
+                inner.serialize_field("token", &TokenAuth { id: token })?
+            }
+            Identity::ApplicationCredential(ref cred) => {
+                inner.serialize_field("application_credential", cred)?
+            }
+            Identity::Totp(ref totp) => inner.serialize_field(
+                "totp",
+                &TotpAuth {
+                    user: TotpUser {
+                        user: &totp.user,
+                        domain: totp.domain.as_ref(),
+                        passcode: &totp.passcode,
+                    },
+                },
+            )?,
+            Identity::MultiFactor(ref identities) => {
+                for identity in identities {
+                    identity.serialize_methods(inner)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A reference to a trust.
+#[derive(Clone, Debug, Serialize)]
+pub struct Trust {
+    pub id: String,
+}
+
+/// Authentication scope.
+///
+/// Same as in `osproto` with the addition of trusts.
+#[derive(Clone, Debug, Serialize)]
+pub enum Scope {
+    /// Project scope.
+    #[serde(rename = "project")]
+    Project(Project),
+    /// Domain scope.
+    #[serde(rename = "domain")]
+    Domain(IdOrName),
+    /// System scope.
+    #[serde(rename = "system", serialize_with = "ser_system_scope")]
+    System,
+    /// Trust scope.
+    #[serde(rename = "OS-TRUST:trust")]
+    Trust(Trust),
+}
+
+/// An authentication object.
+#[derive(Clone, Debug, Serialize)]
+pub struct Auth {
+    /// Authentication identity.
+    pub identity: Identity,
+    /// Authentication scope (if needed).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<Scope>,
+}
+
+/// An authentication request root.
+#[derive(Clone, Debug, Serialize)]
+pub struct AuthRoot {
+    pub auth: Auth,
+}
+
+/// An authentication receipt returned when more authentication methods are required.
+#[derive(Debug, Deserialize)]
+pub struct Receipt {
+    /// Methods that have already succeeded.
+    #[serde(default)]
+    pub methods: Vec<String>,
+}
+
+/// A response with an authentication receipt.
+#[derive(Debug, Deserialize)]
+pub struct ReceiptRoot {
+    pub receipt: Receipt,
+    /// Combinations of methods, any of which is sufficient for authentication.
+    #[serde(default)]
+    pub required_auth_methods: Vec<Vec<String>>,
+}
+
+impl ReceiptRoot {
+    /// The smallest set of methods that still has to succeed.
+    pub fn missing_methods(&self) -> Vec<&str> {
+        self.required_auth_methods
+            .iter()
+            .map(|rule| {
+                rule.iter()
+                    .map(String::as_str)
+                    .filter(|method| !self.receipt.methods.iter().any(|m| m == method))
+                    .collect::<Vec<_>>()
+            })
+            .min_by_key(Vec::len)
+            .unwrap_or_default()
+    }
+}
+
+/// Versions as returned by the Identity service root.
+#[derive(Debug, Deserialize)]
+pub struct VersionValues {
+    pub values: Vec<Version>,
+}
+
+/// A version discovery document of the Identity service.
+///
+/// Keystone wraps the list of versions in an additional object, so the generic `Root` is only
+/// used as a fallback.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum VersionsRoot {
+    /// Keystone-specific list of versions.
+    Identity { versions: VersionValues },
+    /// A generic version discovery document.
+    Generic(Root),
+}
+
+impl VersionsRoot {
+    /// All versions from the document.
+    pub fn into_versions(self) -> Vec<Version> {
+        match self {
+            VersionsRoot::Identity { versions } => versions.values,
+            VersionsRoot::Generic(Root::MultipleVersions { versions }) => versions,
+            VersionsRoot::Generic(Root::OneVersion { version }) => vec![version],
+        }
+    }
+}
+
+/// Placeholder for secrets in the debug output.
+const REDACTED: &str = "***";
+
+impl fmt::Debug for ApplicationCredential {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ApplicationCredential")
+            .field("id", &self.id)
+            .field("secret", &REDACTED)
+            .field("user", &self.user)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Identity::Password(ref user) => f
+                .debug_struct("Password")
+                .field("user", &user.user)
+                .field("password", &REDACTED)
+                .field("domain", &user.domain)
+                .finish(),
+            Identity::Token(..) => f.debug_tuple("Token").field(&REDACTED).finish(),
+            Identity::ApplicationCredential(ref cred) => {
+                f.debug_tuple("ApplicationCredential").field(cred).finish()
+            }
+            Identity::Totp(ref totp) => f
+                .debug_struct("Totp")
+                .field("user", &totp.user)
+                .field("passcode", &REDACTED)
+                .field("domain", &totp.domain)
+                .finish(),
+            Identity::MultiFactor(ref identities) => {
+                f.debug_tuple("MultiFactor").field(identities).finish()
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PasswordAuth<'a> {
+    user: &'a UserAndPassword,
+}
+
+#[derive(Serialize)]
+struct TokenAuth<'a> {
+    id: &'a str,
+}
+
+#[derive(Serialize)]
+struct TotpUser<'a> {
+    #[serde(flatten)]
+    user: &'a IdOrName,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    domain: Option<&'a IdOrName>,
+    passcode: &'a str,
+}
+
+#[derive(Serialize)]
+struct TotpAuth<'a> {
+    user: TotpUser<'a>,
+}
+
+#[derive(Serialize)]
+struct ApplicationCredentialAuth<'a> {
+    #[serde(flatten)]
+    id: &'a IdOrName,
+    secret: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<&'a User>,
+}
+
+impl Serialize for ApplicationCredential {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let user = match self.id {
+            IdOrName::Id(..) => None,
+            IdOrName::Name(..) => self.user.as_ref(),
+        };
+        ApplicationCredentialAuth {
+            id: &self.id,
+            secret: &self.secret,
+            user,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl Serialize for Identity {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut methods = Vec::new();
+        self.methods(&mut methods);
+        let mut inner = serializer.serialize_struct("Identity", methods.len() + 1)?;
+        inner.serialize_field("methods", &methods)?;
+        self.serialize_methods(&mut inner)?;
+        inner.end()
+    }
+}
+
+fn ser_system_scope<S>(serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut inner = serializer.serialize_struct("System", 1)?;
+    inner.serialize_field("all", &true)?;
+    inner.end()
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use serde::Serialize;
+    use serde_json::Value;
+
+    use super::*;
+
+    /// Compare the serialized value with the expected JSON.
+    pub fn compare<T: Serialize>(expected: &str, value: T) {
+        let expected: Value = serde_json::from_str(expected).unwrap();
+        assert_eq!(serde_json::to_value(value).unwrap(), expected);
+    }
+
+    const APPLICATION_CREDENTIAL_ID: &str = r#"
+{
+    "auth": {
+        "identity": {
+            "methods": [
+                "application_credential"
+            ],
+            "application_credential": {
+                "id": "423f19a4ac1e4f48bbb4180756e6eb6c",
+                "secret": "rEaqvJka48mpv"
+            }
+        }
+    }
+}"#;
+
+    const APPLICATION_CREDENTIAL_NAME: &str = r#"
+{
+    "auth": {
+        "identity": {
+            "methods": [
+                "application_credential"
+            ],
+            "application_credential": {
+                "name": "monitoring",
+                "secret": "rEaqvJka48mpv",
+                "user": {
+                    "name": "admin",
+                    "domain": {
+                        "name": "Default"
+                    }
+                }
+            }
+        }
+    }
+}"#;
+
+    const IDENTITY_ROOT: &str = r#"
+{
+    "versions": {
+        "values": [
+            {
+                "id": "v3.14",
+                "status": "stable",
+                "updated": "2020-04-07T00:00:00Z",
+                "links": [
+                    {
+                        "rel": "self",
+                        "href": "https://cloud.local/identity/v3/"
+                    }
+                ]
+            }
+        ]
+    }
+}"#;
+
+    const TRUST_SCOPE: &str = r#"
+{
+    "auth": {
+        "identity": {
+            "methods": [
+                "token"
+            ],
+            "token": {
+                "id": "abcdef"
+            }
+        },
+        "scope": {
+            "OS-TRUST:trust": {
+                "id": "de0945a"
+            }
+        }
+    }
+}"#;
+
+    const MULTI_FACTOR: &str = r#"
+{
+    "auth": {
+        "identity": {
+            "methods": [
+                "password",
+                "totp"
+            ],
+            "password": {
+                "user": {
+                    "name": "admin",
+                    "domain": {
+                        "name": "Default"
+                    },
+                    "password": "pa$$w0rd"
+                }
+            },
+            "totp": {
+                "user": {
+                    "name": "admin",
+                    "domain": {
+                        "name": "Default"
+                    },
+                    "passcode": "123456"
+                }
+            }
+        }
+    }
+}"#;
+
+    #[test]
+    fn test_multi_factor() {
+        let value = AuthRoot {
+            auth: Auth {
+                identity: Identity::MultiFactor(vec![
+                    Identity::Password(UserAndPassword {
+                        user: IdOrName::from_name("admin"),
+                        password: "pa$$w0rd".to_string(),
+                        domain: Some(IdOrName::from_name("Default")),
+                    }),
+                    Identity::Totp(UserAndPasscode {
+                        user: IdOrName::from_name("admin"),
+                        domain: Some(IdOrName::from_name("Default")),
+                        passcode: "123456".to_string(),
+                    }),
+                ]),
+                scope: None,
+            },
+        };
+        compare(MULTI_FACTOR, value);
+    }
+
+    #[test]
+    fn test_receipt_missing_methods() {
+        let root: ReceiptRoot = serde_json::from_value(serde_json::json!({
+            "receipt": {"methods": ["password"], "expires_at": "2100-01-01T00:00:00Z"},
+            "required_auth_methods": [["password", "totp", "token"], ["password", "totp"]],
+        }))
+        .unwrap();
+        assert_eq!(root.missing_methods(), vec!["totp"]);
+    }
+
+    #[test]
+    fn test_trust_scope() {
+        let value = AuthRoot {
+            auth: Auth {
+                identity: Identity::Token("abcdef".to_string()),
+                scope: Some(Scope::Trust(Trust {
+                    id: "de0945a".to_string(),
+                })),
+            },
+        };
+        compare(TRUST_SCOPE, value);
+    }
+
+    #[test]
+    fn test_system_scope() {
+        let value = serde_json::to_value(Scope::System).unwrap();
+        assert_eq!(value, serde_json::json!({"system": {"all": true}}));
+    }
+
+    #[test]
+    fn test_versions_root_identity() {
+        let root: VersionsRoot = serde_json::from_str(IDENTITY_ROOT).unwrap();
+        let versions = root.into_versions();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].id.0, 3);
+        assert!(versions[0].is_stable());
+    }
+
+    #[test]
+    fn test_identity_debug_redacted() {
+        let identities = vec![
+            Identity::Password(UserAndPassword {
+                user: IdOrName::from_name("admin"),
+                password: "pa$$w0rd".to_string(),
+                domain: None,
+            }),
+            Identity::Token("pa$$w0rd".to_string()),
+            Identity::ApplicationCredential(ApplicationCredential {
+                id: IdOrName::from_id("abcdef"),
+                secret: "pa$$w0rd".to_string(),
+                user: None,
+            }),
+            Identity::MultiFactor(vec![Identity::Totp(UserAndPasscode {
+                user: IdOrName::from_name("admin"),
+                domain: None,
+                passcode: "pa$$w0rd".to_string(),
+            })]),
+        ];
+        for identity in identities {
+            let debug = format!("{:?}", identity);
+            assert!(!debug.contains("pa$$w0rd"), "{}", debug);
+            assert!(debug.contains("***"), "{}", debug);
+        }
+    }
+
+    #[test]
+    fn test_application_credential_id() {
+        let value = AuthRoot {
+            auth: Auth {
+                identity: Identity::ApplicationCredential(ApplicationCredential {
+                    id: IdOrName::from_id("423f19a4ac1e4f48bbb4180756e6eb6c"),
+                    secret: "rEaqvJka48mpv".to_string(),
+                    user: Some(User {
+                        user: IdOrName::from_name("admin"),
+                        domain: Some(IdOrName::from_name("Default")),
+                    }),
+                }),
+                scope: None,
+            },
+        };
+        compare(APPLICATION_CREDENTIAL_ID, value);
+    }
+
+    #[test]
+    fn test_application_credential_name() {
+        let value = AuthRoot {
+            auth: Auth {
+                identity: Identity::ApplicationCredential(ApplicationCredential {
+                    id: IdOrName::from_name("monitoring"),
+                    secret: "rEaqvJka48mpv".to_string(),
+                    user: Some(User {
+                        user: IdOrName::from_name("admin"),
+                        domain: Some(IdOrName::from_name("Default")),
+                    }),
+                }),
+                scope: None,
+            },
+        };
+        compare(APPLICATION_CREDENTIAL_NAME, value);
+    }
+}
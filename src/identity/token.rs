@@ -0,0 +1,412 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Token authentication.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Url};
+
+use super::super::{
+    request, AuthType, EndpointFilters, Error, InterfaceType, Metrics, ValidInterfaces,
+};
+use super::internal::Internal;
+use super::protocol;
+use super::{AuthEvent, AuthHook, IdOrName, Identity, Scope, TokenInfo};
+
+/// Token authentication using Identity API V3.
+///
+/// Uses an existing token to obtain a new one, optionally with a different scope. Since the new
+/// token cannot outlive the original one, authentication stops working once the original token
+/// has expired.
+///
+/// ```rust,no_run
+/// # use osauth::identity::IdOrName;
+/// let auth = osauth::identity::Token::new(
+///     "https://cloud.local/identity",
+///     "<a token>",
+/// )
+/// .expect("Invalid auth_url")
+/// .with_project_scope(IdOrName::Name("project1".to_string()), None);
+///
+/// let session = osauth::Session::new(auth);
+/// ```
+///
+/// The original token can also be read from a file with
+/// [new_from_file](#method.new_from_file), e.g. a projected token that is rotated externally.
+///
+/// The authentication token is cached while it's still valid or until
+/// [refresh](../trait.AuthType.html#tymethod.refresh) is called.
+/// Clones of a `Token` also start with an empty cache.
+#[derive(Clone, Debug)]
+pub struct Token {
+    inner: Internal,
+}
+
+impl Identity for Token {
+    fn auth_url(&self) -> &Url {
+        self.inner.auth_url()
+    }
+}
+
+impl Token {
+    /// Create a token authentication.
+    pub fn new<U, S>(auth_url: U, token: S) -> Result<Token, Error>
+    where
+        U: IntoUrl,
+        S: Into<String>,
+    {
+        Token::new_with_client(auth_url, request::default_client(), token)
+    }
+
+    /// Create a token authentication with the provided HTTP client.
+    pub fn new_with_client<U, S>(auth_url: U, client: Client, token: S) -> Result<Token, Error>
+    where
+        U: IntoUrl,
+        S: Into<String>,
+    {
+        Ok(Token {
+            inner: Internal::new(
+                client,
+                auth_url.into_url()?,
+                protocol::Identity::Token(token.into()),
+            ),
+        })
+    }
+
+    /// Create a token authentication with the token read from a file.
+    ///
+    /// The file is re-read on every authentication, so a rotated token is picked up once the
+    /// current one expires or on refresh. Surrounding whitespace is removed.
+    pub fn new_from_file<U, P>(auth_url: U, path: P) -> Result<Token, Error>
+    where
+        U: IntoUrl,
+        P: AsRef<Path>,
+    {
+        Token::new_from_file_with_client(auth_url, request::default_client(), path)
+    }
+
+    /// Create a token authentication with the token read from a file and the provided HTTP
+    /// client.
+    pub fn new_from_file_with_client<U, P>(
+        auth_url: U,
+        client: Client,
+        path: P,
+    ) -> Result<Token, Error>
+    where
+        U: IntoUrl,
+        P: AsRef<Path>,
+    {
+        let mut inner = Internal::new(
+            client,
+            auth_url.into_url()?,
+            protocol::Identity::Token(String::new()),
+        );
+        inner.set_token_file(path.as_ref().to_path_buf())?;
+        Ok(Token { inner })
+    }
+
+    /// Endpoint filters.
+    #[inline]
+    pub fn endpoint_filters(&self) -> &EndpointFilters {
+        self.inner.endpoint_filters()
+    }
+
+    /// Mutable endpoint filters.
+    #[inline]
+    pub fn endpoint_filters_mut(&mut self) -> &mut EndpointFilters {
+        self.inner.endpoint_filters_mut()
+    }
+
+    /// Set the default endpoint interface to use.
+    ///
+    /// Re-authentication uses the identity endpoint of this interface from the service catalog
+    /// if it is not `public`: the credentials are sent to that endpoint. The authentication URL
+    /// is used if the endpoint cannot be reached.
+    pub fn set_default_endpoint_interface(&mut self, endpoint_interface: InterfaceType) {
+        self.endpoint_filters_mut().interfaces = ValidInterfaces::one(endpoint_interface);
+    }
+
+    /// Set endpoint filters.
+    #[inline]
+    pub fn set_endpoint_filters(&mut self, filters: EndpointFilters) {
+        *self.endpoint_filters_mut() = filters;
+    }
+
+    /// Set a time out for authentication requests.
+    ///
+    /// Defaults to 60 seconds, `None` disables the time out.
+    #[inline]
+    pub fn set_auth_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.inner.set_auth_timeout(timeout);
+    }
+
+    /// Set the cloud name reported to the [auth hook](#method.set_auth_hook).
+    #[inline]
+    pub(crate) fn set_cloud_name(&mut self, cloud_name: String) {
+        self.inner.set_cloud_name(cloud_name);
+    }
+
+    /// Set a hook called after each authentication attempt.
+    ///
+    /// See [Password::set_auth_hook](struct.Password.html#method.set_auth_hook).
+    pub fn set_auth_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&AuthEvent) + Send + Sync + 'static,
+    {
+        self.inner.set_auth_hook(AuthHook::new(hook));
+    }
+
+    /// Set the maximum size of a token response in bytes.
+    ///
+    /// Defaults to 64 MiB, see
+    /// [Password::set_max_response_size](struct.Password.html#method.set_max_response_size).
+    #[inline]
+    pub fn set_max_response_size(&mut self, max_size: usize) {
+        self.inner.set_max_response_size(max_size);
+    }
+
+    /// Enable discovery of the Identity API v3 endpoint.
+    ///
+    /// When enabled, the version document at the auth URL is requested first to find the
+    /// endpoint of a stable Identity API v3 instead of assuming `<auth_url>/v3`. If discovery
+    /// fails, the default endpoint is used. Disabled by default.
+    #[inline]
+    pub fn set_version_discovery(&mut self, enabled: bool) {
+        self.inner.set_version_discovery(enabled);
+    }
+
+    /// Set how long before its expiration the token is refreshed.
+    ///
+    /// Defaults to 10 minutes, see
+    /// [Password::set_token_refresh_skew](struct.Password.html#method.set_token_refresh_skew).
+    #[inline]
+    pub fn set_token_refresh_skew(&mut self, skew: std::time::Duration) -> Result<(), Error> {
+        self.inner.set_token_refresh_skew(skew)
+    }
+
+    /// Scope authentication to the given project.
+    ///
+    /// A convenience wrapper around `set_scope`.
+    #[inline]
+    pub fn set_project_scope(&mut self, project: IdOrName, domain: impl Into<Option<IdOrName>>) {
+        self.set_scope(Scope::Project {
+            project,
+            domain: domain.into(),
+        });
+    }
+
+    /// Scope authentication to the trust with the given ID.
+    ///
+    /// The user must be the trustee of the trust. A convenience wrapper around `set_scope`.
+    #[inline]
+    pub fn set_trust_scope<S: Into<String>>(&mut self, trust_id: S) {
+        self.set_scope(Scope::Trust(trust_id.into()));
+    }
+
+    /// Add a scope to the authentication.
+    #[inline]
+    pub fn set_scope(&mut self, scope: Scope) {
+        self.inner.set_scope(scope);
+    }
+
+    /// Convert this authentication into one using the given endpoint interface.
+    #[inline]
+    pub fn with_default_endpoint_interface(mut self, endpoint_interface: InterfaceType) -> Self {
+        self.set_default_endpoint_interface(endpoint_interface);
+        self
+    }
+
+    /// Add endpoint filters.
+    #[inline]
+    pub fn with_endpoint_filters(mut self, filters: EndpointFilters) -> Self {
+        self.set_endpoint_filters(filters);
+        self
+    }
+
+    /// Set the maximum size of a token response in bytes.
+    #[inline]
+    pub fn with_max_response_size(mut self, max_size: usize) -> Self {
+        self.set_max_response_size(max_size);
+        self
+    }
+
+    /// Set a hook called after each authentication attempt.
+    #[inline]
+    pub fn with_auth_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&AuthEvent) + Send + Sync + 'static,
+    {
+        self.set_auth_hook(hook);
+        self
+    }
+
+    /// Enable discovery of the Identity API v3 endpoint.
+    ///
+    /// See [set_version_discovery](#method.set_version_discovery) for details.
+    #[inline]
+    pub fn with_version_discovery(mut self, enabled: bool) -> Self {
+        self.set_version_discovery(enabled);
+        self
+    }
+
+    /// Scope authentication to the given project.
+    ///
+    /// A convenience wrapper around `with_scope`.
+    #[inline]
+    pub fn with_project_scope(
+        mut self,
+        project: IdOrName,
+        domain: impl Into<Option<IdOrName>>,
+    ) -> Token {
+        self.set_project_scope(project, domain);
+        self
+    }
+
+    /// Scope authentication to the trust with the given ID.
+    ///
+    /// A convenience wrapper around `with_scope`.
+    #[inline]
+    pub fn with_trust_scope<S: Into<String>>(mut self, trust_id: S) -> Self {
+        self.set_trust_scope(trust_id);
+        self
+    }
+
+    /// Set a region for this authentication method.
+    #[inline]
+    pub fn with_region<S>(mut self, region: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.endpoint_filters_mut().set_region(region);
+        self
+    }
+
+    /// Add a scope to the authentication.
+    #[inline]
+    pub fn with_scope(mut self, scope: Scope) -> Self {
+        self.set_scope(scope);
+        self
+    }
+
+    /// Project name or ID (if project scoped).
+    #[inline]
+    pub fn project(&self) -> Option<&IdOrName> {
+        self.inner.project()
+    }
+}
+
+#[async_trait]
+impl AuthType for Token {
+    /// The authentication URL.
+    fn identity_url(&self) -> Option<&Url> {
+        Some(self.inner.auth_url())
+    }
+
+    /// Endpoint filters in use.
+    fn default_filters(&self) -> Option<&EndpointFilters> {
+        Some(self.inner.endpoint_filters())
+    }
+
+    /// A token read from a file can be re-read to obtain a new token.
+    fn can_reauthenticate(&self) -> bool {
+        self.inner.has_token_file()
+    }
+
+    /// Report token requests to the metrics.
+    fn set_metrics(&self, metrics: Arc<dyn Metrics>) {
+        self.inner.set_metrics(metrics);
+    }
+
+    /// A copy with the same credentials and a different scope.
+    fn with_new_scope(&self, scope: Scope) -> Option<Box<dyn AuthType>> {
+        Some(Box::new(self.clone().with_scope(scope)))
+    }
+
+    /// Create an authenticated request.
+    async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+        self.inner.request(method, url).await
+    }
+
+    /// The service catalog received with the token.
+    async fn raw_catalog(&self) -> Result<Option<serde_json::Value>, Error> {
+        self.inner.raw_catalog().await.map(Some)
+    }
+
+    /// Information about the current token.
+    async fn token_info(&self) -> Result<Option<TokenInfo>, Error> {
+        self.inner.token_info().await.map(Some)
+    }
+
+    /// Get a URL for the requested service.
+    async fn get_endpoint(
+        &self,
+        service_type: String,
+        filters: EndpointFilters,
+    ) -> Result<Url, Error> {
+        self.inner.get_endpoint(service_type, filters).await
+    }
+
+    /// Refresh the cached token and service catalog.
+    async fn refresh(&self) -> Result<(), Error> {
+        self.inner.do_refresh(true).await
+    }
+
+    /// Revoke the cached token (if any).
+    async fn revoke(&self) -> Result<(), Error> {
+        self.inner.revoke().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::super::AuthType;
+    use super::{IdOrName, Identity, Token};
+
+    #[test]
+    fn test_token_new() {
+        let id = Token::new("http://127.0.0.1:8080/identity", "abcdef")
+            .unwrap()
+            .with_project_scope(IdOrName::Name("project1".to_string()), None);
+        assert_eq!(id.auth_url().to_string(), "http://127.0.0.1:8080/identity");
+        assert_eq!(id.project(), Some(&IdOrName::Name("project1".to_string())));
+        assert_eq!(
+            id.inner.token_endpoint(),
+            "http://127.0.0.1:8080/identity/v3/auth/tokens"
+        );
+        assert!(!id.can_reauthenticate());
+    }
+
+    #[test]
+    fn test_token_new_from_file() {
+        let path = std::env::temp_dir().join(format!("osauth-token-{}", std::process::id()));
+        std::fs::write(&path, "abcdef\n").unwrap();
+        let id = Token::new_from_file("http://127.0.0.1:8080/identity", &path).unwrap();
+        assert!(id.can_reauthenticate());
+        std::fs::remove_file(&path).unwrap();
+
+        let _ = Token::new_from_file("http://127.0.0.1:8080/identity", &path)
+            .err()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_token_new_invalid() {
+        let _ = Token::new("http://127.0.0.1 8080/", "abcdef")
+            .err()
+            .unwrap();
+    }
+}
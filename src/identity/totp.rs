@@ -0,0 +1,412 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TOTP authentication.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Url};
+
+use super::super::{request, AuthType, EndpointFilters, Error, InterfaceType, Metrics};
+use super::internal::Internal;
+use super::protocol;
+use super::{AuthEvent, AuthHook, CredentialsProvider, IdOrName, Identity, Scope, TokenInfo};
+
+/// Authentication with a time-based one-time passcode (TOTP) using Identity API V3.
+///
+/// Use this type when TOTP is the only authentication method of the user. To combine it with a
+/// password, see [Password::with_totp_passcode](struct.Password.html#method.with_totp_passcode).
+///
+/// Since passcodes expire quickly, a `Totp` created with a fixed passcode cannot re-authenticate.
+/// Use [with_credentials_provider](#method.with_credentials_provider) to fetch a new passcode on
+/// every authentication.
+///
+/// ```rust,no_run
+/// # use osauth::identity::IdOrName;
+/// let auth = osauth::identity::Totp::new(
+///     "https://cloud.local/identity",
+///     "admin",
+///     "123456",
+///     "Default"
+/// )
+/// .expect("Invalid auth_url")
+/// .with_project_scope(IdOrName::Name("project1".to_string()), None);
+///
+/// let session = osauth::Session::new(auth);
+/// ```
+///
+/// The authentication token is cached while it's still valid or until
+/// [refresh](../trait.AuthType.html#tymethod.refresh) is called.
+/// Clones of a `Totp` also start with an empty cache.
+#[derive(Clone, Debug)]
+pub struct Totp {
+    inner: Internal,
+}
+
+impl Identity for Totp {
+    fn auth_url(&self) -> &Url {
+        self.inner.auth_url()
+    }
+}
+
+impl Totp {
+    /// Create a TOTP authentication.
+    pub fn new<U, S1, S2, S3>(
+        auth_url: U,
+        user_name: S1,
+        passcode: S2,
+        user_domain_name: S3,
+    ) -> Result<Totp, Error>
+    where
+        U: IntoUrl,
+        S1: Into<String>,
+        S2: Into<String>,
+        S3: Into<String>,
+    {
+        Totp::new_with_client(
+            auth_url,
+            request::default_client(),
+            user_name,
+            passcode,
+            user_domain_name,
+        )
+    }
+
+    /// Create a TOTP authentication with the provided HTTP client.
+    pub fn new_with_client<U, S1, S2, S3>(
+        auth_url: U,
+        client: Client,
+        user_name: S1,
+        passcode: S2,
+        user_domain_name: S3,
+    ) -> Result<Totp, Error>
+    where
+        U: IntoUrl,
+        S1: Into<String>,
+        S2: Into<String>,
+        S3: Into<String>,
+    {
+        let totp = protocol::UserAndPasscode {
+            user: IdOrName::Name(user_name.into()),
+            passcode: passcode.into(),
+            domain: Some(IdOrName::Name(user_domain_name.into())),
+        };
+        Ok(Totp {
+            inner: Internal::new(client, auth_url.into_url()?, protocol::Identity::Totp(totp)),
+        })
+    }
+
+    /// Endpoint filters.
+    #[inline]
+    pub fn endpoint_filters(&self) -> &EndpointFilters {
+        self.inner.endpoint_filters()
+    }
+
+    /// Mutable endpoint filters.
+    #[inline]
+    pub fn endpoint_filters_mut(&mut self) -> &mut EndpointFilters {
+        self.inner.endpoint_filters_mut()
+    }
+
+    /// Set the default endpoint interface to use.
+    pub fn set_default_endpoint_interface(&mut self, endpoint_interface: InterfaceType) {
+        self.endpoint_filters_mut().interfaces = endpoint_interface.into();
+    }
+
+    /// Set endpoint filters.
+    #[inline]
+    pub fn set_endpoint_filters(&mut self, filters: EndpointFilters) {
+        *self.endpoint_filters_mut() = filters;
+    }
+
+    /// Set a region for this authentication method.
+    #[inline]
+    pub fn set_region<S>(&mut self, region: S)
+    where
+        S: Into<String>,
+    {
+        self.endpoint_filters_mut().set_region(region);
+    }
+
+    /// Set a time out for authentication requests.
+    ///
+    /// Defaults to 60 seconds, `None` disables the time out.
+    #[inline]
+    pub fn set_auth_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.inner.set_auth_timeout(timeout);
+    }
+
+    /// Time out for authentication requests.
+    #[inline]
+    pub fn auth_timeout(&self) -> Option<std::time::Duration> {
+        self.inner.auth_timeout()
+    }
+
+    /// Set a hook called after each authentication attempt.
+    ///
+    /// See [Password::set_auth_hook](struct.Password.html#method.set_auth_hook).
+    pub fn set_auth_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&AuthEvent) + Send + Sync + 'static,
+    {
+        self.inner.set_auth_hook(AuthHook::new(hook));
+    }
+
+    /// Set the maximum size of a token response in bytes.
+    ///
+    /// Defaults to 64 MiB, see
+    /// [Password::set_max_response_size](struct.Password.html#method.set_max_response_size).
+    #[inline]
+    pub fn set_max_response_size(&mut self, max_size: usize) {
+        self.inner.set_max_response_size(max_size);
+    }
+
+    /// Set how long before its expiration the token is refreshed.
+    ///
+    /// Defaults to 10 minutes, see
+    /// [Password::set_token_refresh_skew](struct.Password.html#method.set_token_refresh_skew).
+    #[inline]
+    pub fn set_token_refresh_skew(&mut self, skew: std::time::Duration) -> Result<(), Error> {
+        self.inner.set_token_refresh_skew(skew)
+    }
+
+    /// Set the domain of the user.
+    pub fn set_user_domain(&mut self, domain: IdOrName) {
+        match self.inner.identity_mut() {
+            protocol::Identity::Totp(ref mut totp) => totp.domain = Some(domain),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Scope authentication to the given project.
+    ///
+    /// A convenience wrapper around `set_scope`.
+    #[inline]
+    pub fn set_project_scope(&mut self, project: IdOrName, domain: impl Into<Option<IdOrName>>) {
+        self.set_scope(Scope::Project {
+            project,
+            domain: domain.into(),
+        });
+    }
+
+    /// Add a scope to the authentication.
+    #[inline]
+    pub fn set_scope(&mut self, scope: Scope) {
+        self.inner.set_scope(scope);
+    }
+
+    /// Fetch the passcode from the provider on every authentication.
+    ///
+    /// The passcode passed to [new](#method.new) is ignored afterwards.
+    #[inline]
+    pub fn set_credentials_provider<P>(&mut self, provider: P)
+    where
+        P: CredentialsProvider + 'static,
+    {
+        self.inner.set_credentials_provider(Arc::new(provider));
+    }
+
+    /// Convert this authentication into one using the given endpoint interface.
+    #[inline]
+    pub fn with_default_endpoint_interface(mut self, endpoint_interface: InterfaceType) -> Self {
+        self.set_default_endpoint_interface(endpoint_interface);
+        self
+    }
+
+    /// Convert this authentication into one fetching the passcode from the provider.
+    #[inline]
+    pub fn with_credentials_provider<P>(mut self, provider: P) -> Self
+    where
+        P: CredentialsProvider + 'static,
+    {
+        self.set_credentials_provider(provider);
+        self
+    }
+
+    /// Add endpoint filters.
+    #[inline]
+    pub fn with_endpoint_filters(mut self, filters: EndpointFilters) -> Self {
+        self.set_endpoint_filters(filters);
+        self
+    }
+
+    /// Convert this authentication into one using the given time out for authentication requests.
+    #[inline]
+    pub fn with_auth_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.set_auth_timeout(Some(timeout));
+        self
+    }
+
+    /// Set the maximum size of a token response in bytes.
+    #[inline]
+    pub fn with_max_response_size(mut self, max_size: usize) -> Self {
+        self.set_max_response_size(max_size);
+        self
+    }
+
+    /// Set a hook called after each authentication attempt.
+    #[inline]
+    pub fn with_auth_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&AuthEvent) + Send + Sync + 'static,
+    {
+        self.set_auth_hook(hook);
+        self
+    }
+
+    /// Scope authentication to the given project.
+    ///
+    /// A convenience wrapper around `with_scope`.
+    #[inline]
+    pub fn with_project_scope(
+        mut self,
+        project: IdOrName,
+        domain: impl Into<Option<IdOrName>>,
+    ) -> Totp {
+        self.set_project_scope(project, domain);
+        self
+    }
+
+    /// Set the domain of the user.
+    #[inline]
+    pub fn with_user_domain(mut self, domain: IdOrName) -> Self {
+        self.set_user_domain(domain);
+        self
+    }
+
+    /// Set a region for this authentication method.
+    #[inline]
+    pub fn with_region<S>(mut self, region: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.set_region(region);
+        self
+    }
+
+    /// Add a scope to the authentication.
+    #[inline]
+    pub fn with_scope(mut self, scope: Scope) -> Self {
+        self.set_scope(scope);
+        self
+    }
+
+    /// User name or ID.
+    #[inline]
+    pub fn user(&self) -> &IdOrName {
+        match self.inner.identity() {
+            protocol::Identity::Totp(ref totp) => &totp.user,
+            _ => unreachable!(),
+        }
+    }
+
+    /// User domain name or ID.
+    #[inline]
+    pub fn user_domain(&self) -> Option<&IdOrName> {
+        match self.inner.identity() {
+            protocol::Identity::Totp(ref totp) => totp.domain.as_ref(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Project name or ID (if project scoped).
+    #[inline]
+    pub fn project(&self) -> Option<&IdOrName> {
+        self.inner.project()
+    }
+}
+
+#[async_trait]
+impl AuthType for Totp {
+    /// The authentication URL.
+    fn identity_url(&self) -> Option<&Url> {
+        Some(self.inner.auth_url())
+    }
+
+    /// Endpoint filters in use.
+    fn default_filters(&self) -> Option<&EndpointFilters> {
+        Some(self.inner.endpoint_filters())
+    }
+
+    /// Only a provider can supply a new passcode for re-authentication.
+    fn can_reauthenticate(&self) -> bool {
+        self.inner.has_credentials_provider()
+    }
+
+    /// Report token requests to the metrics.
+    fn set_metrics(&self, metrics: Arc<dyn Metrics>) {
+        self.inner.set_metrics(metrics);
+    }
+
+    /// A copy with the same credentials and a different scope.
+    fn with_new_scope(&self, scope: Scope) -> Option<Box<dyn AuthType>> {
+        Some(Box::new(self.clone().with_scope(scope)))
+    }
+
+    /// Create an authenticated request.
+    async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+        self.inner.request(method, url).await
+    }
+
+    /// The service catalog received with the token.
+    async fn raw_catalog(&self) -> Result<Option<serde_json::Value>, Error> {
+        self.inner.raw_catalog().await.map(Some)
+    }
+
+    /// Information about the current token.
+    async fn token_info(&self) -> Result<Option<TokenInfo>, Error> {
+        self.inner.token_info().await.map(Some)
+    }
+
+    /// Get a URL for the requested service.
+    async fn get_endpoint(
+        &self,
+        service_type: String,
+        filters: EndpointFilters,
+    ) -> Result<Url, Error> {
+        self.inner.get_endpoint(service_type, filters).await
+    }
+
+    /// Refresh the cached token and service catalog.
+    async fn refresh(&self) -> Result<(), Error> {
+        self.inner.do_refresh(true).await
+    }
+
+    /// Revoke the cached token (if any).
+    async fn revoke(&self) -> Result<(), Error> {
+        self.inner.revoke().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::super::AuthType;
+    use super::super::EnvCredentials;
+    use super::{IdOrName, Totp};
+
+    #[test]
+    fn test_identity_new() {
+        let id = Totp::new("http://127.0.0.1:8080/", "admin", "123456", "Default").unwrap();
+        assert_eq!(id.user(), &IdOrName::Name("admin".to_string()));
+        assert_eq!(
+            id.user_domain(),
+            Some(&IdOrName::Name("Default".to_string()))
+        );
+        assert!(!format!("{:?}", id).contains("123456"));
+        assert!(!id.can_reauthenticate());
+        let id = id.with_credentials_provider(EnvCredentials::new("OSAUTH_TEST_TOTP"));
+        assert!(id.can_reauthenticate());
+    }
+}
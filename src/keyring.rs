@@ -0,0 +1,164 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Passwords from the operating system secret store.
+
+use std::io;
+use std::process::{Command, Output};
+
+use log::{debug, warn};
+
+use super::{Error, ErrorKind};
+
+/// Service name under which passwords are stored.
+#[cfg(unix)]
+const KEYRING_SERVICE: &str = "openstack";
+
+/// Command to look up the password of the cloud.
+#[cfg(target_os = "macos")]
+fn lookup_command(cloud_name: &str) -> Option<Command> {
+    let mut command = Command::new("security");
+    let _ = command.args([
+        "find-generic-password",
+        "-s",
+        KEYRING_SERVICE,
+        "-a",
+        cloud_name,
+        "-w",
+    ]);
+    Some(command)
+}
+
+/// Command to look up the password of the cloud.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn lookup_command(cloud_name: &str) -> Option<Command> {
+    let mut command = Command::new("secret-tool");
+    let _ = command.args(["lookup", "service", KEYRING_SERVICE, "cloud", cloud_name]);
+    Some(command)
+}
+
+/// Command to look up the password of the cloud.
+#[cfg(not(unix))]
+fn lookup_command(_cloud_name: &str) -> Option<Command> {
+    None
+}
+
+/// Extract the password from the output of the lookup command.
+fn password_from_output(cloud_name: &str, output: Output) -> Result<Option<String>, Error> {
+    if !output.status.success() {
+        debug!(
+            "No password for cloud {} in the secret store: {}",
+            cloud_name, output.status
+        );
+        return Ok(None);
+    }
+
+    let stdout = String::from_utf8(output.stdout).map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidConfig,
+            format!(
+                "Password of cloud {} in the secret store is not valid UTF-8",
+                cloud_name
+            ),
+        )
+    })?;
+    Ok(stdout
+        .lines()
+        .next()
+        .filter(|line| !line.is_empty())
+        .map(ToString::to_string))
+}
+
+/// Look up the password of the cloud in the secret store.
+///
+/// Returns `None` if the secret store is not available or has no password for the cloud.
+/// Fails on platforms where the secret store is not supported (e.g. Windows), so that the
+/// `keyring` feature does not silently do nothing.
+pub(crate) fn lookup_password(cloud_name: &str) -> Result<Option<String>, Error> {
+    let mut command = lookup_command(cloud_name).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidConfig,
+            format!(
+                "The secret store is not supported on this platform, password or \
+                 password_command is required for cloud {}",
+                cloud_name
+            ),
+        )
+    })?;
+
+    debug!(
+        "Looking up the password of cloud {} in the secret store",
+        cloud_name
+    );
+    match command.output() {
+        Ok(output) => password_from_output(cloud_name, output),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            debug!("The secret store tool is not installed");
+            Ok(None)
+        }
+        Err(e) => {
+            warn!("Cannot query the secret store: {}", e);
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(all(test, not(unix)))]
+mod test {
+    use super::super::ErrorKind;
+    use super::lookup_password;
+
+    #[test]
+    fn test_lookup_password_unsupported() {
+        let err = lookup_password("cloud").err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidConfig);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Output};
+
+    use super::password_from_output;
+
+    fn output(code: i32, stdout: &[u8]) -> Output {
+        Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.to_vec(),
+            stderr: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_password_from_output() {
+        let pw = password_from_output("cloud", output(0, b"pa$$w0rd\n")).unwrap();
+        assert_eq!(pw.unwrap(), "pa$$w0rd");
+    }
+
+    #[test]
+    fn test_password_from_output_missing() {
+        assert!(password_from_output("cloud", output(1, b""))
+            .unwrap()
+            .is_none());
+        assert!(password_from_output("cloud", output(0, b"\n"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_password_from_output_invalid() {
+        assert!(password_from_output("cloud", output(0, b"\xff\xfe\n")).is_err());
+    }
+}
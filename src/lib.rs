@@ -25,7 +25,6 @@
 //!
 //! It does NOT provide:
 //! 1. Protocol structures for any services.
-//! 2. Automatic microversion negotiation.
 //!
 //! See [openstack crate](https://crates.io/crates/openstack) for these features.
 //!
@@ -35,6 +34,60 @@
 //! [futures](https://crates.io/crates/futures) 0.1. It has not yet been updated for the new
 //! `async`/`await` syntax and may not be compatible with it out-of-box.
 //!
+//! # TLS backends
+//!
+//! The TLS backend used for all HTTP requests (including authentication) is selected at build
+//! time via cargo features:
+//! * `native-tls` (the default) uses the platform TLS library (OpenSSL on Linux).
+//! * `rustls-tls` uses [rustls](https://crates.io/crates/rustls) and does not require linking to
+//!   OpenSSL, which is handy for static musl builds.
+//!
+//! To use rustls, disable the default features:
+//!
+//! ```toml
+//! [dependencies]
+//! osauth = { version = "0.3", default-features = false, features = ["config-file", "rustls-tls", "stream", "sync"] }
+//! ```
+//!
+//! With neither backend enabled, the crate only talks plain HTTP and TLS settings (CA
+//! certificates, client certificates, a minimum TLS version or `insecure`) are rejected with
+//! `ErrorKind::InvalidConfig`. This is useful when TLS is terminated elsewhere (e.g. by a local
+//! proxy). Alternatively, HTTP clients configured elsewhere can be passed to the `*_with_client`
+//! constructors of the authentication types.
+//!
+//! # Configuration files
+//!
+//! Support for `clouds.yaml` (including [from_config](fn.from_config.html) and `OS_CLOUD` in
+//! [from_env](fn.from_env.html)) is enabled by the default `config-file` feature. Disable it to
+//! avoid the `dirs` and `serde_yaml` dependencies when sessions are only created from
+//! parameters or environment variables.
+//!
+//! The optional `keyring` feature allows keeping passwords of clouds from `clouds.yaml` in the
+//! operating system secret store instead of plain text files, see
+//! [from_config](fn.from_config.html) for details.
+//!
+//! The optional `token-cache` feature allows sharing tokens between processes via an on-disk
+//! cache, so that e.g. CLI tools do not authenticate on every invocation, see
+//! [Password::set_token_cache](identity/struct.Password.html#method.set_token_cache).
+//!
+//! The optional `ec2` feature allows obtaining EC2 credentials and signing requests to
+//! S3-compatible APIs with them, see [ec2](ec2/index.html).
+//!
+//! The optional `temp-url` feature allows creating Object Storage TempURLs and form POST
+//! signatures, see [object_storage](object_storage/index.html).
+//!
+//! The optional `test-util` feature provides a fake authentication type and a mock server for
+//! testing code that uses a `Session`, see [test](test/index.html).
+//!
+//! The optional `tracing` feature emits [tracing](https://docs.rs/tracing) spans for token
+//! issuance (`osauth.auth`), endpoint resolution (`osauth.endpoint`) and every HTTP request
+//! (`osauth.http`) with the method, URL, status, request ID and duration.
+//!
+//! # Proxies
+//!
+//! Proxies are taken from `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` or from the `proxy` of
+//! a cloud. SOCKS5 proxies (e.g. an SSH tunnel to a bastion host) require the `socks` feature.
+//!
 //! # Usage
 //!
 //! Your entry point to the API is the [Session](struct.Session.html) structure. To create it you
@@ -55,6 +108,40 @@
 //! [Adapter::new](struct.Adapter.html#method.new) or from an existing `Session` using
 //! [Session::adapter](struct.Session.html#method.adapter) or
 //! [Session::into_adapter](struct.Session.html#method.into_adapter).
+//!
+//! A [Cloud](struct.Cloud.html) wraps a `Session` created from the configuration and provides
+//! a few high-level calls, similar to the `Connection` object of openstacksdk.
+//!
+//! # Sharing connections
+//!
+//! Every authentication type owns an HTTP client with its own connection pool. A
+//! `reqwest::Client` is reference-counted internally, so its clones share the same pool. To reuse
+//! connections between many sessions (e.g. one per project in a multi-tenant service), create one
+//! client and pass its clones to the authentication types:
+//!
+//! ```rust,no_run
+//! use osauth::identity::{IdOrName, Password};
+//!
+//! let client = reqwest::Client::new();
+//! let sessions = vec!["project1", "project2"]
+//!     .into_iter()
+//!     .map(|project| {
+//!         Password::new_with_client(
+//!             "https://cloud.local/identity",
+//!             client.clone(),
+//!             "admin",
+//!             "pa$$w0rd",
+//!             "Default",
+//!         )
+//!         .map(|auth| {
+//!             osauth::Session::new(auth.with_project_scope(IdOrName::from_name(project), None))
+//!         })
+//!     })
+//!     .collect::<Result<Vec<_>, _>>()
+//!     .expect("Invalid auth_url");
+//! ```
+//!
+//! [NoAuth::new_with_client](struct.NoAuth.html#method.new_with_client) can be used the same way.
 
 #![crate_name = "osauth"]
 #![crate_type = "lib"]
@@ -64,7 +151,6 @@
 // Taken from https://github.com/rust-unofficial/patterns/
 #![deny(
     bare_trait_objects,
-    const_err,
     dead_code,
     improper_ctypes,
     missing_copy_implementations,
@@ -75,7 +161,6 @@
     overflowing_literals,
     path_statements,
     patterns_in_fns_without_body,
-    private_in_public,
     trivial_casts,
     trivial_numeric_casts,
     unconditional_recursion,
@@ -94,11 +179,24 @@
 mod adapter;
 mod apiversion;
 mod auth;
+mod catalog;
+mod cloud;
 mod config;
+pub mod console;
+#[cfg(feature = "ec2")]
+pub mod ec2;
 mod endpointfilters;
 mod error;
+mod health;
 pub mod identity;
+#[cfg(feature = "keyring")]
+mod keyring;
+mod metrics;
+mod middleware;
+#[cfg(feature = "stream")]
+pub mod object_storage;
 mod protocol;
+mod proxy;
 pub mod request;
 pub mod services;
 mod session;
@@ -106,12 +204,43 @@ mod session;
 pub mod stream;
 #[cfg(feature = "sync")]
 pub mod sync;
+mod telemetry;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test;
+mod tls;
 mod url;
 
+/// Blocking API, an alias for the [sync](sync/index.html) module.
+#[cfg(feature = "sync")]
+pub use crate::sync as blocking;
+
+/// Attribute for implementing [AuthType](trait.AuthType.html) (re-exported from `async-trait`).
+pub use async_trait::async_trait;
+
 pub use crate::adapter::Adapter;
 pub use crate::apiversion::ApiVersion;
-pub use crate::auth::{AuthType, NoAuth};
-pub use crate::config::{from_config, from_env};
-pub use crate::endpointfilters::{EndpointFilters, InterfaceType, ValidInterfaces};
-pub use crate::error::{Error, ErrorKind};
+pub use crate::auth::{AdminToken, AuthType, NoAuth};
+pub use crate::catalog::{CatalogEndpoint, CatalogService};
+pub use crate::cloud::Cloud;
+#[cfg(feature = "remote-config")]
+pub use crate::config::from_config_url;
+pub use crate::config::{
+    clear_credential_prompt, derive_auth_url, from_env, from_env_strict, has_config,
+    set_credential_prompt, SessionBuilder,
+};
+#[cfg(feature = "config-file")]
+pub use crate::config::{
+    effective_config, from_config, from_config_or_default, from_config_verbose, list_clouds,
+    normalized_config, validate_config, write_cloud, CloudConfig, CloudSummary, ConfigProblem,
+    NormalizedCloud, RedactedCloud,
+};
+pub use crate::endpointfilters::{
+    select_endpoint, EndpointFilters, InterfaceType, ValidInterfaces,
+};
+pub use crate::error::{Error, ErrorKind, Fault};
+pub use crate::health::{HealthReport, ServiceHealth};
+pub use crate::metrics::{ApiCall, Metrics, PrometheusMetrics};
+pub use crate::middleware::{Middleware, Next};
+pub use crate::protocol::{EndpointInfo, VersionInfo, VersionLink};
 pub use crate::session::Session;
+pub use crate::tls::TlsVersion;
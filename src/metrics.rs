@@ -0,0 +1,344 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Metrics of API calls.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::{Method, StatusCode};
+
+use super::ErrorKind;
+
+/// Upper bounds (in seconds) of the buckets of the request duration histogram.
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// A finished API call.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ApiCall {
+    /// Service type (if the URL belongs to a known endpoint of the session).
+    pub service_type: Option<&'static str>,
+    /// HTTP method (unknown for requests with a streaming body).
+    pub method: Option<Method>,
+    /// HTTP status (unknown if no response has been received).
+    pub status: Option<StatusCode>,
+    /// Time from sending the request to receiving the response headers.
+    pub duration: Duration,
+    /// Kind of the error (if the call has failed).
+    pub error: Option<ErrorKind>,
+}
+
+/// Receiver of metrics of a [Session](struct.Session.html).
+///
+/// All methods do nothing by default. They are called synchronously on the request path, so
+/// they should be fast, e.g. only update counters.
+///
+/// Only requests sent by the session are reported, not the authentication and version
+/// discovery requests. Every attempt of a retried request is reported separately.
+pub trait Metrics: Debug + Send + Sync {
+    /// An API call has finished (successfully or not).
+    fn record_call(&self, _call: &ApiCall) {}
+
+    /// A failed API call is going to be retried according to the retry policy.
+    fn record_retry(&self, _service_type: Option<&'static str>, _method: &Method) {}
+
+    /// The authentication type of the session has requested a token.
+    ///
+    /// Reported for the first token, for the renewals ahead of the token expiration and for the
+    /// re-authentication on HTTP 401. Only authentication types using the Identity service
+    /// report it.
+    fn record_token_refresh(&self, _success: bool) {}
+}
+
+impl<T: Metrics + ?Sized> Metrics for Arc<T> {
+    fn record_call(&self, call: &ApiCall) {
+        (**self).record_call(call)
+    }
+
+    fn record_retry(&self, service_type: Option<&'static str>, method: &Method) {
+        (**self).record_retry(service_type, method)
+    }
+
+    fn record_token_refresh(&self, success: bool) {
+        (**self).record_token_refresh(success)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; DURATION_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS.iter()) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+type Labels = (String, String);
+
+#[derive(Debug, Default)]
+struct Counters {
+    calls: BTreeMap<(String, String, String), u64>,
+    durations: BTreeMap<Labels, Histogram>,
+    retries: BTreeMap<Labels, u64>,
+    token_refreshes: BTreeMap<&'static str, u64>,
+}
+
+/// Metrics collected in memory and rendered in the Prometheus text format.
+///
+/// ```rust
+/// use std::sync::Arc;
+///
+/// let metrics = Arc::new(osauth::PrometheusMetrics::new());
+/// let session = osauth::Session::new(osauth::NoAuth::new("http://127.0.0.1/").unwrap())
+///     .with_metrics(Arc::clone(&metrics));
+/// // Serve this from the /metrics endpoint of your application.
+/// println!("{}", metrics.render());
+/// ```
+///
+/// The following metrics are provided:
+/// * `osauth_api_calls_total` with `service`, `method` and `status` (`error` if no response
+///   was received).
+/// * `osauth_api_call_duration_seconds` histogram with `service` and `method`.
+/// * `osauth_api_call_retries_total` with `service` and `method`.
+/// * `osauth_token_refreshes_total` with `result` (`success` or `failure`).
+///
+/// Unknown services are reported as `unknown`, unknown methods as `UNKNOWN`.
+#[derive(Default)]
+pub struct PrometheusMetrics {
+    counters: Mutex<Counters>,
+}
+
+impl Debug for PrometheusMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PrometheusMetrics").finish()
+    }
+}
+
+fn service_label(service_type: Option<&str>) -> String {
+    service_type.unwrap_or("unknown").to_string()
+}
+
+fn method_label(method: Option<&Method>) -> String {
+    method.map(Method::as_str).unwrap_or("UNKNOWN").to_string()
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl PrometheusMetrics {
+    /// Create empty metrics.
+    #[inline]
+    pub fn new() -> PrometheusMetrics {
+        PrometheusMetrics::default()
+    }
+
+    /// Number of API calls recorded so far.
+    pub fn total_calls(&self) -> u64 {
+        self.lock().calls.values().sum()
+    }
+
+    /// Render the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let counters = self.lock();
+        let mut result = String::new();
+
+        // NOTE: writing to a String never fails.
+        let _ = writeln!(
+            result,
+            "# HELP osauth_api_calls_total Number of API calls.\n\
+             # TYPE osauth_api_calls_total counter"
+        );
+        for ((service, method, status), value) in &counters.calls {
+            let _ = writeln!(
+                result,
+                "osauth_api_calls_total{{service=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+                escape(service),
+                escape(method),
+                status,
+                value
+            );
+        }
+
+        let _ = writeln!(
+            result,
+            "# HELP osauth_api_call_duration_seconds Duration of API calls.\n\
+             # TYPE osauth_api_call_duration_seconds histogram"
+        );
+        for ((service, method), histogram) in &counters.durations {
+            let labels = format!(
+                "service=\"{}\",method=\"{}\"",
+                escape(service),
+                escape(method)
+            );
+            for (bucket, bound) in histogram.buckets.iter().zip(DURATION_BUCKETS.iter()) {
+                let _ = writeln!(
+                    result,
+                    "osauth_api_call_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, bucket
+                );
+            }
+            let _ = writeln!(
+                result,
+                "osauth_api_call_duration_seconds_bucket{{{},le=\"+Inf\"}} {}\n\
+                 osauth_api_call_duration_seconds_sum{{{}}} {}\n\
+                 osauth_api_call_duration_seconds_count{{{}}} {}",
+                labels, histogram.count, labels, histogram.sum, labels, histogram.count
+            );
+        }
+
+        let _ = writeln!(
+            result,
+            "# HELP osauth_api_call_retries_total Number of retried API calls.\n\
+             # TYPE osauth_api_call_retries_total counter"
+        );
+        for ((service, method), value) in &counters.retries {
+            let _ = writeln!(
+                result,
+                "osauth_api_call_retries_total{{service=\"{}\",method=\"{}\"}} {}",
+                escape(service),
+                escape(method),
+                value
+            );
+        }
+
+        let _ = writeln!(
+            result,
+            "# HELP osauth_token_refreshes_total Number of token refreshes.\n\
+             # TYPE osauth_token_refreshes_total counter"
+        );
+        for (outcome, value) in &counters.token_refreshes {
+            let _ = writeln!(
+                result,
+                "osauth_token_refreshes_total{{result=\"{}\"}} {}",
+                outcome, value
+            );
+        }
+
+        result
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Counters> {
+        // NOTE: counters stay consistent even if a thread panicked while holding the lock.
+        self.counters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn record_call(&self, call: &ApiCall) {
+        let service = service_label(call.service_type);
+        let method = method_label(call.method.as_ref());
+        let status = call
+            .status
+            .map(|status| status.as_u16().to_string())
+            .unwrap_or_else(|| "error".to_string());
+        let mut counters = self.lock();
+        *counters
+            .calls
+            .entry((service.clone(), method.clone(), status))
+            .or_default() += 1;
+        counters
+            .durations
+            .entry((service, method))
+            .or_default()
+            .observe(call.duration.as_secs_f64());
+    }
+
+    fn record_retry(&self, service_type: Option<&'static str>, method: &Method) {
+        let key = (service_label(service_type), method_label(Some(method)));
+        *self.lock().retries.entry(key).or_default() += 1;
+    }
+
+    fn record_token_refresh(&self, success: bool) {
+        let outcome = if success { "success" } else { "failure" };
+        *self.lock().token_refreshes.entry(outcome).or_default() += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use reqwest::{Method, StatusCode};
+
+    use super::{ApiCall, Metrics, PrometheusMetrics};
+
+    #[test]
+    fn test_prometheus_metrics() {
+        let metrics = PrometheusMetrics::new();
+        let call = ApiCall {
+            service_type: Some("compute"),
+            method: Some(Method::GET),
+            status: Some(StatusCode::OK),
+            duration: Duration::from_millis(20),
+            error: None,
+        };
+        metrics.record_call(&call);
+        metrics.record_call(&call);
+        metrics.record_call(&ApiCall {
+            service_type: None,
+            method: None,
+            status: None,
+            duration: Duration::from_secs(20),
+            error: None,
+        });
+        metrics.record_retry(Some("compute"), &Method::GET);
+        metrics.record_token_refresh(true);
+        assert_eq!(metrics.total_calls(), 3);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "osauth_api_calls_total{service=\"compute\",method=\"GET\",status=\"200\"} 2\n"
+        ));
+        assert!(rendered.contains(
+            "osauth_api_calls_total{service=\"unknown\",method=\"UNKNOWN\",status=\"error\"} 1\n"
+        ));
+        assert!(rendered.contains(
+            "osauth_api_call_duration_seconds_bucket{service=\"compute\",method=\"GET\",\
+             le=\"0.01\"} 0\n"
+        ));
+        assert!(rendered.contains(
+            "osauth_api_call_duration_seconds_bucket{service=\"compute\",method=\"GET\",\
+             le=\"0.025\"} 2\n"
+        ));
+        assert!(rendered.contains(
+            "osauth_api_call_duration_seconds_bucket{service=\"unknown\",method=\"UNKNOWN\",\
+             le=\"+Inf\"} 1\n"
+        ));
+        assert!(rendered.contains(
+            "osauth_api_call_duration_seconds_count{service=\"compute\",method=\"GET\"} 2\n"
+        ));
+        assert!(rendered
+            .contains("osauth_api_call_retries_total{service=\"compute\",method=\"GET\"} 1\n"));
+        assert!(rendered.contains("osauth_token_refreshes_total{result=\"success\"} 1\n"));
+    }
+}
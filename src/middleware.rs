@@ -0,0 +1,156 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Request middleware.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reqwest::{RequestBuilder, Response};
+
+use super::request;
+use super::telemetry;
+use super::Error;
+
+/// A middleware invoked around every request sent by a [Session](struct.Session.html).
+///
+/// A middleware receives the request and the rest of the chain as [Next](struct.Next.html).
+/// It can modify the request before passing it on, inspect or replace the response, or answer
+/// the request itself without calling `next` at all (e.g. to mock a service in tests).
+///
+/// Middleware sees raw responses: HTTP errors are converted into an `Error` after the whole
+/// chain has finished. Requests retried by the session (on re-authentication or according to
+/// the retry policy) pass through the chain again. Authentication requests are not affected.
+///
+/// ```rust,no_run
+/// use osauth::{Error, Middleware, Next};
+///
+/// #[derive(Debug)]
+/// struct Sudo(String);
+///
+/// #[osauth::async_trait]
+/// impl Middleware for Sudo {
+///     async fn handle(
+///         &self,
+///         request: reqwest::RequestBuilder,
+///         next: Next<'_>,
+///     ) -> Result<reqwest::Response, Error> {
+///         next.run(request.header("X-Auth-Sudo-Project-Id", &self.0)).await
+///     }
+/// }
+///
+/// let session = osauth::from_env()
+///     .expect("Failed to create an identity provider from the environment")
+///     .with_middleware(Sudo("8b2e26a4c6d14dd6a4d0e5b4ec78c7e0".to_string()));
+/// ```
+#[async_trait]
+pub trait Middleware: Debug + Send + Sync {
+    /// Handle the request, usually by calling [Next::run](struct.Next.html#method.run).
+    async fn handle(&self, request: RequestBuilder, next: Next<'_>) -> Result<Response, Error>;
+}
+
+/// The rest of the middleware chain.
+#[derive(Clone, Copy, Debug)]
+pub struct Next<'a> {
+    middleware: &'a [Arc<dyn Middleware>],
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(middleware: &'a [Arc<dyn Middleware>]) -> Next<'a> {
+        Next { middleware }
+    }
+
+    /// Pass the request to the next middleware or send it if there are none left.
+    pub async fn run(self, request: RequestBuilder) -> Result<Response, Error> {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first.handle(request, Next::new(rest)).await,
+            None => request.send().await.map_err(Into::into),
+        }
+    }
+}
+
+/// Send the request through the middleware chain and check its result.
+pub(crate) async fn send_checked(
+    builder: RequestBuilder,
+    middleware: &[Arc<dyn Middleware>],
+) -> Result<Response, Error> {
+    if middleware.is_empty() {
+        return request::send_checked(builder).await;
+    }
+
+    telemetry::http_request(&builder)
+        .run(async move { request::check(Next::new(middleware).run(builder).await?).await })
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use reqwest::{Client, RequestBuilder, Response};
+
+    use super::super::{Error, ErrorKind};
+    use super::{send_checked, Middleware, Next};
+
+    #[derive(Debug, Default)]
+    struct Counter(AtomicUsize);
+
+    #[async_trait]
+    impl Middleware for Counter {
+        async fn handle(&self, request: RequestBuilder, next: Next<'_>) -> Result<Response, Error> {
+            let _ = self.0.fetch_add(1, Ordering::SeqCst);
+            next.run(request.header("x-test", "1")).await
+        }
+    }
+
+    #[derive(Debug)]
+    struct Mock(u16);
+
+    #[async_trait]
+    impl Middleware for Mock {
+        async fn handle(
+            &self,
+            request: RequestBuilder,
+            _next: Next<'_>,
+        ) -> Result<Response, Error> {
+            let request = request.build()?;
+            assert_eq!(request.headers()["x-test"], "1");
+            let response = http::Response::builder()
+                .status(self.0)
+                .body(r#"{"itemNotFound": {"message": "Not found"}}"#)
+                .unwrap();
+            Ok(response.into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_checked() {
+        let counter = Arc::new(Counter::default());
+        let chain: Vec<Arc<dyn Middleware>> = vec![counter.clone(), Arc::new(Mock(200))];
+        let builder = Client::new().get("http://127.0.0.1:1/");
+        let resp = send_checked(builder, &chain).await.unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        let chain: Vec<Arc<dyn Middleware>> = vec![counter.clone(), Arc::new(Mock(404))];
+        let builder = Client::new().get("http://127.0.0.1:1/");
+        let err = send_checked(builder, &chain).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::ResourceNotFound);
+        assert_eq!(err.message(), "Not found");
+        assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+    }
+}
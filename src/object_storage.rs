@@ -0,0 +1,503 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Object Storage (Swift) helpers.
+//!
+//! The Object Storage API works with raw bytes rather than JSON, so these helpers stream object
+//! data instead of loading it in memory:
+//!
+//! ```rust,no_run
+//! use futures::TryStreamExt;
+//!
+//! # async fn example() -> Result<(), osauth::Error> {
+//! let session =
+//!     osauth::from_env().expect("Failed to create an identity provider from the environment");
+//! let data = std::io::Cursor::new(b"127.0.0.1 localhost\n".to_vec());
+//! let _ = osauth::object_storage::put_object_reader(&session, "backups", "hosts", data).await?;
+//!
+//! let mut data = osauth::object_storage::get_object(&session, "backups", "hosts").await?;
+//! while let Some(chunk) = data.try_next().await? {
+//!     println!("Received {} bytes", chunk.len());
+//! }
+//! # Ok(()) }
+//! # #[tokio::main]
+//! # async fn main() { example().await.unwrap(); }
+//! ```
+//!
+//! Objects larger than the maximum object size of the cloud (5 GiB by default) must be uploaded
+//! in segments using [put_large_object](fn.put_large_object.html).
+//!
+//! With the `temp-url` feature, [TempUrl](struct.TempUrl.html) and
+//! [FormPost](struct.FormPost.html) create signatures for access without authentication.
+
+use std::error::Error as StdError;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use futures::stream::{Stream, TryStream, TryStreamExt};
+use reqwest::{Body, Method, Response};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::request::{self, NO_PATH};
+use super::services::OBJECT_STORAGE;
+use super::stream::{session_paginated, Resource};
+use super::{Error, ErrorKind, Session};
+
+#[cfg(feature = "temp-url")]
+pub use self::tempurl::{FormPost, TempUrl, TempUrlDigest};
+
+#[cfg(feature = "temp-url")]
+mod tempurl;
+
+const MANIFEST_HEADER: &str = "x-object-manifest";
+
+/// A container in a listing.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Container {
+    /// Container name.
+    pub name: String,
+    /// Number of objects in the container.
+    #[serde(default)]
+    pub count: u64,
+    /// Total size of the objects in bytes.
+    #[serde(default)]
+    pub bytes: u64,
+}
+
+/// An object in a listing.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Object {
+    /// Object name.
+    pub name: String,
+    /// Object size in bytes.
+    #[serde(default)]
+    pub bytes: u64,
+    /// MD5 checksum of the object (or of the concatenated ETags of the segments).
+    #[serde(default)]
+    pub hash: Option<String>,
+    /// Content type of the object.
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// Last modification time as reported by the server.
+    #[serde(default)]
+    pub last_modified: Option<String>,
+}
+
+impl Resource for Container {
+    type Id = String;
+    type Root = Vec<Container>;
+
+    fn resource_id(&self) -> String {
+        self.name.clone()
+    }
+}
+
+impl Resource for Object {
+    type Id = String;
+    type Root = Vec<Object>;
+
+    fn resource_id(&self) -> String {
+        self.name.clone()
+    }
+}
+
+/// Segmentation of a large object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Segmentation {
+    /// Static large object (SLO): the manifest lists the segments explicitly.
+    Static,
+    /// Dynamic large object (DLO): the manifest refers to all segments with a common prefix.
+    Dynamic,
+}
+
+#[derive(Debug, Serialize)]
+struct Segment {
+    path: String,
+    etag: Option<String>,
+    size_bytes: usize,
+}
+
+#[derive(Serialize)]
+struct ListQuery<'a> {
+    format: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prefix: Option<&'a str>,
+}
+
+/// Path segments of an object.
+///
+/// Object names may contain slashes: splitting on them avoids encoding them as `%2F`.
+fn object_path<'a>(container: &'a str, object: &'a str) -> impl Iterator<Item = &'a str> + Send {
+    std::iter::once(container).chain(object.split('/'))
+}
+
+fn read_error(err: std::io::Error) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("Cannot read object data: {}", err),
+    )
+}
+
+/// List containers of the account.
+///
+/// The listing is fetched in pages of `limit` containers (the server default if `None`).
+pub async fn list_containers(
+    session: &Session,
+    limit: Option<usize>,
+) -> Result<impl Stream<Item = Result<Container, Error>>, Error> {
+    let query = ListQuery {
+        format: "json",
+        prefix: None,
+    };
+    let builder = session
+        .request(OBJECT_STORAGE, Method::GET, NO_PATH, None)
+        .await?
+        .query(&query);
+    Ok(session_paginated(session, builder, limit, None))
+}
+
+/// List objects in a container, optionally only those starting with `prefix`.
+///
+/// The listing is fetched in pages of `limit` objects (the server default if `None`).
+pub async fn list_objects(
+    session: &Session,
+    container: &str,
+    prefix: Option<&str>,
+    limit: Option<usize>,
+) -> Result<impl Stream<Item = Result<Object, Error>>, Error> {
+    let query = ListQuery {
+        format: "json",
+        prefix,
+    };
+    let builder = session
+        .request(OBJECT_STORAGE, Method::GET, &[container], None)
+        .await?
+        .query(&query);
+    Ok(session_paginated(session, builder, limit, None))
+}
+
+/// Create a container (does nothing if it already exists).
+pub async fn create_container(session: &Session, container: &str) -> Result<(), Error> {
+    let _ = session
+        .put_empty(OBJECT_STORAGE, &[container], None)
+        .await?;
+    Ok(())
+}
+
+/// Download an object as a stream of chunks.
+pub async fn get_object(
+    session: &Session,
+    container: &str,
+    object: &str,
+) -> Result<impl Stream<Item = Result<Bytes, Error>>, Error> {
+    let response = session
+        .get(OBJECT_STORAGE, object_path(container, object), None)
+        .await?;
+    Ok(response.bytes_stream().map_err(Error::from))
+}
+
+/// Upload an object with the given body.
+///
+/// The body can be anything convertible to `reqwest::Body`, e.g. bytes or a body created with
+/// `Body::wrap_stream`. Requests with a streaming body are not retried.
+pub async fn put_object<B>(
+    session: &Session,
+    container: &str,
+    object: &str,
+    body: B,
+) -> Result<Response, Error>
+where
+    B: Into<Body>,
+{
+    let builder = session
+        .request(
+            OBJECT_STORAGE,
+            Method::PUT,
+            object_path(container, object),
+            None,
+        )
+        .await?
+        .body(body);
+    session.send_checked(builder).await
+}
+
+/// Upload an object from a stream of chunks.
+pub async fn put_object_stream<S>(
+    session: &Session,
+    container: &str,
+    object: &str,
+    stream: S,
+) -> Result<Response, Error>
+where
+    S: TryStream + Send + Sync + 'static,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+    Bytes: From<S::Ok>,
+{
+    put_object(session, container, object, Body::wrap_stream(stream)).await
+}
+
+/// Upload an object from an asynchronous reader.
+pub async fn put_object_reader<R>(
+    session: &Session,
+    container: &str,
+    object: &str,
+    reader: R,
+) -> Result<Response, Error>
+where
+    R: AsyncRead + Send + Sync + Unpin + 'static,
+{
+    put_object(session, container, object, request::reader_body(reader)).await
+}
+
+/// Upload a large object in segments of `segment_size` bytes.
+///
+/// Segments are stored in the `<container>_segments` container (created if missing) under
+/// `<object>/<slo|dlo>/<timestamp>/<segment_size>/<index>`, then the manifest is stored as
+/// `object`. Each segment is kept in memory while it's being uploaded, so that failed segment
+/// uploads can be retried. Segments are not removed if the upload fails.
+///
+/// An empty reader results in an empty regular object.
+pub async fn put_large_object<R>(
+    session: &Session,
+    container: &str,
+    object: &str,
+    mut reader: R,
+    segment_size: usize,
+    segmentation: Segmentation,
+) -> Result<(), Error>
+where
+    R: AsyncRead + Send + Unpin,
+{
+    if segment_size == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Segment size must be positive",
+        ));
+    }
+
+    let segments_container = format!("{}_segments", container);
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|ts| ts.as_secs())
+        .unwrap_or_default();
+    let kind = match segmentation {
+        Segmentation::Static => "slo",
+        Segmentation::Dynamic => "dlo",
+    };
+    let prefix = format!("{}/{}/{}/{}", object, kind, timestamp, segment_size);
+
+    let mut segments = Vec::new();
+    loop {
+        let mut buffer = Vec::with_capacity(segment_size);
+        let _ = (&mut reader)
+            .take(segment_size as u64)
+            .read_to_end(&mut buffer)
+            .await
+            .map_err(read_error)?;
+        if buffer.is_empty() {
+            break;
+        }
+        if segments.is_empty() {
+            create_container(session, &segments_container).await?;
+        }
+
+        let name = format!("{}/{:08}", prefix, segments.len());
+        let size_bytes = buffer.len();
+        let response = put_object(session, &segments_container, &name, buffer).await?;
+        let etag = response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim_matches('"').to_string());
+        segments.push(Segment {
+            path: format!("/{}/{}", segments_container, name),
+            etag,
+            size_bytes,
+        });
+    }
+
+    if segments.is_empty() {
+        let _ = put_object(session, container, object, Vec::new()).await?;
+        return Ok(());
+    }
+
+    let builder = session
+        .request(
+            OBJECT_STORAGE,
+            Method::PUT,
+            object_path(container, object),
+            None,
+        )
+        .await?;
+    let builder = match segmentation {
+        Segmentation::Static => builder
+            .query(&[("multipart-manifest", "put")])
+            .json(&segments),
+        Segmentation::Dynamic => builder
+            .header(
+                MANIFEST_HEADER,
+                format!("{}/{}/", segments_container, prefix),
+            )
+            .body(Vec::new()),
+    };
+    let _ = session.send_checked(builder).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use futures::TryStreamExt;
+    use reqwest::{RequestBuilder, Response, Url};
+
+    use super::super::services::OBJECT_STORAGE;
+    use super::super::{Error, Middleware, Next, NoAuth, Session};
+    use super::{list_objects, put_large_object, Object, Segmentation};
+
+    const URL: &str = "http://127.0.0.1:1/v1/AUTH_test";
+
+    type Log = Arc<Mutex<Vec<(String, String, Vec<u8>)>>>;
+
+    #[derive(Debug)]
+    struct FakeSwift(Log);
+
+    #[async_trait]
+    impl Middleware for FakeSwift {
+        async fn handle(
+            &self,
+            request: RequestBuilder,
+            _next: Next<'_>,
+        ) -> Result<Response, Error> {
+            let request = request.build()?;
+            let body = request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .unwrap_or_default()
+                .to_vec();
+            let url = request.url();
+            let page = match url.query_pairs().find(|(key, _)| key == "marker") {
+                None => r#"[{"name": "a", "bytes": 1}, {"name": "b", "bytes": 2}]"#,
+                Some((_, marker)) if marker == "b" => r#"[{"name": "c", "bytes": 3}]"#,
+                Some(..) => "[]",
+            };
+            let mut log = self.0.lock().unwrap();
+            log.push((request.method().to_string(), url.to_string(), body));
+            let response = http::Response::builder()
+                .status(if request.method() == "GET" { 200 } else { 201 })
+                .header("etag", format!("\"etag{}\"", log.len()))
+                .body(page)
+                .unwrap();
+            Ok(response.into())
+        }
+    }
+
+    fn new_session() -> (Session, Log) {
+        let log = Log::default();
+        let mut session = Session::new(NoAuth::new(URL).unwrap());
+        session.set_endpoint(OBJECT_STORAGE, Url::parse(URL).unwrap());
+        session.add_middleware(FakeSwift(log.clone()));
+        (session, log)
+    }
+
+    #[tokio::test]
+    async fn test_list_objects() {
+        let (session, log) = new_session();
+        let objects: Vec<Object> = list_objects(&session, "cont", Some("pre"), Some(2))
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let names: Vec<_> = objects.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "c"]);
+        assert_eq!(objects[2].bytes, 3);
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 3);
+        assert_eq!(
+            log[0].1,
+            format!("{}/cont?format=json&prefix=pre&limit=2", URL)
+        );
+        assert_eq!(
+            log[1].1,
+            format!("{}/cont?format=json&prefix=pre&limit=2&marker=b", URL)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_large_object_static() {
+        let (session, log) = new_session();
+        put_large_object(
+            &session,
+            "cont",
+            "dir/big",
+            &b"0123456789"[..],
+            4,
+            Segmentation::Static,
+        )
+        .await
+        .unwrap();
+        let log = log.lock().unwrap();
+        let urls: Vec<_> = log.iter().map(|(_, url, _)| url.as_str()).collect();
+        assert_eq!(urls.len(), 5);
+        assert_eq!(urls[0], format!("{}/cont_segments", URL));
+        assert!(urls[1].starts_with(&format!("{}/cont_segments/dir/big/slo/", URL)));
+        assert!(urls[3].ends_with("/4/00000002"));
+        assert_eq!(log[3].2, b"89");
+        assert_eq!(
+            urls[4],
+            format!("{}/cont/dir/big?multipart-manifest=put", URL)
+        );
+        let manifest: serde_json::Value = serde_json::from_slice(&log[4].2).unwrap();
+        assert_eq!(manifest.as_array().unwrap().len(), 3);
+        assert_eq!(manifest[0]["etag"], "etag2");
+        assert_eq!(manifest[2]["size_bytes"], 2);
+        assert!(manifest[1]["path"]
+            .as_str()
+            .unwrap()
+            .starts_with("/cont_segments/dir/big/slo/"));
+    }
+
+    #[tokio::test]
+    async fn test_put_large_object_dynamic() {
+        let (session, log) = new_session();
+        put_large_object(
+            &session,
+            "cont",
+            "big",
+            &b"0123"[..],
+            4,
+            Segmentation::Dynamic,
+        )
+        .await
+        .unwrap();
+        {
+            let log = log.lock().unwrap();
+            assert_eq!(log.len(), 3);
+            assert_eq!(log[2].1, format!("{}/cont/big", URL));
+            assert!(log[2].2.is_empty());
+        }
+
+        let err = put_large_object(&session, "cont", "big", &b""[..], 0, Segmentation::Static)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), super::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,359 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TempURL and form POST signatures.
+
+use chrono::{DateTime, Utc};
+use reqwest::{Method, Url};
+use ring::hmac;
+
+/// Digest algorithm used for signatures.
+///
+/// The cloud must allow the algorithm, SHA-256 is allowed by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TempUrlDigest {
+    /// HMAC-SHA1 (deprecated in Swift).
+    Sha1,
+    /// HMAC-SHA256.
+    Sha256,
+    /// HMAC-SHA512.
+    Sha512,
+}
+
+impl Default for TempUrlDigest {
+    fn default() -> TempUrlDigest {
+        TempUrlDigest::Sha256
+    }
+}
+
+impl TempUrlDigest {
+    /// Calculate a hex-encoded signature.
+    fn sign(self, key: &str, body: &str) -> String {
+        let algorithm = match self {
+            TempUrlDigest::Sha1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+            TempUrlDigest::Sha256 => hmac::HMAC_SHA256,
+            TempUrlDigest::Sha512 => hmac::HMAC_SHA512,
+        };
+        let key = hmac::Key::new(algorithm, key.as_bytes());
+        hmac::sign(&key, body.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// A temporary URL granting access to an object without authentication.
+///
+/// Signing happens locally using a key set as `X-Account-Meta-Temp-URL-Key` (or
+/// `X-Container-Meta-Temp-URL-Key`), no requests are made.
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// use osauth::object_storage::TempUrl;
+///
+/// let session =
+///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+/// let url = session
+///     .get_endpoint(osauth::services::OBJECT_STORAGE, &["backups", "hosts"])
+///     .await?;
+/// let expires = chrono::Utc::now() + chrono::Duration::hours(1);
+/// let temp_url = TempUrl::new(reqwest::Method::GET, expires).sign(&url, "secret");
+/// println!("Download from {}", temp_url);
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[derive(Clone, Debug)]
+pub struct TempUrl {
+    method: Method,
+    expires: DateTime<Utc>,
+    digest: TempUrlDigest,
+    ip_range: Option<String>,
+}
+
+impl TempUrl {
+    /// Create a TempURL for the given method valid until `expires`.
+    pub fn new(method: Method, expires: DateTime<Utc>) -> TempUrl {
+        TempUrl {
+            method,
+            expires,
+            digest: TempUrlDigest::default(),
+            ip_range: None,
+        }
+    }
+
+    /// Set the digest algorithm (SHA-256 by default).
+    #[inline]
+    pub fn set_digest(&mut self, digest: TempUrlDigest) {
+        self.digest = digest;
+    }
+
+    /// Restrict the URL to the given IP address or CIDR range.
+    #[inline]
+    pub fn set_ip_range<S: Into<String>>(&mut self, ip_range: Option<S>) {
+        self.ip_range = ip_range.map(Into::into);
+    }
+
+    /// Set the digest algorithm (SHA-256 by default).
+    #[inline]
+    pub fn with_digest(mut self, digest: TempUrlDigest) -> Self {
+        self.set_digest(digest);
+        self
+    }
+
+    /// Restrict the URL to the given IP address or CIDR range.
+    #[inline]
+    pub fn with_ip_range<S: Into<String>>(mut self, ip_range: S) -> Self {
+        self.set_ip_range(Some(ip_range));
+        self
+    }
+
+    /// Signature for the object at the given URL.
+    ///
+    /// The path is signed with percent-encoding removed, like Swift verifies it.
+    pub fn signature(&self, url: &Url, key: &str) -> String {
+        let mut body = match self.ip_range {
+            Some(ref ip_range) => format!("ip={}\n", ip_range),
+            None => String::new(),
+        };
+        body.push_str(&format!(
+            "{}\n{}\n{}",
+            self.method,
+            self.expires.timestamp(),
+            decoded_path(url)
+        ));
+        self.digest.sign(key, &body)
+    }
+
+    /// Sign the URL of an object.
+    ///
+    /// The existing query of the URL (if any) is preserved.
+    pub fn sign(&self, url: &Url, key: &str) -> Url {
+        let signature = self.signature(url, key);
+        let mut result = url.clone();
+        {
+            let mut query = result.query_pairs_mut();
+            let _ = query
+                .append_pair("temp_url_sig", &signature)
+                .append_pair("temp_url_expires", &self.expires.timestamp().to_string());
+            if let Some(ref ip_range) = self.ip_range {
+                let _ = query.append_pair("temp_url_ip_range", ip_range);
+            }
+        }
+        result
+    }
+}
+
+/// Path of the URL with percent-encoding removed, as Swift signs it.
+fn decoded_path(url: &Url) -> String {
+    let path = url.path().as_bytes();
+    let mut result = Vec::with_capacity(path.len());
+    let mut idx = 0;
+    while idx < path.len() {
+        let decoded = if path[idx] == b'%' && idx + 2 < path.len() {
+            std::str::from_utf8(&path[idx + 1..idx + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match decoded {
+            Some(byte) => {
+                result.push(byte);
+                idx += 3;
+            }
+            None => {
+                result.push(path[idx]);
+                idx += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+/// Parameters of a form POST upload (the `formpost` middleware).
+///
+/// The [fields](#method.fields) are sent as hidden fields of an HTML form next to the files.
+#[derive(Clone, Debug)]
+pub struct FormPost {
+    redirect: Option<String>,
+    max_file_size: u64,
+    max_file_count: u64,
+    expires: DateTime<Utc>,
+    digest: TempUrlDigest,
+}
+
+impl FormPost {
+    /// Create form POST parameters valid until `expires`.
+    pub fn new(max_file_size: u64, max_file_count: u64, expires: DateTime<Utc>) -> FormPost {
+        FormPost {
+            redirect: None,
+            max_file_size,
+            max_file_count,
+            expires,
+            digest: TempUrlDigest::default(),
+        }
+    }
+
+    /// Set the digest algorithm (SHA-256 by default).
+    #[inline]
+    pub fn set_digest(&mut self, digest: TempUrlDigest) {
+        self.digest = digest;
+    }
+
+    /// Set the URL to redirect to after the upload.
+    #[inline]
+    pub fn set_redirect<S: Into<String>>(&mut self, redirect: Option<S>) {
+        self.redirect = redirect.map(Into::into);
+    }
+
+    /// Set the digest algorithm (SHA-256 by default).
+    #[inline]
+    pub fn with_digest(mut self, digest: TempUrlDigest) -> Self {
+        self.set_digest(digest);
+        self
+    }
+
+    /// Set the URL to redirect to after the upload.
+    #[inline]
+    pub fn with_redirect<S: Into<String>>(mut self, redirect: S) -> Self {
+        self.set_redirect(Some(redirect));
+        self
+    }
+
+    /// Signature for uploads to the given URL (a container with an optional object prefix).
+    pub fn signature(&self, url: &Url, key: &str) -> String {
+        let body = format!(
+            "{}\n{}\n{}\n{}\n{}",
+            decoded_path(url),
+            self.redirect.as_deref().unwrap_or_default(),
+            self.max_file_size,
+            self.max_file_count,
+            self.expires.timestamp()
+        );
+        self.digest.sign(key, &body)
+    }
+
+    /// Form fields for uploads to the given URL, including the signature.
+    pub fn fields(&self, url: &Url, key: &str) -> Vec<(&'static str, String)> {
+        vec![
+            (
+                "redirect",
+                self.redirect.as_deref().unwrap_or_default().to_string(),
+            ),
+            ("max_file_size", self.max_file_size.to_string()),
+            ("max_file_count", self.max_file_count.to_string()),
+            ("expires", self.expires.timestamp().to_string()),
+            ("signature", self.signature(url, key)),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+    use reqwest::{Method, Url};
+
+    use super::{decoded_path, FormPost, TempUrl, TempUrlDigest};
+
+    const URL: &str = "https://swift.local/v1/AUTH_account/container/object";
+
+    #[test]
+    fn test_temp_url() {
+        let url = Url::parse(URL).unwrap();
+        let expires = Utc.timestamp_opt(1440619048, 0).unwrap();
+        let temp_url = TempUrl::new(Method::GET, expires).sign(&url, "mykey");
+        assert_eq!(
+            temp_url.as_str(),
+            format!(
+                "{}?temp_url_sig={}&temp_url_expires=1440619048",
+                URL, "9ef8c448d4184fd16dd4013a1e3349149f895555655621dbf6d6a166f585af72"
+            )
+        );
+
+        let sha1 = TempUrl::new(Method::GET, expires).with_digest(TempUrlDigest::Sha1);
+        assert_eq!(
+            sha1.signature(&url, "mykey"),
+            "da720a7e11f9f2c7b0fe46039811229c1c7a9cb4"
+        );
+        let sha512 = TempUrl::new(Method::GET, expires).with_digest(TempUrlDigest::Sha512);
+        assert_eq!(sha512.signature(&url, "mykey").len(), 128);
+
+        let with_ip = TempUrl::new(Method::GET, expires).with_ip_range("10.0.0.0/24");
+        assert_eq!(
+            with_ip.signature(&url, "mykey"),
+            "aa697c4be574d9bd7110ec4449f867062f45467317a10abefdc29b66b66fd8ed"
+        );
+        assert!(with_ip
+            .sign(&url, "mykey")
+            .as_str()
+            .ends_with("&temp_url_ip_range=10.0.0.0%2F24"));
+    }
+
+    #[test]
+    fn test_temp_url_encoded_name() {
+        // Swift signs the decoded path, i.e. `/v1/AUTH_account/container/my object/é`.
+        let url = Url::parse("https://swift.local/v1/AUTH_account/container/my object/é").unwrap();
+        assert!(url.path().ends_with("/my%20object/%C3%A9"));
+        let expires = Utc.timestamp_opt(1440619048, 0).unwrap();
+        assert_eq!(
+            TempUrl::new(Method::GET, expires).signature(&url, "mykey"),
+            "6e6b817bec52f6e615e5021ad266b547eeb7597851a9245846c50f7f8fca9cf5"
+        );
+    }
+
+    #[test]
+    fn test_decoded_path() {
+        for (path, expected) in &[
+            ("/v1/a/c/o", "/v1/a/c/o"),
+            ("/v1/a/c/my%20object", "/v1/a/c/my object"),
+            ("/v1/a/c/100%25", "/v1/a/c/100%"),
+            ("/v1/a/c/%zz%2", "/v1/a/c/%zz%2"),
+        ] {
+            let url = Url::parse(&format!("https://swift.local{}", path)).unwrap();
+            assert_eq!(decoded_path(&url), *expected);
+        }
+    }
+
+    #[test]
+    fn test_form_post() {
+        let url = Url::parse("https://swift.local/v1/AUTH_account/container/prefix").unwrap();
+        let expires = Utc.timestamp_opt(1440619048, 0).unwrap();
+        let form = FormPost::new(104857600, 10, expires).with_redirect("https://example.com/done");
+        assert_eq!(
+            form.signature(&url, "mykey"),
+            "c1ed793ae7715dd2a1e4fc35f2984abf0c25a4d07a402d1d3f7296af2949cc28"
+        );
+        let fields = form.fields(&url, "mykey");
+        assert_eq!(
+            fields[0],
+            ("redirect", "https://example.com/done".to_string())
+        );
+        assert_eq!(fields[3], ("expires", "1440619048".to_string()));
+
+        let form = FormPost::new(104857600, 10, expires).with_digest(TempUrlDigest::Sha1);
+        assert_eq!(
+            form.signature(&url, "mykey"),
+            "aef20038ad94f9fbe1938ee1982a81d5433d0783"
+        );
+
+        let url = Url::parse("https://swift.local/v1/AUTH_account/container/my prefix").unwrap();
+        assert_eq!(
+            FormPost::new(104857600, 10, expires).signature(&url, "mykey"),
+            "1db6639819ce2a975a260f2623082549733f76fb069a30dd16b3c9feb20a6332"
+        );
+    }
+}
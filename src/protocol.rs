@@ -14,7 +14,10 @@
 
 //! JSON structures and protocol bits for the Identity V3 API.
 
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use log::{debug, trace, warn};
 use osproto::common::{Root, Version};
@@ -38,6 +41,164 @@ pub struct ServiceInfo {
     pub minimum_version: Option<ApiVersion>,
 }
 
+/// Information about a service endpoint received from its version discovery document.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct EndpointInfo {
+    /// URL of the endpoint of the selected major version.
+    pub root_url: Url,
+    /// Major API version (if known).
+    pub major_version: Option<ApiVersion>,
+    /// Current API version (if supported).
+    pub current_version: Option<ApiVersion>,
+    /// Minimum API version (if supported).
+    pub minimum_version: Option<ApiVersion>,
+}
+
+impl From<&ServiceInfo> for EndpointInfo {
+    fn from(value: &ServiceInfo) -> EndpointInfo {
+        EndpointInfo {
+            root_url: value.root_url.clone(),
+            major_version: value.major_version,
+            current_version: value.current_version,
+            minimum_version: value.minimum_version,
+        }
+    }
+}
+
+/// A link of an API version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VersionLink {
+    /// Relation of the link (e.g. `self` or `describedby`).
+    pub rel: String,
+    /// Target of the link.
+    pub href: Url,
+}
+
+/// A major API version from the version discovery document of a service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VersionInfo {
+    /// Major API version (e.g. 2.1 for the compute service).
+    pub id: ApiVersion,
+    /// Status as reported by the service (e.g. `CURRENT`, `SUPPORTED` or `DEPRECATED`).
+    pub status: Option<String>,
+    /// Maximum microversion (if supported).
+    pub version: Option<ApiVersion>,
+    /// Minimum microversion (if supported).
+    pub min_version: Option<ApiVersion>,
+    /// Links, usually including a `self` link to the endpoint of the version.
+    pub links: Vec<VersionLink>,
+}
+
+impl VersionInfo {
+    /// Whether the version is stable (no status is considered stable).
+    pub fn is_stable(&self) -> bool {
+        self.status.as_deref().map_or(true, |status| {
+            let upper = status.to_uppercase();
+            upper == "STABLE" || upper == "CURRENT" || upper == "SUPPORTED"
+        })
+    }
+
+    /// Whether the version is deprecated.
+    pub fn is_deprecated(&self) -> bool {
+        self.status
+            .as_deref()
+            .map_or(false, |status| status.eq_ignore_ascii_case("DEPRECATED"))
+    }
+
+    /// Endpoint of the version (the `self` link).
+    pub fn url(&self) -> Option<&Url> {
+        self.links
+            .iter()
+            .find(|link| link.rel == "self")
+            .map(|link| &link.href)
+    }
+
+    /// Whether the microversion is in the supported range.
+    ///
+    /// Always false if the version does not support microversions.
+    pub fn supports_microversion(&self, version: ApiVersion) -> bool {
+        match (self.min_version, self.version) {
+            (Some(min), Some(max)) => min <= version && max >= version,
+            _ => false,
+        }
+    }
+}
+
+impl From<Version> for VersionInfo {
+    fn from(value: Version) -> VersionInfo {
+        VersionInfo {
+            id: value.id.into(),
+            status: value.status,
+            version: value.version.map(From::from),
+            min_version: value.min_version.map(From::from),
+            links: value
+                .links
+                .into_iter()
+                .map(|link| VersionLink {
+                    rel: link.rel,
+                    href: link.href,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Time for which a fetched version discovery document is reused by default.
+pub const DEFAULT_ROOT_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Cache of version discovery documents keyed by the URL they were fetched from.
+#[derive(Debug)]
+pub struct RootCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<Url, (Instant, Root)>>,
+}
+
+impl Default for RootCache {
+    fn default() -> RootCache {
+        RootCache::new(DEFAULT_ROOT_CACHE_TTL)
+    }
+}
+
+impl RootCache {
+    /// Create a cache keeping documents for `ttl`.
+    pub fn new(ttl: Duration) -> RootCache {
+        RootCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Drop all cached documents.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    fn get(&self, url: &Url) -> Option<Root> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(url) {
+            Some((fetched_at, root)) if fetched_at.elapsed() < self.ttl => Some(root.clone()),
+            Some(_) => {
+                let _ = entries.remove(url);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, url: Url, root: &Root) {
+        if self.ttl > Duration::from_secs(0) {
+            let _ = self
+                .entries
+                .lock()
+                .unwrap()
+                .insert(url, (Instant::now(), root.clone()));
+        }
+    }
+}
+
 impl TryFrom<Version> for ServiceInfo {
     type Error = Error;
 
@@ -61,14 +222,63 @@ impl TryFrom<Version> for ServiceInfo {
     }
 }
 
-#[inline]
 async fn fetch_root(
     catalog_type: &'static str,
     endpoint: Url,
     auth: &dyn AuthType,
+    cache: &RootCache,
 ) -> Result<Root, Error> {
+    if let Some(root) = cache.get(&endpoint) {
+        debug!(
+            "Using cached {} service info from {}",
+            catalog_type, endpoint
+        );
+        return Ok(root);
+    }
     debug!("Fetching {} service info from {}", catalog_type, endpoint);
-    request::fetch_json(auth.request(Method::GET, endpoint).await?).await
+    let root = request::fetch_json(auth.request(Method::GET, endpoint.clone()).await?).await?;
+    cache.insert(endpoint, &root);
+    Ok(root)
+}
+
+/// Fetch the version discovery document, trying the parent endpoint on HTTP 404.
+async fn fetch_root_or_parent(
+    catalog_type: &'static str,
+    endpoint: Url,
+    auth: &dyn AuthType,
+    cache: &RootCache,
+) -> Result<Root, Error> {
+    match fetch_root(catalog_type, endpoint.clone(), auth, cache).await {
+        Ok(root) => Ok(root),
+        Err(e) if e.kind() == ErrorKind::ResourceNotFound => {
+            if url::is_root(&endpoint) {
+                Err(Error::new_endpoint_not_found(catalog_type))
+            } else {
+                debug!("Got HTTP 404 from {}, trying parent endpoint", endpoint);
+                fetch_root(catalog_type, url::pop(endpoint, true), auth, cache).await
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Fetch all major versions reported by the service, sorted from the lowest to the highest.
+///
+/// Version discovery documents are taken from `cache` if possible.
+pub async fn fetch_versions(
+    catalog_type: &'static str,
+    endpoint: Url,
+    auth: &dyn AuthType,
+    cache: &RootCache,
+) -> Result<Vec<VersionInfo>, Error> {
+    let versions = match fetch_root_or_parent(catalog_type, endpoint, auth, cache)
+        .await?
+        .into_sorted()
+    {
+        Root::MultipleVersions { versions } => versions,
+        Root::OneVersion { version } => vec![version],
+    };
+    Ok(versions.into_iter().map(VersionInfo::from).collect())
 }
 
 impl ServiceInfo {
@@ -120,10 +330,13 @@ impl ServiceInfo {
     }
 
     /// Generic code to extract a `ServiceInfo` from a URL.
+    ///
+    /// Version discovery documents are taken from `cache` if possible.
     pub async fn fetch<Srv: ServiceType>(
         service: Srv,
         endpoint: Url,
         auth: &dyn AuthType,
+        cache: &RootCache,
     ) -> Result<ServiceInfo, Error> {
         let fallback = ServiceInfo {
             root_url: endpoint.clone(),
@@ -146,19 +359,7 @@ impl ServiceInfo {
         let secure = endpoint.scheme() == "https";
         let catalog_type = service.catalog_type();
 
-        let root = match fetch_root(catalog_type, endpoint.clone(), auth.clone()).await {
-            Ok(root) => root,
-            Err(e) if e.kind() == ErrorKind::ResourceNotFound => {
-                if url::is_root(&endpoint) {
-                    let err = Error::new_endpoint_not_found(catalog_type);
-                    return Err(err);
-                } else {
-                    debug!("Got HTTP 404 from {}, trying parent endpoint", endpoint);
-                    fetch_root(catalog_type, url::pop(endpoint, true), auth).await?
-                }
-            }
-            Err(e) => return Err(e),
-        };
+        let root = fetch_root_or_parent(catalog_type, endpoint, auth, cache).await?;
 
         let mut info = ServiceInfo::from_root(root, service).or_else(move |e| {
             if e.kind() == ErrorKind::EndpointNotFound {
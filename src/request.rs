@@ -14,50 +14,690 @@
 
 //! Utilities to work with OpenStack requests.
 
-use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hasher};
+use std::iter::FromIterator;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use log::trace;
-use reqwest::{RequestBuilder, Response};
+#[cfg(feature = "stream")]
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+#[cfg(feature = "stream")]
+use futures::pin_mut;
+#[cfg(feature = "stream")]
+use futures::stream::{self, Stream, TryStreamExt};
+use log::{debug, trace, warn};
+use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+#[cfg(feature = "stream")]
+use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use reqwest::redirect::Policy;
+#[cfg(feature = "stream")]
+use reqwest::Body;
+use reqwest::{Client, ClientBuilder, Method, Proxy, RequestBuilder, Response, StatusCode, Url};
 use serde::de::DeserializeOwned;
-use serde::Deserialize;
+use serde::{Serialize, Serializer};
+#[cfg(feature = "stream")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-use super::Error;
+use super::proxy;
+use super::telemetry;
+use super::tls;
+use super::{Error, ErrorKind, Fault};
 
-#[derive(Debug, Deserialize)]
-struct Message {
-    message: String,
+/// Header with the ID of the request, used to correlate requests with server logs.
+pub const REQUEST_ID_HEADER: &str = "x-openstack-request-id";
+
+/// User agent sent by default: `rust-osauth/<version>`.
+pub const DEFAULT_USER_AGENT: &str = concat!("rust-osauth/", env!("CARGO_PKG_VERSION"));
+
+#[cfg(feature = "stream")]
+const BODY_CHUNK_SIZE: usize = 16384;
+
+/// Maximum number of redirects followed by default.
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+// Maximum size of an error response body in bytes, the rest is ignored.
+const MAX_ERROR_BODY_SIZE: usize = 64 * 1024;
+
+/// Policy for following HTTP redirects.
+///
+/// Redirects from HTTPS to plain HTTP are never followed, since they would expose the
+/// authentication token. By default, up to
+/// [DEFAULT_MAX_REDIRECTS](constant.DEFAULT_MAX_REDIRECTS.html) redirects to the same host and
+/// port are followed: reqwest only strips `Authorization` and `Cookie` on a redirect to another
+/// host, so `X-Auth-Token` (and the credentials in a re-sent request body) would leak to it.
+/// The policy can be used with a custom HTTP client:
+///
+/// ```rust
+/// use osauth::request::RedirectPolicy;
+///
+/// let client = reqwest::Client::builder()
+///     .redirect(RedirectPolicy::new(3).into())
+///     .build()
+///     .expect("Cannot create an HTTP client");
+/// let auth = osauth::identity::Password::new_with_client(
+///     "https://cloud.local/identity",
+///     client,
+///     "admin",
+///     "pa$$w0rd",
+///     "Default",
+/// )
+/// .expect("Invalid auth_url");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RedirectPolicy {
+    max_redirects: usize,
+    same_host_only: bool,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> RedirectPolicy {
+        RedirectPolicy::new(DEFAULT_MAX_REDIRECTS)
+    }
+}
+
+impl RedirectPolicy {
+    /// Create a policy following at most `max_redirects` redirects to the same host (zero
+    /// disables redirects).
+    #[inline]
+    pub fn new(max_redirects: usize) -> RedirectPolicy {
+        RedirectPolicy {
+            max_redirects,
+            same_host_only: true,
+        }
+    }
+
+    /// Maximum number of redirects to follow.
+    #[inline]
+    pub fn max_redirects(&self) -> usize {
+        self.max_redirects
+    }
+
+    /// Whether only redirects to the same host and port are followed.
+    #[inline]
+    pub fn same_host_only(&self) -> bool {
+        self.same_host_only
+    }
+
+    /// Only follow redirects to the same host and port (the default).
+    ///
+    /// Set to `false` only if the other hosts are trusted with the authentication token.
+    #[inline]
+    pub fn set_same_host_only(&mut self, value: bool) {
+        self.same_host_only = value;
+    }
+
+    /// Only follow redirects to the same host and port (the default).
+    #[inline]
+    pub fn with_same_host_only(mut self, value: bool) -> Self {
+        self.set_same_host_only(value);
+        self
+    }
+
+    /// Check whether a redirect to `next` should be followed.
+    fn check(&self, next: &Url, previous: &[Url]) -> Result<(), String> {
+        if previous.len() > self.max_redirects {
+            return Err(format!(
+                "Too many redirects (more than {}) when requesting {}",
+                self.max_redirects,
+                previous.first().map(Url::as_str).unwrap_or_default()
+            ));
+        }
+
+        if let Some(last) = previous.last() {
+            if last.scheme() == "https" && next.scheme() != "https" {
+                return Err(format!(
+                    "Refusing to follow a redirect from {} to insecure {}",
+                    last, next
+                ));
+            }
+
+            if self.same_host_only
+                && (last.host_str() != next.host_str()
+                    || last.port_or_known_default() != next.port_or_known_default())
+            {
+                return Err(format!(
+                    "Refusing to follow a redirect from {} to a different host {}",
+                    last, next
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl From<RedirectPolicy> for Policy {
+    fn from(value: RedirectPolicy) -> Policy {
+        Policy::custom(
+            move |attempt| match value.check(attempt.url(), attempt.previous()) {
+                Ok(()) => attempt.follow(),
+                Err(msg) => {
+                    warn!("{}", msg);
+                    attempt.error(msg)
+                }
+            },
+        )
+    }
+}
+
+/// Policy for retrying requests that failed with a transient error.
+///
+/// Requests are retried on HTTP 429 (Too Many Requests). Requests with idempotent methods
+/// (`GET`, `HEAD`, `PUT`, `DELETE`, `OPTIONS` and `TRACE`) are also retried on transport errors
+/// (e.g. connection failures) and HTTP 5xx other than 501. Requests with streaming bodies are
+/// never retried.
+///
+/// The delay before the first retry is the initial delay, it is doubled before every next retry
+/// up to the maximum delay. With jitter enabled (the default), a random delay of up to a half of
+/// that is subtracted. A delay from the `Retry-After` header overrides the computed one, but is
+/// also limited by the maximum delay.
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// use osauth::request::RetryPolicy;
+///
+/// let session = osauth::Session::new(osauth::NoAuth::new("http://cloud.local/").unwrap())
+///     .with_retry_policy(RetryPolicy::new(5).with_max_delay(Duration::from_secs(10)));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    initial_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+}
+
+impl RetryPolicy {
+    /// Create a policy making at most `max_attempts` attempts (including the first one).
+    ///
+    /// The initial delay is 500 milliseconds, the maximum delay is 30 seconds.
+    #[inline]
+    pub fn new(max_attempts: usize) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+
+    /// Maximum number of attempts (including the first one).
+    #[inline]
+    pub fn max_attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+    /// Delay before the first retry.
+    #[inline]
+    pub fn initial_delay(&self) -> Duration {
+        self.initial_delay
+    }
+
+    /// Maximum delay between attempts.
+    #[inline]
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+
+    /// Whether the delays are randomized.
+    #[inline]
+    pub fn jitter(&self) -> bool {
+        self.jitter
+    }
+
+    /// Set the delay before the first retry.
+    #[inline]
+    pub fn set_initial_delay(&mut self, value: Duration) {
+        self.initial_delay = value;
+    }
+
+    /// Set the maximum delay between attempts.
+    #[inline]
+    pub fn set_max_delay(&mut self, value: Duration) {
+        self.max_delay = value;
+    }
+
+    /// Enable or disable randomizing the delays.
+    #[inline]
+    pub fn set_jitter(&mut self, value: bool) {
+        self.jitter = value;
+    }
+
+    /// Set the delay before the first retry.
+    #[inline]
+    pub fn with_initial_delay(mut self, value: Duration) -> Self {
+        self.set_initial_delay(value);
+        self
+    }
+
+    /// Set the maximum delay between attempts.
+    #[inline]
+    pub fn with_max_delay(mut self, value: Duration) -> Self {
+        self.set_max_delay(value);
+        self
+    }
+
+    /// Enable or disable randomizing the delays.
+    #[inline]
+    pub fn with_jitter(mut self, value: bool) -> Self {
+        self.set_jitter(value);
+        self
+    }
+
+    /// Delay before retrying a request that failed on the given attempt (starting with 1).
+    ///
+    /// Returns `None` if the request should not be retried.
+    pub(crate) fn delay(&self, method: &Method, error: &Error, attempt: usize) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+
+        let idempotent = match *method {
+            Method::GET
+            | Method::HEAD
+            | Method::PUT
+            | Method::DELETE
+            | Method::OPTIONS
+            | Method::TRACE => true,
+            _ => false,
+        };
+        let retriable = match error.status() {
+            Some(StatusCode::TOO_MANY_REQUESTS) => true,
+            Some(StatusCode::NOT_IMPLEMENTED) => false,
+            Some(status) => idempotent && status.is_server_error(),
+            None => idempotent && error.kind() == ErrorKind::Transport,
+        };
+        if !retriable {
+            return None;
+        }
+
+        if let Some(retry_after) = error.retry_after() {
+            return Some(retry_after.min(self.max_delay));
+        }
+
+        // NOTE: attempts start with 1, treat 0 the same way instead of underflowing.
+        let exponent = attempt.saturating_sub(1).min(std::u32::MAX as usize) as u32;
+        let factor = 1u32.checked_shl(exponent).unwrap_or(std::u32::MAX);
+        let delay = self
+            .initial_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        Some(if self.jitter {
+            // A random number in [0, 1) without an additional dependency.
+            let random =
+                (RandomState::new().build_hasher().finish() >> 11) as f64 / (1u64 << 53) as f64;
+            delay.mul_f64(1.0 - random / 2.0)
+        } else {
+            delay
+        })
+    }
+}
+
+/// Convert a JSON object into a fault if it has a message.
+fn fault_from_object(
+    name: Option<&str>,
+    object: &serde_json::Map<String, serde_json::Value>,
+) -> Option<Fault> {
+    let string = |keys: &[&str]| {
+        keys.iter()
+            .filter_map(|key| object.get(*key))
+            .find_map(|value| match value {
+                serde_json::Value::Null => None,
+                serde_json::Value::String(value) => Some(value.clone()),
+                other => Some(other.to_string()),
+            })
+            .filter(|value| !value.is_empty())
+    };
+    let message = match object
+        .get("message")
+        .or_else(|| object.get("faultstring"))?
+    {
+        serde_json::Value::String(value) => value.clone(),
+        _ => return None,
+    };
+    let code = object.get("code").and_then(|value| match value {
+        serde_json::Value::Number(value) => value.as_u64().and_then(|x| u16::try_from(x).ok()),
+        serde_json::Value::String(value) => value.parse().ok(),
+        _ => None,
+    });
+    Some(Fault {
+        name: name.map(ToString::to_string),
+        message,
+        code,
+        fault_type: string(&["type", "title", "faultcode"]),
+        details: string(&["details", "detail", "debuginfo"]),
+    })
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum ErrorResponse {
-    Map(HashMap<String, Message>),
-    Message(Message),
+/// Parse the fault from the body of an error response.
+///
+/// The fault is either the body itself or an object inside it (e.g. `{"itemNotFound": {...}}`).
+fn parse_fault(text: &str) -> Option<Fault> {
+    let body: serde_json::Value = serde_json::from_str(text).ok()?;
+    let object = body.as_object()?;
+    fault_from_object(None, object).or_else(|| {
+        object.iter().find_map(|(name, value)| {
+            value
+                .as_object()
+                .and_then(|inner| fault_from_object(Some(name), inner))
+        })
+    })
 }
 
-async fn extract_message(resp: Response) -> Result<String, Error> {
-    let text = resp.text().await?;
-    Ok(serde_json::from_str::<ErrorResponse>(&text)
+/// Whether the fault means that a quota or a rate limit is exceeded.
+fn is_quota_fault(status: StatusCode, message: &str, fault: Option<&Fault>) -> bool {
+    (status == StatusCode::FORBIDDEN || status == StatusCode::PAYLOAD_TOO_LARGE)
+        && (fault.and_then(|fault| fault.name.as_deref()) == Some("overLimit")
+            || message.to_lowercase().contains("quota"))
+}
+
+/// Create an HTTP client builder with the default settings.
+///
+/// The default [RedirectPolicy](struct.RedirectPolicy.html) is used. Proxies are configured
+/// from the `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` environment variables.
+#[inline]
+pub(crate) fn client_builder() -> ClientBuilder {
+    proxy::with_env_proxy(base_client_builder())
+}
+
+/// Create an HTTP client builder with the default settings using the given proxy.
+///
+/// The proxy is used for all requests, proxies from the environment are ignored.
+#[inline]
+pub(crate) fn client_builder_with_proxy(proxy: Proxy) -> ClientBuilder {
+    base_client_builder().proxy(proxy)
+}
+
+/// Create an HTTP client builder with the default settings not using any proxies.
+#[inline]
+pub(crate) fn client_builder_without_proxy() -> ClientBuilder {
+    base_client_builder().no_proxy()
+}
+
+#[inline]
+fn base_client_builder() -> ClientBuilder {
+    Client::builder()
+        .user_agent(DEFAULT_USER_AGENT)
+        .redirect(RedirectPolicy::default().into())
+}
+
+/// Create an HTTP client with the default settings.
+#[inline]
+pub(crate) fn default_client() -> Client {
+    tls::configure(client_builder(), None, &[], None, false)
+        .and_then(|builder| builder.build().map_err(Error::from))
+        .expect("Cannot create an HTTP client")
+}
+
+/// Generate a new random request ID in the `req-<UUID>` format expected by OpenStack.
+///
+/// Pass it to [Session::set_request_id](../struct.Session.html#method.set_request_id) or
+/// [with_request_id](fn.with_request_id.html) to correlate requests with the service logs.
+pub fn generate_request_id() -> String {
+    // A random version 4 UUID without an additional dependency.
+    let random = || RandomState::new().build_hasher().finish();
+    let (high, low) = (random(), random());
+    format!(
+        "req-{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xffff,
+        (high & 0x0fff) | 0x4000,
+        ((low >> 48) & 0x3fff) | 0x8000,
+        low & 0xffff_ffff_ffff
+    )
+}
+
+/// Send the request with the given request ID.
+///
+/// Replaces the request ID of the session (if any) for this request only.
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// let session =
+///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+/// let request_id = osauth::request::generate_request_id();
+/// let builder = session
+///     .request(osauth::services::COMPUTE, reqwest::Method::GET, &["servers"], None)
+///     .await?;
+/// let response =
+///     osauth::request::send_checked(osauth::request::with_request_id(builder, &request_id)?)
+///         .await?;
+/// println!("Sent {}, the server saw {:?}", request_id, osauth::request::request_id(&response));
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+pub fn with_request_id(builder: RequestBuilder, request_id: &str) -> Result<RequestBuilder, Error> {
+    let value = HeaderValue::from_str(request_id).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid request ID {:?}: {}", request_id, e),
+        )
+    })?;
+    let mut headers = HeaderMap::new();
+    let _ = headers.insert(REQUEST_ID_HEADER, value);
+    // NOTE: unlike `header`, `headers` replaces existing values.
+    Ok(builder.headers(headers))
+}
+
+/// Request ID reported by the server in the response (if any).
+///
+/// Servers usually echo the request ID sent by the client, otherwise they generate a new one.
+pub fn request_id(response: &Response) -> Option<String> {
+    response
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(ToString::to_string)
+}
+
+/// Rate limit information reported by the server.
+///
+/// There is no standard for these headers: `X-RateLimit-Limit`, `X-RateLimit-Remaining` and
+/// `X-RateLimit-Reset` are used by most rate-limiting proxies. The reset time is either a number
+/// of seconds or, for large values, a Unix timestamp.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RateLimit {
+    /// Maximum number of requests in the current window (`X-RateLimit-Limit`).
+    pub limit: Option<u64>,
+    /// Number of requests left in the current window (`X-RateLimit-Remaining`).
+    pub remaining: Option<u64>,
+    /// Time until the current window is reset (`X-RateLimit-Reset`).
+    pub reset: Option<Duration>,
+    /// Delay requested by the server (`Retry-After`).
+    pub retry_after: Option<Duration>,
+}
+
+/// Values after which `X-RateLimit-Reset` is a Unix timestamp rather than a number of seconds.
+const RESET_TIMESTAMP_THRESHOLD: u64 = 1_000_000_000;
+
+/// Integer from the header, ignoring any parameters (e.g. `100;w=60`).
+fn header_number(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers
+        .get(name)?
+        .to_str()
+        .ok()?
+        .split(&[',', ';'][..])
+        .next()?
+        .trim()
+        .parse()
         .ok()
-        .and_then(|body| match body {
-            ErrorResponse::Map(map) => map.into_iter().next().map(|(_k, v)| v.message),
-            ErrorResponse::Message(msg) => Some(msg.message),
+}
+
+fn rate_limit_from_headers(headers: &HeaderMap) -> Option<RateLimit> {
+    let reset = header_number(headers, "x-ratelimit-reset").map(|value| {
+        if value >= RESET_TIMESTAMP_THRESHOLD {
+            (UNIX_EPOCH + Duration::from_secs(value))
+                .duration_since(SystemTime::now())
+                .unwrap_or_default()
+        } else {
+            Duration::from_secs(value)
+        }
+    });
+    let result = RateLimit {
+        limit: header_number(headers, "x-ratelimit-limit"),
+        remaining: header_number(headers, "x-ratelimit-remaining"),
+        reset,
+        retry_after: retry_after(headers),
+    };
+    if result == RateLimit::default() {
+        None
+    } else {
+        Some(result)
+    }
+}
+
+/// Rate limit information reported by the server in the response (if any).
+pub fn rate_limit(response: &Response) -> Option<RateLimit> {
+    rate_limit_from_headers(response.headers())
+}
+
+/// Limiter of the rate of outgoing requests, shared between clones of a session.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// Create a limiter allowing the given number of requests per second.
+    ///
+    /// Returns `None` if the number is not positive and finite.
+    pub fn new(requests_per_second: f64) -> Option<RateLimiter> {
+        if !(requests_per_second > 0.0 && requests_per_second.is_finite()) {
+            return None;
+        }
+        Some(RateLimiter {
+            interval: Duration::from_secs_f64(1.0 / requests_per_second),
+            next: Mutex::new(Instant::now()),
         })
-        .unwrap_or(text))
+    }
+
+    /// Number of requests per second.
+    pub fn requests_per_second(&self) -> f64 {
+        1.0 / self.interval.as_secs_f64()
+    }
+
+    /// Reserve a slot for a request, returning how long to wait for it.
+    pub fn reserve(&self) -> Duration {
+        let now = Instant::now();
+        let mut next = self.next.lock().unwrap();
+        let slot = (*next).max(now);
+        *next = slot + self.interval;
+        slot - now
+    }
+
+    /// Do not send requests for the given time.
+    pub fn pause(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut next = self.next.lock().unwrap();
+        if *next < until {
+            *next = until;
+        }
+    }
+
+    /// Pause if the server reported that no requests are left.
+    pub fn update(&self, rate_limit: &RateLimit) {
+        let delay = match (
+            rate_limit.retry_after,
+            rate_limit.remaining,
+            rate_limit.reset,
+        ) {
+            (Some(retry_after), ..) => retry_after,
+            (None, Some(0), Some(reset)) => reset,
+            _ => return,
+        };
+        debug!(
+            "Pausing requests for {:?} as requested by the server",
+            delay
+        );
+        self.pause(delay);
+    }
+}
+
+/// Delay from the `Retry-After` header: either a number of seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        date.signed_duration_since(Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Read at most `MAX_ERROR_BODY_SIZE` bytes of the body of an error response.
+async fn read_error_body(mut response: Response) -> Result<String, Error> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        let left = MAX_ERROR_BODY_SIZE - body.len();
+        if chunk.len() >= left {
+            body.extend_from_slice(&chunk[..left]);
+            debug!(
+                "Error response from {} truncated to {} bytes",
+                response.url(),
+                MAX_ERROR_BODY_SIZE
+            );
+            break;
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
 }
 
 /// Check the response and convert errors into OpenStack ones.
+///
+/// The request ID, the `Retry-After` delay, the fault from the body and the URL of the response
+/// (if any) are added to the error. Only the first 64 KiB of the error body are read.
 pub async fn check(response: Response) -> Result<Response, Error> {
     let status = response.status();
+    let request_id = request_id(&response);
     if status.is_client_error() || status.is_server_error() {
-        let message = extract_message(response).await?;
-        trace!("HTTP request returned {}; error: {:?}", status, message);
-        Err(Error::new(status.into(), message).with_status(status))
+        let retry_after = retry_after(response.headers());
+        let url = response.url().clone();
+        let text = read_error_body(response).await?;
+        let fault = parse_fault(&text);
+        let message = fault
+            .as_ref()
+            .map(|fault| fault.message.clone())
+            .unwrap_or(text);
+        trace!(
+            "HTTP request to {} returned {} (request ID {:?}); error: {:?}",
+            url,
+            status,
+            request_id,
+            message
+        );
+        let kind = if is_quota_fault(status, &message, fault.as_ref()) {
+            ErrorKind::QuotaExceeded
+        } else {
+            status.into()
+        };
+        let mut error = Error::new(kind, message).with_status(status).with_url(url);
+        if let Some(fault) = fault {
+            error.set_fault(fault);
+        }
+        if let Some(request_id) = request_id {
+            error.set_request_id(request_id);
+        }
+        if let Some(retry_after) = retry_after {
+            error.set_retry_after(retry_after);
+        }
+        Err(error)
     } else {
         trace!(
-            "HTTP request to {} returned {}",
+            "HTTP request to {} returned {} (request ID {:?})",
             response.url(),
-            response.status()
+            response.status(),
+            request_id
         );
         Ok(response)
     }
@@ -66,7 +706,9 @@ pub async fn check(response: Response) -> Result<Response, Error> {
 /// Send the request and check its result.
 #[inline]
 pub async fn send_checked(builder: RequestBuilder) -> Result<Response, Error> {
-    check(builder.send().await?).await
+    telemetry::http_request(&builder)
+        .run(async move { check(builder.send().await?).await })
+        .await
 }
 
 /// Check the response and convert it to a JSON.
@@ -91,6 +733,338 @@ where
         .map_err(Into::into)
 }
 
+/// Body of the response as a stream of chunks.
+///
+/// The body is not buffered in memory, which makes the stream suitable for large downloads.
+#[cfg(feature = "stream")]
+#[inline]
+pub fn bytes_stream(response: Response) -> impl Stream<Item = Result<Bytes, Error>> {
+    response.bytes_stream().map_err(Error::from)
+}
+
+/// Write the body of the response to the writer chunk by chunk.
+///
+/// Returns the number of bytes written. The body is not buffered in memory:
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), osauth::Error> {
+/// let session =
+///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+/// let response = session
+///     .get(osauth::services::IMAGE, &["images", "8a3f2b1c", "file"], None)
+///     .await?;
+/// // Any tokio::io::AsyncWrite works, e.g. a file.
+/// let mut buffer = Vec::new();
+/// let size = osauth::request::download_to(response, &mut buffer).await?;
+/// println!("Downloaded {} bytes", size);
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[cfg(feature = "stream")]
+#[inline]
+pub async fn download_to<W>(response: Response, writer: &mut W) -> Result<u64, Error>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    download_to_with_progress(response, writer, |_, _| ()).await
+}
+
+/// Write the body of the response to the writer, reporting progress.
+///
+/// The `progress` callback is called after each chunk with the number of bytes written so far
+/// and the total size of the body (if known from the response headers).
+#[cfg(feature = "stream")]
+pub async fn download_to_with_progress<W, F>(
+    response: Response,
+    writer: &mut W,
+    mut progress: F,
+) -> Result<u64, Error>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+    F: FnMut(u64, Option<u64>),
+{
+    let total = response.content_length();
+    let stream = bytes_stream(response);
+    pin_mut!(stream);
+    let mut written = 0;
+    while let Some(chunk) = stream.try_next().await? {
+        writer.write_all(&chunk).await.map_err(write_error)?;
+        written += chunk.len() as u64;
+        progress(written, total);
+    }
+    writer.flush().await.map_err(write_error)?;
+    Ok(written)
+}
+
+/// Request body streamed from an asynchronous reader.
+///
+/// The reader is consumed in chunks, so the data is never loaded in memory completely. Requests
+/// with such a body cannot be retried.
+#[cfg(feature = "stream")]
+pub fn reader_body<R>(reader: R) -> Body
+where
+    R: AsyncRead + Send + Sync + Unpin + 'static,
+{
+    let chunks = stream::try_unfold(reader, |mut reader| async move {
+        let mut buffer = vec![0; BODY_CHUNK_SIZE];
+        let size = reader.read(&mut buffer).await?;
+        if size == 0 {
+            Ok::<_, std::io::Error>(None)
+        } else {
+            buffer.truncate(size);
+            Ok(Some((Bytes::from(buffer), reader)))
+        }
+    });
+    Body::wrap_stream(chunks)
+}
+
+/// Set a binary body (`Content-Type: application/octet-stream`) on the request.
+///
+/// Streaming bodies (e.g. from [reader_body](fn.reader_body.html) or `Body::wrap_stream`) are
+/// sent with `Transfer-Encoding: chunked` unless `content_length` is provided. Some services
+/// and proxies reject chunked uploads, so provide the length whenever it is known:
+///
+/// ```rust,no_run
+/// use reqwest::Method;
+///
+/// # async fn example() -> Result<(), osauth::Error> {
+/// let session =
+///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+/// let file = std::io::Cursor::new(vec![0u8; 1024]);
+/// let builder = session
+///     .request(
+///         osauth::services::IMAGE,
+///         Method::PUT,
+///         &["images", "8a3f2b1c", "file"],
+///         None,
+///     )
+///     .await?;
+/// let builder =
+///     osauth::request::with_binary_body(builder, osauth::request::reader_body(file), Some(1024));
+/// let _ = osauth::request::send_checked(builder).await?;
+/// # Ok(()) }
+/// # #[tokio::main]
+/// # async fn main() { example().await.unwrap(); }
+/// ```
+#[cfg(feature = "stream")]
+pub fn with_binary_body<B>(
+    builder: RequestBuilder,
+    body: B,
+    content_length: Option<u64>,
+) -> RequestBuilder
+where
+    B: Into<Body>,
+{
+    let builder = builder
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .body(body);
+    match content_length {
+        Some(length) => builder.header(CONTENT_LENGTH, length),
+        None => builder,
+    }
+}
+
+#[cfg(feature = "stream")]
+fn write_error(err: std::io::Error) -> Error {
+    Error::new(
+        ErrorKind::OperationFailed,
+        format!("Failed to write the downloaded data: {}", err),
+    )
+}
+
+/// Direction of sorting in a [Query](struct.Query.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortDir {
+    /// Ascending order.
+    Asc,
+    /// Descending order.
+    Desc,
+}
+
+impl SortDir {
+    /// Name of the direction for the `sort_dir` parameter.
+    #[inline]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SortDir::Asc => "asc",
+            SortDir::Desc => "desc",
+        }
+    }
+}
+
+/// A query string for list requests.
+///
+/// Keys can be repeated, the order of the parameters is preserved. The query can be passed
+/// anywhere a serializable query is expected, e.g. to `RequestBuilder::query` or
+/// [Session::get_json_query](../struct.Session.html#method.get_json_query):
+///
+/// ```rust
+/// use osauth::request::{Query, SortDir};
+///
+/// let query = Query::new()
+///     .with("status", "ACTIVE")
+///     .with_bool("all_tenants", true)
+///     .with_sort("created_at", SortDir::Desc)
+///     .with_limit(50);
+/// assert_eq!(
+///     query.to_query_string(),
+///     "status=ACTIVE&all_tenants=True&sort_key=created_at&sort_dir=desc&limit=50"
+/// );
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query(Vec<(String, String)>);
+
+impl Query {
+    /// Create an empty query.
+    #[inline]
+    pub fn new() -> Query {
+        Query::default()
+    }
+
+    /// Add a parameter, keeping existing parameters with the same key.
+    pub fn push<K, V>(&mut self, key: K, value: V)
+    where
+        K: Into<String>,
+        V: ToString,
+    {
+        self.0.push((key.into(), value.to_string()));
+    }
+
+    /// Add a boolean parameter.
+    ///
+    /// Booleans are sent as `True` and `False`, which all OpenStack services understand (some
+    /// of them do not accept other spellings).
+    #[inline]
+    pub fn push_bool<K: Into<String>>(&mut self, key: K, value: bool) {
+        self.push(key, if value { "True" } else { "False" });
+    }
+
+    /// Add a sorting key and direction.
+    ///
+    /// Can be used several times for services that support sorting by several keys.
+    pub fn push_sort<K: Into<String>>(&mut self, key: K, dir: SortDir) {
+        self.push("sort_key", key.into());
+        self.push("sort_dir", dir.as_str());
+    }
+
+    /// Set a parameter, removing existing parameters with the same key.
+    pub fn set<K, V>(&mut self, key: K, value: V)
+    where
+        K: Into<String>,
+        V: ToString,
+    {
+        let key = key.into();
+        self.remove(&key);
+        self.0.push((key, value.to_string()));
+    }
+
+    /// Set the maximum number of items to return.
+    #[inline]
+    pub fn set_limit(&mut self, limit: usize) {
+        self.set("limit", limit);
+    }
+
+    /// Set the ID of the last item of the previous page.
+    #[inline]
+    pub fn set_marker<S: Into<String>>(&mut self, marker: S) {
+        self.set("marker", marker.into());
+    }
+
+    /// Remove all parameters with the given key.
+    pub fn remove(&mut self, key: &str) {
+        self.0.retain(|(k, _)| k != key);
+    }
+
+    /// Add a parameter, keeping existing parameters with the same key.
+    #[inline]
+    pub fn with<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: ToString,
+    {
+        self.push(key, value);
+        self
+    }
+
+    /// Add a boolean parameter.
+    #[inline]
+    pub fn with_bool<K: Into<String>>(mut self, key: K, value: bool) -> Self {
+        self.push_bool(key, value);
+        self
+    }
+
+    /// Set the maximum number of items to return.
+    #[inline]
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.set_limit(limit);
+        self
+    }
+
+    /// Set the ID of the last item of the previous page.
+    #[inline]
+    pub fn with_marker<S: Into<String>>(mut self, marker: S) -> Self {
+        self.set_marker(marker);
+        self
+    }
+
+    /// Add a sorting key and direction.
+    #[inline]
+    pub fn with_sort<K: Into<String>>(mut self, key: K, dir: SortDir) -> Self {
+        self.push_sort(key, dir);
+        self
+    }
+
+    /// Values of the given key in order.
+    pub fn get<'q>(&'q self, key: &'q str) -> impl Iterator<Item = &'q str> + 'q {
+        self.0
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Whether the query has no parameters.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterate over the parameters in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// URL-encoded query string (without the leading `?`).
+    pub fn to_query_string(&self) -> String {
+        // NOTE: any base URL works, only the query is used.
+        let mut url = Url::parse("http://localhost/").expect("Invalid static URL");
+        let _ = url.query_pairs_mut().extend_pairs(self.iter());
+        url.query().unwrap_or_default().to_string()
+    }
+}
+
+impl<K: Into<String>, V: ToString> Extend<(K, V)> for Query {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.push(key, value);
+        }
+    }
+}
+
+impl<K: Into<String>, V: ToString> FromIterator<(K, V)> for Query {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut result = Query::new();
+        result.extend(iter);
+        result
+    }
+}
+
+impl Serialize for Query {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
 /// A properly typed constant for use with root paths.
 ///
 /// The problem with just using `None` is that the exact type of `Option` is not known.
@@ -103,3 +1077,462 @@ where
 /// let future = session.get(osauth::services::OBJECT_STORAGE, osauth::request::NO_PATH, None);
 /// ```
 pub const NO_PATH: Option<&'static str> = None;
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use reqwest::header::{HeaderMap, RETRY_AFTER};
+    use reqwest::{Method, StatusCode, Url};
+
+    use super::super::test::{Mock, MockServer};
+    use super::super::{Error, ErrorKind};
+    use super::{
+        check, generate_request_id, is_quota_fault, parse_fault, rate_limit_from_headers,
+        retry_after, with_request_id, Query, RateLimit, RateLimiter, RedirectPolicy, RetryPolicy,
+        SortDir, MAX_ERROR_BODY_SIZE, REQUEST_ID_HEADER,
+    };
+
+    fn urls(items: &[&str]) -> Vec<Url> {
+        items.iter().map(|x| Url::parse(x).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_redirect_policy_default() {
+        let policy = RedirectPolicy::default();
+        assert_eq!(policy.max_redirects(), 10);
+        assert!(policy.same_host_only());
+
+        let next = Url::parse("https://cloud.local/v3").unwrap();
+        policy
+            .check(&next, &urls(&["https://cloud.local/identity"]))
+            .unwrap();
+        let next = Url::parse("https://other.local/identity/v3").unwrap();
+        assert!(policy
+            .check(&next, &urls(&["https://cloud.local/identity"]))
+            .is_err());
+        let next = Url::parse("http://cloud.local/identity/v3").unwrap();
+        policy
+            .check(&next, &urls(&["http://cloud.local/identity"]))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_redirect_policy_insecure() {
+        let policy = RedirectPolicy::default();
+        let next = Url::parse("http://cloud.local/identity/v3").unwrap();
+        let err = policy
+            .check(&next, &urls(&["https://cloud.local/identity"]))
+            .unwrap_err();
+        assert!(err.contains("insecure"));
+    }
+
+    #[test]
+    fn test_redirect_policy_same_host() {
+        let policy = RedirectPolicy::new(10);
+        let previous = urls(&["https://cloud.local/identity"]);
+        policy
+            .check(&Url::parse("https://cloud.local/v3").unwrap(), &previous)
+            .unwrap();
+        assert!(policy
+            .check(&Url::parse("https://other.local/v3").unwrap(), &previous)
+            .is_err());
+        assert!(policy
+            .check(
+                &Url::parse("https://cloud.local:5000/v3").unwrap(),
+                &previous
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_redirect_policy_any_host() {
+        let policy = RedirectPolicy::new(10).with_same_host_only(false);
+        assert!(!policy.same_host_only());
+        policy
+            .check(
+                &Url::parse("https://other.local/v3").unwrap(),
+                &urls(&["https://cloud.local/identity"]),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_redirect_policy_limit() {
+        let policy = RedirectPolicy::new(2);
+        let next = Url::parse("https://cloud.local/3").unwrap();
+        policy
+            .check(
+                &next,
+                &urls(&["https://cloud.local/1", "https://cloud.local/2"]),
+            )
+            .unwrap();
+        let previous = urls(&[
+            "https://cloud.local/1",
+            "https://cloud.local/2",
+            "https://cloud.local/3",
+        ]);
+        assert!(policy.check(&next, &previous).is_err());
+        let next = Url::parse("https://cloud.local/2").unwrap();
+        assert!(RedirectPolicy::new(0)
+            .check(&next, &urls(&["https://cloud.local/1"]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_generate_request_id() {
+        let first = generate_request_id();
+        assert_eq!(first.len(), 40);
+        assert!(first.starts_with("req-"));
+        let parts: Vec<&str> = first[4..].split('-').collect();
+        assert_eq!(
+            parts.iter().map(|x| x.len()).collect::<Vec<_>>(),
+            vec![8, 4, 4, 4, 12]
+        );
+        assert!(parts[2].starts_with('4'));
+        assert!("89ab".contains(&parts[3][..1]));
+        assert_ne!(first, generate_request_id());
+    }
+
+    #[test]
+    fn test_with_request_id() {
+        let client = reqwest::Client::new();
+        let builder = client
+            .get("http://127.0.0.1:1/")
+            .header(REQUEST_ID_HEADER, "req-session");
+        let request = with_request_id(builder, "req-call")
+            .unwrap()
+            .build()
+            .unwrap();
+        let values: Vec<_> = request
+            .headers()
+            .get_all(REQUEST_ID_HEADER)
+            .iter()
+            .collect();
+        assert_eq!(values, vec!["req-call"]);
+
+        let err = with_request_id(client.get("http://127.0.0.1:1/"), "req\n1")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_parse_fault() {
+        let fault = parse_fault(
+            r#"{"itemNotFound": {"code": 404, "message": "Instance could not be found."}}"#,
+        )
+        .unwrap();
+        assert_eq!(fault.name.as_deref(), Some("itemNotFound"));
+        assert_eq!(fault.message, "Instance could not be found.");
+        assert_eq!(fault.code, Some(404));
+
+        let fault = parse_fault(
+            r#"{"error": {"code": 401, "title": "Unauthorized", "message": "Bad token"}}"#,
+        )
+        .unwrap();
+        assert_eq!(fault.name.as_deref(), Some("error"));
+        assert_eq!(fault.fault_type.as_deref(), Some("Unauthorized"));
+
+        let fault = parse_fault(
+            r#"{"NeutronError": {"type": "PortNotFound", "message": "Port abcd not found",
+                "detail": ""}}"#,
+        )
+        .unwrap();
+        assert_eq!(fault.fault_type.as_deref(), Some("PortNotFound"));
+        assert!(fault.details.is_none());
+
+        let fault = parse_fault(
+            r#"{"faultstring": "Node busy", "faultcode": "Client", "debuginfo": null}"#,
+        )
+        .unwrap();
+        assert!(fault.name.is_none());
+        assert_eq!(fault.message, "Node busy");
+        assert_eq!(fault.fault_type.as_deref(), Some("Client"));
+        assert!(fault.details.is_none());
+
+        assert!(parse_fault("<html>Bad gateway</html>").is_none());
+        assert!(parse_fault(r#"{"servers": []}"#).is_none());
+    }
+
+    #[test]
+    fn test_is_quota_fault() {
+        let fault = parse_fault(r#"{"overLimit": {"code": 413, "message": "Over limit"}}"#);
+        assert!(is_quota_fault(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "Over limit",
+            fault.as_ref()
+        ));
+        assert!(is_quota_fault(
+            StatusCode::FORBIDDEN,
+            "Quota exceeded for cores",
+            None
+        ));
+        assert!(!is_quota_fault(
+            StatusCode::FORBIDDEN,
+            "Policy does not allow",
+            None
+        ));
+        assert!(!is_quota_fault(
+            StatusCode::BAD_REQUEST,
+            "Invalid quota value",
+            None
+        ));
+    }
+
+    fn http_error(status: StatusCode) -> Error {
+        Error::new(status.into(), "error").with_status(status)
+    }
+
+    #[test]
+    fn test_retry_policy_delay() {
+        let policy = RetryPolicy::new(4)
+            .with_initial_delay(Duration::from_secs(1))
+            .with_max_delay(Duration::from_secs(3))
+            .with_jitter(false);
+        let error = http_error(StatusCode::SERVICE_UNAVAILABLE);
+        let delay = |attempt| policy.delay(&Method::GET, &error, attempt);
+        assert_eq!(delay(0), Some(Duration::from_secs(1)));
+        assert_eq!(delay(1), Some(Duration::from_secs(1)));
+        assert_eq!(delay(2), Some(Duration::from_secs(2)));
+        assert_eq!(delay(3), Some(Duration::from_secs(3)));
+        assert_eq!(delay(4), None);
+    }
+
+    #[test]
+    fn test_retry_policy_jitter() {
+        let policy = RetryPolicy::new(2).with_initial_delay(Duration::from_secs(2));
+        let error = http_error(StatusCode::BAD_GATEWAY);
+        let delay = policy.delay(&Method::GET, &error, 1).unwrap();
+        assert!(delay >= Duration::from_secs(1) && delay <= Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_retry_policy_errors() {
+        let policy = RetryPolicy::new(2).with_jitter(false);
+        let retried = |method, error: Error| policy.delay(&method, &error, 1).is_some();
+        assert!(retried(
+            Method::POST,
+            http_error(StatusCode::TOO_MANY_REQUESTS)
+        ));
+        assert!(retried(
+            Method::GET,
+            http_error(StatusCode::INTERNAL_SERVER_ERROR)
+        ));
+        assert!(!retried(
+            Method::POST,
+            http_error(StatusCode::SERVICE_UNAVAILABLE)
+        ));
+        assert!(!retried(
+            Method::GET,
+            http_error(StatusCode::NOT_IMPLEMENTED)
+        ));
+        assert!(!retried(Method::GET, http_error(StatusCode::NOT_FOUND)));
+        assert!(retried(
+            Method::DELETE,
+            Error::new(ErrorKind::Transport, "connection refused")
+        ));
+        assert!(!retried(
+            Method::POST,
+            Error::new(ErrorKind::Transport, "connection refused")
+        ));
+        assert!(!retried(
+            Method::GET,
+            Error::new(ErrorKind::InvalidConfig, "bad config")
+        ));
+    }
+
+    #[test]
+    fn test_retry_policy_retry_after() {
+        let policy = RetryPolicy::new(2).with_max_delay(Duration::from_secs(60));
+        let error =
+            http_error(StatusCode::TOO_MANY_REQUESTS).with_retry_after(Duration::from_secs(5));
+        assert_eq!(
+            policy.delay(&Method::POST, &error, 1),
+            Some(Duration::from_secs(5))
+        );
+        let error = error.with_retry_after(Duration::from_secs(3600));
+        assert_eq!(
+            policy.delay(&Method::POST, &error, 1),
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        let _ = headers.insert(RETRY_AFTER, "120".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+        let _ = headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(0)));
+        let _ = headers.insert(RETRY_AFTER, "soon".parse().unwrap());
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(rate_limit_from_headers(&headers), None);
+        let _ = headers.insert("x-ratelimit-limit", "100, 100;w=60".parse().unwrap());
+        let _ = headers.insert("x-ratelimit-remaining", "0".parse().unwrap());
+        let _ = headers.insert("x-ratelimit-reset", "30".parse().unwrap());
+        let rate_limit = rate_limit_from_headers(&headers).unwrap();
+        assert_eq!(rate_limit.limit, Some(100));
+        assert_eq!(rate_limit.remaining, Some(0));
+        assert_eq!(rate_limit.reset, Some(Duration::from_secs(30)));
+        assert_eq!(rate_limit.retry_after, None);
+
+        // A timestamp in the past.
+        let _ = headers.insert("x-ratelimit-reset", "1445412480".parse().unwrap());
+        let rate_limit = rate_limit_from_headers(&headers).unwrap();
+        assert_eq!(rate_limit.reset, Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        assert!(RateLimiter::new(0.0).is_none());
+        assert!(RateLimiter::new(f64::INFINITY).is_none());
+        let limiter = RateLimiter::new(2.0).unwrap();
+        assert_eq!(limiter.requests_per_second(), 2.0);
+        assert_eq!(limiter.reserve(), Duration::from_secs(0));
+        let wait = limiter.reserve();
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_rate_limiter_update() {
+        let limiter = RateLimiter::new(1000.0).unwrap();
+        limiter.update(&RateLimit {
+            remaining: Some(1),
+            reset: Some(Duration::from_secs(10)),
+            ..RateLimit::default()
+        });
+        assert!(limiter.reserve() < Duration::from_secs(1));
+        limiter.update(&RateLimit {
+            remaining: Some(0),
+            reset: Some(Duration::from_secs(10)),
+            ..RateLimit::default()
+        });
+        assert!(limiter.reserve() > Duration::from_secs(9));
+    }
+
+    #[test]
+    fn test_query() {
+        let mut query = Query::new()
+            .with("name", "my server")
+            .with("tag", "a")
+            .with("tag", "b")
+            .with_bool("all_tenants", true)
+            .with_bool("deleted", false)
+            .with_sort("name", SortDir::Asc)
+            .with_sort("created_at", SortDir::Desc)
+            .with_marker("abcd")
+            .with_limit(10);
+        query.set_limit(20);
+        query.set_marker("efgh");
+        assert_eq!(query.get("tag").collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(query.get("limit").collect::<Vec<_>>(), vec!["20"]);
+        assert_eq!(
+            query.to_query_string(),
+            "name=my+server&tag=a&tag=b&all_tenants=True&deleted=False&sort_key=name&\
+             sort_dir=asc&sort_key=created_at&sort_dir=desc&limit=20&marker=efgh"
+        );
+        query.remove("tag");
+        assert!(query.get("tag").next().is_none());
+        assert!(Query::new().is_empty());
+        assert_eq!(Query::new().to_query_string(), "");
+    }
+
+    #[test]
+    fn test_query_serialize() {
+        let query: Query = vec![("status", "ACTIVE"), ("tag", "a&b")]
+            .into_iter()
+            .collect();
+        let request = reqwest::Client::new()
+            .get("http://127.0.0.1/servers")
+            .query(&query)
+            .build()
+            .unwrap();
+        assert_eq!(request.url().query(), Some("status=ACTIVE&tag=a%26b"));
+    }
+
+    #[cfg(feature = "stream")]
+    #[tokio::test]
+    async fn test_download_to() {
+        use futures::stream::TryStreamExt;
+
+        let body = vec![42u8; 100_000];
+        let response: reqwest::Response = http::Response::builder()
+            .status(200)
+            .header("content-length", "100000")
+            .body(body.clone())
+            .unwrap()
+            .into();
+        let mut progress = Vec::new();
+        let mut buffer = Vec::new();
+        let size = super::download_to_with_progress(response, &mut buffer, |done, total| {
+            progress.push((done, total))
+        })
+        .await
+        .unwrap();
+        assert_eq!(size, 100_000);
+        assert_eq!(buffer, body);
+        assert_eq!(progress.last(), Some(&(100_000, Some(100_000))));
+
+        let response: reqwest::Response = http::Response::builder()
+            .status(200)
+            .body("abc")
+            .unwrap()
+            .into();
+        let chunks: Vec<_> = super::bytes_stream(response).try_collect().await.unwrap();
+        assert_eq!(chunks.concat(), b"abc");
+    }
+
+    #[tokio::test]
+    async fn test_check_error_body_limit() {
+        let server = MockServer::start();
+        server.mock(
+            Mock::new(Method::GET, "/")
+                .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+                .with_body(vec![b'x'; MAX_ERROR_BODY_SIZE * 2]),
+        );
+        let response = reqwest::Client::new()
+            .get(server.url().clone())
+            .send()
+            .await
+            .unwrap();
+        let err = check(response).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InternalServerError);
+        assert_eq!(err.status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+        assert_eq!(err.to_string().matches('x').count(), MAX_ERROR_BODY_SIZE);
+    }
+
+    #[cfg(feature = "stream")]
+    #[tokio::test]
+    async fn test_binary_body() {
+        for length in &[None, Some(11)] {
+            let server = MockServer::start();
+            server.mock(Mock::new(Method::PUT, "/").with_status(StatusCode::NO_CONTENT));
+            let body = super::reader_body(std::io::Cursor::new(b"hello world".to_vec()));
+            let builder = reqwest::Client::new().put(server.url().clone());
+            let response = super::with_binary_body(builder, body, *length)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            let request = &server.requests()[0];
+            assert_eq!(request.headers["content-type"], "application/octet-stream");
+            assert_eq!(request.body, b"hello world");
+            if length.is_some() {
+                assert_eq!(request.headers["content-length"], "11");
+                assert!(!request.headers.contains_key("transfer-encoding"));
+            } else {
+                assert_eq!(request.headers["transfer-encoding"], "chunked");
+            }
+        }
+    }
+}
@@ -14,7 +14,7 @@
 
 //! OpenStack service types.
 
-use reqwest::header::HeaderMap;
+use reqwest::header::{HeaderMap, HeaderValue};
 
 use super::{ApiVersion, Error, ErrorKind};
 
@@ -71,7 +71,7 @@ pub enum VersionSelector {
 macro_rules! service {
     ($(#[$attr:meta])* $var:ident: $cls:ident -> $name:expr, discovery $disc:expr) => {
         $(#[$attr])*
-        #[derive(Copy, Clone, Debug)]
+        #[derive(Copy, Clone, Debug, Default)]
         #[non_exhaustive]
         pub struct $cls;
 
@@ -105,7 +105,7 @@ macro_rules! service {
 
     ($(#[$attr:meta])* $var:ident: $cls:ident -> $name:expr, header $hdr:expr) => {
         $(#[$attr])*
-        #[derive(Copy, Clone, Debug)]
+        #[derive(Copy, Clone, Debug, Default)]
         #[non_exhaustive]
         pub struct $cls;
 
@@ -134,6 +134,49 @@ macro_rules! service {
         $(#[$attr])*
         pub const $var: $cls = $cls::new();
     };
+
+    ($(#[$attr:meta])* $var:ident: $cls:ident -> $name:expr, api_version $svc:expr) => {
+        $(#[$attr])*
+        #[derive(Copy, Clone, Debug, Default)]
+        #[non_exhaustive]
+        pub struct $cls;
+
+        impl $cls {
+            /// Create a new service type.
+            pub const fn new() -> $cls {
+                $cls
+            }
+        }
+
+        impl $crate::services::ServiceType for $cls {
+            fn catalog_type(&self) -> &'static str {
+                $name
+            }
+
+            fn set_api_version_headers(
+                &self,
+                headers: &mut HeaderMap,
+                version: ApiVersion,
+            ) -> Result<(), Error> {
+                $crate::services::set_openstack_api_version(headers, $svc, version);
+                Ok(())
+            }
+        }
+
+        $(#[$attr])*
+        pub const $var: $cls = $cls::new();
+    };
+}
+
+/// Set the generic `OpenStack-API-Version` header for the service.
+pub(crate) fn set_openstack_api_version(
+    headers: &mut HeaderMap,
+    service: &str,
+    version: ApiVersion,
+) {
+    let value = HeaderValue::from_str(&format!("{} {}", service, version))
+        .expect("Service names are valid header values");
+    let _ = headers.insert("openstack-api-version", value);
 }
 
 /// A generic service.
@@ -144,7 +187,7 @@ pub struct GenericService {
 }
 
 /// Compute service.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Default)]
 #[non_exhaustive]
 pub struct ComputeService;
 
@@ -170,7 +213,7 @@ service! {
 
 service! {
     #[doc = "Block Storage service (v3)."]
-    BLOCK_STORAGE: BlockStorageService -> "volumev3"
+    BLOCK_STORAGE: BlockStorageService -> "volumev3", api_version "volume"
 }
 
 impl GenericService {
@@ -219,11 +262,49 @@ impl ServiceType for ComputeService {
         headers: &mut HeaderMap,
         version: ApiVersion,
     ) -> Result<(), Error> {
-        // TODO: new-style header support
+        // The legacy header is required for versions before 2.27.
         let _ = headers.insert("x-openstack-nova-api-version", version.into());
+        set_openstack_api_version(headers, "compute", version);
         Ok(())
     }
 }
 
 /// Compute service.
 pub const COMPUTE: ComputeService = ComputeService::new();
+
+#[cfg(test)]
+mod test {
+    use reqwest::header::HeaderMap;
+
+    use super::super::ApiVersion;
+    use super::{ServiceType, BAREMETAL, BLOCK_STORAGE, COMPUTE, IMAGE};
+
+    fn headers<Srv: ServiceType>(service: Srv) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        service
+            .set_api_version_headers(&mut headers, ApiVersion(3, 42))
+            .unwrap();
+        headers
+    }
+
+    #[test]
+    fn test_api_version_headers() {
+        let compute = headers(COMPUTE);
+        assert_eq!(compute["x-openstack-nova-api-version"], "3.42");
+        assert_eq!(compute["openstack-api-version"], "compute 3.42");
+        let baremetal = headers(BAREMETAL);
+        assert_eq!(baremetal["x-openstack-ironic-api-version"], "3.42");
+        let block_storage = headers(BLOCK_STORAGE);
+        assert_eq!(block_storage["openstack-api-version"], "volume 3.42");
+        assert_eq!(block_storage.len(), 1);
+    }
+
+    #[test]
+    fn test_api_version_headers_unsupported() {
+        let mut headers = HeaderMap::new();
+        assert!(IMAGE
+            .set_api_version_headers(&mut headers, ApiVersion(2, 1))
+            .is_err());
+        assert!(headers.is_empty());
+    }
+}
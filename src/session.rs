@@ -15,27 +15,64 @@
 //! Session structure definition.
 
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::mem;
 use std::ops::Deref;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "stream")]
 use futures::Stream;
 use log::{debug, trace};
-use reqwest::header::HeaderMap;
-use reqwest::{Method, RequestBuilder, Response, Url};
+use osproto::identity::{CatalogRecord, Endpoint};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT};
+use reqwest::{IntoUrl, Method, Request, RequestBuilder, Response, Url};
 use serde::de::DeserializeOwned;
-use serde::Serialize;
-use tokio::sync::RwLock;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+use tokio::time;
 
-use super::protocol::ServiceInfo;
-use super::request;
-use super::services::ServiceType;
+use super::catalog::{self, CatalogEndpoint, CatalogService};
+#[cfg(feature = "ec2")]
+use super::ec2::{Ec2Credentials, Ec2CredentialsListRoot, Ec2CredentialsRoot};
+use super::health::{HealthReport, ServiceHealth};
+#[cfg(feature = "ec2")]
+use super::identity::v3_endpoint_for;
+use super::identity::{
+    auth_endpoint_for, token_endpoint_for, AvailableProject, IdOrName, Password, Scope, TokenInfo,
+};
+use super::metrics::{ApiCall, Metrics};
+use super::middleware::{self, Middleware};
+use super::protocol::{self, EndpointInfo, RootCache, ServiceInfo, VersionInfo};
+use super::request::{self, RateLimiter, RetryPolicy};
+use super::services::{GenericService, ServiceType, VersionSelector};
 #[cfg(feature = "stream")]
-use super::stream::{paginated, Resource};
+use super::stream::{session_paginated, Resource};
+use super::telemetry;
 use super::url;
-use super::{Adapter, ApiVersion, AuthType, EndpointFilters, Error, InterfaceType};
+use super::{
+    Adapter, ApiVersion, AuthType, EndpointFilters, Error, ErrorKind, InterfaceType,
+    ValidInterfaces,
+};
 
-type Cache = HashMap<&'static str, ServiceInfo>;
+type RequestIdHook = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Service information keyed by the catalog type and the endpoint filters it was resolved with.
+///
+/// Every entry has its own lock, so that different services are resolved concurrently while
+/// concurrent requests to the same service wait for one resolution.
+type Cache = HashMap<(&'static str, EndpointFilters), Arc<Mutex<Option<ServiceInfo>>>>;
+
+#[derive(Debug, Deserialize)]
+struct TokenInfoRoot {
+    token: TokenInfo,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProjectsRoot {
+    projects: Vec<AvailableProject>,
+}
 
 /// An OpenStack API session.
 ///
@@ -46,23 +83,152 @@ type Cache = HashMap<&'static str, ServiceInfo>;
 ///
 /// All clones of one session share the same authentication and endpoint cache. Use
 /// [with_auth_type](#method.with_auth_type) to detach a session.
-#[derive(Debug, Clone)]
+///
+/// The `Debug` output does not contain the service token or other secrets.
+#[derive(Clone)]
 pub struct Session {
     auth: Arc<dyn AuthType>,
     cached_info: Arc<RwLock<Cache>>,
+    cloud_name: Option<String>,
+    default_api_versions: HashMap<&'static str, ApiVersion>,
+    default_headers: HeaderMap,
     endpoint_filters: EndpointFilters,
+    endpoint_overrides: HashMap<String, Url>,
+    max_api_versions: HashMap<&'static str, ApiVersion>,
+    metrics: Option<Arc<dyn Metrics>>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    request_id: Option<String>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    request_id_hook: Option<RequestIdHook>,
+    retry_policy: Option<RetryPolicy>,
+    root_cache: Arc<RootCache>,
+    service_interfaces: HashMap<String, ValidInterfaces>,
+    service_token: Option<String>,
+    timeout: Option<Duration>,
+    user_agent: Option<String>,
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("auth", &self.auth)
+            .field("cloud_name", &self.cloud_name)
+            .field("default_api_versions", &self.default_api_versions)
+            .field("default_headers", &self.default_headers)
+            .field("endpoint_filters", &self.endpoint_filters)
+            .field("endpoint_overrides", &self.endpoint_overrides)
+            .field("max_api_versions", &self.max_api_versions)
+            .field("metrics", &self.metrics)
+            .field("middleware", &self.middleware)
+            .field("rate_limit", &self.rate_limit())
+            .field("request_id", &self.request_id)
+            .field(
+                "request_id_hook",
+                &self.request_id_hook.as_ref().map(|_| "..."),
+            )
+            .field("retry_policy", &self.retry_policy)
+            .field("service_interfaces", &self.service_interfaces)
+            .field("service_token", &self.service_token.as_ref().map(|_| "***"))
+            .field("timeout", &self.timeout)
+            .field("user_agent", &self.user_agent)
+            .finish()
+    }
 }
 
 impl Session {
     /// Create a new session with a given authentication plugin.
     ///
     /// The resulting session will use the default endpoint interface (usually, public).
+    /// Authentication happens on the first request, use
+    /// [new_authenticated](#method.new_authenticated) to authenticate right away.
     pub fn new<Auth: AuthType + 'static>(auth_type: Auth) -> Session {
         Session {
             auth: Arc::new(auth_type),
             cached_info: Arc::new(RwLock::new(HashMap::new())),
+            cloud_name: None,
+            default_api_versions: HashMap::new(),
+            default_headers: HeaderMap::new(),
             endpoint_filters: EndpointFilters::default(),
+            endpoint_overrides: HashMap::new(),
+            max_api_versions: HashMap::new(),
+            metrics: None,
+            middleware: Vec::new(),
+            request_id: None,
+            rate_limiter: None,
+            request_id_hook: None,
+            retry_policy: None,
+            root_cache: Arc::new(RootCache::default()),
+            service_interfaces: HashMap::new(),
+            service_token: None,
+            timeout: None,
+            user_agent: None,
+        }
+    }
+
+    /// Create a new session and authenticate immediately.
+    ///
+    /// Unlike [new](#method.new), which only authenticates on the first request, this call fails
+    /// right away if the credentials are invalid or the Identity service is not reachable.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let auth = osauth::identity::Password::new(
+    ///     "https://cloud.local/identity",
+    ///     "admin",
+    ///     "pa$$w0rd",
+    ///     "Default",
+    /// )?;
+    /// let session = osauth::Session::new_authenticated(auth).await?;
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn new_authenticated<Auth: AuthType + 'static>(
+        auth_type: Auth,
+    ) -> Result<Session, Error> {
+        let session = Session::new(auth_type);
+        session.auth.refresh().await?;
+        Ok(session)
+    }
+
+    /// Create a new session using password authentication.
+    ///
+    /// A convenience wrapper around [identity::Password](identity/struct.Password.html):
+    ///
+    /// ```rust,no_run
+    /// use osauth::identity::{IdOrName, Scope};
+    ///
+    /// let scope = Scope::Project {
+    ///     project: IdOrName::Name("project1".to_string()),
+    ///     domain: Some(IdOrName::Name("Default".to_string())),
+    /// };
+    /// let session = osauth::Session::from_password(
+    ///     "https://cloud.local/identity",
+    ///     "admin",
+    ///     "pa$$w0rd",
+    ///     "Default",
+    ///     scope,
+    /// )
+    /// .expect("Invalid auth_url");
+    /// ```
+    pub fn from_password<U, S1, S2, S3>(
+        auth_url: U,
+        user_name: S1,
+        password: S2,
+        user_domain_name: S3,
+        scope: impl Into<Option<Scope>>,
+    ) -> Result<Session, Error>
+    where
+        U: IntoUrl,
+        S1: Into<String>,
+        S2: Into<String>,
+        S3: Into<String>,
+    {
+        let mut auth = Password::new(auth_url, user_name, password, user_domain_name)?;
+        if let Some(scope) = scope.into() {
+            auth.set_scope(scope);
         }
+        Ok(Session::new(auth))
     }
 
     /// Create an adapter for the specific service type.
@@ -107,6 +273,23 @@ impl Session {
         self.auth.as_ref()
     }
 
+    /// Name of the cloud this session was created for.
+    ///
+    /// Set by [from_config](fn.from_config.html) and by [from_env](fn.from_env.html) when
+    /// `OS_CLOUD` is used, `None` otherwise.
+    #[inline]
+    pub fn cloud_name(&self) -> Option<&str> {
+        self.cloud_name.as_deref()
+    }
+
+    /// The URL of the Identity service this session authenticates against.
+    ///
+    /// `None` if the authentication type does not use the Identity service, e.g. for `NoAuth`.
+    #[inline]
+    pub fn auth_url(&self) -> Option<&Url> {
+        self.auth.identity_url()
+    }
+
     /// Endpoint filters in use.
     #[inline]
     pub fn endpoint_filters(&self) -> &EndpointFilters {
@@ -134,6 +317,172 @@ impl Session {
         self.auth.refresh().await
     }
 
+    /// Drop the cached endpoint and version discovery information.
+    ///
+    /// Endpoints are resolved again on the next request. Unlike [refresh](#method.refresh), this
+    /// does not re-authenticate and affects the clones of this `Session` as well, since they share
+    /// the cache.
+    pub async fn clear_cache(&self) {
+        self.cached_info.write().await.clear();
+        self.root_cache.clear();
+    }
+
+    /// Re-authenticate to pull a fresh service catalog.
+    ///
+    /// Useful for long-lived sessions when services are added to the project. Unlike
+    /// [refresh](#method.refresh), this does not require a mutable reference and clears the cached
+    /// service information for all clones of this `Session` as well, since they share the same
+    /// authentication object (and thus the catalog).
+    pub async fn refresh_catalog(&self) -> Result<(), Error> {
+        self.auth.refresh().await?;
+        self.clear_cache().await;
+        Ok(())
+    }
+
+    /// Revoke the authentication token and drop the cached endpoint information.
+    ///
+    /// Useful for short-lived jobs to clean up their tokens instead of leaving them valid until
+    /// they expire. The token is also removed from the token cache (if enabled). The session can
+    /// still be used afterwards: a new token is requested on the next call that needs it.
+    ///
+    /// # Warning
+    ///
+    /// The token is revoked for clones of this `Session` as well, since they share the same
+    /// authentication object.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session =
+    ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    /// // ... do the work ...
+    /// session.invalidate().await?;
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn invalidate(&self) -> Result<(), Error> {
+        self.clear_cache().await;
+        self.auth.revoke().await
+    }
+
+    /// Create a new session with a token scoped to a different project.
+    ///
+    /// The current credentials are used to request a new token for the project, which is useful
+    /// for admin tools acting on behalf of other projects. This session is not affected. Fails
+    /// with `InvalidInput` if the authentication type does not support changing the scope (e.g.
+    /// application credentials, which are always bound to their project).
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// use osauth::identity::IdOrName;
+    ///
+    /// let session =
+    ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    /// let tenant_session = session
+    ///     .scoped_to_project(
+    ///         IdOrName::Name("tenant".to_string()),
+    ///         Some(IdOrName::Name("Default".to_string())),
+    ///     )
+    ///     .await?;
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn scoped_to_project(
+        &self,
+        project: IdOrName,
+        domain: Option<IdOrName>,
+    ) -> Result<Session, Error> {
+        self.scoped_to(Scope::Project { project, domain }).await
+    }
+
+    /// Create a new session with a token of a different scope.
+    ///
+    /// Works like [scoped_to_project](#method.scoped_to_project), but also accepts domain and
+    /// system scopes:
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session =
+    ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    /// let system_session = session.scoped_to(osauth::identity::Scope::System).await?;
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn scoped_to(&self, scope: Scope) -> Result<Session, Error> {
+        let session = self.with_scope(scope)?;
+        session.auth.refresh().await?;
+        Ok(session)
+    }
+
+    /// Create a new session with a different scope without authenticating.
+    ///
+    /// Works like [scoped_to](#method.scoped_to), but the token is only requested on the first
+    /// call that needs it. The new session keeps the settings, the HTTP client and the version
+    /// discovery cache of this session, but has its own token and endpoint cache. Useful when
+    /// iterating over many projects:
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// use osauth::identity::{IdOrName, Scope};
+    ///
+    /// let session =
+    ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    /// for project in session.available_projects().await? {
+    ///     let scoped = session.with_scope(Scope::Project {
+    ///         project: IdOrName::Id(project.id.clone()),
+    ///         domain: None,
+    ///     })?;
+    ///     let servers: serde_json::Value = scoped
+    ///         .get_json(osauth::services::COMPUTE, &["servers"], None)
+    ///         .await?;
+    ///     println!("{}: {}", project.name, servers);
+    /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub fn with_scope(&self, scope: Scope) -> Result<Session, Error> {
+        let auth = self.auth.with_new_scope(scope).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "The authentication type does not support changing the scope",
+            )
+        })?;
+        let mut session = self.clone();
+        session.reset_cache();
+        session.auth = Arc::from(auth);
+        Ok(session)
+    }
+
+    /// Create a new session using endpoints from a different region.
+    ///
+    /// The new session shares the authentication (and thus the token) with this session, so no
+    /// additional authentication is done. Use [regions](#method.regions) to find the regions
+    /// available in the catalog:
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session =
+    ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    /// for region in session.regions().await? {
+    ///     let servers: serde_json::Value = session
+    ///         .for_region(region.clone())
+    ///         .get_json(osauth::services::COMPUTE, &["servers"], None)
+    ///         .await?;
+    ///     println!("{}: {}", region, servers);
+    /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub fn for_region<S: Into<String>>(&self, region: S) -> Session {
+        let mut session = self.clone();
+        session.endpoint_filters_mut().set_region(region);
+        session
+    }
+
     /// Reset the internal cache.
     #[inline]
     fn reset_cache(&mut self) {
@@ -150,6 +499,14 @@ impl Session {
         self.auth = Arc::new(auth_type);
     }
 
+    /// Set the name of the cloud this session is for.
+    ///
+    /// The name is only used for informational purposes, e.g. in logging.
+    #[inline]
+    pub fn set_cloud_name<S: Into<String>>(&mut self, cloud_name: Option<S>) {
+        self.cloud_name = cloud_name.map(Into::into);
+    }
+
     /// A convenience call to set an endpoint interface.
     ///
     /// This call clears the cached service information for this `Session`.
@@ -159,792 +516,3510 @@ impl Session {
         self.endpoint_filters.set_interfaces(endpoint_interface);
     }
 
-    /// Convert this session into one using the given authentication.
-    #[inline]
-    pub fn with_auth_type<Auth: AuthType + 'static>(mut self, auth_method: Auth) -> Session {
-        self.set_auth_type(auth_method);
-        self
+    /// Use the given endpoint for the service instead of the one from the service catalog.
+    ///
+    /// Version discovery is still done using this endpoint if the service supports it. Other
+    /// services are not affected.
+    ///
+    /// This call clears the cached service information for this `Session`.
+    /// It does not, however, affect clones of this `Session`.
+    pub fn set_endpoint<Srv: ServiceType>(&mut self, service: Srv, endpoint: Url) {
+        self.set_endpoint_override(service.catalog_type(), endpoint);
     }
 
-    /// Convert this session into one using the given endpoint filters.
-    #[inline]
-    pub fn with_endpoint_filters(mut self, endpoint_filters: EndpointFilters) -> Session {
-        *self.endpoint_filters_mut() = endpoint_filters;
-        self
+    /// Use the given endpoint for the service with the catalog type.
+    ///
+    /// Works like [set_endpoint](#method.set_endpoint) for services only known by their catalog
+    /// type, e.g. from the configuration.
+    ///
+    /// This call clears the cached service information for this `Session`.
+    /// It does not, however, affect clones of this `Session`.
+    pub fn set_endpoint_override<S: Into<String>>(&mut self, catalog_type: S, endpoint: Url) {
+        self.reset_cache();
+        let _ = self
+            .endpoint_overrides
+            .insert(catalog_type.into(), endpoint);
     }
 
-    /// Convert this session into one using the given endpoint filters.
-    #[inline]
-    pub fn with_endpoint_interface(mut self, endpoint_interface: InterfaceType) -> Session {
-        self.set_endpoint_interface(endpoint_interface);
-        self
+    /// Set for how long version discovery documents are reused.
+    ///
+    /// Documents are cached by the URL they were fetched from, so that the same document is not
+    /// requested again e.g. after the endpoint filters change. Defaults to 5 minutes, zero disables
+    /// the cache.
+    ///
+    /// This call clears the cached documents for this `Session`.
+    /// It does not, however, affect clones of this `Session`.
+    pub fn set_discovery_cache_ttl(&mut self, ttl: Duration) {
+        self.root_cache = Arc::new(RootCache::new(ttl));
     }
 
-    /// Get minimum/maximum API (micro)version information.
+    /// Use the given endpoint interfaces for the service with the catalog type.
     ///
-    /// Returns `None` if the range cannot be determined, which usually means
-    /// that microversioning is not supported.
+    /// Overrides the interfaces from the endpoint filters for this service only, e.g. for clouds
+    /// where only some services have an internal endpoint.
+    ///
+    /// This call clears the cached service information for this `Session`.
+    /// It does not, however, affect clones of this `Session`.
+    pub fn set_service_interfaces<S, T>(&mut self, catalog_type: S, interfaces: T)
+    where
+        S: Into<String>,
+        T: Into<ValidInterfaces>,
+    {
+        self.reset_cache();
+        let _ = self
+            .service_interfaces
+            .insert(catalog_type.into(), interfaces.into());
+    }
+
+    /// Set the default microversion for the service.
+    ///
+    /// The version is given as an `X.Y` string and is validated against the range supported by
+    /// the service, failing with `IncompatibleApiVersion` if the service does not support it.
+    /// Requests to the service that do not specify an API version will use this version instead
+    /// of the one negotiated via [set_max_api_version](#method.set_max_api_version).
     ///
     /// ```rust,no_run
     /// # async fn example() -> Result<(), osauth::Error> {
-    /// let session =
+    /// let mut session =
     ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
-    /// let maybe_versions = session
-    ///     .get_api_versions(osauth::services::COMPUTE)
+    /// session
+    ///     .set_default_microversion(osauth::services::COMPUTE, "2.79")
     ///     .await?;
-    /// if let Some((min, max)) = maybe_versions {
-    ///     println!("The compute service supports versions {} to {}", min, max);
-    /// } else {
-    ///     println!("The compute service does not support microversioning");
-    /// }
     /// # Ok(()) }
     /// # #[tokio::main]
     /// # async fn main() { example().await.unwrap(); }
     /// ```
-    pub async fn get_api_versions<Srv: ServiceType + Send>(
-        &self,
+    pub async fn set_default_microversion<Srv, S>(
+        &mut self,
         service: Srv,
-    ) -> Result<Option<(ApiVersion, ApiVersion)>, Error> {
-        self.extract_service_info(service, |info| {
-            match (info.minimum_version, info.current_version) {
-                (Some(min), Some(max)) => Some((min, max)),
-                _ => None,
-            }
-        })
-        .await
-    }
-
-    /// Construct and endpoint for the given service from the path.
-    ///
-    /// You won't need to use this call most of the time, since all request calls can fetch the
-    /// endpoint automatically.
-    pub async fn get_endpoint<Srv, I>(&self, service: Srv, path: I) -> Result<Url, Error>
+        version: S,
+    ) -> Result<(), Error>
     where
         Srv: ServiceType + Send,
-        I: IntoIterator,
-        I::Item: AsRef<str>,
-        I::IntoIter: Send,
+        S: AsRef<str>,
     {
-        let path_iter = path.into_iter();
-        self.extract_service_info(service, |info| {
-            url::extend(info.root_url.clone(), path_iter)
-        })
-        .await
+        let version_str = version.as_ref();
+        let version: ApiVersion = version_str
+            .parse()
+            .map_err(|e: Error| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+        let catalog_type = service.catalog_type();
+        if !self.supports_api_version(service, version).await? {
+            let msg = format!(
+                "The {} service does not support microversion {}",
+                catalog_type, version_str
+            );
+            return Err(Error::new(ErrorKind::IncompatibleApiVersion, msg));
+        }
+        let _ = self.default_api_versions.insert(catalog_type, version);
+        Ok(())
     }
 
-    /// Get the currently used major version from the given service.
+    /// Negotiate the API version for the service automatically.
     ///
-    /// Can return `None` if the service does not support API version discovery at all.
-    pub async fn get_major_version<Srv>(&self, service: Srv) -> Result<Option<ApiVersion>, Error>
-    where
-        Srv: ServiceType + Send,
-    {
-        self.extract_service_info(service, |info| info.major_version)
-            .await
+    /// Requests to the service that do not specify an API version will use the highest version
+    /// supported by both the service and the caller (`max_version`). If there is no such version,
+    /// no API version is sent.
+    pub fn set_max_api_version<Srv: ServiceType>(&mut self, service: Srv, max_version: ApiVersion) {
+        let _ = self
+            .max_api_versions
+            .insert(service.catalog_type(), max_version);
     }
 
-    /// Pick the highest API version supported by the service.
+    /// Add a middleware invoked around every request.
     ///
-    /// Returns `None` if none of the requested versions are available.
+    /// Middleware is called for requests sent by the convenience methods of this `Session` (e.g.
+    /// [get](#method.get)) in the order it was added, see [Middleware](trait.Middleware.html).
+    /// The middleware is shared by the clones of this `Session` created after this call.
+    #[inline]
+    pub fn add_middleware<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    /// Set a header to send with every request to services.
+    ///
+    /// Default headers replace the headers with the same name set by the session (e.g. the user
+    /// agent) or by the authentication type. To add a header to one request only, use
+    /// `RequestBuilder::header` on the result of [request](#method.request):
     ///
     /// ```rust,no_run
     /// # async fn example() -> Result<(), osauth::Error> {
-    /// let session =
+    /// let mut session =
     ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
-    /// let candidates = vec![osauth::ApiVersion(1, 2), osauth::ApiVersion(1, 42)];
-    /// let maybe_version = session
-    ///     .pick_api_version(osauth::services::COMPUTE, candidates)
-    ///     .await?;
-    /// if let Some(version) = maybe_version {
-    ///     println!("Using version {}", version);
-    /// } else {
-    ///     println!("Using the base version");
-    /// }
-    /// let response = session
-    ///     .get(osauth::services::COMPUTE, &["servers"], maybe_version)
-    ///     .await?;
+    /// session.set_default_header("x-auth-sudo-tenant-id", "8a3f2b1c")?;
+    /// let builder = session
+    ///     .request(
+    ///         osauth::services::IMAGE,
+    ///         reqwest::Method::PUT,
+    ///         &["images", "1234"],
+    ///         None,
+    ///     )
+    ///     .await?
+    ///     .header("x-image-meta-name", "cirros");
     /// # Ok(()) }
     /// # #[tokio::main]
     /// # async fn main() { example().await.unwrap(); }
     /// ```
-    pub async fn pick_api_version<Srv, I>(
-        &self,
-        service: Srv,
-        versions: I,
-    ) -> Result<Option<ApiVersion>, Error>
+    ///
+    /// Fails with `ErrorKind::InvalidInput` if the name or the value is not a valid header.
+    pub fn set_default_header<K, V>(&mut self, name: K, value: V) -> Result<(), Error>
     where
-        Srv: ServiceType + Send,
-        I: IntoIterator<Item = ApiVersion>,
-        I::IntoIter: Send,
+        HeaderName: TryFrom<K>,
+        <HeaderName as TryFrom<K>>::Error: fmt::Display,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: fmt::Display,
     {
-        let vers = versions.into_iter();
-        Ok(if vers.size_hint().1 == Some(0) {
-            None
-        } else {
-            self.extract_service_info(service, |info| {
-                vers.filter(|item| info.supports_api_version(*item)).max()
-            })
-            .await?
-        })
+        let name = HeaderName::try_from(name).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid header name: {}", e),
+            )
+        })?;
+        let value = HeaderValue::try_from(value).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid value of header {}: {}", name, e),
+            )
+        })?;
+        let _ = self.default_headers.insert(name, value);
+        Ok(())
     }
 
-    /// Check if the service supports the API version.
-    pub async fn supports_api_version<Srv>(
-        &self,
-        service: Srv,
-        version: ApiVersion,
-    ) -> Result<bool, Error>
-    where
-        Srv: ServiceType + Send,
-    {
-        self.pick_api_version(service, Some(version))
-            .await
-            .map(|x| x.is_some())
+    /// Stop sending a default header.
+    #[inline]
+    pub fn remove_default_header(&mut self, name: &str) {
+        let _ = self.default_headers.remove(name);
     }
 
-    /// Make an HTTP request to the given service.
-    ///
-    /// The `service` argument is an object implementing the
-    /// [ServiceType](services/trait.ServiceType.html) trait. Some known service types are available
-    /// in the [services](services/index.html) module.
+    /// Headers sent with every request to services.
+    #[inline]
+    pub fn default_headers(&self) -> &HeaderMap {
+        &self.default_headers
+    }
+
+    /// Set a receiver of metrics of API calls.
     ///
-    /// The `path` argument is a URL path without the service endpoint (e.g. `/servers/1234`). For
-    /// an empty path, [NO_PATH](request/constant.NO_PATH.html) can be used.
+    /// The metrics are reported for requests sent by the convenience methods of this `Session`
+    /// (e.g. [get](#method.get)), see [Metrics](trait.Metrics.html). Pass an `Arc` to keep
+    /// access to the metrics, e.g. to [PrometheusMetrics](struct.PrometheusMetrics.html). The
+    /// receiver is shared by the clones of this `Session` created after this call.
     ///
-    /// If `api_version` is set, it is send with the request to enable a higher API version.
-    /// Otherwise the base API version is used. You can use
-    /// [pick_api_version](#method.pick_api_version) to choose an API version to use.
+    /// Token requests are reported by the authentication type, which is shared by all clones of
+    /// this `Session`, so they are reported to the receiver set last.
+    pub fn set_metrics<M: Metrics + 'static>(&mut self, metrics: M) {
+        let metrics: Arc<dyn Metrics> = Arc::new(metrics);
+        self.auth.set_metrics(Arc::clone(&metrics));
+        self.metrics = Some(metrics);
+    }
+
+    /// Set a request ID to send with every request.
     ///
-    /// The result is a `RequestBuilder` that can be customized further. Error checking and response
-    /// parsing can be done using functions from the [request](request/index.html) module.
+    /// The ID is sent in the `X-OpenStack-Request-ID` header and allows correlating requests
+    /// to several services. OpenStack expects it in the `req-<UUID>` format, see
+    /// [request::generate_request_id](request/fn.generate_request_id.html). Use
+    /// [request::with_request_id](request/fn.with_request_id.html) to override it for a single
+    /// request.
+    #[inline]
+    pub fn set_request_id<S: Into<String>>(&mut self, request_id: Option<S>) {
+        self.request_id = request_id.map(Into::into);
+    }
+
+    /// Request ID sent with every request (if any).
+    #[inline]
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    /// Set a hook called with the request ID of every response.
+    ///
+    /// The hook is called for requests sent by the convenience methods of this `Session` (e.g.
+    /// [get](#method.get)), including failed ones. Use
+    /// [request::request_id](request/fn.request_id.html) for requests sent manually. Errors
+    /// also carry the [request ID](struct.Error.html#method.request_id).
     ///
     /// ```rust,no_run
-    /// # async fn example() -> Result<(), osauth::Error> {
-    /// let session =
+    /// let mut session =
     ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
-    /// let response = osauth::request::send_checked(
-    ///     session
-    ///         .request(osauth::services::COMPUTE, reqwest::Method::HEAD, &["servers", "1234"], None)
-    ///         .await?
-    ///     )
-    ///     .await?;
-    /// println!("Response: {:?}", response);
-    /// # Ok(()) }
-    /// # #[tokio::main]
-    /// # async fn main() { example().await.unwrap(); }
+    /// session.set_request_id_hook(|request_id| println!("Request ID: {}", request_id));
     /// ```
-    ///
-    /// This is the most generic call to make a request. You may prefer to use more specific `get`,
-    /// `post`, `put` or `delete` calls instead.
-    pub async fn request<Srv, I>(
-        &self,
-        service: Srv,
-        method: Method,
-        path: I,
-        api_version: Option<ApiVersion>,
-    ) -> Result<RequestBuilder, Error>
+    pub fn set_request_id_hook<F>(&mut self, hook: F)
     where
-        Srv: ServiceType + Send + Clone,
-        I: IntoIterator,
-        I::Item: AsRef<str>,
-        I::IntoIter: Send,
+        F: Fn(&str) + Send + Sync + 'static,
     {
-        let url = self.get_endpoint(service.clone(), path).await?;
-        trace!(
-            "Sending HTTP {} request to {} with API version {:?}",
-            method,
-            url,
-            api_version
-        );
-        let mut builder = self.auth.request(method, url).await?;
-        if let Some(version) = api_version {
-            let mut headers = HeaderMap::new();
-            service.set_api_version_headers(&mut headers, version)?;
-            builder = builder.headers(headers)
-        }
-        Ok(builder)
+        self.request_id_hook = Some(Arc::new(hook));
     }
 
-    /// Issue a GET request.
+    /// Limit the rate of requests to services to the given number of requests per second.
     ///
-    /// See [request](#method.request) for an explanation of the parameters.
+    /// Requests sent by the convenience methods of this `Session` (e.g. [get](#method.get)) wait
+    /// for their turn. Requests are also paused when the server reports that none are left
+    /// (see [RateLimit](request/struct.RateLimit.html)) or responds with HTTP 429 and
+    /// `Retry-After`. The limit is shared by the clones of this `Session` created after this call.
+    ///
+    /// `None` or a number that is not positive disables the limit (the default).
     #[inline]
-    pub async fn get<Srv, I>(
-        &self,
+    pub fn set_rate_limit(&mut self, requests_per_second: Option<f64>) {
+        self.rate_limiter = requests_per_second.and_then(RateLimiter::new).map(Arc::new);
+    }
+
+    /// Maximum number of requests per second (if limited).
+    #[inline]
+    pub fn rate_limit(&self) -> Option<f64> {
+        self.rate_limiter
+            .as_ref()
+            .map(|limiter| limiter.requests_per_second())
+    }
+
+    /// Set a policy for retrying requests that failed with a transient error.
+    ///
+    /// The policy applies to requests sent by the convenience methods of this `Session` (e.g.
+    /// [get](#method.get)), see [RetryPolicy](request/struct.RetryPolicy.html) for details.
+    /// Requests are not retried by default.
+    #[inline]
+    pub fn set_retry_policy(&mut self, retry_policy: Option<RetryPolicy>) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// Policy for retrying requests that failed with a transient error.
+    #[inline]
+    pub fn retry_policy(&self) -> Option<RetryPolicy> {
+        self.retry_policy
+    }
+
+    /// Set a service token to send with every request.
+    ///
+    /// The service token is sent in the `X-Service-Token` header in addition to the normal
+    /// authentication. It is used by OpenStack services acting on behalf of themselves.
+    #[inline]
+    pub fn set_service_token<S: Into<String>>(&mut self, service_token: Option<S>) {
+        self.service_token = service_token.map(Into::into);
+    }
+
+    /// Service token in use (if any).
+    #[inline]
+    pub fn service_token(&self) -> Option<&str> {
+        self.service_token.as_deref()
+    }
+
+    /// Endpoint interfaces used for the service with the catalog type (if overridden).
+    #[inline]
+    pub fn service_interfaces(&self, catalog_type: &str) -> Option<&ValidInterfaces> {
+        self.service_interfaces.get(catalog_type)
+    }
+
+    /// Set a time out for requests to services.
+    ///
+    /// Authentication requests are not affected, see e.g.
+    /// [Password::set_auth_timeout](identity/struct.Password.html#method.set_auth_timeout).
+    /// No time out is used by default.
+    #[inline]
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Time out for requests to services.
+    #[inline]
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Set a custom user agent for requests to services.
+    ///
+    /// Requests are sent with `rust-osauth/<version>` by default. Authentication requests are
+    /// sent by the authentication type with its own HTTP client, use
+    /// [SessionBuilder::user_agent](struct.SessionBuilder.html#method.user_agent) or the
+    /// `user_agent` of a cloud in `clouds.yaml` to set the user agent for both.
+    #[inline]
+    pub fn set_user_agent<S: Into<String>>(&mut self, user_agent: S) {
+        self.user_agent = Some(user_agent.into());
+    }
+
+    /// User agent in use for requests to services.
+    #[inline]
+    pub fn user_agent(&self) -> &str {
+        self.user_agent
+            .as_deref()
+            .unwrap_or(request::DEFAULT_USER_AGENT)
+    }
+
+    /// Convert this session into one using the given authentication.
+    #[inline]
+    pub fn with_auth_type<Auth: AuthType + 'static>(mut self, auth_method: Auth) -> Session {
+        self.set_auth_type(auth_method);
+        self
+    }
+
+    /// Convert this session into one with the given cloud name.
+    #[inline]
+    pub fn with_cloud_name<S: Into<String>>(mut self, cloud_name: S) -> Session {
+        self.set_cloud_name(Some(cloud_name));
+        self
+    }
+
+    /// Convert this session into one using the given endpoint filters.
+    #[inline]
+    pub fn with_endpoint_filters(mut self, endpoint_filters: EndpointFilters) -> Session {
+        *self.endpoint_filters_mut() = endpoint_filters;
+        self
+    }
+
+    /// Convert this session into one negotiating the API version for the service.
+    #[inline]
+    pub fn with_max_api_version<Srv: ServiceType>(
+        mut self,
         service: Srv,
-        path: I,
-        api_version: Option<ApiVersion>,
-    ) -> Result<Response, Error>
+        max_version: ApiVersion,
+    ) -> Session {
+        self.set_max_api_version(service, max_version);
+        self
+    }
+
+    /// Convert this session into one using the given endpoint for the service with the catalog
+    /// type.
+    #[inline]
+    pub fn with_endpoint_override<S: Into<String>>(
+        mut self,
+        catalog_type: S,
+        endpoint: Url,
+    ) -> Session {
+        self.set_endpoint_override(catalog_type, endpoint);
+        self
+    }
+
+    /// Convert this session into one sending the header with every request.
+    #[inline]
+    pub fn with_default_header<K, V>(mut self, name: K, value: V) -> Result<Session, Error>
     where
-        Srv: ServiceType + Send + Clone,
-        I: IntoIterator,
-        I::Item: AsRef<str>,
-        I::IntoIter: Send,
+        HeaderName: TryFrom<K>,
+        <HeaderName as TryFrom<K>>::Error: fmt::Display,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: fmt::Display,
     {
-        request::send_checked(
-            self.request(service, Method::GET, path, api_version)
-                .await?,
-        )
-        .await
+        self.set_default_header(name, value)?;
+        Ok(self)
     }
 
-    /// Fetch a JSON using the GET request.
+    /// Convert this session into one reporting metrics of API calls.
+    #[inline]
+    pub fn with_metrics<M: Metrics + 'static>(mut self, metrics: M) -> Session {
+        self.set_metrics(metrics);
+        self
+    }
+
+    /// Convert this session into one with the given middleware added.
+    #[inline]
+    pub fn with_middleware<M: Middleware + 'static>(mut self, middleware: M) -> Session {
+        self.add_middleware(middleware);
+        self
+    }
+
+    /// Convert this session into one sending the given request ID.
+    #[inline]
+    pub fn with_request_id<S: Into<String>>(mut self, request_id: S) -> Session {
+        self.set_request_id(Some(request_id));
+        self
+    }
+
+    /// Convert this session into one using the given endpoint interfaces for the service.
+    #[inline]
+    pub fn with_service_interfaces<S, T>(mut self, catalog_type: S, interfaces: T) -> Session
+    where
+        S: Into<String>,
+        T: Into<ValidInterfaces>,
+    {
+        self.set_service_interfaces(catalog_type, interfaces);
+        self
+    }
+
+    /// Convert this session into one limiting the rate of requests to services.
+    #[inline]
+    pub fn with_rate_limit(mut self, requests_per_second: f64) -> Session {
+        self.set_rate_limit(Some(requests_per_second));
+        self
+    }
+
+    /// Convert this session into one retrying requests according to the given policy.
+    #[inline]
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Session {
+        self.set_retry_policy(Some(retry_policy));
+        self
+    }
+
+    /// Convert this session into one using the given service token.
+    #[inline]
+    pub fn with_service_token<S: Into<String>>(mut self, service_token: S) -> Session {
+        self.set_service_token(Some(service_token));
+        self
+    }
+
+    /// Convert this session into one using the given time out for requests to services.
+    #[inline]
+    pub fn with_timeout(mut self, timeout: Duration) -> Session {
+        self.set_timeout(Some(timeout));
+        self
+    }
+
+    /// Convert this session into one using the given user agent for requests to services.
     ///
-    /// ```rust,no_run
-    /// # async fn example() -> Result<(), osauth::Error> {
-    /// use osproto::common::IdAndName;
-    /// use serde::Deserialize;
+    /// See [set_user_agent](#method.set_user_agent) for authentication requests.
+    #[inline]
+    pub fn with_user_agent<S: Into<String>>(mut self, user_agent: S) -> Session {
+        self.set_user_agent(user_agent);
+        self
+    }
+
+    /// Convert this session into one using the given endpoint filters.
+    #[inline]
+    pub fn with_endpoint_interface(mut self, endpoint_interface: InterfaceType) -> Session {
+        self.set_endpoint_interface(endpoint_interface);
+        self
+    }
+
+    /// Get minimum/maximum API (micro)version information.
     ///
-    /// #[derive(Debug, Deserialize)]
-    /// pub struct ServersRoot {
-    ///     pub servers: Vec<IdAndName>,
-    /// }
+    /// Returns `None` if the range cannot be determined, which usually means
+    /// that microversioning is not supported.
     ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
     /// let session =
     ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
-    ///
-    /// let servers: ServersRoot = session
-    ///     .get_json(osauth::services::COMPUTE, &["servers"], None)
+    /// let maybe_versions = session
+    ///     .get_api_versions(osauth::services::COMPUTE)
     ///     .await?;
-    /// for srv in servers.servers {
-    ///     println!("ID = {}, Name = {}", srv.id, srv.name);
+    /// if let Some((min, max)) = maybe_versions {
+    ///     println!("The compute service supports versions {} to {}", min, max);
+    /// } else {
+    ///     println!("The compute service does not support microversioning");
     /// }
     /// # Ok(()) }
     /// # #[tokio::main]
     /// # async fn main() { example().await.unwrap(); }
     /// ```
-    ///
-    /// See [request](#method.request) for an explanation of the parameters.
-    #[inline]
-    pub async fn get_json<Srv, I, T>(
+    pub async fn get_api_versions<Srv: ServiceType + Send>(
         &self,
         service: Srv,
-        path: I,
-        api_version: Option<ApiVersion>,
-    ) -> Result<T, Error>
-    where
-        Srv: ServiceType + Send + Clone,
-        I: IntoIterator,
-        I::Item: AsRef<str>,
-        I::IntoIter: Send,
-        T: DeserializeOwned + Send,
-    {
-        request::fetch_json(
-            self.request(service, Method::GET, path, api_version)
-                .await?,
-        )
+    ) -> Result<Option<(ApiVersion, ApiVersion)>, Error> {
+        self.extract_service_info(service, |info| {
+            match (info.minimum_version, info.current_version) {
+                (Some(min), Some(max)) => Some((min, max)),
+                _ => None,
+            }
+        })
         .await
     }
 
-    /// Fetch a paginated list of JSON objects using the GET request.
+    /// The raw service catalog, failing if the authentication type does not provide one.
+    async fn raw_catalog(&self) -> Result<serde_json::Value, Error> {
+        self.auth.raw_catalog().await?.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "The authentication type does not provide a service catalog",
+            )
+        })
+    }
+
+    /// Get the service catalog.
+    ///
+    /// Services and their endpoints are returned in the catalog order. Endpoint overrides (e.g.
+    /// from [set_endpoint](#method.set_endpoint)) are not included. Fails with
+    /// `ErrorKind::InvalidInput` if the authentication type does not provide a service catalog.
     ///
     /// ```rust,no_run
     /// # async fn example() -> Result<(), osauth::Error> {
-    /// use futures::pin_mut;
-    /// use futures::stream::TryStreamExt;
-    /// use serde::Deserialize;
-    ///
-    /// #[derive(Debug, Deserialize)]
-    /// pub struct Server {
-    ///     pub id: String,
-    ///     pub name: String,
+    /// let session =
+    ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    /// for service in session.catalog().await? {
+    ///     for endpoint in &service.endpoints {
+    ///         println!("{} ({}): {}", service.service_type, endpoint.interface, endpoint.url);
+    ///     }
     /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn catalog(&self) -> Result<Vec<CatalogService>, Error> {
+        catalog::parse_catalog(self.raw_catalog().await?)
+    }
+
+    /// Get the service catalog as JSON in the format returned by the Identity service.
     ///
-    /// #[derive(Debug, Deserialize)]
-    /// pub struct ServersRoot {
-    ///     pub servers: Vec<Server>,
-    /// }
+    /// The result can be passed to other OpenStack clients to avoid re-authentication. Fails with
+    /// `ErrorKind::InvalidInput` if the authentication type does not provide a service catalog.
+    pub async fn catalog_json(&self) -> Result<String, Error> {
+        let catalog = self.raw_catalog().await?;
+        serde_json::to_string(&catalog)
+            .map_err(|e| Error::new(ErrorKind::InvalidResponse, e.to_string()))
+    }
+
+    /// List the distinct service types present in the service catalog.
     ///
-    /// // This implementatin defines the relationship between the root resource and its items.
-    /// impl osauth::stream::Resource for Server {
-    ///     type Id = String;
-    ///     type Root = ServersRoot;
-    ///     fn resource_id(&self) -> Self::Id {
-    ///         self.id.clone()
-    ///     }
-    /// }
+    /// The result is sorted. Fails with `ErrorKind::InvalidInput` if the authentication type does
+    /// not provide a service catalog.
+    pub async fn service_types(&self) -> Result<Vec<String>, Error> {
+        let catalog = self.raw_catalog().await?;
+        let mut result: Vec<String> = catalog
+            .as_array()
+            .map(|records| {
+                records
+                    .iter()
+                    .filter_map(|record| record.get("type")?.as_str())
+                    .map(ToString::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        result.sort();
+        result.dedup();
+        Ok(result)
+    }
+
+    /// List the distinct regions present in the service catalog.
     ///
-    /// // This is another required part of the pagination contract.
-    /// impl From<ServersRoot> for Vec<Server> {
-    ///     fn from(value: ServersRoot) -> Vec<Server> {
-    ///         value.servers
+    /// The result is sorted. Fails with `ErrorKind::InvalidInput` if the authentication type does
+    /// not provide a service catalog.
+    pub async fn regions(&self) -> Result<Vec<String>, Error> {
+        let mut result: Vec<String> = self
+            .catalog()
+            .await?
+            .iter()
+            .flat_map(|service| service.endpoints.iter())
+            .filter_map(CatalogEndpoint::region)
+            .filter(|region| !region.is_empty())
+            .map(ToString::to_string)
+            .collect();
+        result.sort();
+        result.dedup();
+        Ok(result)
+    }
+
+    /// List all catalog endpoints of the service matching the endpoint filters.
+    ///
+    /// The endpoint that is used by default comes first. Endpoints are ordered by region and
+    /// interface as listed in the filters, otherwise equal endpoints (e.g. from duplicated catalog
+    /// records) keep their catalog order. Endpoints set with [set_endpoint](#method.set_endpoint)
+    /// are not included. Fails with `ErrorKind::InvalidInput` if the authentication type does not
+    /// provide a service catalog.
+    pub async fn endpoints_for<Srv: ServiceType>(
+        &self,
+        service: Srv,
+    ) -> Result<Vec<Endpoint>, Error> {
+        let catalog = self.raw_catalog().await?;
+        let catalog: Vec<CatalogRecord> = serde_json::from_value(catalog).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidResponse,
+                format!("Invalid service catalog: {}", e),
+            )
+        })?;
+        let filters = self.filters_for(service.catalog_type());
+        let filters = match self.auth.default_filters() {
+            Some(defaults) => filters.with_defaults(defaults),
+            None => filters,
+        };
+        Ok(filters
+            .find_endpoints(&catalog, service.catalog_type())?
+            .into_iter()
+            .cloned()
+            .collect())
+    }
+
+    /// Get information about the endpoint of the service from its version discovery document.
+    ///
+    /// The information is cached like the one used for requests.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session =
+    ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    /// let info = session.endpoint_info(osauth::services::COMPUTE).await?;
+    /// println!("Using {} with microversions up to {:?}", info.root_url, info.current_version);
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn endpoint_info<Srv>(&self, service: Srv) -> Result<EndpointInfo, Error>
+    where
+        Srv: ServiceType + Send,
+    {
+        self.extract_service_info(service, |info| EndpointInfo::from(info))
+            .await
+    }
+
+    /// Get information about the current authentication token.
+    ///
+    /// Obtains a token if needed. Fails with `ErrorKind::InvalidInput` if the authentication type
+    /// does not use tokens.
+    pub async fn token_info(&self) -> Result<TokenInfo, Error> {
+        self.auth.token_info().await?.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "The authentication type does not use tokens",
+            )
+        })
+    }
+
+    /// Check that the cloud can be used with the credentials of this session.
+    ///
+    /// Authenticates (unless a valid token is cached), resolves the endpoints of the given
+    /// services (catalog types, e.g. `compute`) and sends a `GET` request to the first endpoint
+    /// found to verify that it responds. Authentication failures are returned as errors, problems
+    /// with the services are recorded in the report.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session =
+    ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    /// let report = session.validate(&["compute", "network"]).await?;
+    /// if !report.is_healthy() {
+    ///     for service in &report.services {
+    ///         if let Some(ref error) = service.error {
+    ///             eprintln!("{} cannot be used: {}", service.service_type, error);
+    ///         }
     ///     }
     /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn validate(&self, services: &[&'static str]) -> Result<HealthReport, Error> {
+        let started = Instant::now();
+        let token = self.auth.token_info().await?;
+        let auth_time = started.elapsed();
+
+        let mut probed = false;
+        let mut result = Vec::with_capacity(services.len());
+        for catalog_type in services {
+            let mut health = ServiceHealth {
+                service_type: catalog_type.to_string(),
+                endpoint: None,
+                response_time: None,
+                error: None,
+            };
+            match self
+                .service_endpoint(catalog_type, self.filters_for(catalog_type))
+                .await
+            {
+                Ok(endpoint) => {
+                    if !probed {
+                        probed = true;
+                        let started = Instant::now();
+                        match self.probe(catalog_type, endpoint.clone()).await {
+                            Ok(()) => health.response_time = Some(started.elapsed()),
+                            Err(e) => health.error = Some(e),
+                        }
+                    }
+                    health.endpoint = Some(endpoint);
+                }
+                Err(e) => health.error = Some(e),
+            }
+            result.push(health);
+        }
+
+        Ok(HealthReport {
+            token,
+            auth_time,
+            services: result,
+        })
+    }
+
+    /// Send a `GET` request to the endpoint of the service.
+    async fn probe(&self, catalog_type: &'static str, endpoint: Url) -> Result<(), Error> {
+        debug!("Probing {} endpoint {}", catalog_type, endpoint);
+        let service = GenericService::new(catalog_type, VersionSelector::Any);
+        let builder = self
+            .request_to(service, Method::GET, endpoint, None)
+            .await?;
+        let _ = self.send_reauthenticating(builder).await?;
+        Ok(())
+    }
+
+    /// Validate a token presented by a client.
+    ///
+    /// The token is checked by the Identity service, using the token of this session for
+    /// authentication, which usually requires the `admin` or `service` role. The token endpoint
+    /// is derived from the authentication URL. Returns information about the validated token or
+    /// `ErrorKind::AuthenticationFailed` if it is invalid or expired.
     ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
     /// let session =
     ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    /// let info = session.validate_token("gAAAAABe...").await?;
+    /// println!("The token expires at {}", info.expires_at);
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn validate_token(&self, token: &str) -> Result<TokenInfo, Error> {
+        let identity_url = self.identity_url()?;
+        let url = Url::parse(&token_endpoint_for(identity_url))
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+        let builder = self
+            .auth
+            .request(Method::GET, url)
+            .await?
+            .header("x-subject-token", token);
+        let builder = match self.timeout {
+            Some(timeout) => builder.timeout(timeout),
+            None => builder,
+        };
+        match self.fetch_json::<TokenInfoRoot>(builder).await {
+            Ok(root) => Ok(root.token),
+            Err(err) if err.kind() == ErrorKind::ResourceNotFound => Err(Error::new(
+                ErrorKind::AuthenticationFailed,
+                format!("Token is invalid or expired: {}", err),
+            )),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// List projects the user of this session has access to.
     ///
-    /// let servers = session
-    ///     .get_json_paginated::<_, _, Server>(
-    ///         osauth::services::COMPUTE,
-    ///         &["servers"],
-    ///         None,
-    ///         None,
-    ///         None
-    ///     )
-    ///     .await?;
+    /// Works with any token, including an unscoped one obtained without a project, so that an
+    /// application can let the user pick a project and then switch to it with
+    /// [scoped_to_project](#method.scoped_to_project).
     ///
-    /// pin_mut!(servers);
-    /// while let Some(srv) = servers.try_next().await? {
-    ///     println!("ID = {}, Name = {}", srv.id, srv.name);
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let auth = osauth::identity::Password::new(
+    ///     "https://cloud.local/identity",
+    ///     "admin",
+    ///     "pa$$w0rd",
+    ///     "Default",
+    /// )?;
+    /// let session = osauth::Session::new(auth);
+    /// let projects = session.available_projects().await?;
+    /// if let Some(project) = projects.first() {
+    ///     let _scoped = session
+    ///         .scoped_to_project(osauth::identity::IdOrName::Id(project.id.clone()), None)
+    ///         .await?;
     /// }
     /// # Ok(()) }
     /// # #[tokio::main]
     /// # async fn main() { example().await.unwrap(); }
     /// ```
+    pub async fn available_projects(&self) -> Result<Vec<AvailableProject>, Error> {
+        let identity_url = self.identity_url()?;
+        let url = Url::parse(&auth_endpoint_for(identity_url, "projects"))
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+        let builder = self.auth.request(Method::GET, url).await?;
+        let builder = match self.timeout {
+            Some(timeout) => builder.timeout(timeout),
+            None => builder,
+        };
+        self.fetch_json::<ProjectsRoot>(builder)
+            .await
+            .map(|root| root.projects)
+    }
+
+    /// List EC2 credentials of the user of this session.
     ///
-    /// See [request](#method.request) for an explanation of the parameters.
-    #[cfg(feature = "stream")]
-    pub async fn get_json_paginated<Srv, I, T>(
-        &self,
-        service: Srv,
-        path: I,
-        api_version: Option<ApiVersion>,
-        limit: Option<usize>,
-        starting_with: Option<<T as Resource>::Id>,
-    ) -> Result<impl Stream<Item = Result<T, Error>>, Error>
+    /// EC2 credentials can be used with S3-compatible APIs, see the [ec2](ec2/index.html) module.
+    #[cfg(feature = "ec2")]
+    pub async fn ec2_credentials(&self) -> Result<Vec<Ec2Credentials>, Error> {
+        let builder = self.ec2_credentials_request(Method::GET).await?;
+        self.fetch_json::<Ec2CredentialsListRoot>(builder)
+            .await
+            .map(|root| root.credentials)
+    }
+
+    /// Create EC2 credentials for the user and the project of this session.
+    ///
+    /// Requires a project-scoped token. Fails with `ErrorKind::InvalidInput` otherwise.
+    #[cfg(feature = "ec2")]
+    pub async fn create_ec2_credentials(&self) -> Result<Ec2Credentials, Error> {
+        let builder = self.ec2_credentials_request(Method::POST).await?;
+        self.fetch_json::<Ec2CredentialsRoot>(builder)
+            .await
+            .map(|root| root.credential)
+    }
+
+    /// Prepare a request to the EC2 credentials API of the current user.
+    #[cfg(feature = "ec2")]
+    async fn ec2_credentials_request(&self, method: Method) -> Result<RequestBuilder, Error> {
+        let identity_url = self.identity_url()?;
+        let info = self.token_info().await?;
+        let user = info.user.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidResponse,
+                "The Identity service did not report the user of the token",
+            )
+        })?;
+        let segments = ["users", &user.id, "credentials", "OS-EC2"];
+        let url = Url::parse(&v3_endpoint_for(identity_url, &segments))
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+        let builder = self.auth.request(method.clone(), url).await?;
+        let builder = if method == Method::POST {
+            let project = info.project.ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    "EC2 credentials can only be created with a project-scoped token",
+                )
+            })?;
+            builder.json(&serde_json::json!({ "tenant_id": project.id }))
+        } else {
+            builder
+        };
+        Ok(match self.timeout {
+            Some(timeout) => builder.timeout(timeout),
+            None => builder,
+        })
+    }
+
+    /// URL of the Identity service used for authentication.
+    fn identity_url(&self) -> Result<&Url, Error> {
+        self.auth.identity_url().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "The authentication type does not use the Identity service",
+            )
+        })
+    }
+
+    /// Construct and endpoint for the given service from the path.
+    ///
+    /// You won't need to use this call most of the time, since all request calls can fetch the
+    /// endpoint automatically. Use an empty path to find out which endpoint is used for the
+    /// service, and [set_endpoint](#method.set_endpoint) to override it.
+    pub async fn get_endpoint<Srv, I>(&self, service: Srv, path: I) -> Result<Url, Error>
     where
-        Srv: ServiceType + Send + Clone,
+        Srv: ServiceType + Send,
         I: IntoIterator,
         I::Item: AsRef<str>,
         I::IntoIter: Send,
-        T: Resource + Unpin,
-        <T as Resource>::Root: Into<Vec<T>> + Send,
     {
-        let builder = self
-            .request(service, Method::GET, path, api_version)
-            .await?;
-        Ok(paginated(builder, limit, starting_with))
+        let path_iter = path.into_iter();
+        self.extract_service_info(service, |info| {
+            url::extend(info.root_url.clone(), path_iter)
+        })
+        .await
     }
 
-    /// Fetch a JSON using the GET request with a query.
+    /// Construct an endpoint for the given service using a specific interface.
     ///
-    /// See `reqwest` crate documentation for how to define a query.
-    /// See [request](#method.request) for an explanation of the parameters.
-    #[inline]
-    pub async fn get_json_query<Srv, I, Q, T>(
+    /// The interface only applies to this call, the session's endpoint filters are used for
+    /// everything else. The result is cached separately for each interface. An endpoint set with
+    /// [set_endpoint](#method.set_endpoint) takes precedence over the interface.
+    pub async fn get_endpoint_with_interface<Srv, I>(
         &self,
         service: Srv,
+        interface: InterfaceType,
         path: I,
-        query: Q,
-        api_version: Option<ApiVersion>,
-    ) -> Result<T, Error>
+    ) -> Result<Url, Error>
     where
-        Srv: ServiceType + Send + Clone,
+        Srv: ServiceType + Send,
         I: IntoIterator,
         I::Item: AsRef<str>,
         I::IntoIter: Send,
-        Q: Serialize + Send,
-        T: DeserializeOwned + Send,
     {
-        request::fetch_json(
-            self.request(service, Method::GET, path, api_version)
-                .await?
-                .query(&query),
-        )
+        let filters = self.endpoint_filters.clone().with_interfaces(interface);
+        let path_iter = path.into_iter();
+        self.extract_service_info_with(service, filters, |info| {
+            url::extend(info.root_url.clone(), path_iter)
+        })
         .await
     }
 
-    /// Fetch a paginated list of JSON objects using the GET request with a query.
+    /// Get the currently used major version from the given service.
     ///
-    /// See `reqwest` crate documentation for how to define a query.
-    /// See [request](#method.request) for an explanation of the parameters.
-    #[cfg(feature = "stream")]
-    pub async fn get_json_query_paginated<Srv, I, Q, T>(
+    /// Can return `None` if the service does not support API version discovery at all.
+    pub async fn get_major_version<Srv>(&self, service: Srv) -> Result<Option<ApiVersion>, Error>
+    where
+        Srv: ServiceType + Send,
+    {
+        self.extract_service_info(service, |info| info.major_version)
+            .await
+    }
+
+    /// Pick the highest API version supported by the service.
+    ///
+    /// Returns `None` if none of the requested versions are available.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session =
+    ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    /// let candidates = vec![osauth::ApiVersion(1, 2), osauth::ApiVersion(1, 42)];
+    /// let maybe_version = session
+    ///     .pick_api_version(osauth::services::COMPUTE, candidates)
+    ///     .await?;
+    /// if let Some(version) = maybe_version {
+    ///     println!("Using version {}", version);
+    /// } else {
+    ///     println!("Using the base version");
+    /// }
+    /// let response = session
+    ///     .get(osauth::services::COMPUTE, &["servers"], maybe_version)
+    ///     .await?;
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn pick_api_version<Srv, I>(
         &self,
         service: Srv,
-        path: I,
-        query: Q,
-        api_version: Option<ApiVersion>,
-        limit: Option<usize>,
-        starting_with: Option<<T as Resource>::Id>,
-    ) -> Result<impl Stream<Item = Result<T, Error>>, Error>
+        versions: I,
+    ) -> Result<Option<ApiVersion>, Error>
     where
-        Srv: ServiceType + Send + Clone,
-        I: IntoIterator,
-        I::Item: AsRef<str>,
+        Srv: ServiceType + Send,
+        I: IntoIterator<Item = ApiVersion>,
         I::IntoIter: Send,
-        Q: Serialize + Send,
-        T: Resource + Unpin,
-        <T as Resource>::Root: Into<Vec<T>> + Send,
     {
-        let builder = self
-            .request(service, Method::GET, path, api_version)
+        let vers = versions.into_iter();
+        Ok(if vers.size_hint().1 == Some(0) {
+            None
+        } else {
+            self.extract_service_info(service, |info| {
+                vers.filter(|item| info.supports_api_version(*item)).max()
+            })
             .await?
-            .query(&query);
-        Ok(paginated(builder, limit, starting_with))
+        })
     }
 
-    /// Issue a GET request with a query
+    /// Default microversion for the service.
     ///
-    /// See `reqwest` crate documentation for how to define a query.
-    /// See [request](#method.request) for an explanation of the parameters.
+    /// Returns `None` if [set_default_microversion](#method.set_default_microversion) was not
+    /// called for this service.
     #[inline]
-    pub async fn get_query<Srv, I, Q>(
+    pub fn default_microversion<Srv: ServiceType>(&self, service: Srv) -> Option<ApiVersion> {
+        self.default_api_versions
+            .get(service.catalog_type())
+            .copied()
+    }
+
+    /// API version negotiated for the service.
+    ///
+    /// Returns `None` if [set_max_api_version](#method.set_max_api_version) was not called for
+    /// this service or if the service supports no version up to the maximum.
+    pub async fn negotiated_api_version<Srv>(
         &self,
         service: Srv,
-        path: I,
-        query: Q,
-        api_version: Option<ApiVersion>,
-    ) -> Result<Response, Error>
+    ) -> Result<Option<ApiVersion>, Error>
     where
-        Srv: ServiceType + Send + Clone,
-        I: IntoIterator,
-        I::Item: AsRef<str>,
-        I::IntoIter: Send,
-        Q: Serialize + Send,
+        Srv: ServiceType + Send,
     {
-        request::send_checked(
-            self.request(service, Method::GET, path, api_version)
-                .await?
-                .query(&query),
-        )
+        let max_version = match self.max_api_versions.get(service.catalog_type()) {
+            Some(version) => *version,
+            None => return Ok(None),
+        };
+        self.extract_service_info(service, |info| {
+            info.current_version
+                .map(|current| std::cmp::min(current, max_version))
+                .filter(|version| info.minimum_version.map_or(true, |min| *version >= min))
+        })
         .await
     }
 
-    /// POST a JSON object.
-    ///
-    /// The `body` argument is anything that can be serialized into JSON.
+    /// Check if the service supports the API version.
+    pub async fn supports_api_version<Srv>(
+        &self,
+        service: Srv,
+        version: ApiVersion,
+    ) -> Result<bool, Error>
+    where
+        Srv: ServiceType + Send,
+    {
+        self.pick_api_version(service, Some(version))
+            .await
+            .map(|x| x.is_some())
+    }
+
+    /// Check if the service supports the microversion `major.minor` (e.g. 2.79).
     ///
-    /// See [request](#method.request) for an explanation of the other parameters.
+    /// A convenience wrapper around [supports_api_version](#method.supports_api_version) for
+    /// feature-gating client logic against older clouds:
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session =
+    ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    /// if session
+    ///     .supports_microversion(osauth::services::COMPUTE, 2, 79)
+    ///     .await?
+    /// {
+    ///     println!("Volumes can be deleted on termination when attaching");
+    /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
     #[inline]
-    pub async fn post<Srv, I, T>(
+    pub async fn supports_microversion<Srv>(
+        &self,
+        service: Srv,
+        major: u16,
+        minor: u16,
+    ) -> Result<bool, Error>
+    where
+        Srv: ServiceType + Send,
+    {
+        self.supports_api_version(service, ApiVersion(major, minor))
+            .await
+    }
+
+    /// Get all major API versions from the version discovery document of the service.
+    ///
+    /// Versions are sorted from the lowest to the highest, unstable versions are included.
+    /// Services that only report the version of their endpoint return one version. The result
+    /// is empty if the service does not support version discovery.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session =
+    ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    /// for version in session.get_versions(osauth::services::BLOCK_STORAGE).await? {
+    ///     println!("{} ({:?})", version.id, version.status);
+    /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn get_versions<Srv>(&self, service: Srv) -> Result<Vec<VersionInfo>, Error>
+    where
+        Srv: ServiceType + Send,
+    {
+        if !service.version_discovery_supported() {
+            return Ok(Vec::new());
+        }
+        let catalog_type = service.catalog_type();
+        let ep = self
+            .service_endpoint(catalog_type, self.filters_for(catalog_type))
+            .await?;
+        protocol::fetch_versions(catalog_type, ep, self.auth.deref(), &self.root_cache).await
+    }
+
+    /// Make an HTTP request to the given service.
+    ///
+    /// The `service` argument is an object implementing the
+    /// [ServiceType](services/trait.ServiceType.html) trait. Some known service types are available
+    /// in the [services](services/index.html) module.
+    ///
+    /// The `path` argument is a URL path without the service endpoint (e.g. `/servers/1234`). For
+    /// an empty path, [NO_PATH](request/constant.NO_PATH.html) can be used.
+    ///
+    /// If `api_version` is set, it is send with the request to enable a higher API version.
+    /// Otherwise the version set via [set_default_microversion](#method.set_default_microversion),
+    /// the version negotiated via [set_max_api_version](#method.set_max_api_version) or the base
+    /// API version is used. You can use [pick_api_version](#method.pick_api_version) to
+    /// choose an API version to use.
+    ///
+    /// The result is a `RequestBuilder` that can be customized further. Error checking and response
+    /// parsing can be done using functions from the [request](request/index.html) module.
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session =
+    ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    /// let response = osauth::request::send_checked(
+    ///     session
+    ///         .request(osauth::services::COMPUTE, reqwest::Method::HEAD, &["servers", "1234"], None)
+    ///         .await?
+    ///     )
+    ///     .await?;
+    /// println!("Response: {:?}", response);
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    ///
+    /// This is the most generic call to make a request. You may prefer to use more specific `get`,
+    /// `post`, `put` or `delete` calls instead.
+    pub async fn request<Srv, I>(
         &self,
         service: Srv,
+        method: Method,
         path: I,
-        body: T,
         api_version: Option<ApiVersion>,
-    ) -> Result<Response, Error>
+    ) -> Result<RequestBuilder, Error>
     where
         Srv: ServiceType + Send + Clone,
         I: IntoIterator,
         I::Item: AsRef<str>,
         I::IntoIter: Send,
-        T: Serialize + Send,
     {
-        request::send_checked(
-            self.request(service, Method::POST, path, api_version)
-                .await?
-                .json(&body),
-        )
-        .await
+        let url = self.get_endpoint(service.clone(), path).await?;
+        self.request_to(service, method, url, api_version).await
     }
 
-    /// POST a JSON object and receive a JSON back.
+    /// Prepare an HTTP request using a specific interface for this request only.
     ///
-    /// The `body` argument is anything that can be serialized into JSON.
+    /// See [request](#method.request) for an explanation of the parameters and
+    /// [get_endpoint_with_interface](#method.get_endpoint_with_interface) for how the endpoint
+    /// is resolved.
     ///
-    /// See [request](#method.request) for an explanation of the other parameters.
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// let session =
+    ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    /// let response = osauth::request::send_checked(
+    ///     session
+    ///         .request_with_interface(
+    ///             osauth::services::COMPUTE,
+    ///             osauth::InterfaceType::Admin,
+    ///             reqwest::Method::GET,
+    ///             &["os-hypervisors"],
+    ///             None,
+    ///         )
+    ///         .await?
+    ///     )
+    ///     .await?;
+    /// println!("Response: {:?}", response);
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    pub async fn request_with_interface<Srv, I>(
+        &self,
+        service: Srv,
+        interface: InterfaceType,
+        method: Method,
+        path: I,
+        api_version: Option<ApiVersion>,
+    ) -> Result<RequestBuilder, Error>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+    {
+        let url = self
+            .get_endpoint_with_interface(service.clone(), interface, path)
+            .await?;
+        self.request_to(service, method, url, api_version).await
+    }
+
+    /// Prepare an HTTP request to the given URL of the service.
+    async fn request_to<Srv>(
+        &self,
+        service: Srv,
+        method: Method,
+        url: Url,
+        api_version: Option<ApiVersion>,
+    ) -> Result<RequestBuilder, Error>
+    where
+        Srv: ServiceType + Send + Clone,
+    {
+        let api_version = match api_version.or_else(|| self.default_microversion(service.clone())) {
+            Some(version) => Some(version),
+            None => self.negotiated_api_version(service.clone()).await?,
+        };
+        trace!(
+            "Sending HTTP {} request to {} with API version {:?}",
+            method,
+            url,
+            api_version
+        );
+        let mut builder = self.auth.request(method, url).await?;
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(ref user_agent) = self.user_agent {
+            builder = builder.header(USER_AGENT, user_agent);
+        }
+        if let Some(ref token) = self.service_token {
+            builder = builder.header("x-service-token", token);
+        }
+        if let Some(ref request_id) = self.request_id {
+            builder = builder.header(request::REQUEST_ID_HEADER, request_id);
+        }
+        if let Some(version) = api_version {
+            let mut headers = HeaderMap::new();
+            service.set_api_version_headers(&mut headers, version)?;
+            builder = builder.headers(headers)
+        }
+        if !self.default_headers.is_empty() {
+            builder = builder.headers(self.default_headers.clone());
+        }
+        Ok(builder)
+    }
+
+    /// Issue a GET request.
+    ///
+    /// See [request](#method.request) for an explanation of the parameters.
     #[inline]
-    pub async fn post_json<Srv, I, T, R>(
+    pub async fn get<Srv, I>(
         &self,
         service: Srv,
         path: I,
-        body: T,
         api_version: Option<ApiVersion>,
-    ) -> Result<R, Error>
+    ) -> Result<Response, Error>
     where
         Srv: ServiceType + Send + Clone,
         I: IntoIterator,
         I::Item: AsRef<str>,
         I::IntoIter: Send,
-        T: Serialize + Send,
-        R: DeserializeOwned + Send,
     {
-        request::fetch_json(
-            self.request(service, Method::POST, path, api_version)
-                .await?
-                .json(&body),
+        self.send_checked(
+            self.request(service, Method::GET, path, api_version)
+                .await?,
         )
         .await
     }
 
-    /// PUT a JSON object.
+    /// Fetch a JSON using the GET request.
     ///
-    /// The `body` argument is anything that can be serialized into JSON.
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// use osproto::common::IdAndName;
+    /// use serde::Deserialize;
     ///
-    /// See [request](#method.request) for an explanation of the other parameters.
+    /// #[derive(Debug, Deserialize)]
+    /// pub struct ServersRoot {
+    ///     pub servers: Vec<IdAndName>,
+    /// }
+    ///
+    /// let session =
+    ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    ///
+    /// let servers: ServersRoot = session
+    ///     .get_json(osauth::services::COMPUTE, &["servers"], None)
+    ///     .await?;
+    /// for srv in servers.servers {
+    ///     println!("ID = {}, Name = {}", srv.id, srv.name);
+    /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    ///
+    /// See [request](#method.request) for an explanation of the parameters.
     #[inline]
-    pub async fn put<Srv, I, T>(
+    pub async fn get_json<Srv, I, T>(
         &self,
         service: Srv,
         path: I,
-        body: T,
         api_version: Option<ApiVersion>,
-    ) -> Result<Response, Error>
+    ) -> Result<T, Error>
     where
         Srv: ServiceType + Send + Clone,
         I: IntoIterator,
         I::Item: AsRef<str>,
         I::IntoIter: Send,
-        T: Serialize + Send,
+        T: DeserializeOwned + Send,
     {
-        request::send_checked(
-            self.request(service, Method::PUT, path, api_version)
-                .await?
-                .json(&body),
+        self.fetch_json(
+            self.request(service, Method::GET, path, api_version)
+                .await?,
         )
         .await
     }
 
-    /// Issue an empty PUT request.
-    ///
-    /// See [request](#method.request) for an explanation of the parameters.
-    #[inline]
-    pub async fn put_empty<Srv, I>(
-        &self,
-        service: Srv,
-        path: I,
-        api_version: Option<ApiVersion>,
-    ) -> Result<Response, Error>
-    where
-        Srv: ServiceType + Send + Clone,
-        I: IntoIterator,
-        I::Item: AsRef<str>,
-        I::IntoIter: Send,
-    {
-        request::send_checked(
-            self.request(service, Method::PUT, path, api_version)
-                .await?,
-        )
-        .await
+    /// Fetch a paginated list of JSON objects using the GET request.
+    ///
+    /// Markers are used for pagination unless the resource provides links to the next pages via
+    /// [Resource::next_link](stream/trait.Resource.html#method.next_link).
+    ///
+    /// ```rust,no_run
+    /// # async fn example() -> Result<(), osauth::Error> {
+    /// use futures::pin_mut;
+    /// use futures::stream::TryStreamExt;
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Debug, Deserialize)]
+    /// pub struct Server {
+    ///     pub id: String,
+    ///     pub name: String,
+    /// }
+    ///
+    /// #[derive(Debug, Deserialize)]
+    /// pub struct ServersRoot {
+    ///     pub servers: Vec<Server>,
+    /// }
+    ///
+    /// // This implementatin defines the relationship between the root resource and its items.
+    /// impl osauth::stream::Resource for Server {
+    ///     type Id = String;
+    ///     type Root = ServersRoot;
+    ///     fn resource_id(&self) -> Self::Id {
+    ///         self.id.clone()
+    ///     }
+    /// }
+    ///
+    /// // This is another required part of the pagination contract.
+    /// impl From<ServersRoot> for Vec<Server> {
+    ///     fn from(value: ServersRoot) -> Vec<Server> {
+    ///         value.servers
+    ///     }
+    /// }
+    ///
+    /// let session =
+    ///     osauth::from_env().expect("Failed to create an identity provider from the environment");
+    ///
+    /// let servers = session
+    ///     .get_json_paginated::<_, _, Server>(
+    ///         osauth::services::COMPUTE,
+    ///         &["servers"],
+    ///         None,
+    ///         None,
+    ///         None
+    ///     )
+    ///     .await?;
+    ///
+    /// pin_mut!(servers);
+    /// while let Some(srv) = servers.try_next().await? {
+    ///     println!("ID = {}, Name = {}", srv.id, srv.name);
+    /// }
+    /// # Ok(()) }
+    /// # #[tokio::main]
+    /// # async fn main() { example().await.unwrap(); }
+    /// ```
+    ///
+    /// See [request](#method.request) for an explanation of the parameters.
+    #[cfg(feature = "stream")]
+    pub async fn get_json_paginated<Srv, I, T>(
+        &self,
+        service: Srv,
+        path: I,
+        api_version: Option<ApiVersion>,
+        limit: Option<usize>,
+        starting_with: Option<<T as Resource>::Id>,
+    ) -> Result<impl Stream<Item = Result<T, Error>>, Error>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+        T: Resource + Unpin,
+        <T as Resource>::Root: Into<Vec<T>> + Send,
+    {
+        let builder = self
+            .request(service, Method::GET, path, api_version)
+            .await?;
+        Ok(session_paginated(self, builder, limit, starting_with))
+    }
+
+    /// Fetch a JSON using the GET request with a query.
+    ///
+    /// See `reqwest` crate documentation for how to define a query.
+    /// See [request](#method.request) for an explanation of the parameters.
+    #[inline]
+    pub async fn get_json_query<Srv, I, Q, T>(
+        &self,
+        service: Srv,
+        path: I,
+        query: Q,
+        api_version: Option<ApiVersion>,
+    ) -> Result<T, Error>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+        Q: Serialize + Send,
+        T: DeserializeOwned + Send,
+    {
+        self.fetch_json(
+            self.request(service, Method::GET, path, api_version)
+                .await?
+                .query(&query),
+        )
+        .await
+    }
+
+    /// Fetch a paginated list of JSON objects using the GET request with a query.
+    ///
+    /// See `reqwest` crate documentation for how to define a query.
+    /// See [request](#method.request) for an explanation of the parameters.
+    #[cfg(feature = "stream")]
+    pub async fn get_json_query_paginated<Srv, I, Q, T>(
+        &self,
+        service: Srv,
+        path: I,
+        query: Q,
+        api_version: Option<ApiVersion>,
+        limit: Option<usize>,
+        starting_with: Option<<T as Resource>::Id>,
+    ) -> Result<impl Stream<Item = Result<T, Error>>, Error>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+        Q: Serialize + Send,
+        T: Resource + Unpin,
+        <T as Resource>::Root: Into<Vec<T>> + Send,
+    {
+        let builder = self
+            .request(service, Method::GET, path, api_version)
+            .await?
+            .query(&query);
+        Ok(session_paginated(self, builder, limit, starting_with))
+    }
+
+    /// Issue a GET request with a query
+    ///
+    /// See `reqwest` crate documentation for how to define a query.
+    /// See [request](#method.request) for an explanation of the parameters.
+    #[inline]
+    pub async fn get_query<Srv, I, Q>(
+        &self,
+        service: Srv,
+        path: I,
+        query: Q,
+        api_version: Option<ApiVersion>,
+    ) -> Result<Response, Error>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+        Q: Serialize + Send,
+    {
+        self.send_checked(
+            self.request(service, Method::GET, path, api_version)
+                .await?
+                .query(&query),
+        )
+        .await
+    }
+
+    /// POST a JSON object.
+    ///
+    /// The `body` argument is anything that can be serialized into JSON.
+    ///
+    /// See [request](#method.request) for an explanation of the other parameters.
+    #[inline]
+    pub async fn post<Srv, I, T>(
+        &self,
+        service: Srv,
+        path: I,
+        body: T,
+        api_version: Option<ApiVersion>,
+    ) -> Result<Response, Error>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+        T: Serialize + Send,
+    {
+        self.send_checked(
+            self.request(service, Method::POST, path, api_version)
+                .await?
+                .json(&body),
+        )
+        .await
+    }
+
+    /// POST a JSON object and receive a JSON back.
+    ///
+    /// The `body` argument is anything that can be serialized into JSON.
+    ///
+    /// See [request](#method.request) for an explanation of the other parameters.
+    #[inline]
+    pub async fn post_json<Srv, I, T, R>(
+        &self,
+        service: Srv,
+        path: I,
+        body: T,
+        api_version: Option<ApiVersion>,
+    ) -> Result<R, Error>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+        T: Serialize + Send,
+        R: DeserializeOwned + Send,
+    {
+        self.fetch_json(
+            self.request(service, Method::POST, path, api_version)
+                .await?
+                .json(&body),
+        )
+        .await
+    }
+
+    /// PUT a JSON object.
+    ///
+    /// The `body` argument is anything that can be serialized into JSON.
+    ///
+    /// See [request](#method.request) for an explanation of the other parameters.
+    #[inline]
+    pub async fn put<Srv, I, T>(
+        &self,
+        service: Srv,
+        path: I,
+        body: T,
+        api_version: Option<ApiVersion>,
+    ) -> Result<Response, Error>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+        T: Serialize + Send,
+    {
+        self.send_checked(
+            self.request(service, Method::PUT, path, api_version)
+                .await?
+                .json(&body),
+        )
+        .await
+    }
+
+    /// Issue an empty PUT request.
+    ///
+    /// See [request](#method.request) for an explanation of the parameters.
+    #[inline]
+    pub async fn put_empty<Srv, I>(
+        &self,
+        service: Srv,
+        path: I,
+        api_version: Option<ApiVersion>,
+    ) -> Result<Response, Error>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+    {
+        self.send_checked(
+            self.request(service, Method::PUT, path, api_version)
+                .await?,
+        )
+        .await
+    }
+
+    /// PUT a JSON object and receive a JSON back.
+    ///
+    /// The `body` argument is anything that can be serialized into JSON.
+    ///
+    /// See [request](#method.request) for an explanation of the other parameters.
+    #[inline]
+    pub async fn put_json<Srv, I, T, R>(
+        &self,
+        service: Srv,
+        path: I,
+        body: T,
+        api_version: Option<ApiVersion>,
+    ) -> Result<R, Error>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+        T: Serialize + Send,
+        R: DeserializeOwned + Send,
+    {
+        self.fetch_json(
+            self.request(service, Method::PUT, path, api_version)
+                .await?
+                .json(&body),
+        )
+        .await
+    }
+
+    /// PATCH a JSON object.
+    ///
+    /// The `body` argument is anything that can be serialized into JSON.
+    ///
+    /// See [request](#method.request) for an explanation of the other parameters.
+    #[inline]
+    pub async fn patch<Srv, I, T>(
+        &self,
+        service: Srv,
+        path: I,
+        body: T,
+        api_version: Option<ApiVersion>,
+    ) -> Result<Response, Error>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+        T: Serialize + Send,
+    {
+        self.send_checked(
+            self.request(service, Method::PATCH, path, api_version)
+                .await?
+                .json(&body),
+        )
+        .await
+    }
+
+    /// PATCH a JSON object and receive a JSON back.
+    ///
+    /// The `body` argument is anything that can be serialized into JSON.
+    ///
+    /// See [request](#method.request) for an explanation of the other parameters.
+    #[inline]
+    pub async fn patch_json<Srv, I, T, R>(
+        &self,
+        service: Srv,
+        path: I,
+        body: T,
+        api_version: Option<ApiVersion>,
+    ) -> Result<R, Error>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+        T: Serialize + Send,
+        R: DeserializeOwned + Send,
+    {
+        self.fetch_json(
+            self.request(service, Method::PATCH, path, api_version)
+                .await?
+                .json(&body),
+        )
+        .await
+    }
+
+    /// Issue a DELETE request.
+    ///
+    /// See [request](#method.request) for an explanation of the parameters.
+    #[inline]
+    pub async fn delete<Srv, I>(
+        &self,
+        service: Srv,
+        path: I,
+        api_version: Option<ApiVersion>,
+    ) -> Result<Response, Error>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+    {
+        self.send_checked(
+            self.request(service, Method::DELETE, path, api_version)
+                .await?,
+        )
+        .await
+    }
+
+    /// Send the request and check its result.
+    ///
+    /// On an HTTP 401, the authentication type re-authenticates using its original credentials
+    /// (if it can) and the request is retried once with the new token. If the retry fails with an
+    /// HTTP 401 as well or the request cannot be retried (e.g. its body is a stream), the error is
+    /// reported as `ErrorKind::TokenExpired`. An HTTP 403 (or an HTTP 401 mentioning access rules)
+    /// is reported as `ErrorKind::AccessRuleDenied` if the token has access rules.
+    ///
+    /// Transient failures are retried according to the [retry policy](#method.set_retry_policy).
+    pub(crate) async fn send_checked(&self, builder: RequestBuilder) -> Result<Response, Error> {
+        let policy = match self.retry_policy {
+            Some(policy) => policy,
+            None => return self.send_reauthenticating(builder).await,
+        };
+        let method = builder
+            .try_clone()
+            .and_then(|builder| builder.build().ok())
+            .map(|request| request.method().clone());
+        let mut builder = builder;
+        let mut attempt = 1;
+        loop {
+            let next = builder.try_clone();
+            let result = self.send_reauthenticating(builder).await;
+            let (next, method, err) = match (result, next, &method) {
+                (Err(err), Some(next), Some(method)) => (next, method, err),
+                (result, ..) => return result,
+            };
+            match policy.delay(method, &err, attempt) {
+                Some(delay) => {
+                    debug!(
+                        "Retrying a {} request in {:?} after attempt {} failed: {}",
+                        method, delay, attempt, err
+                    );
+                    if let Some(ref metrics) = self.metrics {
+                        let service_type = match next.try_clone().and_then(|b| b.build().ok()) {
+                            Some(request) => self.service_for_url(request.url()).await,
+                            None => None,
+                        };
+                        metrics.record_retry(service_type, method);
+                    }
+                    time::delay_for(delay).await;
+                    builder = next;
+                    attempt += 1;
+                }
+                None => return Err(err),
+            }
+        }
+    }
+
+    /// Send the request, re-authenticating and retrying once on HTTP 401.
+    async fn send_reauthenticating(&self, builder: RequestBuilder) -> Result<Response, Error> {
+        let retry = if self.auth.can_reauthenticate() {
+            builder.try_clone().and_then(|builder| builder.build().ok())
+        } else {
+            None
+        };
+        match self.send_once(builder).await {
+            Err(err) if err.kind() == ErrorKind::TokenExpired => match retry {
+                Some(request) => {
+                    debug!(
+                        "Retrying {} {} with a new token",
+                        request.method(),
+                        request.url()
+                    );
+                    self.send_once(self.reauthenticated(request).await?).await
+                }
+                None => Err(err),
+            },
+            other => other,
+        }
+    }
+
+    /// Re-create the request with the headers of the current authentication.
+    pub(crate) async fn reauthenticated(
+        &self,
+        mut request: Request,
+    ) -> Result<RequestBuilder, Error> {
+        let builder = self
+            .auth
+            .request(request.method().clone(), request.url().clone())
+            .await?;
+        // Headers set by the authentication type (e.g. X-Auth-Token) replace the stale ones.
+        let auth_headers = builder
+            .try_clone()
+            .and_then(|builder| builder.build().ok())
+            .map(|fresh| fresh.headers().clone())
+            .unwrap_or_default();
+        let mut headers = mem::take(request.headers_mut());
+        for name in auth_headers.keys() {
+            let _ = headers.remove(name);
+        }
+        let mut builder = builder.headers(headers);
+        if let Some(body) = request.body_mut().take() {
+            builder = builder.body(body);
+        }
+        if let Some(timeout) = request.timeout() {
+            builder = builder.timeout(*timeout);
+        }
+        Ok(builder)
+    }
+
+    /// Send the request once and check its result.
+    async fn send_once(&self, builder: RequestBuilder) -> Result<Response, Error> {
+        if let Some(ref limiter) = self.rate_limiter {
+            let wait = limiter.reserve();
+            if wait > Duration::from_secs(0) {
+                trace!("Waiting {:?} for the rate limit", wait);
+                time::delay_for(wait).await;
+            }
+        }
+        let result = match self.metrics {
+            Some(ref metrics) => self.send_measured(builder, metrics.as_ref()).await,
+            None => middleware::send_checked(builder, &self.middleware).await,
+        };
+        if let Some(ref limiter) = self.rate_limiter {
+            match result {
+                Ok(ref resp) => {
+                    if let Some(rate_limit) = request::rate_limit(resp) {
+                        limiter.update(&rate_limit);
+                    }
+                }
+                Err(ref err) => {
+                    if let Some(retry_after) = err.retry_after() {
+                        limiter.pause(retry_after);
+                    }
+                }
+            }
+        }
+        if let Some(ref hook) = self.request_id_hook {
+            let request_id = match result {
+                Ok(ref resp) => request::request_id(resp),
+                Err(ref e) => e.request_id().map(ToString::to_string),
+            };
+            if let Some(request_id) = request_id {
+                hook(&request_id);
+            }
+        }
+        match result {
+            Err(err)
+                if err.kind() == ErrorKind::AuthenticationFailed
+                    || err.kind() == ErrorKind::AccessDenied =>
+            {
+                let err = self.check_access_rules(err).await;
+                if err.kind() == ErrorKind::AuthenticationFailed {
+                    Err(self.check_token_expired(err).await)
+                } else {
+                    Err(err)
+                }
+            }
+            other => other,
+        }
+    }
+
+    /// Send the request through the middleware and report it to the metrics.
+    async fn send_measured(
+        &self,
+        builder: RequestBuilder,
+        metrics: &dyn Metrics,
+    ) -> Result<Response, Error> {
+        // NOTE: requests with streaming bodies cannot be cloned, their method stays unknown.
+        let request = builder.try_clone().and_then(|builder| builder.build().ok());
+        let started = Instant::now();
+        let result = middleware::send_checked(builder, &self.middleware).await;
+        let duration = started.elapsed();
+        let url = match (&result, &request) {
+            (Ok(resp), _) => Some(resp.url()),
+            (Err(_), Some(request)) => Some(request.url()),
+            (Err(_), None) => None,
+        };
+        let service_type = match url {
+            Some(url) => self.service_for_url(url).await,
+            None => None,
+        };
+        metrics.record_call(&ApiCall {
+            service_type,
+            method: request.map(|request| request.method().clone()),
+            status: match result {
+                Ok(ref resp) => Some(resp.status()),
+                Err(ref err) => err.status(),
+            },
+            duration,
+            error: result.as_ref().err().map(Error::kind),
+        });
+        result
+    }
+
+    /// Service type with the longest cached endpoint that is a prefix of the URL.
+    async fn service_for_url(&self, url: &Url) -> Option<&'static str> {
+        let cache = self.cached_info.read().await;
+        cache
+            .iter()
+            .filter_map(|((catalog_type, _), info)| {
+                // NOTE: do not wait for services that are being resolved right now.
+                let info = info.try_lock().ok()?;
+                let root = info.as_ref()?.root_url.as_str().trim_end_matches('/');
+                if url.as_str().starts_with(root) {
+                    Some((root.len(), *catalog_type))
+                } else {
+                    None
+                }
+            })
+            .max()
+            .map(|(_, catalog_type)| catalog_type)
+    }
+
+    /// Send the request and convert the response to a JSON.
+    pub(crate) async fn fetch_json<T>(&self, builder: RequestBuilder) -> Result<T, Error>
+    where
+        T: DeserializeOwned + Send,
+    {
+        self.send_checked(builder)
+            .await?
+            .json::<T>()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Figure out whether a request was rejected by the access rules of the token.
+    async fn check_access_rules(&self, err: Error) -> Error {
+        let mentions_rules = err.to_string().to_lowercase().contains("access rule");
+        if err.kind() == ErrorKind::AuthenticationFailed && !mentions_rules {
+            return err;
+        }
+
+        match self.auth.token_info().await {
+            Ok(Some(info)) if !info.access_rules().is_empty() => {
+                debug!(
+                    "Request rejected with a token limited by access rules {:?}",
+                    info.access_rules()
+                );
+                err.with_kind(ErrorKind::AccessRuleDenied)
+            }
+            _ => err,
+        }
+    }
+
+    /// Figure out whether an authentication failure is caused by an expired token.
+    async fn check_token_expired(&self, err: Error) -> Error {
+        if !self.auth.can_reauthenticate() {
+            return err;
+        }
+
+        debug!("Got HTTP 401, trying to re-authenticate");
+        match self.auth.refresh().await {
+            // Credentials are still valid, so it was the token.
+            Ok(()) => err.with_kind(ErrorKind::TokenExpired),
+            Err(auth_err) => {
+                debug!("Re-authentication failed: {}", auth_err);
+                auth_err
+            }
+        }
+    }
+
+    /// Ensure service info and return the cache.
+    async fn extract_service_info<Srv, F, T>(&self, service: Srv, filter: F) -> Result<T, Error>
+    where
+        Srv: ServiceType + Send,
+        F: FnOnce(&ServiceInfo) -> T + Send,
+        T: Send,
+    {
+        let filters = self.filters_for(service.catalog_type());
+        self.extract_service_info_with(service, filters, filter)
+            .await
+    }
+
+    /// Ensure service info for the given filters and return the cache.
+    async fn extract_service_info_with<Srv, F, T>(
+        &self,
+        service: Srv,
+        filters: EndpointFilters,
+        filter: F,
+    ) -> Result<T, Error>
+    where
+        Srv: ServiceType + Send,
+        F: FnOnce(&ServiceInfo) -> T + Send,
+        T: Send,
+    {
+        let key = (service.catalog_type(), filters);
+        let existing = self.cached_info.read().await.get(&key).cloned();
+        let entry = match existing {
+            Some(entry) => entry,
+            None => self
+                .cached_info
+                .write()
+                .await
+                .entry(key.clone())
+                .or_default()
+                .clone(),
+        };
+
+        let mut slot = entry.lock().await;
+        // NOTE: another task may have resolved the service while we were waiting for the lock.
+        if let Some(ref info) = *slot {
+            return Ok(filter(info));
+        }
+
+        debug!(
+            "No cached information for service {} with {:?}, fetching",
+            key.0, key.1
+        );
+        let info = self.fetch_service_info(service, key.1).await?;
+        let value = filter(&info);
+        *slot = Some(info);
+        Ok(value)
+    }
+
+    /// Endpoint filters for the service, including its interfaces (if set).
+    fn filters_for(&self, catalog_type: &str) -> EndpointFilters {
+        match self.service_interfaces.get(catalog_type) {
+            Some(interfaces) => self.endpoint_filters.clone().with_interfaces(*interfaces),
+            None => self.endpoint_filters.clone(),
+        }
+    }
+
+    /// Fetch service info using the given filters, bypassing the cache.
+    async fn fetch_service_info<Srv>(
+        &self,
+        service: Srv,
+        filters: EndpointFilters,
+    ) -> Result<ServiceInfo, Error>
+    where
+        Srv: ServiceType + Send,
+    {
+        let ep = self
+            .service_endpoint(service.catalog_type(), filters)
+            .await?;
+        ServiceInfo::fetch(service, ep, self.auth.deref(), &self.root_cache).await
+    }
+
+    /// Endpoint of the service from the overrides or the catalog.
+    async fn service_endpoint(
+        &self,
+        catalog_type: &'static str,
+        filters: EndpointFilters,
+    ) -> Result<Url, Error> {
+        if let Some(ep) = self.endpoint_overrides.get(catalog_type) {
+            debug!("Using endpoint override {} for {}", ep, catalog_type);
+            Ok(ep.clone())
+        } else {
+            telemetry::endpoint(catalog_type, &filters)
+                .run(self.auth.get_endpoint(catalog_type.to_string(), filters))
+                .await
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn cache_fake_service(
+        &mut self,
+        service_type: &'static str,
+        service_info: ServiceInfo,
+    ) {
+        // NOTE: keep the services cached before unless the cache is shared with a clone.
+        let cached = mem::replace(&mut self.cached_info, Arc::new(RwLock::new(HashMap::new())));
+        let mut hm = Arc::try_unwrap(cached)
+            .map(RwLock::into_inner)
+            .unwrap_or_default();
+        let key = (service_type, self.filters_for(service_type));
+        let _ = hm.insert(key, Arc::new(Mutex::new(Some(service_info))));
+        self.cached_info = Arc::new(RwLock::new(hm));
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use async_trait::async_trait;
+    #[cfg(feature = "stream")]
+    use futures::stream::TryStreamExt;
+    use osproto::identity::Endpoint;
+    use reqwest::{Method, RequestBuilder, Response, StatusCode, Url};
+    #[cfg(feature = "stream")]
+    use serde::Deserialize;
+
+    use super::super::identity::{IdOrName, Password, Scope, TokenInfo};
+    use super::super::protocol::ServiceInfo;
+    use super::super::request;
+    use super::super::request::{RetryPolicy, NO_PATH};
+    use super::super::services::{GenericService, VersionSelector, COMPUTE, OBJECT_STORAGE};
+    #[cfg(feature = "stream")]
+    use super::super::stream::Resource;
+    use super::super::test::{token_mock, Mock, MockServer};
+    use super::super::{
+        ApiVersion, AuthType, EndpointFilters, Error, ErrorKind, InterfaceType, Middleware, Next,
+        NoAuth, PrometheusMetrics, ValidInterfaces,
+    };
+    use super::Session;
+
+    pub const URL: &str = "http://127.0.0.1:5000/";
+
+    pub const URL_WITH_SUFFIX: &str = "http://127.0.0.1:5000/v2/servers";
+
+    pub fn new_simple_session(url: &str) -> Session {
+        let service_info = ServiceInfo {
+            root_url: Url::parse(url).unwrap(),
+            major_version: None,
+            minimum_version: None,
+            current_version: None,
+        };
+        new_session(url, service_info)
+    }
+
+    pub fn new_session(url: &str, service_info: ServiceInfo) -> Session {
+        let auth = NoAuth::new(url).unwrap();
+        let mut session = Session::new(auth);
+        session.cache_fake_service("fake", service_info);
+        session
+    }
+
+    pub const FAKE: GenericService = GenericService::new("fake", VersionSelector::Any);
+
+    #[tokio::test]
+    async fn test_get_endpoint() {
+        let s = new_simple_session(URL);
+        let ep = s.get_endpoint(FAKE, &[""]).await.unwrap();
+        assert_eq!(&ep.to_string(), URL);
+    }
+
+    #[tokio::test]
+    async fn test_get_endpoint_slice() {
+        let s = new_simple_session(URL);
+        let ep = s.get_endpoint(FAKE, &["v2", "servers"]).await.unwrap();
+        assert_eq!(&ep.to_string(), URL_WITH_SUFFIX);
+    }
+
+    #[tokio::test]
+    async fn test_get_endpoint_vec() {
+        let s = new_simple_session(URL);
+        let ep = s
+            .get_endpoint(FAKE, vec!["v2".to_string(), "servers".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(&ep.to_string(), URL_WITH_SUFFIX);
+    }
+
+    #[tokio::test]
+    async fn test_get_major_version_absent() {
+        let s = new_simple_session(URL);
+        let res = s.get_major_version(FAKE).await.unwrap();
+        assert!(res.is_none());
+    }
+
+    pub const MAJOR_VERSION: ApiVersion = ApiVersion(2, 0);
+
+    #[tokio::test]
+    async fn test_get_major_version_present() {
+        let service_info = ServiceInfo {
+            root_url: Url::parse(URL).unwrap(),
+            major_version: Some(MAJOR_VERSION),
+            minimum_version: None,
+            current_version: None,
+        };
+        let s = new_session(URL, service_info);
+        let res = s.get_major_version(FAKE).await.unwrap();
+        assert_eq!(res, Some(MAJOR_VERSION));
+    }
+
+    pub const MIN_VERSION: ApiVersion = ApiVersion(2, 1);
+    pub const MAX_VERSION: ApiVersion = ApiVersion(2, 42);
+
+    pub fn fake_service_info() -> ServiceInfo {
+        ServiceInfo {
+            root_url: Url::parse(URL).unwrap(),
+            major_version: Some(MAJOR_VERSION),
+            minimum_version: Some(MIN_VERSION),
+            current_version: Some(MAX_VERSION),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pick_api_version_empty() {
+        let service_info = fake_service_info();
+        let s = new_session(URL, service_info);
+        let res = s.pick_api_version(FAKE, None).await.unwrap();
+        assert!(res.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pick_api_version_empty_vec() {
+        let service_info = fake_service_info();
+        let s = new_session(URL, service_info);
+        let res = s.pick_api_version(FAKE, Vec::new()).await.unwrap();
+        assert!(res.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pick_api_version() {
+        let service_info = fake_service_info();
+        let s = new_session(URL, service_info);
+        let choice = vec![
+            ApiVersion(2, 0),
+            ApiVersion(2, 2),
+            ApiVersion(2, 4),
+            ApiVersion(2, 99),
+        ];
+        let res = s.pick_api_version(FAKE, choice).await.unwrap();
+        assert_eq!(res, Some(ApiVersion(2, 4)));
+    }
+
+    #[tokio::test]
+    async fn test_pick_api_version_option() {
+        let service_info = fake_service_info();
+        let s = new_session(URL, service_info);
+        let res = s
+            .pick_api_version(FAKE, Some(ApiVersion(2, 4)))
+            .await
+            .unwrap();
+        assert_eq!(res, Some(ApiVersion(2, 4)));
+    }
+
+    #[tokio::test]
+    async fn test_pick_api_version_impossible() {
+        let service_info = fake_service_info();
+        let s = new_session(URL, service_info);
+        let choice = vec![ApiVersion(2, 0), ApiVersion(2, 99)];
+        let res = s.pick_api_version(FAKE, choice).await.unwrap();
+        assert!(res.is_none());
+    }
+
+    #[derive(Debug)]
+    struct FakeReauth {
+        valid_credentials: bool,
+    }
+
+    #[async_trait]
+    impl AuthType for FakeReauth {
+        async fn get_endpoint(
+            &self,
+            _service_type: String,
+            _filters: EndpointFilters,
+        ) -> Result<Url, Error> {
+            Ok(Url::parse(URL).unwrap())
+        }
+
+        async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+            Ok(reqwest::Client::new().request(method, url))
+        }
+
+        async fn refresh(&self) -> Result<(), Error> {
+            if self.valid_credentials {
+                Ok(())
+            } else {
+                Err(Error::new(
+                    ErrorKind::AuthenticationFailed,
+                    "bad credentials",
+                ))
+            }
+        }
+
+        fn can_reauthenticate(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn test_new_authenticated() {
+        let _ = Session::new_authenticated(FakeReauth {
+            valid_credentials: true,
+        })
+        .await
+        .unwrap();
+        let err = Session::new_authenticated(FakeReauth {
+            valid_credentials: false,
+        })
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), ErrorKind::AuthenticationFailed);
+    }
+
+    #[derive(Debug)]
+    struct FakeIdentity {
+        url: Url,
+    }
+
+    #[async_trait]
+    impl AuthType for FakeIdentity {
+        async fn get_endpoint(
+            &self,
+            _service_type: String,
+            _filters: EndpointFilters,
+        ) -> Result<Url, Error> {
+            Ok(self.url.clone())
+        }
+
+        async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+            Ok(reqwest::Client::new()
+                .request(method, url)
+                .header("x-auth-token", "service"))
+        }
+
+        async fn refresh(&self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn identity_url(&self) -> Option<&Url> {
+            Some(&self.url)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validate_token() {
+        let server = serve_once(
+            json_once(
+                Method::GET,
+                "/v3/auth/tokens",
+                StatusCode::OK,
+                r#"{"token": {"expires_at": "2100-01-01T00:00:00Z", "methods": ["password"],
+                    "audit_ids": ["abc"], "roles": [], "catalog": []}}"#,
+            )
+            .with_header("x-subject-token", "user"),
+        );
+        let s = Session::new(FakeIdentity {
+            url: server.url().clone(),
+        });
+        let info = s.validate_token("user").await.unwrap();
+        assert_eq!(info.methods, vec!["password".to_string()]);
+        assert_eq!(info.audit_ids, vec!["abc".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_invalid() {
+        let server = serve_once(json_once(
+            Method::GET,
+            "/v3/auth/tokens",
+            StatusCode::NOT_FOUND,
+            r#"{"error": {"message": "Could not find token: user."}}"#,
+        ));
+        let s = Session::new(FakeIdentity {
+            url: server.url().clone(),
+        });
+        let err = s.validate_token("user").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AuthenticationFailed);
+        assert!(err.to_string().contains("Could not find token"));
+
+        let err = new_simple_session(URL)
+            .validate_token("user")
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn test_available_projects() {
+        let server = serve_once(json_once(
+            Method::GET,
+            "/identity/v3/auth/projects",
+            StatusCode::OK,
+            r#"{"projects": [
+                {"id": "1234", "name": "project1", "domain_id": "default", "enabled": true,
+                 "links": {}},
+                {"id": "5678", "name": "project2", "enabled": false}
+            ]}"#,
+        ));
+        let s = Session::new(FakeIdentity {
+            url: server.identity_url(),
+        });
+        let projects = s.available_projects().await.unwrap();
+        assert_eq!(projects.len(), 2);
+        assert_eq!(projects[0].name, "project1");
+        assert_eq!(projects[0].domain_id.as_deref(), Some("default"));
+        assert!(!projects[1].enabled);
+        assert_eq!(
+            projects[1].scope(),
+            Scope::Project {
+                project: IdOrName::Id("5678".to_string()),
+                domain: None,
+            }
+        );
+        assert_eq!(server.requests().len(), 1);
+
+        let err = new_simple_session(URL)
+            .available_projects()
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[derive(Debug)]
+    struct FakeInterfaces;
+
+    #[async_trait]
+    impl AuthType for FakeInterfaces {
+        async fn get_endpoint(
+            &self,
+            _service_type: String,
+            filters: EndpointFilters,
+        ) -> Result<Url, Error> {
+            if filters.interfaces == ValidInterfaces::one(InterfaceType::Admin) {
+                Ok(Url::parse("http://127.0.0.1:35357/").unwrap())
+            } else {
+                Ok(Url::parse(URL).unwrap())
+            }
+        }
+
+        async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+            Ok(reqwest::Client::new().request(method, url))
+        }
+
+        async fn refresh(&self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_with_interface() {
+        let s = Session::new(FakeInterfaces);
+        let req = s
+            .request_with_interface(
+                OBJECT_STORAGE,
+                InterfaceType::Admin,
+                Method::GET,
+                &["v1"],
+                None,
+            )
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(req.url().as_str(), "http://127.0.0.1:35357/v1");
+        // The session's filters are still used for other requests.
+        let ep = s.get_endpoint(OBJECT_STORAGE, &["v1"]).await.unwrap();
+        assert_eq!(ep.as_str(), "http://127.0.0.1:5000/v1");
+    }
+
+    #[derive(Debug, Default)]
+    struct FakeCounting(AtomicUsize);
+
+    #[async_trait]
+    impl AuthType for FakeCounting {
+        async fn get_endpoint(
+            &self,
+            service_type: String,
+            filters: EndpointFilters,
+        ) -> Result<Url, Error> {
+            let _ = self.0.fetch_add(1, Ordering::SeqCst);
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+            let port = if filters.interfaces == ValidInterfaces::one(InterfaceType::Internal) {
+                5001
+            } else {
+                5000
+            };
+            Ok(Url::parse(&format!("http://127.0.0.1:{}/{}", port, service_type)).unwrap())
+        }
+
+        async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+            Ok(reqwest::Client::new().request(method, url))
+        }
+
+        async fn refresh(&self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_cache() {
+        let auth = Arc::new(FakeCounting::default());
+        let s = Session::new(auth.clone());
+        let (first, second, internal) = tokio::join!(
+            s.get_endpoint(OBJECT_STORAGE, NO_PATH),
+            s.get_endpoint(OBJECT_STORAGE, NO_PATH),
+            s.get_endpoint_with_interface(OBJECT_STORAGE, InterfaceType::Internal, NO_PATH),
+        );
+        assert_eq!(
+            first.unwrap().as_str(),
+            "http://127.0.0.1:5000/object-store"
+        );
+        assert_eq!(
+            second.unwrap().as_str(),
+            "http://127.0.0.1:5000/object-store"
+        );
+        assert_eq!(
+            internal.unwrap().as_str(),
+            "http://127.0.0.1:5001/object-store"
+        );
+        assert_eq!(auth.0.load(Ordering::SeqCst), 2);
+
+        let _ = s
+            .get_endpoint_with_interface(OBJECT_STORAGE, InterfaceType::Internal, NO_PATH)
+            .await
+            .unwrap();
+        assert_eq!(auth.0.load(Ordering::SeqCst), 2);
+
+        s.clear_cache().await;
+        let _ = s.get_endpoint(OBJECT_STORAGE, NO_PATH).await.unwrap();
+        assert_eq!(auth.0.load(Ordering::SeqCst), 3);
+    }
+
+    fn unauthorized() -> Error {
+        Error::new(ErrorKind::AuthenticationFailed, "unauthorized")
+    }
+
+    #[tokio::test]
+    async fn test_token_expired() {
+        let s = Session::new(FakeReauth {
+            valid_credentials: true,
+        });
+        let err = s.check_token_expired(unauthorized()).await;
+        assert_eq!(err.kind(), ErrorKind::TokenExpired);
+    }
+
+    #[tokio::test]
+    async fn test_token_expired_bad_credentials() {
+        let s = Session::new(FakeReauth {
+            valid_credentials: false,
+        });
+        let err = s.check_token_expired(unauthorized()).await;
+        assert_eq!(err.kind(), ErrorKind::AuthenticationFailed);
+        assert!(err.to_string().contains("bad credentials"));
+    }
+
+    #[tokio::test]
+    async fn test_token_expired_no_reauth() {
+        let s = new_simple_session(URL);
+        let err = s.check_token_expired(unauthorized()).await;
+        assert_eq!(err.kind(), ErrorKind::AuthenticationFailed);
+    }
+
+    #[tokio::test]
+    async fn test_service_token() {
+        let s = new_simple_session(URL).with_service_token("s3cr3t");
+        assert_eq!(s.service_token(), Some("s3cr3t"));
+        assert!(!format!("{:?}", s).contains("s3cr3t"));
+        let req = s
+            .request(FAKE, Method::GET, &["v2"], None)
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(req.headers().get("x-service-token").unwrap(), "s3cr3t");
+    }
+
+    #[tokio::test]
+    async fn test_no_service_token() {
+        let s = new_simple_session(URL);
+        let req = s
+            .request(FAKE, Method::GET, &["v2"], None)
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(req.headers().get("x-service-token").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_user_agent() {
+        let s = new_simple_session(URL);
+        assert_eq!(s.user_agent(), request::DEFAULT_USER_AGENT);
+        let s = s.with_user_agent("my-tool/1.0");
+        assert_eq!(s.user_agent(), "my-tool/1.0");
+        let req = s
+            .request(FAKE, Method::GET, &["v2"], None)
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(req.headers().get("user-agent").unwrap(), "my-tool/1.0");
+    }
+
+    #[test]
+    fn test_from_password() {
+        let scope = Scope::Project {
+            project: IdOrName::Name("project1".to_string()),
+            domain: None,
+        };
+        let _ = Session::from_password(
+            "http://127.0.0.1:5000/identity",
+            "admin",
+            "pa$$w0rd",
+            "Default",
+            scope,
+        )
+        .unwrap();
+        let err = Session::from_password("not a url", "admin", "pa$$w0rd", "Default", None)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_auth_url() {
+        assert!(new_simple_session(URL).auth_url().is_none());
+        let id = Password::new(URL, "admin", "pa$$w0rd", "Default").unwrap();
+        assert_eq!(Session::new(id).auth_url().unwrap().as_str(), URL);
+    }
+
+    #[tokio::test]
+    async fn test_scoped_to_project() {
+        let server = serve_once(token_mock("scoped"));
+        let url = server.identity_url();
+        let id = Password::new(url.as_str(), "admin", "pa$$w0rd", "Default")
+            .unwrap()
+            .with_project_scope(IdOrName::Name("admin".to_string()), None);
+        let s = Session::new(id).with_cloud_name("cloud1");
+        let scoped = s
+            .scoped_to_project(
+                IdOrName::Name("tenant".to_string()),
+                Some(IdOrName::Name("Default".to_string())),
+            )
+            .await
+            .unwrap();
+        assert_eq!(scoped.cloud_name(), Some("cloud1"));
+        assert_eq!(scoped.auth_url().unwrap(), &url);
+        assert!(!Arc::ptr_eq(&s.auth, &scoped.auth));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        let body = requests[0].json::<serde_json::Value>().unwrap();
+        assert_eq!(
+            body["auth"]["scope"],
+            serde_json::json!({"project": {"name": "tenant", "domain": {"name": "Default"}}})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scoped_to_project_unsupported() {
+        let err = new_simple_session(URL)
+            .scoped_to_project(IdOrName::Name("tenant".to_string()), None)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn test_with_scope() {
+        let id = Password::new(URL, "admin", "pa$$w0rd", "Default")
+            .unwrap()
+            .with_project_scope(IdOrName::Name("admin".to_string()), None);
+        let s = Session::new(id).with_cloud_name("cloud1");
+        let scoped = s
+            .with_scope(Scope::Project {
+                project: IdOrName::Id("1234".to_string()),
+                domain: None,
+            })
+            .unwrap();
+        assert_eq!(scoped.cloud_name(), Some("cloud1"));
+        assert!(!Arc::ptr_eq(&s.auth, &scoped.auth));
+        assert!(!Arc::ptr_eq(&s.cached_info, &scoped.cached_info));
+        assert!(Arc::ptr_eq(&s.root_cache, &scoped.root_cache));
+
+        let err = new_simple_session(URL)
+            .with_scope(Scope::System)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_catalog() {
+        let s = new_session(URL, fake_service_info());
+        let cloned = s.clone();
+        s.refresh_catalog().await.unwrap();
+        assert!(s.cached_info.read().await.is_empty());
+        assert!(cloned.cached_info.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_invalidate() {
+        let id = Password::new(URL, "admin", "pa$$w0rd", "Default").unwrap();
+        let mut s = Session::new(id);
+        s.cache_fake_service("fake", fake_service_info());
+        let cloned = s.clone();
+        // No token has been received yet, so nothing is revoked.
+        s.invalidate().await.unwrap();
+        assert!(cloned.cached_info.read().await.is_empty());
+        assert!(s.cached_info.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_set_endpoint() {
+        let mut s = new_simple_session(URL);
+        let override_url = "http://127.0.0.1:8080/swift/v1";
+        s.set_endpoint(OBJECT_STORAGE, Url::parse(override_url).unwrap());
+        let ep = s.get_endpoint(OBJECT_STORAGE, NO_PATH).await.unwrap();
+        assert_eq!(ep.as_str(), override_url);
+        let ep = s.get_endpoint(OBJECT_STORAGE, &["info"]).await.unwrap();
+        assert_eq!(ep.as_str(), "http://127.0.0.1:8080/swift/v1/info");
+    }
+
+    #[tokio::test]
+    async fn test_set_endpoint_override() {
+        let s = new_simple_session(URL).with_endpoint_override(
+            "object-store",
+            Url::parse("http://127.0.0.1:8080/swift/v1").unwrap(),
+        );
+        let ep = s.get_endpoint(OBJECT_STORAGE, &["info"]).await.unwrap();
+        assert_eq!(ep.as_str(), "http://127.0.0.1:8080/swift/v1/info");
+    }
+
+    #[tokio::test]
+    async fn test_catalog_json_no_catalog() {
+        let s = new_simple_session(URL);
+        let err = s.catalog_json().await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[derive(Debug)]
+    struct FakeCatalog;
+
+    #[async_trait]
+    impl AuthType for FakeCatalog {
+        async fn get_endpoint(
+            &self,
+            _service_type: String,
+            _filters: EndpointFilters,
+        ) -> Result<Url, Error> {
+            Ok(Url::parse(URL).unwrap())
+        }
+
+        async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+            Ok(reqwest::Client::new().request(method, url))
+        }
+
+        async fn refresh(&self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        async fn raw_catalog(&self) -> Result<Option<serde_json::Value>, Error> {
+            Ok(Some(serde_json::json!([
+                {"type": "object-store", "endpoints": [
+                    {"interface": "public", "region": "RegionTwo", "url": "http://swift.two"},
+                ]},
+                {"type": "compute", "endpoints": [
+                    {"interface": "public", "region": "RegionOne", "url": "http://compute.one"},
+                    {"interface": "internal", "region": "RegionOne", "url": "http://compute.two"},
+                ]},
+                {"type": "object-store", "endpoints": []},
+                {"type": "compute", "endpoints": [
+                    {"interface": "public", "region": "RegionOne", "url": "http://compute.three"},
+                ]},
+            ])))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_catalog() {
+        let s = Session::new(FakeCatalog);
+        let catalog = s.catalog().await.unwrap();
+        assert_eq!(catalog.len(), 4);
+        assert_eq!(catalog[1].service_type, "compute");
+        assert_eq!(catalog[1].endpoints.len(), 2);
+        assert_eq!(catalog[1].endpoints[1].interface, "internal");
+        assert_eq!(catalog[1].endpoints[1].region(), Some("RegionOne"));
+        assert_eq!(catalog[1].endpoints[1].url, "http://compute.two");
+        let err = new_simple_session(URL).catalog().await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn test_service_types() {
+        let s = Session::new(FakeCatalog);
+        assert_eq!(
+            s.service_types().await.unwrap(),
+            vec!["compute".to_string(), "object-store".to_string()]
+        );
+        let err = new_simple_session(URL).service_types().await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn test_regions() {
+        let s = Session::new(FakeCatalog);
+        assert_eq!(
+            s.regions().await.unwrap(),
+            vec!["RegionOne".to_string(), "RegionTwo".to_string()]
+        );
+        let err = new_simple_session(URL).regions().await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn test_for_region() {
+        let s = Session::new(FakeCatalog);
+        let two = s.for_region("RegionTwo");
+        assert!(s.endpoint_filters().region().is_none());
+        assert_eq!(two.endpoint_filters().region(), Some("RegionTwo"));
+        assert!(Arc::ptr_eq(&s.auth, &two.auth));
+        assert!(!Arc::ptr_eq(&s.cached_info, &two.cached_info));
+        let endpoints = two.endpoints_for(OBJECT_STORAGE).await.unwrap();
+        assert_eq!(endpoints.len(), 1);
+        assert_eq!(endpoints[0].url, "http://swift.two");
+        let err = two.endpoints_for(COMPUTE).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::EndpointNotFound);
+    }
+
+    #[tokio::test]
+    async fn test_endpoints_for() {
+        let s = Session::new(FakeCatalog);
+        let urls = |endpoints: Vec<Endpoint>| {
+            endpoints
+                .into_iter()
+                .map(|x| x.url)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            urls(s.endpoints_for(COMPUTE).await.unwrap()),
+            vec!["http://compute.one", "http://compute.three"]
+        );
+        let s = s.with_endpoint_interface(InterfaceType::Internal);
+        assert_eq!(
+            urls(s.endpoints_for(COMPUTE).await.unwrap()),
+            vec!["http://compute.two"]
+        );
+        let err = s.endpoints_for(FAKE).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::EndpointNotFound);
+        let err = new_simple_session(URL)
+            .endpoints_for(COMPUTE)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn test_negotiated_api_version() {
+        let s = new_session(URL, fake_service_info());
+        assert_eq!(s.negotiated_api_version(FAKE).await.unwrap(), None);
+        let s = s.with_max_api_version(FAKE, ApiVersion(2, 10));
+        assert_eq!(
+            s.negotiated_api_version(FAKE).await.unwrap(),
+            Some(ApiVersion(2, 10))
+        );
+        let s = s.with_max_api_version(FAKE, ApiVersion(2, 100));
+        assert_eq!(
+            s.negotiated_api_version(FAKE).await.unwrap(),
+            Some(MAX_VERSION)
+        );
+        let s = s.with_max_api_version(FAKE, ApiVersion(2, 0));
+        assert_eq!(s.negotiated_api_version(FAKE).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_set_default_microversion() {
+        let mut s = new_session(URL, fake_service_info());
+        assert_eq!(s.default_microversion(FAKE), None);
+        s.set_default_microversion(FAKE, "2.10").await.unwrap();
+        assert_eq!(s.default_microversion(FAKE), Some(ApiVersion(2, 10)));
+
+        let err = s
+            .set_default_microversion(FAKE, "2.99")
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::IncompatibleApiVersion);
+        let err = s.set_default_microversion(FAKE, "x.y").await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(s.default_microversion(FAKE), Some(ApiVersion(2, 10)));
+    }
+
+    #[tokio::test]
+    async fn test_set_default_microversion_no_microversions() {
+        let mut s = new_simple_session(URL);
+        let err = s
+            .set_default_microversion(FAKE, "2.10")
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::IncompatibleApiVersion);
+        assert_eq!(s.default_microversion(FAKE), None);
+    }
+
+    #[tokio::test]
+    async fn test_negotiated_api_version_no_microversions() {
+        let s = new_simple_session(URL).with_max_api_version(FAKE, ApiVersion(2, 10));
+        assert_eq!(s.negotiated_api_version(FAKE).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let s = new_simple_session(URL);
+        assert_eq!(s.timeout(), None);
+        let s = s.with_timeout(Duration::from_secs(5));
+        assert_eq!(s.timeout(), Some(Duration::from_secs(5)));
+        let req = s
+            .request(FAKE, Method::GET, &["v2"], None)
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(req.timeout(), Some(&Duration::from_secs(5)));
+    }
+
+    #[tokio::test]
+    async fn test_token_info_no_tokens() {
+        let s = new_simple_session(URL);
+        let err = s.token_info().await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn test_request_id() {
+        let s = new_simple_session(URL).with_request_id("req-caller");
+        assert_eq!(s.request_id(), Some("req-caller"));
+        let req = s
+            .request(FAKE, Method::GET, &["v2"], None)
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(
+            req.headers().get("x-openstack-request-id").unwrap(),
+            "req-caller"
+        );
+    }
+
+    /// A mock answering once with the status and JSON body.
+    fn json_once(method: Method, path: &str, status: StatusCode, body: &str) -> Mock {
+        Mock::new(method, path)
+            .with_status(status)
+            .with_header("content-type", "application/json")
+            .with_body(body)
+            .with_times(1)
+    }
+
+    /// Start a mock server with one mock.
+    fn serve_once(mock: Mock) -> MockServer {
+        let server = MockServer::start();
+        server.mock(mock);
+        server
+    }
+
+    const ROOT: &str = r#"{"version": {"id": "v2.1", "status": "CURRENT", "version": "2.42",
+        "min_version": "2.1", "links": [{"rel": "self", "href": "http://127.0.0.1:1/v2.1/"}]}}"#;
+
+    #[tokio::test]
+    async fn test_endpoint_info_cached() {
+        let server = serve_once(json_once(Method::GET, "/", StatusCode::OK, ROOT));
+        let url = server.url().clone();
+        let mut s = Session::new(NoAuth::new(url.as_str()).unwrap());
+        let info = s.endpoint_info(FAKE).await.unwrap();
+        assert_eq!(info.root_url.as_str(), "http://127.0.0.1:1/v2.1/");
+        assert_eq!(info.major_version, Some(ApiVersion(2, 1)));
+        assert_eq!(info.minimum_version, Some(ApiVersion(2, 1)));
+        assert_eq!(info.current_version, Some(ApiVersion(2, 42)));
+
+        // The server only responds once, the document must come from the cache.
+        s.set_endpoint(FAKE, url);
+        assert_eq!(s.endpoint_info(FAKE).await.unwrap(), info);
+    }
+
+    const VERSIONS: &str = r#"{"versions": [
+        {"id": "v3.0", "status": "CURRENT", "version": "3.70", "min_version": "3.0",
+         "links": [{"rel": "self", "href": "http://127.0.0.1:1/v3/"}]},
+        {"id": "v2.0", "status": "DEPRECATED",
+         "links": [{"rel": "self", "href": "http://127.0.0.1:1/v2/"}]}
+    ]}"#;
+
+    #[tokio::test]
+    async fn test_get_versions() {
+        let server = serve_once(json_once(Method::GET, "/", StatusCode::OK, VERSIONS));
+        let s = Session::new(NoAuth::new(server.url().as_str()).unwrap());
+        let versions = s.get_versions(FAKE).await.unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].id, ApiVersion(2, 0));
+        assert!(versions[0].is_deprecated());
+        assert!(!versions[0].is_stable());
+        assert_eq!(versions[0].version, None);
+        assert_eq!(versions[1].id, ApiVersion(3, 0));
+        assert!(versions[1].is_stable());
+        assert_eq!(
+            versions[1].url().unwrap().as_str(),
+            "http://127.0.0.1:1/v3/"
+        );
+        assert!(versions[1].supports_microversion(ApiVersion(3, 42)));
+        assert!(!versions[1].supports_microversion(ApiVersion(3, 71)));
+
+        // The document is cached and used for the version checks.
+        assert!(s.supports_microversion(FAKE, 3, 70).await.unwrap());
+        assert!(!s.supports_microversion(FAKE, 2, 0).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_info_cache_disabled() {
+        let server = MockServer::start();
+        server.mock(json_once(Method::GET, "/", StatusCode::OK, ROOT).with_times(2));
+        let url = server.url().clone();
+        let mut s = Session::new(NoAuth::new(url.as_str()).unwrap());
+        s.set_discovery_cache_ttl(Duration::from_secs(0));
+        let info = s.endpoint_info(FAKE).await.unwrap();
+        s.set_endpoint(FAKE, url);
+        // The document is fetched again.
+        assert_eq!(s.endpoint_info(FAKE).await.unwrap(), info);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_validate() {
+        let server = serve_once(json_once(Method::GET, "/", StatusCode::OK, "{}"));
+        let url = server.url().as_str();
+        let auth = NoAuth::without_default_endpoint()
+            .with_service_endpoint("fake", url)
+            .unwrap();
+        let s = Session::new(auth);
+        let report = s.validate(&["compute", "fake"]).await.unwrap();
+        assert!(report.token.is_none());
+        assert!(!report.is_healthy());
+        assert_eq!(report.missing_services(), vec!["compute"]);
+        assert!(report.services[0].endpoint.is_none());
+        assert!(report.services[1].is_healthy());
+        assert_eq!(report.services[1].endpoint.as_ref().unwrap().as_str(), url);
+        assert!(report.services[1].response_time.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_validate_probe_failed() {
+        let server = serve_once(json_once(
+            Method::GET,
+            "/",
+            StatusCode::SERVICE_UNAVAILABLE,
+            "{}",
+        ));
+        let s = new_simple_session(server.url().as_str());
+        let report = s.validate(&["fake", "compute"]).await.unwrap();
+        assert!(report.missing_services().is_empty());
+        assert_eq!(
+            report.services[0].error.as_ref().unwrap().kind(),
+            ErrorKind::InternalServerError
+        );
+        // Only the first service is probed.
+        assert!(report.services[1].is_healthy());
+        assert!(report.services[1].response_time.is_none());
     }
 
-    /// PUT a JSON object and receive a JSON back.
-    ///
-    /// The `body` argument is anything that can be serialized into JSON.
-    ///
-    /// See [request](#method.request) for an explanation of the other parameters.
-    #[inline]
-    pub async fn put_json<Srv, I, T, R>(
-        &self,
-        service: Srv,
-        path: I,
-        body: T,
-        api_version: Option<ApiVersion>,
-    ) -> Result<R, Error>
-    where
-        Srv: ServiceType + Send + Clone,
-        I: IntoIterator,
-        I::Item: AsRef<str>,
-        I::IntoIter: Send,
-        T: Serialize + Send,
-        R: DeserializeOwned + Send,
-    {
-        request::fetch_json(
-            self.request(service, Method::PUT, path, api_version)
-                .await?
-                .json(&body),
-        )
-        .await
+    #[tokio::test]
+    async fn test_request_id_hook() {
+        let server = serve_once(
+            json_once(
+                Method::GET,
+                "/nodes",
+                StatusCode::NOT_FOUND,
+                r#"{"error": {"message": "Not found"}}"#,
+            )
+            .with_header("x-openstack-request-id", "req-1234"),
+        );
+
+        let ids = Arc::new(Mutex::new(Vec::new()));
+        let ids_clone = ids.clone();
+        let mut s = new_simple_session(server.url().as_str());
+        s.set_request_id_hook(move |id| ids_clone.lock().unwrap().push(id.to_string()));
+        let err = s.get(FAKE, &["nodes"], None).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::ResourceNotFound);
+        assert_eq!(err.request_id(), Some("req-1234"));
+        assert_eq!(*ids.lock().unwrap(), vec!["req-1234".to_string()]);
     }
 
-    /// Issue a DELETE request.
-    ///
-    /// See [request](#method.request) for an explanation of the parameters.
-    #[inline]
-    pub async fn delete<Srv, I>(
-        &self,
-        service: Srv,
-        path: I,
-        api_version: Option<ApiVersion>,
-    ) -> Result<Response, Error>
-    where
-        Srv: ServiceType + Send + Clone,
-        I: IntoIterator,
-        I::Item: AsRef<str>,
-        I::IntoIter: Send,
-    {
-        request::send_checked(
-            self.request(service, Method::DELETE, path, api_version)
-                .await?,
-        )
-        .await
+    #[derive(Debug)]
+    struct FakeService;
+
+    #[async_trait]
+    impl Middleware for FakeService {
+        async fn handle(
+            &self,
+            request: RequestBuilder,
+            _next: Next<'_>,
+        ) -> Result<Response, Error> {
+            let request = request.build()?;
+            assert_eq!(request.headers()["x-auth-sudo-project-id"], "1234");
+            let response = http::Response::builder()
+                .status(200)
+                .body(format!(r#"{{"path": "{}"}}"#, request.url().path()))
+                .unwrap();
+            Ok(response.into())
+        }
     }
 
-    /// Ensure service info and return the cache.
-    async fn extract_service_info<Srv, F, T>(&self, service: Srv, filter: F) -> Result<T, Error>
-    where
-        Srv: ServiceType + Send,
-        F: FnOnce(&ServiceInfo) -> T + Send,
-        T: Send,
-    {
-        let catalog_type = service.catalog_type();
-        if let Some(info) = self.cached_info.read().await.get(catalog_type) {
-            return Ok(filter(info));
+    #[derive(Debug)]
+    struct Sudo;
+
+    #[async_trait]
+    impl Middleware for Sudo {
+        async fn handle(&self, request: RequestBuilder, next: Next<'_>) -> Result<Response, Error> {
+            next.run(request.header("X-Auth-Sudo-Project-Id", "1234"))
+                .await
         }
+    }
 
-        debug!(
-            "No cached information for service {}, fetching",
-            catalog_type
+    #[tokio::test]
+    async fn test_middleware() {
+        let s = new_simple_session("http://127.0.0.1:1/")
+            .with_middleware(Sudo)
+            .with_middleware(FakeService);
+        let resp: serde_json::Value = s.get_json(FAKE, &["nodes"], None).await.unwrap();
+        assert_eq!(resp["path"], "/nodes");
+    }
+
+    #[tokio::test]
+    async fn test_error_fault() {
+        let server = serve_once(
+            json_once(
+                Method::GET,
+                "/servers/1234",
+                StatusCode::CONFLICT,
+                r#"{"conflictingRequest": {"code": 409, "message": "Instance is locked"}}"#,
+            )
+            .with_header("x-openstack-request-id", "req-5678"),
         );
+        let s = new_simple_session(server.url().as_str());
+        let err = s.get(FAKE, &["servers", "1234"], None).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Conflict);
+        assert_eq!(err.status(), Some(StatusCode::CONFLICT));
+        assert_eq!(err.request_id(), Some("req-5678"));
+        assert_eq!(err.message(), "Instance is locked");
+        assert_eq!(err.url().unwrap().path(), "/servers/1234");
+        let fault = err.fault().unwrap();
+        assert_eq!(fault.name.as_deref(), Some("conflictingRequest"));
+        assert_eq!(fault.code, Some(409));
 
-        let mut lock = self.cached_info.write().await;
-        // Additonal check in case another thread has updated the token while we were waiting for
-        // the write lock.
-        Ok(if let Some(info) = lock.get(catalog_type) {
-            filter(info)
-        } else {
-            let ep = self
-                .auth
-                .get_endpoint(catalog_type.to_string(), self.endpoint_filters.clone())
-                .await?;
-            let info = ServiceInfo::fetch(service, ep, self.auth.deref()).await?;
-            let value = filter(&info);
-            let _ = lock.insert(catalog_type, info);
-            value
-        })
+        let server = serve_once(json_once(
+            Method::GET,
+            "/servers",
+            StatusCode::FORBIDDEN,
+            r#"{"forbidden": {"code": 403, "message": "Quota exceeded for cores"}}"#,
+        ));
+        let s = new_simple_session(server.url().as_str());
+        let err = s.get(FAKE, &["servers"], None).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::QuotaExceeded);
+        assert_eq!(err.status(), Some(StatusCode::FORBIDDEN));
     }
 
-    #[cfg(test)]
-    pub(crate) fn cache_fake_service(
-        &mut self,
-        service_type: &'static str,
-        service_info: ServiceInfo,
-    ) {
-        let mut hm = HashMap::new();
-        let _ = hm.insert(service_type, service_info);
-        self.cached_info = Arc::new(RwLock::new(hm));
+    #[derive(Debug)]
+    struct FakeAccessRules {
+        url: String,
     }
-}
 
-#[cfg(test)]
-pub(crate) mod test {
-    use reqwest::Url;
+    #[async_trait]
+    impl AuthType for FakeAccessRules {
+        async fn get_endpoint(
+            &self,
+            _service_type: String,
+            _filters: EndpointFilters,
+        ) -> Result<Url, Error> {
+            Ok(Url::parse(&self.url).unwrap())
+        }
 
-    use super::super::protocol::ServiceInfo;
-    use super::super::services::{GenericService, VersionSelector};
-    use super::super::{ApiVersion, NoAuth};
-    use super::Session;
+        async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+            Ok(reqwest::Client::new().request(method, url))
+        }
 
-    pub const URL: &str = "http://127.0.0.1:5000/";
+        async fn refresh(&self) -> Result<(), Error> {
+            Ok(())
+        }
 
-    pub const URL_WITH_SUFFIX: &str = "http://127.0.0.1:5000/v2/servers";
+        async fn token_info(&self) -> Result<Option<TokenInfo>, Error> {
+            let info = serde_json::from_str(
+                r#"{
+                    "expires_at": "2100-01-01T00:00:00Z",
+                    "application_credential": {
+                        "id": "abcdef",
+                        "access_rules": [
+                            {"method": "GET", "path": "/v1/*", "service": "object-store"}
+                        ]
+                    }
+                }"#,
+            )
+            .unwrap();
+            Ok(Some(info))
+        }
 
-    pub fn new_simple_session(url: &str) -> Session {
-        let service_info = ServiceInfo {
-            root_url: Url::parse(url).unwrap(),
-            major_version: None,
-            minimum_version: None,
-            current_version: None,
-        };
-        new_session(url, service_info)
+        fn can_reauthenticate(&self) -> bool {
+            true
+        }
     }
 
-    pub fn new_session(url: &str, service_info: ServiceInfo) -> Session {
-        let auth = NoAuth::new(url).unwrap();
-        let mut session = Session::new(auth);
-        session.cache_fake_service("fake", service_info);
-        session
+    #[tokio::test]
+    async fn test_access_rule_denied() {
+        let server = serve_once(json_once(
+            Method::DELETE,
+            "/v1",
+            StatusCode::FORBIDDEN,
+            r#"{"error": {"message": "You are not authorized"}}"#,
+        ));
+        let s = Session::new(FakeAccessRules {
+            url: server.url().to_string(),
+        });
+        let err = s.delete(OBJECT_STORAGE, &["v1"], None).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::AccessRuleDenied);
     }
 
-    pub const FAKE: GenericService = GenericService::new("fake", VersionSelector::Any);
-
     #[tokio::test]
-    async fn test_get_endpoint() {
-        let s = new_simple_session(URL);
-        let ep = s.get_endpoint(FAKE, &[""]).await.unwrap();
-        assert_eq!(&ep.to_string(), URL);
+    async fn test_access_rule_denied_unauthorized() {
+        let server = serve_once(json_once(
+            Method::DELETE,
+            "/v1",
+            StatusCode::UNAUTHORIZED,
+            r#"{"error": {"message": "Not permitted by the access rules"}}"#,
+        ));
+        let s = Session::new(FakeAccessRules {
+            url: server.url().to_string(),
+        });
+        let err = s.delete(OBJECT_STORAGE, &["v1"], None).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::AccessRuleDenied);
     }
 
     #[tokio::test]
-    async fn test_get_endpoint_slice() {
-        let s = new_simple_session(URL);
-        let ep = s.get_endpoint(FAKE, &["v2", "servers"]).await.unwrap();
-        assert_eq!(&ep.to_string(), URL_WITH_SUFFIX);
+    async fn test_access_denied_without_access_rules() {
+        let server = serve_once(json_once(
+            Method::DELETE,
+            "/v1",
+            StatusCode::FORBIDDEN,
+            r#"{"error": {"message": "You are not authorized"}}"#,
+        ));
+        let s = new_simple_session(server.url().as_str());
+        let err = s.delete(FAKE, &["v1"], None).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::AccessDenied);
+    }
+
+    /// Start a mock server answering `method path` with the statuses one after another.
+    fn serve_statuses(method: Method, path: &str, statuses: &[StatusCode]) -> MockServer {
+        let server = MockServer::start();
+        for status in statuses {
+            server.mock(json_once(method.clone(), path, *status, "{}"));
+        }
+        server
+    }
+
+    #[cfg(feature = "stream")]
+    #[derive(Debug, Deserialize)]
+    struct Image {
+        id: String,
+    }
+
+    #[cfg(feature = "stream")]
+    #[derive(Debug, Deserialize)]
+    struct ImagesRoot {
+        images: Vec<Image>,
+        next: Option<String>,
+    }
+
+    #[cfg(feature = "stream")]
+    impl From<ImagesRoot> for Vec<Image> {
+        fn from(value: ImagesRoot) -> Vec<Image> {
+            value.images
+        }
+    }
+
+    #[cfg(feature = "stream")]
+    impl Resource for Image {
+        type Id = String;
+        type Root = ImagesRoot;
+
+        fn resource_id(&self) -> String {
+            self.id.clone()
+        }
+
+        fn next_link(root: &ImagesRoot) -> Option<&str> {
+            root.next.as_deref()
+        }
     }
 
+    #[cfg(feature = "stream")]
     #[tokio::test]
-    async fn test_get_endpoint_vec() {
-        let s = new_simple_session(URL);
-        let ep = s
-            .get_endpoint(FAKE, vec!["v2".to_string(), "servers".to_string()])
+    async fn test_get_json_paginated_links() {
+        let server = MockServer::start();
+        server.mock(json_once(
+            Method::GET,
+            "/image/v2/images?marker=2",
+            StatusCode::OK,
+            r#"{"images": [{"id": "3"}]}"#,
+        ));
+        server.mock(json_once(
+            Method::GET,
+            "/image/v2/images",
+            StatusCode::OK,
+            r#"{"images": [{"id": "1"}, {"id": "2"}], "next": "/v2/images?marker=2"}"#,
+        ));
+        let s = Session::new(NoAuth::new(server.url().join("image/").unwrap().as_str()).unwrap());
+        let images = s
+            .get_json_paginated::<_, _, Image>(OBJECT_STORAGE, &["v2", "images"], None, None, None)
             .await
             .unwrap();
-        assert_eq!(&ep.to_string(), URL_WITH_SUFFIX);
+        let ids = images
+            .map_ok(|image| image.id)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(ids, vec!["1", "2", "3"]);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].query, None);
+        assert_eq!(requests[1].query.as_deref(), Some("marker=2"));
+    }
+
+    #[derive(Debug)]
+    struct FakeRotatingToken {
+        url: String,
+        generation: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl AuthType for FakeRotatingToken {
+        async fn get_endpoint(
+            &self,
+            _service_type: String,
+            _filters: EndpointFilters,
+        ) -> Result<Url, Error> {
+            Ok(Url::parse(&self.url).unwrap())
+        }
+
+        async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+            let token = format!("token-{}", self.generation.load(Ordering::SeqCst));
+            Ok(reqwest::Client::new()
+                .request(method, url)
+                .header("x-auth-token", token))
+        }
+
+        async fn refresh(&self) -> Result<(), Error> {
+            let _ = self.generation.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn can_reauthenticate(&self) -> bool {
+            true
+        }
     }
 
     #[tokio::test]
-    async fn test_get_major_version_absent() {
-        let s = new_simple_session(URL);
-        let res = s.get_major_version(FAKE).await.unwrap();
-        assert!(res.is_none());
+    async fn test_rate_limit() {
+        let server = serve_statuses(
+            Method::GET,
+            "/v1",
+            &[StatusCode::OK, StatusCode::OK, StatusCode::OK],
+        );
+        let s = Session::new(NoAuth::new(server.url().as_str()).unwrap()).with_rate_limit(20.0);
+        assert_eq!(s.rate_limit(), Some(20.0));
+        let start = Instant::now();
+        for _ in 0..3 {
+            let _ = s.get(OBJECT_STORAGE, &["v1"], None).await.unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(server.requests().len(), 3);
+        let mut s = s;
+        s.set_rate_limit(Some(0.0));
+        assert_eq!(s.rate_limit(), None);
     }
 
-    pub const MAJOR_VERSION: ApiVersion = ApiVersion(2, 0);
+    #[tokio::test]
+    async fn test_retry_policy() {
+        let server = serve_statuses(
+            Method::GET,
+            "/v1",
+            &[
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::OK,
+            ],
+        );
+        let s = Session::new(NoAuth::new(server.url().as_str()).unwrap())
+            .with_retry_policy(RetryPolicy::new(3).with_initial_delay(Duration::from_millis(1)));
+        let resp = s.get(OBJECT_STORAGE, &["v1"], None).await.unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(server.requests().len(), 3);
+    }
 
     #[tokio::test]
-    async fn test_get_major_version_present() {
-        let service_info = ServiceInfo {
-            root_url: Url::parse(URL).unwrap(),
-            major_version: Some(MAJOR_VERSION),
-            minimum_version: None,
-            current_version: None,
-        };
-        let s = new_session(URL, service_info);
-        let res = s.get_major_version(FAKE).await.unwrap();
-        assert_eq!(res, Some(MAJOR_VERSION));
+    async fn test_retry_policy_exhausted() {
+        let server = serve_statuses(
+            Method::GET,
+            "/v1",
+            &[
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ],
+        );
+        let s = Session::new(NoAuth::new(server.url().as_str()).unwrap())
+            .with_retry_policy(RetryPolicy::new(2).with_initial_delay(Duration::from_millis(1)));
+        let err = s.get(OBJECT_STORAGE, &["v1"], None).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InternalServerError);
+        assert_eq!(server.requests().len(), 2);
     }
 
-    pub const MIN_VERSION: ApiVersion = ApiVersion(2, 1);
-    pub const MAX_VERSION: ApiVersion = ApiVersion(2, 42);
+    #[tokio::test]
+    async fn test_default_headers() {
+        let server = serve_statuses(Method::GET, "/v1", &[StatusCode::OK, StatusCode::OK]);
+        let mut s = Session::new(NoAuth::new(server.url().as_str()).unwrap())
+            .with_user_agent("my-agent")
+            .with_default_header("x-auth-sudo-tenant-id", "1234")
+            .unwrap()
+            .with_default_header(reqwest::header::USER_AGENT, "other-agent")
+            .unwrap();
+        assert_eq!(s.default_headers().len(), 2);
+        let builder = s
+            .request(OBJECT_STORAGE, Method::GET, &["v1"], None)
+            .await
+            .unwrap()
+            .header("x-image-meta-name", "cirros");
+        let _ = s.send_checked(builder).await.unwrap();
+        s.remove_default_header("x-auth-sudo-tenant-id");
+        let _ = s.get(OBJECT_STORAGE, &["v1"], None).await.unwrap();
 
-    pub fn fake_service_info() -> ServiceInfo {
-        ServiceInfo {
-            root_url: Url::parse(URL).unwrap(),
-            major_version: Some(MAJOR_VERSION),
-            minimum_version: Some(MIN_VERSION),
-            current_version: Some(MAX_VERSION),
-        }
+        let requests = server.requests();
+        assert_eq!(requests[0].headers["x-auth-sudo-tenant-id"], "1234");
+        assert_eq!(requests[0].headers["x-image-meta-name"], "cirros");
+        let agents: Vec<_> = requests[0].headers.get_all("user-agent").iter().collect();
+        assert_eq!(agents, vec!["other-agent"]);
+        assert!(!requests[1].headers.contains_key("x-auth-sudo-tenant-id"));
+        assert!(!requests[1].headers.contains_key("x-image-meta-name"));
+
+        let err = s.set_default_header("x-broken", "a\nb").err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = s.set_default_header("bad header", "value").err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
     }
 
     #[tokio::test]
-    async fn test_pick_api_version_empty() {
-        let service_info = fake_service_info();
-        let s = new_session(URL, service_info);
-        let res = s.pick_api_version(FAKE, None).await.unwrap();
-        assert!(res.is_none());
+    async fn test_metrics() {
+        let server = serve_statuses(
+            Method::GET,
+            "/v1",
+            &[StatusCode::SERVICE_UNAVAILABLE, StatusCode::OK],
+        );
+        let metrics = Arc::new(PrometheusMetrics::new());
+        let s = Session::new(NoAuth::new(server.url().as_str()).unwrap())
+            .with_retry_policy(RetryPolicy::new(2).with_initial_delay(Duration::from_millis(1)))
+            .with_metrics(Arc::clone(&metrics));
+        let resp = s.get(OBJECT_STORAGE, &["v1"], None).await.unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(server.requests().len(), 2);
+
+        assert_eq!(metrics.total_calls(), 2);
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "osauth_api_calls_total{service=\"object-store\",method=\"GET\",status=\"503\"} 1\n"
+        ));
+        assert!(rendered.contains(
+            "osauth_api_calls_total{service=\"object-store\",method=\"GET\",status=\"200\"} 1\n"
+        ));
+        assert!(rendered.contains(
+            "osauth_api_call_retries_total{service=\"object-store\",method=\"GET\"} 1\n"
+        ));
     }
 
     #[tokio::test]
-    async fn test_pick_api_version_empty_vec() {
-        let service_info = fake_service_info();
-        let s = new_session(URL, service_info);
-        let res = s.pick_api_version(FAKE, Vec::new()).await.unwrap();
-        assert!(res.is_none());
+    async fn test_metrics_token_refresh() {
+        let server = MockServer::start();
+        server.mock(token_mock("abcdef").with_times(2));
+        let mut id = Password::new(
+            server.identity_url().as_str(),
+            "admin",
+            "pa$$w0rd",
+            "Default",
+        )
+        .unwrap();
+        // NOTE: every token expires too soon, so it is renewed before each use.
+        id.set_token_refresh_skew(Duration::from_secs(100 * 365 * 24 * 3600))
+            .unwrap();
+        let metrics = Arc::new(PrometheusMetrics::new());
+        let s = Session::new(id).with_metrics(Arc::clone(&metrics));
+        let _ = s.token_info().await.unwrap();
+        let _ = s.token_info().await.unwrap();
+        assert_eq!(server.requests().len(), 2);
+        assert!(metrics
+            .render()
+            .contains("osauth_token_refreshes_total{result=\"success\"} 2\n"));
     }
 
     #[tokio::test]
-    async fn test_pick_api_version() {
-        let service_info = fake_service_info();
-        let s = new_session(URL, service_info);
-        let choice = vec![
-            ApiVersion(2, 0),
-            ApiVersion(2, 2),
-            ApiVersion(2, 4),
-            ApiVersion(2, 99),
-        ];
-        let res = s.pick_api_version(FAKE, choice).await.unwrap();
-        assert_eq!(res, Some(ApiVersion(2, 4)));
+    async fn test_retry_policy_not_idempotent() {
+        let server = serve_statuses(Method::POST, "/v1", &[StatusCode::SERVICE_UNAVAILABLE]);
+        let s = Session::new(NoAuth::new(server.url().as_str()).unwrap())
+            .with_retry_policy(RetryPolicy::new(3).with_initial_delay(Duration::from_millis(1)));
+        let err = s
+            .post(OBJECT_STORAGE, &["v1"], serde_json::json!({}), None)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InternalServerError);
+        assert_eq!(server.requests().len(), 1);
     }
 
     #[tokio::test]
-    async fn test_pick_api_version_option() {
-        let service_info = fake_service_info();
-        let s = new_session(URL, service_info);
-        let res = s
-            .pick_api_version(FAKE, Some(ApiVersion(2, 4)))
+    async fn test_patch_json() {
+        let server = serve_statuses(Method::PATCH, "/v1", &[StatusCode::OK]);
+        let s = Session::new(NoAuth::new(server.url().as_str()).unwrap());
+        let resp: serde_json::Value = s
+            .patch_json(
+                OBJECT_STORAGE,
+                &["v1"],
+                serde_json::json!({"key": "value"}),
+                None,
+            )
             .await
             .unwrap();
-        assert_eq!(res, Some(ApiVersion(2, 4)));
+        assert_eq!(resp, serde_json::json!({}));
+        let requests = server.requests();
+        assert_eq!(requests[0].headers["content-type"], "application/json");
     }
 
     #[tokio::test]
-    async fn test_pick_api_version_impossible() {
-        let service_info = fake_service_info();
-        let s = new_session(URL, service_info);
-        let choice = vec![ApiVersion(2, 0), ApiVersion(2, 99)];
-        let res = s.pick_api_version(FAKE, choice).await.unwrap();
-        assert!(res.is_none());
+    async fn test_retry_with_new_token() {
+        let server = serve_statuses(
+            Method::POST,
+            "/v1",
+            &[StatusCode::UNAUTHORIZED, StatusCode::OK],
+        );
+        let s = Session::new(FakeRotatingToken {
+            url: server.url().to_string(),
+            generation: AtomicUsize::new(0),
+        });
+        let resp = s
+            .post(
+                OBJECT_STORAGE,
+                &["v1"],
+                serde_json::json!({"key": "value"}),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        let requests = server.requests();
+        assert_eq!(requests[0].headers["x-auth-token"], "token-0");
+        assert_eq!(requests[1].headers["x-auth-token"], "token-1");
+        assert_eq!(requests[1].headers["content-type"], "application/json");
+        // The body is preserved.
+        assert_eq!(requests[1].body, br#"{"key":"value"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_new_token_rejected() {
+        let server = serve_statuses(
+            Method::GET,
+            "/v1",
+            &[StatusCode::UNAUTHORIZED, StatusCode::UNAUTHORIZED],
+        );
+        let s = Session::new(FakeRotatingToken {
+            url: server.url().to_string(),
+            generation: AtomicUsize::new(0),
+        });
+        let err = s.get(OBJECT_STORAGE, &["v1"], None).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::TokenExpired);
+        assert_eq!(server.requests().len(), 2);
     }
 }
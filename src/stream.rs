@@ -14,17 +14,18 @@
 
 //! A stream of resources.
 
+use std::borrow::Cow;
 use std::fmt::Debug;
 
 use async_stream::try_stream;
 use futures::pin_mut;
 use futures::stream::{Stream, TryStreamExt};
-use reqwest::RequestBuilder;
+use reqwest::{RequestBuilder, Url};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
 use super::request;
-use super::Error;
+use super::{Error, ErrorKind, Session};
 
 /// A single resource.
 pub trait Resource {
@@ -36,6 +37,18 @@ pub trait Resource {
 
     /// Retrieve a copy of the ID.
     fn resource_id(&self) -> Self::Id;
+
+    /// Link to the next page of the listing (if any).
+    ///
+    /// Some services (e.g. Image or Identity) return links to the next page instead of relying
+    /// on markers. If a listing has ever returned a link, it ends with the first page without
+    /// one; otherwise the ID of the last resource is used as a marker. Only the path and the
+    /// query of the link are used, relative to the endpoint of the service.
+    ///
+    /// The default implementation returns `None`.
+    fn next_link(_root: &Self::Root) -> Option<&str> {
+        None
+    }
 }
 
 #[derive(Serialize)]
@@ -46,7 +59,81 @@ struct Query<T: Serialize> {
     marker: Option<T>,
 }
 
+/// Resolve a pagination link against the URL of the current request.
+///
+/// Links are reduced to their path and query to avoid sending the token to another host (some
+/// services return links with their internal host names). If the current path ends with the path
+/// of the link, the link is treated as relative to the endpoint (e.g. the Image service returns
+/// `/v2/images?marker=<ID>` even if its endpoint has a path).
+fn resolve_link(current: &Url, link: &str) -> Result<Url, Error> {
+    let invalid = |e| {
+        Error::new(
+            ErrorKind::InvalidResponse,
+            format!("Invalid pagination link {}: {}", link, e),
+        )
+    };
+    let relative = match Url::parse(link) {
+        Ok(url) => Cow::Owned(match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        }),
+        Err(_) => Cow::Borrowed(link),
+    };
+    let link_path = relative.split(&['?', '#'][..]).next().unwrap_or_default();
+    let path = current.path();
+    let relative = if link_path.starts_with('/') && path.ends_with(link_path) {
+        let prefix = &path[..path.len() - link_path.len()];
+        Cow::Owned(format!("{}{}", prefix, relative))
+    } else {
+        relative
+    };
+    current.join(&relative).map_err(invalid)
+}
+
+/// URL of the request that will be made by the builder.
+fn request_url(builder: &RequestBuilder) -> Option<Url> {
+    builder
+        .try_clone()
+        .and_then(|builder| builder.build().ok())
+        .map(|request| request.url().clone())
+}
+
+/// Fetch one page, returning its resources and the link to the next page (if any).
+async fn fetch_page<T>(
+    session: Option<&Session>,
+    builder: &RequestBuilder,
+    query: Query<T::Id>,
+    link: Option<Url>,
+) -> Result<(Vec<T>, Option<Url>), Error>
+where
+    T: Resource,
+    T::Root: Into<Vec<T>> + Send,
+{
+    let template = builder
+        .try_clone()
+        .expect("Builder with a streaming body cannot be used");
+    let prepared = match (link, session) {
+        (Some(url), Some(session)) => {
+            let mut request = template.build()?;
+            *request.url_mut() = url;
+            session.reauthenticated(request).await?
+        }
+        _ => template.query(&query),
+    };
+    let current_url = request_url(&prepared);
+    let result: T::Root = match session {
+        Some(session) => session.fetch_json(prepared).await?,
+        None => request::fetch_json(prepared).await?,
+    };
+    let next_link = match (T::next_link(&result), current_url, session) {
+        (Some(link), Some(current_url), Some(_)) => Some(resolve_link(&current_url, link)?),
+        _ => None,
+    };
+    Ok((result.into(), next_link))
+}
+
 fn chunks<T>(
+    session: Option<Session>,
     builder: RequestBuilder,
     limit: Option<usize>,
     starting_with: Option<T::Id>,
@@ -56,20 +143,39 @@ where
     T::Root: Into<Vec<T>> + Send,
 {
     let mut marker = starting_with;
+    let mut next_link = None;
+    let mut uses_links = false;
 
     try_stream! {
         loop {
-            let prepared = builder
-                .try_clone()
-                .expect("Builder with a streaming body cannot be used")
-                .query(&Query{ limit: limit, marker: marker.take() });
-            let result: T::Root = request::fetch_json(prepared).await?;
-            let items = result.into();
-            if let Some(new_m) = items.last() {
-                marker = Some(new_m.resource_id());
-                yield items;
-            } else {
-                break
+            let query = Query { limit: limit, marker: marker.take() };
+            let (items, link) =
+                fetch_page(session.as_ref(), &builder, query, next_link.take()).await?;
+            if items.is_empty() {
+                break;
+            }
+            uses_links |= link.is_some();
+            if link.is_none() {
+                if uses_links {
+                    yield items;
+                    break;
+                }
+                marker = items.last().map(Resource::resource_id);
+            }
+            next_link = link;
+            yield items;
+        }
+    }
+}
+
+fn items<T: Unpin>(
+    chunks: impl Stream<Item = Result<Vec<T>, Error>>,
+) -> impl Stream<Item = Result<T, Error>> {
+    try_stream! {
+        pin_mut!(chunks);
+        while let Some(chunk) = chunks.try_next().await? {
+            for item in chunk {
+                yield item;
             }
         }
     }
@@ -77,6 +183,9 @@ where
 
 /// Creates a paginated resource stream.
 ///
+/// Only markers are used: following [next_link](trait.Resource.html#method.next_link) requires
+/// a `Session`, e.g. [get_json_paginated](../struct.Session.html#method.get_json_paginated).
+///
 /// # Panics
 ///
 /// Will panic during iteration if the request builder has a streaming body.
@@ -89,13 +198,68 @@ where
     T: Resource + Unpin,
     T::Root: Into<Vec<T>> + Send,
 {
-    try_stream! {
-        let iter = chunks(builder, limit, starting_with);
-        pin_mut!(iter);
-        while let Some(chunk) = iter.try_next().await? {
-            for item in chunk {
-                yield item;
-            }
-        }
+    items(chunks(None, builder, limit, starting_with))
+}
+
+/// Creates a paginated resource stream following links to the next pages.
+///
+/// # Panics
+///
+/// Will panic during iteration if the request builder has a streaming body.
+pub(crate) fn session_paginated<T>(
+    session: &Session,
+    builder: RequestBuilder,
+    limit: Option<usize>,
+    starting_with: Option<T::Id>,
+) -> impl Stream<Item = Result<T, Error>>
+where
+    T: Resource + Unpin,
+    T::Root: Into<Vec<T>> + Send,
+{
+    items(chunks(Some(session.clone()), builder, limit, starting_with))
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::Url;
+
+    use super::resolve_link;
+
+    fn resolve(current: &str, link: &str) -> String {
+        resolve_link(&Url::parse(current).unwrap(), link)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_resolve_link() {
+        assert_eq!(
+            resolve("http://cloud/image/v2/images", "/v2/images?marker=1"),
+            "http://cloud/image/v2/images?marker=1"
+        );
+        assert_eq!(
+            resolve("http://cloud/v2/images?limit=1", "/v2/images?marker=1"),
+            "http://cloud/v2/images?marker=1"
+        );
+        assert_eq!(
+            resolve("http://cloud/compute/v2.1/servers", "/other?marker=1"),
+            "http://cloud/other?marker=1"
+        );
+        assert_eq!(
+            resolve("http://cloud/compute/v2.1/servers", "servers?marker=1"),
+            "http://cloud/compute/v2.1/servers?marker=1"
+        );
+    }
+
+    #[test]
+    fn test_resolve_link_absolute() {
+        // Only the path and the query are used.
+        assert_eq!(
+            resolve(
+                "https://cloud/identity/v3/users",
+                "http://internal:5000/identity/v3/users?marker=1"
+            ),
+            "https://cloud/identity/v3/users?marker=1"
+        );
     }
 }
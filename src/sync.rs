@@ -14,7 +14,19 @@
 
 //! Synchronous wrapper for a session.
 //!
-//! This module is only available when the `sync` feature is enabled.
+//! This module is only available when the `sync` feature is enabled. It is also available as
+//! `osauth::blocking`.
+//!
+//! A `SyncSession` runs its own single-threaded runtime, so no asynchronous code is needed:
+//!
+//! ```rust,no_run
+//! let session = osauth::sync::SyncSession::from_env()
+//!     .expect("Failed to create an identity provider from the environment");
+//! let servers: serde_json::Value = session
+//!     .get_json(osauth::services::COMPUTE, &["servers"], None)
+//!     .expect("Failed to list servers");
+//! println!("{}", servers);
+//! ```
 
 use std::cell::RefCell;
 use std::io;
@@ -26,6 +38,7 @@ use bytes::Bytes;
 use futures::executor::{self, BlockingStream};
 use futures::stream::Stream;
 use futures::Future;
+use osproto::identity::Endpoint;
 use pin_project::pin_project;
 use reqwest::{Body, RequestBuilder, Response};
 use reqwest::{Method, Url};
@@ -33,9 +46,12 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 use tokio::runtime::{Builder as RuntimeBuilder, Runtime};
 
-use super::request;
+use super::identity::{AvailableProject, IdOrName, Scope, TokenInfo};
 use super::services::ServiceType;
-use super::{ApiVersion, AuthType, EndpointFilters, Error, InterfaceType, Session};
+use super::{
+    ApiVersion, AuthType, CatalogService, EndpointFilters, EndpointInfo, Error, HealthReport,
+    InterfaceType, Session, SessionBuilder, VersionInfo,
+};
 
 /// A result of an OpenStack operation.
 pub type Result<T> = result::Result<T, Error>;
@@ -49,7 +65,7 @@ pub struct SyncStream<'s, S, E = ::reqwest::Error>
 where
     S: Stream<Item = result::Result<Bytes, E>> + Unpin,
 {
-    session: &'s SyncSession,
+    _session: &'s SyncSession,
     inner: BlockingStream<S>,
     current: io::Cursor<Bytes>,
 }
@@ -93,16 +109,48 @@ impl SyncSession {
     pub fn new(session: Session) -> SyncSession {
         SyncSession {
             inner: session,
-            runtime: RefCell::new(
-                RuntimeBuilder::new()
-                    .basic_scheduler()
-                    .enable_io()
-                    .build()
-                    .expect("Could not create a runtime"),
-            ),
+            runtime: RefCell::new(new_runtime()),
         }
     }
 
+    /// Create a new session and authenticate it.
+    ///
+    /// See [Session::new_authenticated](../struct.Session.html#method.new_authenticated).
+    pub fn new_authenticated<Auth: AuthType + 'static>(auth_type: Auth) -> Result<SyncSession> {
+        let mut session = SyncSession::new(Session::new(auth_type));
+        session.refresh()?;
+        Ok(session)
+    }
+
+    /// Create and authenticate a session from a `SessionBuilder`.
+    ///
+    /// See [SessionBuilder::build](../struct.SessionBuilder.html#method.build).
+    pub fn from_builder(builder: SessionBuilder) -> Result<SyncSession> {
+        let mut runtime = new_runtime();
+        let inner = runtime.block_on(builder.build())?;
+        Ok(SyncSession {
+            inner,
+            runtime: RefCell::new(runtime),
+        })
+    }
+
+    /// Create a session from the config file.
+    ///
+    /// See [from_config](../fn.from_config.html).
+    #[cfg(feature = "config-file")]
+    #[inline]
+    pub fn from_config<S: AsRef<str>>(cloud_name: S) -> Result<SyncSession> {
+        super::from_config(cloud_name).map(SyncSession::new)
+    }
+
+    /// Create a session from environment variables.
+    ///
+    /// See [from_env](../fn.from_env.html).
+    #[inline]
+    pub fn from_env() -> Result<SyncSession> {
+        super::from_env().map(SyncSession::new)
+    }
+
     /// Get a reference to the authentication type in use.
     #[inline]
     pub fn auth_type(&self) -> &dyn AuthType {
@@ -131,12 +179,147 @@ impl SyncSession {
         self.runtime.borrow_mut().block_on(fut)
     }
 
+    /// Re-authenticate to pull a fresh service catalog.
+    ///
+    /// See [Session::refresh_catalog](../struct.Session.html#method.refresh_catalog).
+    #[inline]
+    pub fn refresh_catalog(&self) -> Result<()> {
+        let fut = self.inner.refresh_catalog();
+        self.runtime.borrow_mut().block_on(fut)
+    }
+
+    /// Revoke the authentication token and drop the cached endpoint information.
+    ///
+    /// See [Session::invalidate](../struct.Session.html#method.invalidate).
+    #[inline]
+    pub fn invalidate(&self) -> Result<()> {
+        self.block_on(self.inner.invalidate())
+    }
+
+    /// Create a new session with a token scoped to a different project.
+    ///
+    /// See [Session::scoped_to_project](../struct.Session.html#method.scoped_to_project).
+    #[inline]
+    pub fn scoped_to_project(
+        &self,
+        project: IdOrName,
+        domain: Option<IdOrName>,
+    ) -> Result<SyncSession> {
+        self.block_on(self.inner.scoped_to_project(project, domain))
+            .map(SyncSession::new)
+    }
+
+    /// Create a new session with a token of a different scope.
+    ///
+    /// See [Session::scoped_to](../struct.Session.html#method.scoped_to).
+    #[inline]
+    pub fn scoped_to(&self, scope: Scope) -> Result<SyncSession> {
+        self.block_on(self.inner.scoped_to(scope))
+            .map(SyncSession::new)
+    }
+
+    /// Create a new session with a different scope without authenticating.
+    ///
+    /// See [Session::with_scope](../struct.Session.html#method.with_scope).
+    #[inline]
+    pub fn with_scope(&self, scope: Scope) -> Result<SyncSession> {
+        self.inner.with_scope(scope).map(SyncSession::new)
+    }
+
+    /// Create a new session using endpoints from a different region.
+    ///
+    /// See [Session::for_region](../struct.Session.html#method.for_region).
+    #[inline]
+    pub fn for_region<S: Into<String>>(&self, region: S) -> SyncSession {
+        SyncSession::new(self.inner.for_region(region))
+    }
+
+    /// List projects the user of this session has access to.
+    ///
+    /// See [Session::available_projects](../struct.Session.html#method.available_projects).
+    #[inline]
+    pub fn available_projects(&self) -> Result<Vec<AvailableProject>> {
+        self.block_on(self.inner.available_projects())
+    }
+
     /// Reference to the asynchronous session used.
     #[inline]
     pub fn session(&self) -> &Session {
         &self.inner
     }
 
+    /// Mutable reference to the asynchronous session used.
+    ///
+    /// Allows changing the settings that have no synchronous counterpart (e.g. the time out).
+    #[inline]
+    pub fn session_mut(&mut self) -> &mut Session {
+        &mut self.inner
+    }
+
+    /// Get the parsed service catalog.
+    ///
+    /// See [Session::catalog](../struct.Session.html#method.catalog).
+    #[inline]
+    pub fn catalog(&self) -> Result<Vec<CatalogService>> {
+        self.block_on(self.inner.catalog())
+    }
+
+    /// Get the service catalog as JSON in the format returned by the Identity service.
+    ///
+    /// See [Session::catalog_json](../struct.Session.html#method.catalog_json).
+    #[inline]
+    pub fn catalog_json(&self) -> Result<String> {
+        self.block_on(self.inner.catalog_json())
+    }
+
+    /// List the distinct service types present in the service catalog.
+    ///
+    /// See [Session::service_types](../struct.Session.html#method.service_types).
+    #[inline]
+    pub fn service_types(&self) -> Result<Vec<String>> {
+        self.block_on(self.inner.service_types())
+    }
+
+    /// List the distinct regions present in the service catalog.
+    ///
+    /// See [Session::regions](../struct.Session.html#method.regions).
+    #[inline]
+    pub fn regions(&self) -> Result<Vec<String>> {
+        self.block_on(self.inner.regions())
+    }
+
+    /// List all catalog endpoints of the service matching the endpoint filters.
+    ///
+    /// See [Session::endpoints_for](../struct.Session.html#method.endpoints_for).
+    #[inline]
+    pub fn endpoints_for<Srv: ServiceType>(&self, service: Srv) -> Result<Vec<Endpoint>> {
+        self.block_on(self.inner.endpoints_for(service))
+    }
+
+    /// Get information about the current authentication token.
+    ///
+    /// See [Session::token_info](../struct.Session.html#method.token_info).
+    #[inline]
+    pub fn token_info(&self) -> Result<TokenInfo> {
+        self.block_on(self.inner.token_info())
+    }
+
+    /// Check that the cloud can be used with the credentials of this session.
+    ///
+    /// See [Session::validate](../struct.Session.html#method.validate).
+    #[inline]
+    pub fn validate(&self, services: &[&'static str]) -> Result<HealthReport> {
+        self.block_on(self.inner.validate(services))
+    }
+
+    /// Validate a token presented by a client.
+    ///
+    /// See [Session::validate_token](../struct.Session.html#method.validate_token).
+    #[inline]
+    pub fn validate_token(&self, token: &str) -> Result<TokenInfo> {
+        self.block_on(self.inner.validate_token(token))
+    }
+
     /// Set a new authentication for this `Session`.
     ///
     /// This call clears the cached service information for this `Session`.
@@ -154,6 +337,24 @@ impl SyncSession {
         self.inner.set_endpoint_interface(endpoint_interface);
     }
 
+    /// Use the given endpoint for the service instead of the one from the service catalog.
+    ///
+    /// This call clears the cached service information for this `Session`.
+    /// It does not, however, affect clones of this `Session`.
+    #[inline]
+    pub fn set_endpoint<Srv: ServiceType>(&mut self, service: Srv, endpoint: Url) {
+        self.inner.set_endpoint(service, endpoint);
+    }
+
+    /// Use the given endpoint for the service with the catalog type.
+    ///
+    /// This call clears the cached service information for this `Session`.
+    /// It does not, however, affect clones of this `Session`.
+    #[inline]
+    pub fn set_endpoint_override<S: Into<String>>(&mut self, catalog_type: S, endpoint: Url) {
+        self.inner.set_endpoint_override(catalog_type, endpoint);
+    }
+
     /// Convert this session into one using the given authentication.
     #[inline]
     pub fn with_auth_type<Auth: AuthType + 'static>(mut self, auth_method: Auth) -> SyncSession {
@@ -175,6 +376,18 @@ impl SyncSession {
         self
     }
 
+    /// Convert this session into one using the given endpoint for the service with the catalog
+    /// type.
+    #[inline]
+    pub fn with_endpoint_override<S: Into<String>>(
+        mut self,
+        catalog_type: S,
+        endpoint: Url,
+    ) -> SyncSession {
+        self.set_endpoint_override(catalog_type, endpoint);
+        self
+    }
+
     /// Get minimum/maximum API (micro)version information.
     ///
     /// Returns `None` if the range cannot be determined, which usually means
@@ -216,6 +429,37 @@ impl SyncSession {
         self.block_on(self.inner.get_endpoint(service, path))
     }
 
+    /// Construct an endpoint for the given service using a specific interface.
+    ///
+    /// See [Session::get_endpoint_with_interface](../struct.Session.html#method.get_endpoint_with_interface).
+    #[inline]
+    pub fn get_endpoint_with_interface<Srv, I>(
+        &self,
+        service: Srv,
+        interface: InterfaceType,
+        path: I,
+    ) -> Result<Url>
+    where
+        Srv: ServiceType + Send,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+    {
+        self.block_on(
+            self.inner
+                .get_endpoint_with_interface(service, interface, path),
+        )
+    }
+
+    /// Get information about the endpoint of the service from its version discovery document.
+    #[inline]
+    pub fn endpoint_info<Srv>(&self, service: Srv) -> Result<EndpointInfo>
+    where
+        Srv: ServiceType + Send,
+    {
+        self.block_on(self.inner.endpoint_info(service))
+    }
+
     /// Get the currently used major version from the given service.
     ///
     /// Can return `None` if the service does not support API version discovery at all.
@@ -255,6 +499,30 @@ impl SyncSession {
         self.block_on(self.inner.pick_api_version(service, versions))
     }
 
+    /// API version negotiated for the service.
+    ///
+    /// See [Session::negotiated_api_version](../struct.Session.html#method.negotiated_api_version).
+    #[inline]
+    pub fn negotiated_api_version<Srv>(&self, service: Srv) -> Result<Option<ApiVersion>>
+    where
+        Srv: ServiceType + Send,
+    {
+        self.block_on(self.inner.negotiated_api_version(service))
+    }
+
+    /// Set the default microversion for the service.
+    ///
+    /// See [Session::set_default_microversion](../struct.Session.html#method.set_default_microversion).
+    #[inline]
+    pub fn set_default_microversion<Srv, S>(&mut self, service: Srv, version: S) -> Result<()>
+    where
+        Srv: ServiceType + Send,
+        S: AsRef<str>,
+    {
+        let fut = self.inner.set_default_microversion(service, version);
+        self.runtime.borrow_mut().block_on(fut)
+    }
+
     /// Check if the service supports the API version.
     #[inline]
     pub fn supports_api_version<Srv: ServiceType + Send>(
@@ -265,6 +533,27 @@ impl SyncSession {
         self.block_on(self.inner.supports_api_version(service, version))
     }
 
+    /// Check if the service supports the microversion `major.minor`.
+    ///
+    /// See [Session::supports_microversion](../struct.Session.html#method.supports_microversion).
+    #[inline]
+    pub fn supports_microversion<Srv: ServiceType + Send>(
+        &self,
+        service: Srv,
+        major: u16,
+        minor: u16,
+    ) -> Result<bool> {
+        self.block_on(self.inner.supports_microversion(service, major, minor))
+    }
+
+    /// Get all major API versions from the version discovery document of the service.
+    ///
+    /// See [Session::get_versions](../struct.Session.html#method.get_versions).
+    #[inline]
+    pub fn get_versions<Srv: ServiceType + Send>(&self, service: Srv) -> Result<Vec<VersionInfo>> {
+        self.block_on(self.inner.get_versions(service))
+    }
+
     /// Make an HTTP request to the given service.
     ///
     /// The `service` argument is an object implementing the
@@ -313,6 +602,32 @@ impl SyncSession {
         self.block_on(self.inner.request(service, method, path, api_version))
     }
 
+    /// Make an HTTP request to the given service using a specific interface.
+    ///
+    /// See [Session::request_with_interface](../struct.Session.html#method.request_with_interface).
+    pub fn request_with_interface<Srv, I>(
+        &self,
+        service: Srv,
+        interface: InterfaceType,
+        method: Method,
+        path: I,
+        api_version: Option<ApiVersion>,
+    ) -> Result<RequestBuilder>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+    {
+        self.block_on(self.inner.request_with_interface(
+            service,
+            interface,
+            method,
+            path,
+            api_version,
+        ))
+    }
+
     /// Issue a GET request.
     ///
     /// See [request](#method.request) for an explanation of the parameters.
@@ -451,7 +766,10 @@ impl SyncSession {
     ///
     /// ```
     #[inline]
-    pub fn download(&self, response: Response) -> SyncStream<impl Stream<Item = SyncStreamItem>> {
+    pub fn download(
+        &self,
+        response: Response,
+    ) -> SyncStream<'_, impl Stream<Item = SyncStreamItem>> {
         SyncStream::new(self, response.bytes_stream())
     }
 
@@ -580,6 +898,59 @@ impl SyncSession {
         )
     }
 
+    /// PATCH a JSON object.
+    ///
+    /// The `body` argument is anything that can be serialized into JSON.
+    ///
+    /// See [request](#method.request) for an explanation of the other parameters.
+    #[inline]
+    pub fn patch<Srv, I, T>(
+        &self,
+        service: Srv,
+        path: I,
+        body: T,
+        api_version: Option<ApiVersion>,
+    ) -> Result<Response>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+        T: Serialize + Send,
+    {
+        self.send_checked(
+            self.request(service, Method::PATCH, path, api_version)?
+                .json(&body),
+        )
+    }
+
+    /// PATCH a JSON object and receive a JSON back.
+    ///
+    /// The `body` argument is anything that can be serialized into JSON.
+    ///
+    /// See [request](#method.request) for an explanation of the other parameters.
+    #[inline]
+    pub fn patch_json<Srv, I, T, R>(
+        &self,
+        service: Srv,
+        path: I,
+        body: T,
+        api_version: Option<ApiVersion>,
+    ) -> Result<R>
+    where
+        Srv: ServiceType + Send + Clone,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: Send,
+        T: Serialize + Send,
+        R: DeserializeOwned + Send,
+    {
+        self.fetch_json(
+            self.request(service, Method::PATCH, path, api_version)?
+                .json(&body),
+        )
+    }
+
     /// Issue a DELETE request.
     ///
     /// See [request](#method.request) for an explanation of the parameters.
@@ -605,13 +976,13 @@ impl SyncSession {
     where
         T: DeserializeOwned + Send,
     {
-        self.block_on(async { request::to_json(builder.send().await?).await })
+        self.block_on(self.inner.fetch_json(builder))
     }
 
     /// Check the response and convert errors into OpenStack ones.
     #[inline]
     pub fn send_checked(&self, builder: RequestBuilder) -> Result<Response> {
-        self.block_on(async { request::check(builder.send().await?).await })
+        self.block_on(self.inner.send_checked(builder))
     }
 
     #[inline]
@@ -623,13 +994,25 @@ impl SyncSession {
     }
 }
 
+/// Create a single-threaded runtime.
+///
+/// Panics if unable to create it.
+fn new_runtime() -> Runtime {
+    RuntimeBuilder::new()
+        .basic_scheduler()
+        .enable_io()
+        .enable_time()
+        .build()
+        .expect("Could not create a runtime")
+}
+
 impl<'s, S, E> SyncStream<'s, S, E>
 where
     S: Stream<Item = result::Result<Bytes, E>> + Unpin,
 {
-    fn new(session: &'s SyncSession, inner: S) -> SyncStream<S, E> {
+    fn new(session: &'s SyncSession, inner: S) -> SyncStream<'s, S, E> {
         SyncStream {
-            session,
+            _session: session,
             inner: executor::block_on_stream(inner),
             current: io::Cursor::default(),
         }
@@ -652,7 +1035,7 @@ where
 
             if let Some(next) = self.inner.next() {
                 self.current =
-                    io::Cursor::new(next.map_err(|err| io::Error::new(io::ErrorKind::Other, err))?);
+                    io::Cursor::new(next.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?);
             } else {
                 return Ok(0);
             }
@@ -703,10 +1086,11 @@ mod test {
 
     use bytes::Bytes;
     use futures::stream;
-    use reqwest::{Body, Error as HttpError};
+    use reqwest::{Body, Error as HttpError, Url};
 
+    use super::super::services::OBJECT_STORAGE;
     use super::super::session::test;
-    use super::super::{ApiVersion, Error};
+    use super::super::{ApiVersion, Error, ErrorKind};
     use super::{SyncBody, SyncSession, SyncStream};
 
     fn new_simple_sync_session(url: &str) -> SyncSession {
@@ -739,6 +1123,30 @@ mod test {
         assert_eq!(&ep.to_string(), test::URL);
     }
 
+    #[test]
+    fn test_set_endpoint_override() {
+        let s = new_simple_sync_session(test::URL).with_endpoint_override(
+            "object-store",
+            Url::parse("http://127.0.0.1:8080/swift/v1").unwrap(),
+        );
+        let ep = s.get_endpoint(OBJECT_STORAGE, &["info"]).unwrap();
+        assert_eq!(ep.as_str(), "http://127.0.0.1:8080/swift/v1/info");
+    }
+
+    #[test]
+    fn test_no_token() {
+        let s = new_simple_sync_session(test::URL);
+        assert_eq!(
+            s.token_info().err().unwrap().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            s.service_types().err().unwrap().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert!(s.negotiated_api_version(test::FAKE).unwrap().is_none());
+    }
+
     #[test]
     fn test_get_endpoint_slice() {
         let s = new_simple_sync_session(test::URL);
@@ -830,7 +1238,7 @@ mod test {
             Ok(Bytes::from(vec![4u8])),
             Ok(Bytes::from(vec![5u8, 6])),
         ];
-        let mut st = SyncStream::new(&s, stream::iter(data.into_iter()));
+        let mut st = SyncStream::new(&s, stream::iter(data));
         let mut buffer = Vec::new();
         assert_eq!(6, st.read_to_end(&mut buffer).unwrap());
         assert_eq!(vec![1, 2, 3, 4, 5, 6], buffer);
@@ -844,7 +1252,7 @@ mod test {
             Ok(Bytes::from(vec![4u8])),
             Ok(Bytes::from(vec![5u8, 6, 7, 8])),
         ];
-        let mut st = SyncStream::new(&s, stream::iter(data.into_iter()));
+        let mut st = SyncStream::new(&s, stream::iter(data));
         let mut buffer = [0; 3];
         assert_eq!(3, st.read(&mut buffer).unwrap());
         assert_eq!([1, 2, 3], buffer);
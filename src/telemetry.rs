@@ -0,0 +1,189 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Instrumentation with `tracing` spans.
+//!
+//! Without the `tracing` feature all spans are no-ops, so that the callers do not need to care
+//! whether the feature is enabled.
+
+use std::future::Future;
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+use reqwest::{RequestBuilder, Response, Url};
+#[cfg(feature = "tracing")]
+use tracing_crate::{field, info_span, Instrument, Span};
+
+use super::{EndpointFilters, Error};
+
+/// A span covering an operation.
+#[derive(Debug)]
+pub(crate) struct Traced {
+    #[cfg(feature = "tracing")]
+    span: Span,
+    #[cfg(feature = "tracing")]
+    started: Instant,
+}
+
+/// A successful result of an operation that can add fields to its span.
+pub(crate) trait Outcome {
+    /// Record the fields of the result (none by default).
+    #[cfg(feature = "tracing")]
+    fn record(&self, _span: &Span) {}
+}
+
+impl Outcome for Response {
+    #[cfg(feature = "tracing")]
+    fn record(&self, span: &Span) {
+        let _ = span.record("status", self.status().as_u16());
+        if let Some(request_id) = super::request::request_id(self) {
+            let _ = span.record("request_id", request_id.as_str());
+        }
+    }
+}
+
+impl Outcome for Url {
+    #[cfg(feature = "tracing")]
+    fn record(&self, span: &Span) {
+        let _ = span.record("endpoint", self.as_str());
+    }
+}
+
+/// Span of an HTTP request: `osauth.http` with `method`, `url`, `status`, `request_id`,
+/// `duration_ms` and `error`.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn http_request(builder: &RequestBuilder) -> Traced {
+    #[cfg(feature = "tracing")]
+    {
+        // NOTE: requests with streaming bodies cannot be cloned, their URL stays unknown.
+        let request = builder.try_clone().and_then(|builder| builder.build().ok());
+        let span = info_span!(
+            "osauth.http",
+            method = field::Empty,
+            url = field::Empty,
+            status = field::Empty,
+            request_id = field::Empty,
+            duration_ms = field::Empty,
+            error = field::Empty,
+        );
+        if let Some(request) = request {
+            let _ = span.record("method", request.method().as_str());
+            let _ = span.record("url", request.url().as_str());
+        }
+        Traced::new(span)
+    }
+    #[cfg(not(feature = "tracing"))]
+    Traced {}
+}
+
+/// Span of an authentication: `osauth.auth` with `auth_url`, `token_endpoint`, `request_id`,
+/// `duration_ms` and `error`.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn authentication(auth_url: &Url, token_endpoint: &str) -> Traced {
+    #[cfg(feature = "tracing")]
+    {
+        Traced::new(info_span!(
+            "osauth.auth",
+            auth_url = auth_url.as_str(),
+            token_endpoint,
+            request_id = field::Empty,
+            duration_ms = field::Empty,
+            error = field::Empty,
+        ))
+    }
+    #[cfg(not(feature = "tracing"))]
+    Traced {}
+}
+
+/// Span of an endpoint resolution: `osauth.endpoint` with `service_type`, `interfaces`,
+/// `region`, `endpoint`, `duration_ms` and `error`.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn endpoint(service_type: &str, filters: &EndpointFilters) -> Traced {
+    #[cfg(feature = "tracing")]
+    {
+        Traced::new(info_span!(
+            "osauth.endpoint",
+            service_type,
+            interfaces = ?filters.interfaces,
+            region = filters.region(),
+            endpoint = field::Empty,
+            duration_ms = field::Empty,
+            error = field::Empty,
+        ))
+    }
+    #[cfg(not(feature = "tracing"))]
+    Traced {}
+}
+
+impl Traced {
+    #[cfg(feature = "tracing")]
+    fn new(span: Span) -> Traced {
+        Traced {
+            span,
+            started: Instant::now(),
+        }
+    }
+
+    /// Run the future inside the span and record its result.
+    pub(crate) async fn run<T, F>(self, future: F) -> Result<T, Error>
+    where
+        T: Outcome,
+        F: Future<Output = Result<T, Error>>,
+    {
+        #[cfg(feature = "tracing")]
+        {
+            let result = future.instrument(self.span.clone()).await;
+            let elapsed = self.started.elapsed().as_millis() as u64;
+            let _ = self.span.record("duration_ms", elapsed);
+            match result {
+                Ok(ref value) => value.record(&self.span),
+                Err(ref err) => {
+                    if let Some(status) = err.status() {
+                        let _ = self.span.record("status", status.as_u16());
+                    }
+                    if let Some(request_id) = err.request_id() {
+                        let _ = self.span.record("request_id", request_id);
+                    }
+                    let _ = self.span.record("error", field::display(err));
+                }
+            }
+            result
+        }
+        #[cfg(not(feature = "tracing"))]
+        future.await
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use reqwest::Client;
+
+    use super::super::{EndpointFilters, Error, ErrorKind};
+    use super::{endpoint, http_request};
+
+    #[tokio::test]
+    async fn test_run() {
+        let builder = Client::new().get("http://127.0.0.1:1/");
+        let result: Result<reqwest::Response, Error> = http_request(&builder)
+            .run(async { Err(Error::new(ErrorKind::Transport, "boom")) })
+            .await;
+        assert_eq!(result.err().unwrap().kind(), ErrorKind::Transport);
+
+        let url = reqwest::Url::parse("http://127.0.0.1/compute").unwrap();
+        let result = endpoint("compute", &EndpointFilters::default())
+            .run(async { Ok(url.clone()) })
+            .await;
+        assert_eq!(result.unwrap(), url);
+    }
+}
@@ -0,0 +1,747 @@
+// Copyright 2020 Dmitry Tantsur <divius.inside@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fakes for testing code that uses a [Session](../struct.Session.html) without a cloud.
+//!
+//! [MockServer](struct.MockServer.html) is a local HTTP server answering with scripted
+//! responses, [FakeAuth](struct.FakeAuth.html) is an authentication type with a canned service
+//! catalog (e.g. pointing to the mock server):
+//!
+//! ```rust
+//! use osauth::test::{Mock, MockServer};
+//! use reqwest::Method;
+//!
+//! # async fn example() -> Result<(), osauth::Error> {
+//! let server = MockServer::start();
+//! server.mock(
+//!     Mock::new(Method::GET, "/compute/servers/1234")
+//!         .with_json(&serde_json::json!({"server": {"id": "1234", "status": "ACTIVE"}})),
+//! );
+//!
+//! let session = server.session(&["compute"]);
+//! let result: serde_json::Value = session
+//!     .get_json(osauth::services::COMPUTE, &["servers", "1234"], None)
+//!     .await?;
+//! assert_eq!(result["server"]["status"], "ACTIVE");
+//!
+//! let requests = server.requests();
+//! let last = requests.last().expect("No requests received");
+//! assert_eq!(last.headers["x-auth-token"], osauth::test::FAKE_TOKEN);
+//! # Ok(()) }
+//! # #[tokio::main]
+//! # async fn main() { example().await.unwrap(); }
+//! ```
+
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use osproto::identity::{CatalogRecord, Endpoint};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Method, RequestBuilder, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::identity::TokenInfo;
+use super::{AuthType, EndpointFilters, Error, ErrorKind, Session};
+
+/// Token used by [MockServer::session](struct.MockServer.html#method.session).
+pub const FAKE_TOKEN: &str = "fake-token";
+
+/// Region of the endpoints added with [FakeAuth::add_endpoint](struct.FakeAuth.html#method.add_endpoint).
+pub const FAKE_REGION: &str = "RegionOne";
+
+/// Authentication type with a static token and a canned service catalog.
+///
+/// The token is sent as `X-Auth-Token`, endpoints are selected from the catalog using the
+/// endpoint filters of the session.
+#[derive(Clone, Debug)]
+pub struct FakeAuth {
+    client: Client,
+    token: String,
+    catalog: Vec<CatalogRecord>,
+    token_info: Option<TokenInfo>,
+}
+
+impl FakeAuth {
+    /// Create a fake authentication type with an empty catalog.
+    pub fn new<S: Into<String>>(token: S) -> FakeAuth {
+        FakeAuth {
+            client: Client::new(),
+            token: token.into(),
+            catalog: Vec::new(),
+            token_info: None,
+        }
+    }
+
+    /// The fake token.
+    #[inline]
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Add a public endpoint in [FAKE_REGION](constant.FAKE_REGION.html) to the catalog.
+    pub fn add_endpoint<S: Into<String>>(&mut self, service_type: S, url: Url) {
+        let service_type = service_type.into();
+        let endpoint = Endpoint {
+            interface: "public".to_string(),
+            region: FAKE_REGION.to_string(),
+            url: url.into(),
+        };
+        match self
+            .catalog
+            .iter_mut()
+            .find(|record| record.service_type == service_type)
+        {
+            Some(record) => record.endpoints.push(endpoint),
+            None => self.catalog.push(CatalogRecord {
+                service_type,
+                endpoints: vec![endpoint],
+            }),
+        }
+    }
+
+    /// Replace the service catalog.
+    #[inline]
+    pub fn set_catalog(&mut self, catalog: Vec<CatalogRecord>) {
+        self.catalog = catalog;
+    }
+
+    /// Set the information about the token.
+    ///
+    /// By default, the token expires in an hour and is not scoped.
+    #[inline]
+    pub fn set_token_info(&mut self, token_info: TokenInfo) {
+        self.token_info = Some(token_info);
+    }
+
+    /// Add a public endpoint in [FAKE_REGION](constant.FAKE_REGION.html) to the catalog.
+    #[inline]
+    pub fn with_endpoint<S: Into<String>>(mut self, service_type: S, url: Url) -> Self {
+        self.add_endpoint(service_type, url);
+        self
+    }
+
+    /// Replace the service catalog.
+    #[inline]
+    pub fn with_catalog(mut self, catalog: Vec<CatalogRecord>) -> Self {
+        self.set_catalog(catalog);
+        self
+    }
+
+    /// Set the information about the token.
+    #[inline]
+    pub fn with_token_info(mut self, token_info: TokenInfo) -> Self {
+        self.set_token_info(token_info);
+        self
+    }
+}
+
+#[async_trait]
+impl AuthType for FakeAuth {
+    /// Create a request with the fake token.
+    async fn request(&self, method: Method, url: Url) -> Result<RequestBuilder, Error> {
+        Ok(self
+            .client
+            .request(method, url)
+            .header("x-auth-token", &self.token))
+    }
+
+    /// Find the endpoint in the canned catalog.
+    async fn get_endpoint(
+        &self,
+        service_type: String,
+        filters: EndpointFilters,
+    ) -> Result<Url, Error> {
+        filters.find_in_catalog(&self.catalog, &service_type)
+    }
+
+    /// This call does nothing for `FakeAuth`.
+    async fn refresh(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// The canned catalog in the format of the Identity service.
+    async fn raw_catalog(&self) -> Result<Option<serde_json::Value>, Error> {
+        let catalog = self
+            .catalog
+            .iter()
+            .map(|record| {
+                let endpoints = record
+                    .endpoints
+                    .iter()
+                    .map(|endpoint| {
+                        serde_json::json!({
+                            "interface": endpoint.interface,
+                            "region": endpoint.region,
+                            "region_id": endpoint.region,
+                            "url": endpoint.url,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                serde_json::json!({"type": record.service_type, "endpoints": endpoints})
+            })
+            .collect();
+        Ok(Some(serde_json::Value::Array(catalog)))
+    }
+
+    /// The configured token information or an unscoped token expiring in an hour.
+    async fn token_info(&self) -> Result<Option<TokenInfo>, Error> {
+        Ok(Some(self.token_info.clone().unwrap_or_else(|| {
+            let now = Utc::now();
+            TokenInfo {
+                issued_at: Some(now.into()),
+                expires_at: (now + Duration::hours(1)).into(),
+                audit_ids: Vec::new(),
+                methods: vec!["token".to_string()],
+                application_credential: None,
+                user: None,
+                project: None,
+                domain: None,
+                roles: Vec::new(),
+            }
+        })))
+    }
+}
+
+/// A scripted response of a [MockServer](struct.MockServer.html).
+#[derive(Clone, Debug)]
+pub struct Mock {
+    method: Method,
+    path: String,
+    query: Option<String>,
+    status: StatusCode,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    remaining: Option<usize>,
+}
+
+impl Mock {
+    /// Respond to requests with the given method and path (relative to the server root).
+    ///
+    /// If the path includes a query (e.g. `/compute/servers?limit=1`), the query must match
+    /// exactly, otherwise it is ignored. The response is HTTP 200 with an empty body by default.
+    pub fn new<S: Into<String>>(method: Method, path: S) -> Mock {
+        let path = path.into();
+        let (path, query) = match path.find('?') {
+            Some(pos) => (path[..pos].to_string(), Some(path[pos + 1..].to_string())),
+            None => (path, None),
+        };
+        Mock {
+            method,
+            path,
+            query,
+            status: StatusCode::OK,
+            headers: Vec::new(),
+            body: Vec::new(),
+            remaining: None,
+        }
+    }
+
+    /// Add a response header.
+    #[inline]
+    pub fn add_header<K: Into<String>, V: Into<String>>(&mut self, name: K, value: V) {
+        self.headers.push((name.into(), value.into()));
+    }
+
+    /// Set the response body.
+    #[inline]
+    pub fn set_body<B: Into<Vec<u8>>>(&mut self, body: B) {
+        self.body = body.into();
+    }
+
+    /// Set a JSON response body (also sets `Content-Type`).
+    ///
+    /// # Panics
+    ///
+    /// If the value cannot be serialized.
+    pub fn set_json<T: Serialize + ?Sized>(&mut self, value: &T) {
+        self.body = serde_json::to_vec(value).expect("Cannot serialize the mock body");
+        self.add_header("content-type", "application/json");
+    }
+
+    /// Set the response status (HTTP 200 by default).
+    #[inline]
+    pub fn set_status(&mut self, status: StatusCode) {
+        self.status = status;
+    }
+
+    /// Only respond to this many requests (any number by default).
+    ///
+    /// Several mocks for the same request respond in the order they were added, so a sequence
+    /// of responses (e.g. an operation in progress, then finished) can be scripted.
+    #[inline]
+    pub fn set_times(&mut self, times: usize) {
+        self.remaining = Some(times);
+    }
+
+    /// Add a response header.
+    #[inline]
+    pub fn with_header<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> Self {
+        self.add_header(name, value);
+        self
+    }
+
+    /// Set the response body.
+    #[inline]
+    pub fn with_body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.set_body(body);
+        self
+    }
+
+    /// Set a JSON response body (also sets `Content-Type`).
+    ///
+    /// # Panics
+    ///
+    /// If the value cannot be serialized.
+    #[inline]
+    pub fn with_json<T: Serialize + ?Sized>(mut self, value: &T) -> Self {
+        self.set_json(value);
+        self
+    }
+
+    /// Set the response status (HTTP 200 by default).
+    #[inline]
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.set_status(status);
+        self
+    }
+
+    /// Only respond to this many requests (any number by default).
+    #[inline]
+    pub fn with_times(mut self, times: usize) -> Self {
+        self.set_times(times);
+        self
+    }
+
+    fn matches(&self, request: &RecordedRequest) -> bool {
+        self.remaining != Some(0)
+            && self.method == request.method
+            && self.path.trim_end_matches('/') == request.path.trim_end_matches('/')
+            && (self.query.is_none() || self.query == request.query)
+    }
+}
+
+/// Path of the token API of the Identity service at [MockServer::identity_url].
+#[cfg(test)]
+pub(crate) const TOKENS_PATH: &str = "/identity/v3/auth/tokens";
+
+/// Body of an unscoped token response of the Identity service.
+#[cfg(test)]
+pub(crate) const TOKEN_BODY: &str =
+    r#"{"token": {"expires_at": "2100-01-01T00:00:00Z", "roles": [], "catalog": []}}"#;
+
+/// A response of the Identity service issuing the token (once).
+#[cfg(test)]
+pub(crate) fn token_mock(token: &str) -> Mock {
+    token_mock_at(TOKENS_PATH, token)
+}
+
+/// A response issuing the token (once) on the given path.
+#[cfg(test)]
+pub(crate) fn token_mock_at(path: &str, token: &str) -> Mock {
+    Mock::new(Method::POST, path)
+        .with_status(StatusCode::CREATED)
+        .with_header("content-type", "application/json")
+        .with_header("x-subject-token", token)
+        .with_body(TOKEN_BODY)
+        .with_times(1)
+}
+
+/// A request received by a [MockServer](struct.MockServer.html).
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct RecordedRequest {
+    /// Request method.
+    pub method: Method,
+    /// Request path.
+    pub path: String,
+    /// Request query (if any).
+    pub query: Option<String>,
+    /// Request headers.
+    pub headers: HeaderMap,
+    /// Request body (chunked bodies are decoded).
+    pub body: Vec<u8>,
+}
+
+impl RecordedRequest {
+    /// Parse the body as JSON.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        serde_json::from_slice(&self.body)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Invalid JSON body: {}", e)))
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    mocks: Vec<Mock>,
+    requests: Vec<RecordedRequest>,
+    service_roots: HashSet<String>,
+}
+
+type Reply = (StatusCode, Vec<(String, String)>, Vec<u8>);
+
+impl State {
+    fn reply(&mut self, request: RecordedRequest) -> Reply {
+        let reply = if let Some(mock) = self.mocks.iter_mut().find(|m| m.matches(&request)) {
+            if let Some(ref mut remaining) = mock.remaining {
+                *remaining -= 1;
+            }
+            (mock.status, mock.headers.clone(), mock.body.clone())
+        } else if request.method == Method::GET
+            && self
+                .service_roots
+                .contains(request.path.trim_end_matches('/'))
+        {
+            // NOTE: an empty list of versions makes the session use the endpoint as it is.
+            json_reply(StatusCode::OK, serde_json::json!({ "versions": [] }))
+        } else {
+            let message = format!("No mock for {} {}", request.method, request.path);
+            json_reply(
+                StatusCode::NOT_FOUND,
+                serde_json::json!({"error": {"code": 404, "message": message}}),
+            )
+        };
+        self.requests.push(request);
+        reply
+    }
+}
+
+fn json_reply(status: StatusCode, body: serde_json::Value) -> Reply {
+    let headers = vec![("content-type".to_string(), "application/json".to_string())];
+    (status, headers, body.to_string().into_bytes())
+}
+
+/// A local HTTP server responding with [mocks](struct.Mock.html).
+///
+/// The server listens on a random port of `127.0.0.1` until dropped. Every request is recorded.
+/// Requests matching no mock get HTTP 404, except for `GET` requests to the roots of
+/// [endpoints](#method.endpoint), which get an empty list of API versions, so that version
+/// discovery succeeds without mocks. Add a mock for the root of an endpoint to test version
+/// negotiation.
+#[derive(Debug)]
+pub struct MockServer {
+    address: SocketAddr,
+    url: Url,
+    state: Arc<Mutex<State>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl MockServer {
+    /// Start a mock server.
+    ///
+    /// # Panics
+    ///
+    /// If the server cannot listen on a local port.
+    pub fn start() -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Cannot start a mock server");
+        let address = listener
+            .local_addr()
+            .expect("Cannot get the address of a mock server");
+        let url = Url::parse(&format!("http://{}/", address)).expect("Invalid mock server URL");
+        let state = Arc::new(Mutex::new(State::default()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let server_state = Arc::clone(&state);
+        let server_shutdown = Arc::clone(&shutdown);
+        let _ = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if server_shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let state = Arc::clone(&server_state);
+                    let _ = thread::spawn(move || handle_connection(stream, &state));
+                }
+            }
+        });
+
+        MockServer {
+            address,
+            url,
+            state,
+            shutdown,
+        }
+    }
+
+    /// Root URL of the server.
+    #[inline]
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// URL of a fake endpoint for the service type: `<url>/<service_type>/`.
+    pub fn endpoint(&self, service_type: &str) -> Url {
+        let _ = self
+            .lock()
+            .service_roots
+            .insert(format!("/{}", service_type));
+        self.url
+            .join(&format!("{}/", service_type))
+            .expect("Invalid service type")
+    }
+
+    /// URL of the fake Identity service: `<url>/identity`.
+    #[cfg(test)]
+    pub(crate) fn identity_url(&self) -> Url {
+        self.url.join("identity").expect("Invalid identity URL")
+    }
+
+    /// Add a mock.
+    ///
+    /// Mocks are checked in the order they were added, the first matching one responds.
+    pub fn mock(&self, mock: Mock) {
+        self.lock().mocks.push(mock);
+    }
+
+    /// All requests received so far.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.lock().requests.clone()
+    }
+
+    /// Fake authentication with [endpoints](#method.endpoint) for the given services.
+    ///
+    /// Uses [FAKE_TOKEN](constant.FAKE_TOKEN.html) as the token.
+    pub fn fake_auth(&self, services: &[&str]) -> FakeAuth {
+        services
+            .iter()
+            .fold(FakeAuth::new(FAKE_TOKEN), |auth, service| {
+                auth.with_endpoint(*service, self.endpoint(service))
+            })
+    }
+
+    /// A session with [fake authentication](#method.fake_auth) for the given services.
+    #[inline]
+    pub fn session(&self, services: &[&str]) -> Session {
+        Session::new(self.fake_auth(services))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        // NOTE: a panic in another test thread must not break the server.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // NOTE: wake up the listening thread so that it notices the shutdown.
+        let _ = TcpStream::connect(self.address);
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, state: &Mutex<State>) {
+    let request = match read_request(&stream) {
+        Ok(request) => request,
+        Err(_) => return,
+    };
+    let is_head = request.method == Method::HEAD;
+    let (status, headers, body) = state
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .reply(request);
+
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\ncontent-length: {}\r\nconnection: close\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default(),
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    let _ = stream.write_all(head.as_bytes());
+    if !is_head {
+        let _ = stream.write_all(&body);
+    }
+    let _ = stream.flush();
+}
+
+fn invalid_request(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_request(stream: &TcpStream) -> io::Result<RecordedRequest> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    let _ = reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts
+        .next()
+        .and_then(|method| Method::from_bytes(method.as_bytes()).ok())
+        .ok_or_else(|| invalid_request("Invalid method"))?;
+    let target = parts
+        .next()
+        .ok_or_else(|| invalid_request("Missing request target"))?;
+    // NOTE: requests sent through a proxy use the absolute URL as the target.
+    let target = match target.find("://") {
+        Some(pos) => target[pos + 3..]
+            .find('/')
+            .map_or("/", |start| &target[pos + 3 + start..]),
+        None => target,
+    };
+    let (path, query) = match target.find('?') {
+        Some(pos) => (
+            target[..pos].to_string(),
+            Some(target[pos + 1..].to_string()),
+        ),
+        None => (target.to_string(), None),
+    };
+
+    let mut headers = HeaderMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid_request("Unexpected end of headers"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line
+            .find(':')
+            .map(|idx| (&line[..idx], &line[idx + 1..]))
+            .ok_or_else(|| invalid_request("Invalid header"))?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| invalid_request("Invalid header name"))?;
+        let value =
+            HeaderValue::from_str(value.trim()).map_err(|_| invalid_request("Invalid header"))?;
+        let _ = headers.append(name, value);
+    }
+
+    let chunked = headers
+        .get("transfer-encoding")
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.eq_ignore_ascii_case("chunked"));
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            let mut size_line = String::new();
+            let _ = reader.read_line(&mut size_line)?;
+            let size = size_line.trim().split(';').next().unwrap_or_default();
+            let size = usize::from_str_radix(size, 16)
+                .map_err(|_| invalid_request("Invalid chunk size"))?;
+            if size == 0 {
+                // NOTE: skip the (empty) trailer.
+                let mut trailer = String::new();
+                while reader.read_line(&mut trailer)? > 0 && !trailer.trim().is_empty() {
+                    trailer.clear();
+                }
+                break;
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+            let mut crlf = [0; 2];
+            reader.read_exact(&mut crlf)?;
+        }
+    } else if let Some(length) = headers
+        .get("content-length")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok())
+    {
+        body.resize(length, 0);
+        reader.read_exact(&mut body)?;
+    }
+
+    Ok(RecordedRequest {
+        method,
+        path,
+        query,
+        headers,
+        body,
+    })
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod test {
+    use reqwest::{Method, StatusCode};
+
+    use super::super::services::{COMPUTE, IMAGE};
+    use super::super::ErrorKind;
+    use super::{Mock, MockServer, FAKE_TOKEN};
+
+    #[tokio::test]
+    async fn test_mock_server() {
+        let server = MockServer::start();
+        server.mock(
+            Mock::new(Method::GET, "/compute/servers?limit=1")
+                .with_json(&serde_json::json!({"servers": [{"id": "1"}]})),
+        );
+        server.mock(
+            Mock::new(Method::POST, "/compute/servers")
+                .with_status(StatusCode::ACCEPTED)
+                .with_json(&serde_json::json!({"server": {"id": "2"}}))
+                .with_times(1),
+        );
+        let session = server.session(&["compute", "image"]);
+
+        let result: serde_json::Value = session
+            .get_json_query(COMPUTE, &["servers"], &[("limit", 1)], None)
+            .await
+            .unwrap();
+        assert_eq!(result["servers"][0]["id"], "1");
+
+        let body = serde_json::json!({"server": {"name": "new"}});
+        let result: serde_json::Value = session
+            .post_json(COMPUTE, &["servers"], &body, None)
+            .await
+            .unwrap();
+        assert_eq!(result["server"]["id"], "2");
+        // NOTE: the mock is exhausted.
+        let err = session
+            .post_json::<_, _, _, serde_json::Value>(COMPUTE, &["servers"], &body, None)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::ResourceNotFound);
+
+        let err = session
+            .get(IMAGE, &["v2", "images"], None)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::ResourceNotFound);
+
+        let requests = server.requests();
+        let posts: Vec<_> = requests
+            .iter()
+            .filter(|r| r.method == Method::POST)
+            .collect();
+        assert_eq!(posts.len(), 2);
+        assert_eq!(posts[0].headers["x-auth-token"], FAKE_TOKEN);
+        assert_eq!(posts[0].json::<serde_json::Value>().unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn test_fake_auth() {
+        let server = MockServer::start();
+        let session = server.session(&["compute"]);
+        assert_eq!(session.service_types().await.unwrap(), vec!["compute"]);
+        let url = session.get_endpoint(COMPUTE, &["servers"]).await.unwrap();
+        assert_eq!(url, server.endpoint("compute").join("servers").unwrap());
+        let token = session.token_info().await.unwrap();
+        assert_eq!(token.methods, vec!["token"]);
+        let err = session.get_endpoint(IMAGE, &["v2"]).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::EndpointNotFound);
+    }
+}